@@ -5,19 +5,24 @@ use log::{info, warn};
 use serde::{Deserialize, Serialize};
 use std::fs::{File, read_dir};
 use std::io::BufWriter;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 
 #[derive(Parser, Debug)]
 #[command(name = "m3gif-cli")]
 #[command(about = "Desktop GIF89a pipeline: RGBA → NN Downsize → NeuQuant → GIF89a")]
 struct Args {
-    /// Input directory containing CBOR frames
-    #[arg(long, value_name = "DIR")]
-    in_cbor: PathBuf,
+    /// Input directories containing CBOR frames (several, or a shell glob, for batch mode)
+    #[arg(long, value_name = "DIR", num_args = 1.., required = true)]
+    in_cbor: Vec<PathBuf>,
     
-    /// Output GIF file path
-    #[arg(long, value_name = "FILE")]
-    out: PathBuf,
+    /// Output GIF file path (single input directory)
+    #[arg(long, value_name = "FILE", required_unless_present = "out_dir")]
+    out: Option<PathBuf>,
+    
+    /// Output directory for batch mode, one `<input dir name>.gif` per input
+    #[arg(long, value_name = "DIR", conflicts_with = "out")]
+    out_dir: Option<PathBuf>,
     
     /// Input frame width (expected)
     #[arg(long, default_value = "729")]
@@ -39,6 +44,18 @@ struct Args {
     #[arg(long)]
     r#loop: bool,
     
+    /// Derive per-frame delays from capture timestamps instead of --delay-cs
+    #[arg(long)]
+    delays_from_timestamps: bool,
+    
+    /// Abort on the first unreadable CBOR frame instead of skipping it
+    #[arg(long)]
+    fail_fast: bool,
+    
+    /// Abort on a V2 frame whose CRC32 doesn't match its data instead of skipping it
+    #[arg(long)]
+    strict: bool,
+    
     /// Quantization method
     #[arg(long, default_value = "neuquant")]
     quant: String,
@@ -83,23 +100,140 @@ impl CurrentCborFrame {
     }
 }
 
+/// Schema version of `CborFrameV2` (major 2, minor 0)
+const CBOR_V2_VERSION: u16 = 0x0200;
+
+/// The rust-core `CborFrameV2` fields the CLI needs; color space and camera
+/// metadata are ignored when parsing
+#[derive(Serialize, Deserialize, Debug)]
+struct CborFrameV2 {
+    version: u16,
+    frame_index: u16,
+    timestamp_ms: u64,
+    checksum: u32,  // CRC32 of rgba_data
+    width: u16,
+    height: u16,
+    stride: u32,
+    pixel_format: u32,
+    #[serde(with = "serde_bytes")]
+    rgba_data: Vec<u8>,  // Tightly packed RGBA
+}
+
+impl CborFrameV2 {
+    fn verify_integrity(&self) -> bool {
+        crc32fast::hash(&self.rgba_data) == self.checksum
+    }
+}
+
+/// Just the `version` field, to tell V2 frames apart from current ones
+#[derive(Deserialize)]
+struct CborVersionProbe {
+    version: Option<u16>,
+}
+
+/// A CBOR file of either format, converted to tight RGBA
+struct ParsedCborFrame {
+    frame: RgbaFrame,
+    frame_index: u32,
+    stride: u32,
+    /// V2 frames whose data no longer matches their CRC32: `(recorded, computed)`
+    checksum_mismatch: Option<(u32, u32)>,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 struct RgbaFrame {
     width: u32,
     height: u32,
+    timestamp_ms: u64,
     data: Vec<u8>, // RGBA bytes
 }
 
+/// Frames read from a capture directory, plus the files that couldn't be
+#[derive(Debug)]
+struct LoadedFrames {
+    frames: Vec<RgbaFrame>,
+    /// Unreadable files and why, in load order
+    skipped: Vec<(PathBuf, String)>,
+}
+
+/// Result of converting one capture directory
+#[derive(Debug)]
+struct ConversionSummary {
+    input: PathBuf,
+    output: PathBuf,
+    frames: usize,
+    size_bytes: u64,
+    duration_ms: u128,
+}
+
 fn main() -> Result<()> {
     env_logger::init();
     let args = Args::parse();
     
     info!("M3GIF CLI: RGBA→NN→Quant→GIF89a pipeline");
-    info!("Input: {:?}, Output: {:?}", args.in_cbor, args.out);
     info!("Dimensions: {}×{} → {}×{}", args.w, args.h, args.target, args.target);
     
+    match (&args.out, &args.out_dir) {
+        (Some(out), None) if args.in_cbor.len() == 1 => {
+            convert_dir(&args.in_cbor[0], out, &args)?;
+        }
+        (_, Some(out_dir)) => {
+            let summaries = process_batch(&args.in_cbor, out_dir, &args)?;
+            print_batch_report(&summaries);
+        }
+        _ => return Err(anyhow::anyhow!("Multiple --in-cbor directories need --out-dir")),
+    }
+    
+    Ok(())
+}
+
+/// Convert each capture directory to `<out_dir>/<dir name>.gif`
+///
+/// Stops at the first directory that fails, naming it in the error.
+fn process_batch(dirs: &[PathBuf], out_dir: &Path, args: &Args) -> Result<Vec<ConversionSummary>> {
+    std::fs::create_dir_all(out_dir)
+        .with_context(|| format!("Failed to create output directory: {:?}", out_dir))?;
+    
+    let mut summaries = Vec::with_capacity(dirs.len());
+    for dir in dirs {
+        let name = dir.file_name()
+            .ok_or_else(|| anyhow::anyhow!("Input directory has no name: {:?}", dir))?;
+        let out = out_dir.join(name).with_extension("gif");
+        
+        let summary = convert_dir(dir, &out, args)
+            .with_context(|| format!("Batch conversion failed for {:?}", dir))?;
+        summaries.push(summary);
+    }
+    
+    Ok(summaries)
+}
+
+fn print_batch_report(summaries: &[ConversionSummary]) {
+    println!("{:<32} {:<40} {:>7} {:>10} {:>8}", "input", "output", "frames", "bytes", "ms");
+    for summary in summaries {
+        println!(
+            "{:<32} {:<40} {:>7} {:>10} {:>8}",
+            summary.input.display(), summary.output.display(),
+            summary.frames, summary.size_bytes, summary.duration_ms
+        );
+    }
+    
+    let total_bytes: u64 = summaries.iter().map(|s| s.size_bytes).sum();
+    let total_ms: u128 = summaries.iter().map(|s| s.duration_ms).sum();
+    println!("{} GIFs, {} bytes, {} ms total", summaries.len(), total_bytes, total_ms);
+}
+
+/// Run the full pipeline on one CBOR capture directory
+fn convert_dir(in_cbor: &PathBuf, out: &PathBuf, args: &Args) -> Result<ConversionSummary> {
+    let start = Instant::now();
+    info!("Input: {:?}, Output: {:?}", in_cbor, out);
+    
     // Step 1: Load CBOR frames
-    let rgba_frames = load_cbor_frames(&args.in_cbor, args.w, args.h)?;
+    let loaded = load_cbor_frames(in_cbor, args.w, args.h, args.fail_fast, args.strict)?;
+    if !loaded.skipped.is_empty() {
+        warn!("Skipped {} unreadable CBOR frames in {:?}", loaded.skipped.len(), in_cbor);
+    }
+    let rgba_frames = loaded.frames;
     info!("Loaded {} RGBA frames", rgba_frames.len());
     
     // Step 2: Downsize 729→81 (M2) 
@@ -110,18 +244,51 @@ fn main() -> Result<()> {
     let quantized_frames = quantize_frames(&downsized_frames, args.samplefac)?;
     info!("Quantized {} frames with NeuQuant", quantized_frames.len());
     
-    // Step 4: Encode GIF89a (M3.2)
-    encode_gif89a(&quantized_frames, &args.out, args.delay_cs, args.r#loop)?;
-    info!("Encoded GIF89a: {:?}", args.out);
+    // Per-frame delays: uniform, or reconstructed from the real capture cadence
+    let delays_cs = if args.delays_from_timestamps {
+        let timestamps: Vec<u64> = rgba_frames.iter().map(|f| f.timestamp_ms).collect();
+        let default_cs = args.delay_cs.min(u8::MAX as u16) as u8;
+        let delays: Vec<u16> = common_types::timing::delays_from_timestamps(&timestamps, default_cs)
+            .into_iter()
+            .map(u16::from)
+            .collect();
+        info!("Derived delays from timestamps: {:?}", delays);
+        delays
+    } else {
+        vec![args.delay_cs; quantized_frames.len()]
+    };
     
-    Ok(())
+    // Step 4: Encode GIF89a (M3.2)
+    encode_gif89a(&quantized_frames, out, &delays_cs, args.r#loop)?;
+    info!("Encoded GIF89a: {:?}", out);
+    
+    Ok(ConversionSummary {
+        input: in_cbor.clone(),
+        output: out.clone(),
+        frames: quantized_frames.len(),
+        size_bytes: std::fs::metadata(out)?.len(),
+        duration_ms: start.elapsed().as_millis(),
+    })
 }
 
-fn load_cbor_frames(cbor_dir: &PathBuf, expected_w: u32, expected_h: u32) -> Result<Vec<RgbaFrame>> {
+/// Load every `.cbor` / `.cbor2` frame in `cbor_dir`, in file name order
+///
+/// A file that can't be opened or parsed (e.g. truncated mid-write) is logged
+/// and skipped, so one bad frame doesn't lose the capture. With `fail_fast`
+/// the first such file is an error instead. V2 frames whose CRC32 doesn't
+/// match their data are skipped the same way, or are an error with `strict`.
+fn load_cbor_frames(
+    cbor_dir: &PathBuf,
+    expected_w: u32,
+    expected_h: u32,
+    fail_fast: bool,
+    strict: bool,
+) -> Result<LoadedFrames> {
     let mut frames = Vec::new();
+    let mut skipped = Vec::new();
     let mut entries: Vec<_> = read_dir(cbor_dir)?
         .filter_map(|entry| entry.ok())
-        .filter(|entry| entry.path().extension().map_or(false, |ext| ext == "cbor"))
+        .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "cbor" || ext == "cbor2"))
         .collect();
     
     entries.sort_by_key(|entry| entry.path());
@@ -130,18 +297,30 @@ fn load_cbor_frames(cbor_dir: &PathBuf, expected_w: u32, expected_h: u32) -> Res
         let path = entry.path();
         info!("Loading: {:?}", path);
         
-        let file = File::open(&path)?;
-        let cbor_frame: CurrentCborFrame = serde_cbor::from_reader(file)
-            .with_context(|| format!("Failed to parse CBOR: {:?}", path))?;
+        let parsed = std::fs::read(&path)
+            .context("Failed to open")
+            .and_then(|bytes| parse_cbor_frame(&path, &bytes));
+        let parsed = match parsed {
+            Ok(parsed) => parsed,
+            Err(err) if fail_fast => return Err(err.context(format!("Unreadable CBOR frame: {:?}", path))),
+            Err(err) => {
+                warn!("Skipping unreadable CBOR frame {:?}: {:#}", path, err);
+                skipped.push((path, format!("{:#}", err)));
+                continue;
+            }
+        };
         
-        // Convert to tight RGBA format
-        let tight_rgba = cbor_frame.to_tight_rgba();
+        if let Some((recorded, computed)) = parsed.checksum_mismatch {
+            let reason = format!("CRC32 mismatch: recorded {:08x}, computed {:08x}", recorded, computed);
+            if strict {
+                return Err(anyhow::anyhow!("Corrupt CBOR frame {:?}: {}", path, reason));
+            }
+            warn!("Skipping corrupt CBOR frame {:?}: {}", path, reason);
+            skipped.push((path, reason));
+            continue;
+        }
         
-        let frame = RgbaFrame {
-            width: cbor_frame.w,
-            height: cbor_frame.h,
-            data: tight_rgba,
-        };
+        let ParsedCborFrame { frame, frame_index, stride, .. } = parsed;
         
         // Validate dimensions
         if frame.width != expected_w || frame.height != expected_h {
@@ -157,8 +336,8 @@ fn load_cbor_frames(cbor_dir: &PathBuf, expected_w: u32, expected_h: u32) -> Res
         }
         
         info!("Frame {} ({}×{}): {} tight RGBA bytes from stride={}", 
-              cbor_frame.frame_index, frame.width, frame.height, 
-              frame.data.len(), cbor_frame.stride);
+              frame_index, frame.width, frame.height, 
+              frame.data.len(), stride);
 
         frames.push(frame);
     }
@@ -169,7 +348,45 @@ fn load_cbor_frames(cbor_dir: &PathBuf, expected_w: u32, expected_h: u32) -> Res
           frames.get(0).map_or(0, |f| f.height), 
           frames.get(0).map_or(0, |f| f.data.len()));
     
-    Ok(frames)
+    Ok(LoadedFrames { frames, skipped })
+}
+
+/// Decode one CBOR frame file, as V2 if it has a `.cbor2` extension or a
+/// version 2.x `version` field, else as the current format
+fn parse_cbor_frame(path: &Path, bytes: &[u8]) -> Result<ParsedCborFrame> {
+    let is_v2 = path.extension().is_some_and(|ext| ext == "cbor2")
+        || serde_cbor::from_slice::<CborVersionProbe>(bytes)
+            .is_ok_and(|probe| probe.version.is_some_and(|v| v >> 8 == CBOR_V2_VERSION >> 8));
+    
+    if is_v2 {
+        let cbor_frame: CborFrameV2 = serde_cbor::from_slice(bytes).context("Failed to parse V2 frame")?;
+        let checksum_mismatch = (!cbor_frame.verify_integrity())
+            .then(|| (cbor_frame.checksum, crc32fast::hash(&cbor_frame.rgba_data)));
+        return Ok(ParsedCborFrame {
+            frame_index: cbor_frame.frame_index as u32,
+            stride: cbor_frame.stride,
+            checksum_mismatch,
+            frame: RgbaFrame {
+                width: cbor_frame.width as u32,
+                height: cbor_frame.height as u32,
+                timestamp_ms: cbor_frame.timestamp_ms,
+                data: cbor_frame.rgba_data,
+            },
+        });
+    }
+    
+    let cbor_frame: CurrentCborFrame = serde_cbor::from_slice(bytes).context("Failed to parse")?;
+    Ok(ParsedCborFrame {
+        frame_index: cbor_frame.frame_index,
+        stride: cbor_frame.stride,
+        checksum_mismatch: None,
+        frame: RgbaFrame {
+            width: cbor_frame.w,
+            height: cbor_frame.h,
+            timestamp_ms: cbor_frame.ts_ms,
+            data: cbor_frame.to_tight_rgba(),
+        },
+    })
 }
 
 fn downsize_frames(rgba_frames: &[RgbaFrame], target_size: u32) -> Result<Vec<RgbaFrame>> {
@@ -191,6 +408,7 @@ fn downsize_frames(rgba_frames: &[RgbaFrame], target_size: u32) -> Result<Vec<Rg
         downsized.push(RgbaFrame {
             width: target_size,
             height: target_size,
+            timestamp_ms: frame.timestamp_ms,
             data: downsized_data,
         });
     }
@@ -312,11 +530,14 @@ fn quantize_frames(rgba_frames: &[RgbaFrame], sample_factor: i32) -> Result<Vec<
 fn encode_gif89a(
     quantized_frames: &[QuantizedFrame], 
     output_path: &PathBuf,
-    delay_cs: u16,
+    delays_cs: &[u16],
     infinite_loop: bool,
 ) -> Result<()> {
-    info!("Encoding GIF89a: {} frames, delay={}cs, loop={}", 
-          quantized_frames.len(), delay_cs, infinite_loop);
+    info!("Encoding GIF89a: {} frames, delays={}cs..{}cs, loop={}", 
+          quantized_frames.len(),
+          delays_cs.iter().min().copied().unwrap_or(0),
+          delays_cs.iter().max().copied().unwrap_or(0),
+          infinite_loop);
     
     if quantized_frames.is_empty() {
         return Err(anyhow::anyhow!("No frames to encode"));
@@ -347,7 +568,7 @@ fn encode_gif89a(
         // Set the local color table manually if needed
         frame.palette = Some(qframe.palette.clone());
         
-        frame.delay = delay_cs;
+        frame.delay = delays_cs[i];
         
         encoder.write_frame(&frame)?;
     }
@@ -360,3 +581,118 @@ fn encode_gif89a(
     
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Write `frames` solid-color 81×81 CBOR frames into `dir`
+    fn write_fixture(dir: &Path, frames: u32, rgb: [u8; 3]) {
+        std::fs::create_dir_all(dir).unwrap();
+        for i in 0..frames {
+            let frame = CurrentCborFrame {
+                w: 81,
+                h: 81,
+                format: "RGBA8888".to_string(),
+                stride: 81 * 4,
+                ts_ms: i as u64 * 40,
+                frame_index: i,
+                data: [rgb[0], rgb[1], rgb[2], 255].repeat(81 * 81),
+            };
+            let file = File::create(dir.join(format!("frame_{:03}.cbor", i))).unwrap();
+            serde_cbor::to_writer(file, &frame).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_process_batch_converts_each_directory() {
+        let root = tempfile::tempdir().unwrap();
+        let (red, blue) = (root.path().join("red"), root.path().join("blue"));
+        write_fixture(&red, 3, [200, 20, 20]);
+        write_fixture(&blue, 2, [20, 20, 200]);
+        let out_dir = root.path().join("gifs");
+
+        let args = Args::parse_from([
+            "m3gif-cli", "--in-cbor", red.to_str().unwrap(), blue.to_str().unwrap(),
+            "--out-dir", out_dir.to_str().unwrap(), "--w", "81", "--h", "81",
+        ]);
+        let summaries = process_batch(&args.in_cbor, &out_dir, &args).unwrap();
+
+        assert_eq!(summaries.len(), 2);
+        for (summary, (name, frames)) in summaries.iter().zip([("red", 3), ("blue", 2)]) {
+            assert_eq!(summary.input, root.path().join(name));
+            assert_eq!(summary.output, out_dir.join(format!("{}.gif", name)));
+            assert_eq!(summary.frames, frames);
+
+            let bytes = std::fs::read(&summary.output).unwrap();
+            assert_eq!(bytes.len() as u64, summary.size_bytes);
+            let mut decoder = gif::DecodeOptions::new().read_info(bytes.as_slice()).unwrap();
+            let mut decoded = 0;
+            while decoder.read_next_frame().unwrap().is_some() {
+                decoded += 1;
+            }
+            assert_eq!(decoded, frames);
+        }
+
+        // A single --out file still parses, and several inputs require --out-dir
+        assert!(Args::try_parse_from(["m3gif-cli", "--in-cbor", "a", "--out", "a.gif"]).is_ok());
+        assert!(Args::try_parse_from(["m3gif-cli", "--in-cbor", "a", "b"]).is_err());
+    }
+
+    #[test]
+    fn test_load_skips_corrupt_frame() {
+        let root = tempfile::tempdir().unwrap();
+        write_fixture(root.path(), 4, [10, 200, 10]);
+        // Truncate one frame as if the capture was interrupted mid-write
+        let corrupt = root.path().join("frame_002.cbor");
+        let bytes = std::fs::read(&corrupt).unwrap();
+        std::fs::write(&corrupt, &bytes[..bytes.len() / 2]).unwrap();
+
+        let loaded = load_cbor_frames(&root.path().to_path_buf(), 81, 81, false, false).unwrap();
+        assert_eq!(loaded.frames.len(), 3);
+        let timestamps: Vec<u64> = loaded.frames.iter().map(|f| f.timestamp_ms).collect();
+        assert_eq!(timestamps, [0, 40, 120]);
+        assert_eq!(loaded.skipped.len(), 1);
+        assert_eq!(loaded.skipped[0].0, corrupt);
+
+        let err = load_cbor_frames(&root.path().to_path_buf(), 81, 81, true, false).unwrap_err();
+        assert!(format!("{:#}", err).contains("frame_002.cbor"), "{:#}", err);
+    }
+
+    #[test]
+    fn test_strict_rejects_v2_frame_with_stale_checksum() {
+        let root = tempfile::tempdir().unwrap();
+        for i in 0..3u16 {
+            let rgba_data = [30, 60, 90, 255].repeat(81 * 81);
+            let mut frame = CborFrameV2 {
+                version: CBOR_V2_VERSION,
+                frame_index: i,
+                timestamp_ms: i as u64 * 40,
+                checksum: crc32fast::hash(&rgba_data),
+                width: 81,
+                height: 81,
+                stride: 81 * 4,
+                pixel_format: 0x01,
+                rgba_data,
+            };
+            if i == 1 {
+                // Flip a byte after the checksum was taken, as a torn write would
+                frame.rgba_data[1000] ^= 0xFF;
+            }
+            // V2 is detected from the version field as well as the extension
+            let ext = if i == 0 { "cbor2" } else { "cbor" };
+            let file = File::create(root.path().join(format!("frame_{:03}.{}", i, ext))).unwrap();
+            serde_cbor::to_writer(file, &frame).unwrap();
+        }
+        let dir = root.path().to_path_buf();
+
+        let err = load_cbor_frames(&dir, 81, 81, false, true).unwrap_err();
+        assert!(format!("{:#}", err).contains("frame_001.cbor"), "{:#}", err);
+
+        let loaded = load_cbor_frames(&dir, 81, 81, false, false).unwrap();
+        let timestamps: Vec<u64> = loaded.frames.iter().map(|f| f.timestamp_ms).collect();
+        assert_eq!(timestamps, [0, 80]);
+        assert_eq!(loaded.skipped.len(), 1);
+        assert!(loaded.skipped[0].1.contains("CRC32 mismatch"));
+    }
+}
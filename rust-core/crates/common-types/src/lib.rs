@@ -1,6 +1,10 @@
 use serde::{Serialize, Deserialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use thiserror::Error;
 
+mod xxh64;
+
 #[cfg(feature = "ffi")]
 uniffi::setup_scaffolding!();
 
@@ -9,6 +13,13 @@ pub const FRAME_SIZE_729: u16 = 729;
 pub const FRAME_SIZE_81: u16 = 81;  
 pub const PALETTE_SIZE: u16 = 256;
 pub const EXPECTED_FRAME_COUNT: u16 = 81;
+/// Most frames a cube may carry into the encoder; `EXPECTED_FRAME_COUNT` stays the default capture
+pub const MAX_FRAME_COUNT: u16 = 1024;
+
+/// Current `QuantizedCubeData` schema; cubes serialized before versioning read as 0
+pub const CUBE_SCHEMA_VERSION: u32 = 1;
+/// Frame delay filled in for cubes that carry none (25fps)
+pub const DEFAULT_DELAY_CS: u8 = 4;
 
 /// Complete quantization result with quality metrics and output artifacts
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -59,12 +70,31 @@ pub struct GifInfo {
     pub palette_size: u32,
     pub has_netscape_loop: bool,
     pub compression_ratio: f32,
+    pub uncompressed_size_bytes: u64,  // RGB size the ratio is measured against
     pub validation_passed: bool,
     pub processing_time_ms: u64,
     pub total_processing_ms: u64,
+    /// Non-fatal problems found while encoding, e.g. suspiciously uniform frames
+    #[serde(default)]
+    pub warnings: Vec<String>,
     pub gif_data: Vec<u8>,  // Raw GIF bytes
 }
 
+impl GifInfo {
+    /// RGB bytes of `frame_count` frames before indexing and LZW, the basis for `compression_ratio`
+    pub fn uncompressed_size(frame_count: usize, pixels_per_frame: usize) -> u64 {
+        (frame_count * pixels_per_frame * 3) as u64
+    }
+
+    /// `uncompressed_size_bytes / compressed_size_bytes`, or 0 for empty output
+    pub fn ratio(uncompressed_size_bytes: u64, compressed_size_bytes: u64) -> f32 {
+        if compressed_size_bytes == 0 {
+            return 0.0;
+        }
+        uncompressed_size_bytes as f32 / compressed_size_bytes as f32
+    }
+}
+
 /// Quantized cube data for WYSIWYG preview and GIF encoding
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[cfg_attr(feature = "ffi", derive(uniffi::Record))]
@@ -73,12 +103,367 @@ pub struct QuantizedCubeData {
     pub height: u16, // 81
     pub global_palette_rgb: Vec<u8>,     // 256 * 3 RGB bytes
     pub indexed_frames: Vec<Vec<u8>>,    // 81 frames of 81*81 indices
+    #[serde(default)]
     pub delays_cs: Vec<u8>,              // length 81, centiseconds per frame
     pub palette_stability: f32,          // [0..1] temporal coherence
     pub mean_delta_e: f32,               // Oklab ΔE mean
     pub p95_delta_e: f32,                // Oklab ΔE p95
     #[cfg_attr(feature = "ffi", uniffi(default = None))]
     pub attention_maps: Option<Vec<Vec<f32>>>, // 81 optional attention maps
+    #[serde(default)]
+    #[cfg_attr(feature = "ffi", uniffi(default = None))]
+    pub frame_checksums: Option<Vec<u32>>,     // CRC32 per indexed frame
+    #[serde(default)]
+    #[cfg_attr(feature = "ffi", uniffi(default = None))]
+    pub source_frame_ids: Option<Vec<u32>>,    // Capture frame each indexed frame came from
+    #[serde(default)]
+    #[cfg_attr(feature = "ffi", uniffi(default = 0))]
+    pub schema_version: u32,                   // CUBE_SCHEMA_VERSION when produced
+}
+
+/// A change made by `QuantizedCubeData::migrate`
+#[derive(Debug, Clone, PartialEq)]
+pub enum CubeMigration {
+    /// `delays_cs` had `from_len` entries and was resized to the frame count, padding with `delay_cs`
+    DelaysFilled { from_len: usize, delay_cs: u8 },
+    /// `attention_maps` didn't match the frame count and were dropped
+    AttentionMapsDropped { maps: usize, frames: usize },
+    /// Schema version raised to `CUBE_SCHEMA_VERSION`
+    SchemaUpgraded { from: u32, to: u32 },
+}
+
+/// Cube metadata and palette without per-frame pixel data, for lightweight previews
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ffi", derive(uniffi::Record))]
+pub struct CubeSummary {
+    pub width: u16,
+    pub height: u16,
+    pub frame_count: u32,
+    pub global_palette_rgb: Vec<u8>,
+    pub delays_cs: Vec<u8>,
+    pub palette_stability: f32,
+    pub mean_delta_e: f32,
+    pub p95_delta_e: f32,
+}
+
+impl QuantizedCubeData {
+    /// Metadata and palette only; indexed frames and attention maps are dropped
+    pub fn summary(&self) -> CubeSummary {
+        CubeSummary {
+            width: self.width,
+            height: self.height,
+            frame_count: self.indexed_frames.len() as u32,
+            global_palette_rgb: self.global_palette_rgb.clone(),
+            delays_cs: self.delays_cs.clone(),
+            palette_stability: self.palette_stability,
+            mean_delta_e: self.mean_delta_e,
+            p95_delta_e: self.p95_delta_e,
+        }
+    }
+
+    /// JSON of `summary()`, small enough for previews that skip the frames
+    pub fn to_summary_json(&self) -> String {
+        serde_json::to_string(&self.summary()).expect("CubeSummary contains only JSON-safe fields")
+    }
+
+    /// Upgrade a cube serialized by an older pipeline to the current schema
+    ///
+    /// Missing or mis-sized delays are filled with the last known delay (or
+    /// `DEFAULT_DELAY_CS`), and attention maps that don't cover every frame are
+    /// dropped. Cubes from a newer schema are rejected rather than guessed at.
+    pub fn migrate(&mut self) -> Result<Vec<CubeMigration>, GifPipeError> {
+        if self.schema_version > CUBE_SCHEMA_VERSION {
+            return Err(GifPipeError::ValidationFailed {
+                message: format!(
+                    "Cube schema version {} is newer than supported version {}",
+                    self.schema_version, CUBE_SCHEMA_VERSION
+                ),
+            });
+        }
+
+        let mut changes = Vec::new();
+        let frame_count = self.indexed_frames.len();
+
+        if self.delays_cs.len() != frame_count {
+            let delay_cs = self.delays_cs.last().copied().unwrap_or(DEFAULT_DELAY_CS);
+            changes.push(CubeMigration::DelaysFilled { from_len: self.delays_cs.len(), delay_cs });
+            self.delays_cs.resize(frame_count, delay_cs);
+        }
+
+        if let Some(maps) = &self.attention_maps {
+            if maps.len() != frame_count {
+                changes.push(CubeMigration::AttentionMapsDropped { maps: maps.len(), frames: frame_count });
+                self.attention_maps = None;
+            }
+        }
+
+        if self.schema_version < CUBE_SCHEMA_VERSION {
+            changes.push(CubeMigration::SchemaUpgraded { from: self.schema_version, to: CUBE_SCHEMA_VERSION });
+            self.schema_version = CUBE_SCHEMA_VERSION;
+        }
+
+        Ok(changes)
+    }
+
+    /// Copy of the cube keeping only frames `start..end`, without re-quantizing
+    ///
+    /// The palette is shared unchanged. Delays, attention maps, checksums and
+    /// source frame ids are sliced when they cover every frame and dropped
+    /// otherwise; palette stability is recomputed for the shorter range, while
+    /// the ΔE metrics of the full capture are kept since the source pixels are gone.
+    pub fn trim(&self, start: usize, end: usize) -> Result<Self, GifPipeError> {
+        let frame_count = self.indexed_frames.len();
+        if start >= end || end > frame_count {
+            return Err(GifPipeError::ValidationFailed {
+                message: format!("Invalid trim range {}..{} for {} frames", start, end, frame_count),
+            });
+        }
+
+        self.select_frames(&(start..end).collect::<Vec<_>>())
+    }
+
+    /// Copy of the cube with frames `indices`, in that order, without re-quantizing
+    ///
+    /// Covers reordering and dropping duplicates as well as trimming, with the
+    /// same handling of per-frame data as `trim`. Indices may repeat.
+    pub fn select_frames(&self, indices: &[usize]) -> Result<Self, GifPipeError> {
+        let frame_count = self.indexed_frames.len();
+        if let Some(&idx) = indices.iter().find(|&&idx| idx >= frame_count) {
+            return Err(GifPipeError::ValidationFailed {
+                message: format!("Frame {} out of range for {} frames", idx, frame_count),
+            });
+        }
+
+        fn pick_full<T: Clone>(values: &[T], frame_count: usize, indices: &[usize]) -> Option<Vec<T>> {
+            (values.len() == frame_count).then(|| indices.iter().map(|&idx| values[idx].clone()).collect())
+        }
+
+        let indexed_frames: Vec<Vec<u8>> = indices.iter().map(|&idx| self.indexed_frames[idx].clone()).collect();
+        Ok(Self {
+            width: self.width,
+            height: self.height,
+            global_palette_rgb: self.global_palette_rgb.clone(),
+            palette_stability: palette_stability(&indexed_frames),
+            indexed_frames,
+            delays_cs: pick_full(&self.delays_cs, frame_count, indices).unwrap_or_default(),
+            mean_delta_e: self.mean_delta_e,
+            p95_delta_e: self.p95_delta_e,
+            attention_maps: self
+                .attention_maps
+                .as_ref()
+                .and_then(|maps| pick_full(maps, frame_count, indices)),
+            frame_checksums: self
+                .frame_checksums
+                .as_ref()
+                .and_then(|checksums| pick_full(checksums, frame_count, indices)),
+            source_frame_ids: self
+                .source_frame_ids
+                .as_ref()
+                .and_then(|ids| pick_full(ids, frame_count, indices)),
+            schema_version: self.schema_version,
+        })
+    }
+
+    /// Number each frame with its current position as its source frame id
+    ///
+    /// Call on the cube as captured; `trim` and `select_frames` then carry the
+    /// ids along so output frames can be mapped back to capture frames.
+    pub fn with_source_frame_ids(mut self) -> Self {
+        self.source_frame_ids = Some((0..self.indexed_frames.len() as u32).collect());
+        self
+    }
+
+    /// Recompute palette stability from the indexed frames
+    ///
+    /// Canonical metric shared by every quantizer path, so the score is
+    /// reproducible regardless of how the cube was produced.
+    pub fn recompute_stability(&self) -> f32 {
+        palette_stability(&self.indexed_frames)
+    }
+
+    /// CRC32 of every indexed frame
+    pub fn compute_frame_checksums(&self) -> Vec<u32> {
+        self.indexed_frames.iter().map(|frame| crc32fast::hash(frame)).collect()
+    }
+
+    /// Record per-frame checksums so later stages can detect corruption
+    pub fn with_frame_checksums(mut self) -> Self {
+        self.frame_checksums = Some(self.compute_frame_checksums());
+        self
+    }
+
+    /// Check indexed frames against recorded checksums, if any were set
+    pub fn verify_frame_checksums(&self) -> Result<(), GifPipeError> {
+        let Some(expected) = &self.frame_checksums else {
+            return Ok(());
+        };
+
+        if expected.len() != self.indexed_frames.len() {
+            return Err(GifPipeError::ValidationFailed {
+                message: format!(
+                    "{} frame checksums for {} frames",
+                    expected.len(),
+                    self.indexed_frames.len()
+                ),
+            });
+        }
+
+        for (idx, (frame, &checksum)) in self.indexed_frames.iter().zip(expected).enumerate() {
+            let actual = crc32fast::hash(frame);
+            if actual != checksum {
+                return Err(GifPipeError::ValidationFailed {
+                    message: format!(
+                        "Frame {} checksum mismatch: expected {:08x}, got {:08x}",
+                        idx, checksum, actual
+                    ),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Stable XXH64 fingerprint over dimensions, palette, frames, and delays
+    ///
+    /// Identical cubes hash identically across runs and platforms, so hosts can
+    /// key an encoded-GIF cache on it. Derived metrics are not included.
+    pub fn fingerprint(&self) -> u64 {
+        let mut hasher = xxh64::Xxh64::new(0);
+
+        hasher.update(&self.width.to_le_bytes());
+        hasher.update(&self.height.to_le_bytes());
+
+        // Length prefixes keep frame boundaries part of the hash
+        hasher.update(&(self.global_palette_rgb.len() as u64).to_le_bytes());
+        hasher.update(&self.global_palette_rgb);
+
+        hasher.update(&(self.indexed_frames.len() as u64).to_le_bytes());
+        for frame in &self.indexed_frames {
+            hasher.update(&(frame.len() as u64).to_le_bytes());
+            hasher.update(frame);
+        }
+
+        hasher.update(&(self.delays_cs.len() as u64).to_le_bytes());
+        hasher.update(&self.delays_cs);
+
+        hasher.finish()
+    }
+}
+
+/// Mean index-histogram intersection between consecutive frames in [0..1]
+pub fn palette_stability(indexed_frames: &[Vec<u8>]) -> f32 {
+    if indexed_frames.len() < 2 {
+        return 1.0;
+    }
+
+    let histograms: Vec<[u32; 256]> = indexed_frames
+        .iter()
+        .map(|frame| {
+            let mut histogram = [0u32; 256];
+            for &index in frame {
+                histogram[index as usize] += 1;
+            }
+            histogram
+        })
+        .collect();
+
+    let total_similarity: f32 = histograms
+        .windows(2)
+        .map(|pair| histogram_similarity(&pair[0], &pair[1]))
+        .sum();
+
+    total_similarity / (histograms.len() - 1) as f32
+}
+
+/// The `n` frames with the highest mean Oklab ΔE against their RGB source
+///
+/// Returns `(frame_index, mean_delta_e)` sorted worst first, to help callers
+/// decide which frames deserve a local color table.
+pub fn worst_frames(cube: &QuantizedCubeData, source_rgb: &[Vec<u8>], n: usize) -> Vec<(u16, f32)> {
+    let mut scores: Vec<(u16, f32)> = cube
+        .indexed_frames
+        .iter()
+        .zip(source_rgb)
+        .enumerate()
+        .map(|(idx, (indices, source))| {
+            (idx as u16, oklab::mean_delta_e_indexed(indices, &cube.global_palette_rgb, source))
+        })
+        .collect();
+
+    scores.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+    scores.truncate(n);
+    scores
+}
+
+/// Index changes a pixel may make across the sequence before it counts as flickering
+///
+/// Real motion passing a pixel changes it a few times; more than this is oscillation.
+pub const FLICKER_MAX_CHANGES: u32 = 4;
+
+/// Fraction of pixels in [0..1] whose palette index changes more than
+/// `FLICKER_MAX_CHANGES` times between consecutive frames
+///
+/// A direct flicker proxy: histogram-based stability misses pixels that
+/// swap indices with each other while the overall color counts stay put.
+pub fn measure_flicker(cube: &QuantizedCubeData) -> f32 {
+    let Some(first) = cube.indexed_frames.first() else {
+        return 0.0;
+    };
+    if first.is_empty() {
+        return 0.0;
+    }
+
+    let mut changes = vec![0u32; first.len()];
+    for pair in cube.indexed_frames.windows(2) {
+        for ((count, &a), &b) in changes.iter_mut().zip(&pair[0]).zip(&pair[1]) {
+            if a != b {
+                *count += 1;
+            }
+        }
+    }
+
+    let flickering = changes.iter().filter(|&&count| count > FLICKER_MAX_CHANGES).count();
+    flickering as f32 / changes.len() as f32
+}
+
+/// Palette blocks per row in `export_palette_swatch`, so 256 colors form a 16×16 grid
+pub const SWATCH_COLUMNS: usize = 16;
+
+/// Render the cube's palette as an RGBA grid of `swatch_size`-pixel square blocks
+///
+/// Colors run left to right, `SWATCH_COLUMNS` per row, so the image is
+/// `SWATCH_COLUMNS * swatch_size` wide and `ceil(colors / SWATCH_COLUMNS) * swatch_size`
+/// tall. Cells past the last color are transparent.
+pub fn export_palette_swatch(cube: &QuantizedCubeData, swatch_size: u32) -> Vec<u8> {
+    let colors = cube.global_palette_rgb.len() / 3;
+    let size = swatch_size as usize;
+    let width = SWATCH_COLUMNS * size;
+    let rows = colors.div_ceil(SWATCH_COLUMNS);
+
+    let mut rgba = vec![0u8; width * rows * size * 4];
+    for (index, rgb) in cube.global_palette_rgb.chunks_exact(3).enumerate() {
+        let (col, row) = (index % SWATCH_COLUMNS, index / SWATCH_COLUMNS);
+        for y in row * size..(row + 1) * size {
+            for x in col * size..(col + 1) * size {
+                let offset = (y * width + x) * 4;
+                rgba[offset..offset + 4].copy_from_slice(&[rgb[0], rgb[1], rgb[2], 255]);
+            }
+        }
+    }
+
+    rgba
+}
+
+fn histogram_similarity(hist1: &[u32], hist2: &[u32]) -> f32 {
+    let total1: u32 = hist1.iter().sum();
+    let total2: u32 = hist2.iter().sum();
+
+    if total1 == 0 || total2 == 0 {
+        return 0.0;
+    }
+
+    let intersection: u32 = hist1.iter().zip(hist2.iter()).map(|(&h1, &h2)| h1.min(h2)).sum();
+    intersection as f32 / total1.max(total2) as f32
 }
 
 // Bevy Resource trait for cube viewer
@@ -176,6 +561,9 @@ pub enum GifPipeError {
     #[error("E_SYSTEM_TIMEOUT: timeout exceeded: {message}")]
     TimeoutExceeded { message: String },
     
+    #[error("E_SYSTEM_CANCELLED: operation cancelled: {message}")]
+    Cancelled { message: String },
+    
     #[error("E_SYSTEM_PANIC: critical panic occurred: {message}")]
     PanicOccurred { message: String },
 
@@ -193,6 +581,20 @@ pub enum GifPipeError {
     LoggingError { message: String },
 }
 impl GifPipeError {
+    /// Frame count outside 1..=`MAX_FRAME_COUNT`, rejected at the FFI boundary (E_M1_INPUT)
+    ///
+    /// Carries the count, the accepted range and what to do about it, so host
+    /// apps can show the message as-is.
+    pub fn frame_count_out_of_range(actual: usize) -> Self {
+        GifPipeError::InvalidFrameData {
+            message: format!(
+                "frame count out of range: actual={} expected=1..={}; capture between 1 and {} frames \
+                 ({} by default), or trim the sequence before quantizing",
+                actual, MAX_FRAME_COUNT, MAX_FRAME_COUNT, EXPECTED_FRAME_COUNT
+            ),
+        }
+    }
+
     /// Get structured error code for logging and monitoring
     pub fn code(&self) -> &'static str {
         match self {
@@ -224,6 +626,7 @@ impl GifPipeError {
             GifPipeError::ConfigInvalid { .. } => "E_SYSTEM_CONFIG",
             GifPipeError::ResourceUnavailable { .. } => "E_SYSTEM_RESOURCE",
             GifPipeError::TimeoutExceeded { .. } => "E_SYSTEM_TIMEOUT",
+            GifPipeError::Cancelled { .. } => "E_SYSTEM_CANCELLED",
             GifPipeError::PanicOccurred { .. } => "E_SYSTEM_PANIC",
 
             // Legacy codes
@@ -235,6 +638,52 @@ impl GifPipeError {
     }
 }
 
+/// Flag for stopping long-running pipeline work, e.g. when the user leaves the capture screen
+///
+/// Clones share one flag. Stages poll it between frames and iterations and
+/// return `GifPipeError::Cancelled` once it is set.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "ffi", derive(uniffi::Object))]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+#[cfg_attr(feature = "ffi", uniffi::export)]
+impl CancellationToken {
+    #[cfg_attr(feature = "ffi", uniffi::constructor)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ask work holding this token to stop at its next check
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+impl CancellationToken {
+    /// `Err(Cancelled)` naming `stage` once the token is cancelled
+    pub fn check(&self, stage: &str) -> Result<(), GifPipeError> {
+        if self.is_cancelled() {
+            return Err(GifPipeError::Cancelled {
+                message: format!("cancelled during {}", stage),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Receives per-frame progress from long-running pipeline stages, e.g. to drive a progress bar
+///
+/// Called from worker threads. `frame` counts frames finished so far and
+/// reaches `total` when the stage is done.
+#[cfg_attr(feature = "ffi", uniffi::export(with_foreign))]
+pub trait ProgressListener: Send + Sync {
+    fn on_progress(&self, stage: String, frame: u32, total: u32);
+}
+
 /// Oklab color space utilities for perceptual quantization
 pub mod oklab {
     /// Convert RGB to Oklab color space
@@ -276,4 +725,558 @@ pub mod oklab {
         let db = lab1[2] - lab2[2];
         (dl * dl + da * da + db * db).sqrt()
     }
+    
+    /// ΔE with each (L, a, b) difference scaled by `weights` before summing
+    ///
+    /// `[1.0; 3]` matches `delta_e_oklab`; raising L favours brightness over hue.
+    pub fn delta_e_oklab_weighted(lab1: [f32; 3], lab2: [f32; 3], weights: [f32; 3]) -> f32 {
+        let dl = (lab1[0] - lab2[0]) * weights[0];
+        let da = (lab1[1] - lab2[1]) * weights[1];
+        let db = (lab1[2] - lab2[2]) * weights[2];
+        (dl * dl + da * da + db * db).sqrt()
+    }
+
+    /// Mean ΔE between an indexed frame and its RGB source
+    ///
+    /// Indices outside the palette are scored against black.
+    pub fn mean_delta_e_indexed(indices: &[u8], palette_rgb: &[u8], source_rgb: &[u8]) -> f32 {
+        let mut total = 0.0f32;
+        let mut count = 0usize;
+
+        for (&index, src) in indices.iter().zip(source_rgb.chunks_exact(3)) {
+            let base = index as usize * 3;
+            let quantized = palette_rgb.get(base..base + 3).unwrap_or(&[0, 0, 0]);
+            total += delta_e_oklab(
+                rgb_to_oklab(src[0], src[1], src[2]),
+                rgb_to_oklab(quantized[0], quantized[1], quantized[2]),
+            );
+            count += 1;
+        }
+
+        if count == 0 {
+            0.0
+        } else {
+            total / count as f32
+        }
+    }
+}
+
+/// Color difference formula used for *reported* quantization error
+///
+/// Palette search always uses Euclidean Oklab; this only changes the
+/// `mean_delta_e`/`p95_delta_e` numbers handed back to callers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum DeltaEFormula {
+    #[default]
+    OklabEuclidean,
+    /// CIE ΔE 2000 on CIELAB (D65), comparable with other imaging tools
+    Ciede2000,
+}
+
+impl DeltaEFormula {
+    pub fn delta_e_rgb(self, rgb1: [u8; 3], rgb2: [u8; 3]) -> f32 {
+        match self {
+            DeltaEFormula::OklabEuclidean => oklab::delta_e_oklab(
+                oklab::rgb_to_oklab(rgb1[0], rgb1[1], rgb1[2]),
+                oklab::rgb_to_oklab(rgb2[0], rgb2[1], rgb2[2]),
+            ),
+            DeltaEFormula::Ciede2000 => cielab::delta_e_2000(
+                cielab::rgb_to_lab(rgb1[0], rgb1[1], rgb1[2]),
+                cielab::rgb_to_lab(rgb2[0], rgb2[1], rgb2[2]),
+            ),
+        }
+    }
+}
+
+/// CIELAB conversion and the CIEDE2000 color difference
+pub mod cielab {
+    /// Convert sRGB to CIELAB (D65 white point)
+    pub fn rgb_to_lab(r: u8, g: u8, b: u8) -> [f32; 3] {
+        let linear = |c: u8| {
+            let c = c as f64 / 255.0;
+            if c > 0.04045 { ((c + 0.055) / 1.055).powf(2.4) } else { c / 12.92 }
+        };
+        let (r, g, b) = (linear(r), linear(g), linear(b));
+
+        let x = (0.4124564 * r + 0.3575761 * g + 0.1804375 * b) / 0.95047;
+        let y = 0.2126729 * r + 0.7151522 * g + 0.0721750 * b;
+        let z = (0.0193339 * r + 0.1191920 * g + 0.9503041 * b) / 1.08883;
+
+        let f = |t: f64| {
+            if t > 216.0 / 24389.0 { t.cbrt() } else { (24389.0 / 27.0 * t + 16.0) / 116.0 }
+        };
+        let (fx, fy, fz) = (f(x), f(y), f(z));
+
+        [
+            (116.0 * fy - 16.0) as f32,
+            (500.0 * (fx - fy)) as f32,
+            (200.0 * (fy - fz)) as f32,
+        ]
+    }
+
+    /// CIEDE2000 difference between two CIELAB colors (kL = kC = kH = 1)
+    pub fn delta_e_2000(lab1: [f32; 3], lab2: [f32; 3]) -> f32 {
+        use std::f64::consts::PI;
+
+        let (l1, a1, b1) = (lab1[0] as f64, lab1[1] as f64, lab1[2] as f64);
+        let (l2, a2, b2) = (lab2[0] as f64, lab2[1] as f64, lab2[2] as f64);
+
+        let c_bar = ((a1 * a1 + b1 * b1).sqrt() + (a2 * a2 + b2 * b2).sqrt()) / 2.0;
+        let c_bar7 = c_bar.powi(7);
+        let g = 0.5 * (1.0 - (c_bar7 / (c_bar7 + 25f64.powi(7))).sqrt());
+
+        let a1p = (1.0 + g) * a1;
+        let a2p = (1.0 + g) * a2;
+        let c1p = (a1p * a1p + b1 * b1).sqrt();
+        let c2p = (a2p * a2p + b2 * b2).sqrt();
+
+        let hue = |b: f64, a: f64| {
+            if a == 0.0 && b == 0.0 {
+                0.0
+            } else {
+                b.atan2(a).to_degrees().rem_euclid(360.0)
+            }
+        };
+        let h1p = hue(b1, a1p);
+        let h2p = hue(b2, a2p);
+
+        let dl = l2 - l1;
+        let dc = c2p - c1p;
+        let dh = if c1p * c2p == 0.0 {
+            0.0
+        } else if (h2p - h1p).abs() <= 180.0 {
+            h2p - h1p
+        } else if h2p - h1p > 180.0 {
+            h2p - h1p - 360.0
+        } else {
+            h2p - h1p + 360.0
+        };
+        let dh_big = 2.0 * (c1p * c2p).sqrt() * (dh.to_radians() / 2.0).sin();
+
+        let l_bar = (l1 + l2) / 2.0;
+        let c_bar_p = (c1p + c2p) / 2.0;
+        let h_bar = if c1p * c2p == 0.0 {
+            h1p + h2p
+        } else if (h1p - h2p).abs() <= 180.0 {
+            (h1p + h2p) / 2.0
+        } else if h1p + h2p < 360.0 {
+            (h1p + h2p + 360.0) / 2.0
+        } else {
+            (h1p + h2p - 360.0) / 2.0
+        };
+
+        let t = 1.0 - 0.17 * (h_bar - 30.0).to_radians().cos()
+            + 0.24 * (2.0 * h_bar).to_radians().cos()
+            + 0.32 * (3.0 * h_bar + 6.0).to_radians().cos()
+            - 0.20 * (4.0 * h_bar - 63.0).to_radians().cos();
+
+        let d_theta = 30.0 * (-((h_bar - 275.0) / 25.0).powi(2)).exp();
+        let c_bar_p7 = c_bar_p.powi(7);
+        let r_c = 2.0 * (c_bar_p7 / (c_bar_p7 + 25f64.powi(7))).sqrt();
+        let l_term = (l_bar - 50.0).powi(2);
+        let s_l = 1.0 + 0.015 * l_term / (20.0 + l_term).sqrt();
+        let s_c = 1.0 + 0.045 * c_bar_p;
+        let s_h = 1.0 + 0.015 * c_bar_p * t;
+        let r_t = -(2.0 * d_theta * PI / 180.0).sin() * r_c;
+
+        let dl_term = dl / s_l;
+        let dc_term = dc / s_c;
+        let dh_term = dh_big / s_h;
+
+        (dl_term * dl_term + dc_term * dc_term + dh_term * dh_term + r_t * dc_term * dh_term).sqrt()
+            as f32
+    }
+}
+
+/// Frame timing utilities for reconstructing capture cadence
+pub mod timing {
+    /// Derive per-frame GIF delays (centiseconds) from capture timestamps
+    ///
+    /// Each frame is shown until the next frame's timestamp, so dropped frames
+    /// produce proportionally longer delays. The last frame reuses the previous
+    /// interval. Non-increasing timestamps fall back to `default_delay_cs`.
+    pub fn delays_from_timestamps(timestamps_ms: &[u64], default_delay_cs: u8) -> Vec<u8> {
+        let mut delays: Vec<u8> = timestamps_ms
+            .windows(2)
+            .map(|pair| {
+                if pair[1] <= pair[0] {
+                    return default_delay_cs;
+                }
+                let delay_cs = ((pair[1] - pair[0]) as f64 / 10.0).round();
+                delay_cs.clamp(1.0, u8::MAX as f64) as u8
+            })
+            .collect();
+
+        if !timestamps_ms.is_empty() {
+            let last = delays.last().copied().unwrap_or(default_delay_cs);
+            delays.push(last);
+        }
+
+        delays
+    }
+
+    /// GIF frame delay (centiseconds) closest to `fps`
+    ///
+    /// GIF delays are whole centiseconds, so e.g. 24fps plays back as 4cs (25fps).
+    /// Non-positive fps gives the longest delay.
+    pub fn delay_cs_for_fps(fps: f32) -> u8 {
+        if fps.is_nan() || fps <= 0.0 {
+            return u8::MAX;
+        }
+        (100.0 / fps).round().clamp(1.0, u8::MAX as f32) as u8
+    }
+
+    /// Playback length of a loop of `frame_count` frames at `fps`, in milliseconds
+    pub fn loop_duration_ms(frame_count: u16, fps: f32) -> u32 {
+        frame_count as u32 * delay_cs_for_fps(fps) as u32 * 10
+    }
+
+    /// Minimum number of frames for a loop lasting at least `duration_ms` at `fps`
+    pub fn frames_for_duration(duration_ms: u32, fps: f32) -> u16 {
+        let frame_ms = delay_cs_for_fps(fps) as u32 * 10;
+        duration_ms.div_ceil(frame_ms).clamp(1, u16::MAX as u32) as u16
+    }
+
+    /// Mismatch between the intended loop length and what a capture setting produces
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum LoopDurationWarning {
+        TooShort { actual_ms: u32, intended_ms: u32 },
+        TooLong { actual_ms: u32, intended_ms: u32 },
+    }
+
+    /// Check that `frame_count` frames at `fps` play for about `intended_ms`
+    ///
+    /// Warns when the loop is off by more than half a frame, i.e. when a
+    /// different frame count would land closer to the intended length.
+    pub fn check_loop_duration(frame_count: u16, fps: f32, intended_ms: u32) -> Option<LoopDurationWarning> {
+        let actual_ms = loop_duration_ms(frame_count, fps);
+        let half_frame_ms = delay_cs_for_fps(fps) as u32 * 5;
+
+        if actual_ms + half_frame_ms < intended_ms {
+            Some(LoopDurationWarning::TooShort { actual_ms, intended_ms })
+        } else if actual_ms > intended_ms + half_frame_ms {
+            Some(LoopDurationWarning::TooLong { actual_ms, intended_ms })
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cube_from_frames(indexed_frames: Vec<Vec<u8>>) -> QuantizedCubeData {
+        let frame_count = indexed_frames.len();
+        QuantizedCubeData {
+            width: 81,
+            height: 81,
+            global_palette_rgb: vec![0; 256 * 3],
+            indexed_frames,
+            delays_cs: vec![4; frame_count],
+            palette_stability: 0.0,
+            mean_delta_e: 0.0,
+            p95_delta_e: 0.0,
+            attention_maps: None,
+            frame_checksums: None,
+            source_frame_ids: None,
+            schema_version: CUBE_SCHEMA_VERSION,
+        }
+    }
+
+    #[test]
+    fn test_trim_keeps_palette_and_slices_frames() {
+        let frames: Vec<Vec<u8>> = (0..81).map(|f| vec![f as u8; 81 * 81]).collect();
+        let mut cube = cube_from_frames(frames);
+        cube.global_palette_rgb = (0..=255u8).flat_map(|i| [i, 255 - i, i / 2]).collect();
+        cube.delays_cs = (0..81).map(|f| f as u8 + 1).collect();
+        cube.attention_maps = Some((0..81).map(|f| vec![f as f32; 81 * 81]).collect());
+        let cube = cube.with_frame_checksums();
+
+        let trimmed = cube.trim(10, 30).unwrap();
+        assert_eq!(trimmed.indexed_frames.len(), 20);
+        assert_eq!(trimmed.indexed_frames[0], cube.indexed_frames[10]);
+        assert_eq!(trimmed.delays_cs, (11..=30).collect::<Vec<u8>>());
+        assert_eq!(trimmed.global_palette_rgb, cube.global_palette_rgb);
+        assert_eq!(trimmed.attention_maps.as_ref().unwrap()[19][0], 29.0);
+        assert!(trimmed.verify_frame_checksums().is_ok());
+
+        assert!(cube.trim(30, 10).is_err());
+        assert!(cube.trim(0, 82).is_err());
+    }
+
+    #[test]
+    fn test_source_frame_ids_survive_trim_and_reorder() {
+        let frames: Vec<Vec<u8>> = (0..81).map(|f| vec![f as u8; 81 * 81]).collect();
+        let cube = cube_from_frames(frames).with_source_frame_ids();
+
+        let trimmed = cube.trim(10, 20).unwrap();
+        assert_eq!(trimmed.source_frame_ids, Some((10..20).collect()));
+
+        // Reverse the trimmed range and drop a duplicate of its last frame
+        let reordered = trimmed.select_frames(&[9, 9, 5, 0]).unwrap();
+        assert_eq!(reordered.source_frame_ids, Some(vec![19, 19, 15, 10]));
+        assert_eq!(reordered.indexed_frames[2], cube.indexed_frames[15]);
+        let deduped = reordered.select_frames(&[0, 2, 3]).unwrap();
+        assert_eq!(deduped.source_frame_ids, Some(vec![19, 15, 10]));
+
+        assert!(trimmed.select_frames(&[10]).is_err());
+        assert_eq!(cube_from_frames(vec![vec![0; 4]; 3]).trim(1, 2).unwrap().source_frame_ids, None);
+    }
+
+    #[test]
+    fn test_static_cube_is_fully_stable() {
+        let frame: Vec<u8> = (0..81 * 81).map(|i| (i % 16) as u8).collect();
+        let cube = cube_from_frames(vec![frame; 81]);
+
+        assert!((cube.recompute_stability() - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_alternating_cube_is_less_stable() {
+        let static_cube = cube_from_frames(vec![vec![0u8; 81 * 81]; 81]);
+
+        let frames = (0..81)
+            .map(|i| {
+                let index = if i % 2 == 0 { 0 } else { 1 };
+                (0..81 * 81).map(|p| if p % 2 == 0 { index } else { 2 }).collect()
+            })
+            .collect();
+        let alternating_cube = cube_from_frames(frames);
+
+        let alternating = alternating_cube.recompute_stability();
+        assert!(alternating < static_cube.recompute_stability());
+        assert!((alternating - 0.5).abs() < 0.01, "Expected ~0.5, got {}", alternating);
+    }
+
+    #[test]
+    fn test_fingerprint_tracks_cube_content() {
+        let frames: Vec<Vec<u8>> = (0..81)
+            .map(|f| (0..81 * 81).map(|i| ((i + f) % 16) as u8).collect())
+            .collect();
+        let cube = cube_from_frames(frames.clone());
+        let mut same = cube_from_frames(frames);
+        same.mean_delta_e = 3.0; // Metrics don't affect the fingerprint
+
+        assert_eq!(cube.fingerprint(), same.fingerprint());
+
+        let mut changed = cube.clone();
+        changed.indexed_frames[40][1234] ^= 1;
+        assert_ne!(cube.fingerprint(), changed.fingerprint());
+
+        let mut retimed = cube.clone();
+        retimed.delays_cs[0] = 8;
+        assert_ne!(cube.fingerprint(), retimed.fingerprint());
+    }
+
+    #[test]
+    fn test_frame_checksums_detect_corruption() {
+        let frames: Vec<Vec<u8>> = (0..81).map(|f| vec![f as u8; 81 * 81]).collect();
+        let cube = cube_from_frames(frames);
+
+        // No checksums recorded: nothing to verify
+        assert!(cube.verify_frame_checksums().is_ok());
+
+        let mut cube = cube.with_frame_checksums();
+        assert!(cube.verify_frame_checksums().is_ok());
+
+        cube.indexed_frames[17][100] ^= 0x40;
+        let err = cube.verify_frame_checksums().unwrap_err();
+        assert_eq!(err.code(), "E_VALIDATION");
+        assert!(err.to_string().contains("Frame 17"));
+    }
+
+    #[test]
+    fn test_palette_swatch_has_one_block_per_color() {
+        let mut cube = cube_from_frames(vec![vec![0u8; 81 * 81]; 81]);
+        cube.global_palette_rgb = (0..16u8).flat_map(|i| [i * 16, 255 - i * 16, i * 7]).collect();
+
+        let swatch = export_palette_swatch(&cube, 4);
+        assert_eq!(swatch.len(), SWATCH_COLUMNS * 4 * 4 * 4);
+
+        // Every pixel of block i is palette color i, and the 16 blocks are distinct
+        let width = SWATCH_COLUMNS * 4;
+        let mut blocks = std::collections::HashSet::new();
+        for i in 0..16 {
+            let expected = [cube.global_palette_rgb[i * 3], cube.global_palette_rgb[i * 3 + 1], cube.global_palette_rgb[i * 3 + 2], 255];
+            for y in 0..4 {
+                for x in i * 4..(i + 1) * 4 {
+                    assert_eq!(&swatch[(y * width + x) * 4..(y * width + x) * 4 + 4], &expected);
+                }
+            }
+            blocks.insert(expected);
+        }
+        assert_eq!(blocks.len(), 16);
+
+        // A 17th color wraps to a second row padded with transparent cells
+        cube.global_palette_rgb.extend_from_slice(&[1, 2, 3]);
+        let swatch = export_palette_swatch(&cube, 4);
+        assert_eq!(swatch.len(), width * 8 * 4);
+        assert_eq!(&swatch[(4 * width) * 4..(4 * width) * 4 + 4], &[1, 2, 3, 255]);
+        assert_eq!(swatch[(4 * width + 4) * 4 + 3], 0);
+    }
+
+    #[test]
+    fn test_worst_frames_ranks_hard_frame_first() {
+        // Grayscale palette: gray frames quantize well, a saturated frame does not
+        let mut cube = cube_from_frames(vec![vec![0u8; 81 * 81]; 81]);
+        cube.global_palette_rgb = (0..=255u8).flat_map(|v| [v, v, v]).collect();
+
+        let mut source_rgb = Vec::with_capacity(81);
+        for frame_idx in 0..81usize {
+            let gray = (frame_idx * 3) as u8;
+            cube.indexed_frames[frame_idx] = vec![gray; 81 * 81];
+            source_rgb.push(vec![gray; 81 * 81 * 3]);
+        }
+
+        // Plant a saturated frame 40: its nearest gray is far off in Oklab
+        let mut hard = Vec::with_capacity(81 * 81 * 3);
+        for _ in 0..81 * 81 {
+            hard.extend_from_slice(&[255, 0, 255]);
+        }
+        source_rgb[40] = hard;
+        cube.indexed_frames[40] = vec![128; 81 * 81];
+
+        let worst = worst_frames(&cube, &source_rgb, 3);
+        assert_eq!(worst.len(), 3);
+        assert_eq!(worst[0].0, 40);
+        assert!(worst[0].1 > worst[1].1);
+        assert!(worst[1].1 >= worst[2].1);
+        assert!(worst[1].1 < 1e-3, "Gray frames should be near lossless");
+    }
+
+    #[test]
+    fn test_ciede2000_reference_pairs() {
+        // Sharma, Wu & Dalal (2005) test data, pairs 1, 7, 17 and 25
+        let pairs = [
+            ([50.0, 2.6772, -79.7751], [50.0, 0.0, -82.7485], 2.0425),
+            ([50.0, 0.0, 0.0], [50.0, -1.0, 2.0], 2.3669),
+            ([50.0, 2.5, 0.0], [73.0, 25.0, -18.0], 27.1492),
+            ([60.2574, -34.0099, 36.2677], [60.4626, -34.1751, 39.4387], 1.2644),
+        ];
+
+        for (lab1, lab2, expected) in pairs {
+            let de = cielab::delta_e_2000(lab1, lab2);
+            assert!((de - expected).abs() < 1e-3, "Expected {}, got {}", expected, de);
+            // Symmetric
+            assert!((cielab::delta_e_2000(lab2, lab1) - de).abs() < 1e-4);
+        }
+
+        assert_eq!(DeltaEFormula::Ciede2000.delta_e_rgb([10, 20, 30], [10, 20, 30]), 0.0);
+    }
+
+    #[test]
+    fn test_delays_follow_non_uniform_timestamps() {
+        // 40ms cadence with a dropped frame between 80 and 160, then a 30ms burst
+        let timestamps = [0, 40, 80, 160, 190, 230];
+        let delays = timing::delays_from_timestamps(&timestamps, 4);
+
+        assert_eq!(delays, vec![4, 4, 8, 3, 4, 4]);
+    }
+
+    #[test]
+    fn test_delays_fallback_for_degenerate_timestamps() {
+        assert!(timing::delays_from_timestamps(&[], 4).is_empty());
+        assert_eq!(timing::delays_from_timestamps(&[1000], 4), vec![4]);
+        assert_eq!(timing::delays_from_timestamps(&[100, 100, 50], 4), vec![4, 4, 4]);
+    }
+
+    #[test]
+    fn test_flicker_static_vs_alternating() {
+        let frame: Vec<u8> = (0..81 * 81).map(|i| (i % 16) as u8).collect();
+        let static_cube = cube_from_frames(vec![frame.clone(); 81]);
+        assert!(measure_flicker(&static_cube) < 0.01);
+
+        // Two index patterns swapping every frame keep identical histograms
+        let swapped: Vec<u8> = frame.iter().map(|&i| 15 - i).collect();
+        let alternating = cube_from_frames(
+            (0..81).map(|f| if f % 2 == 0 { frame.clone() } else { swapped.clone() }).collect(),
+        );
+        assert!(measure_flicker(&alternating) > 0.9);
+        assert!(alternating.recompute_stability() > 0.99, "histograms alone don't see the flicker");
+
+        assert_eq!(measure_flicker(&cube_from_frames(vec![])), 0.0);
+    }
+
+    #[test]
+    fn test_summary_json_omits_frames() {
+        let mut cube = cube_from_frames(vec![vec![3u8; 81 * 81]; 81]);
+        cube.global_palette_rgb = (0..=255u8).flat_map(|v| [v, 255 - v, v / 2]).collect();
+        cube.palette_stability = 0.93;
+        cube.mean_delta_e = 0.012;
+        cube.p95_delta_e = 0.031;
+        cube.attention_maps = Some(vec![vec![0.5; 81 * 81]; 81]);
+
+        let json = cube.to_summary_json();
+        assert!(!json.contains("indexed_frames"));
+        assert!(!json.contains("attention_maps"));
+        assert!(json.len() < 8 * 1024, "summary should be small, got {} bytes", json.len());
+
+        let summary: CubeSummary = serde_json::from_str(&json).unwrap();
+        assert_eq!(summary, cube.summary());
+        assert_eq!(summary.frame_count, 81);
+        assert_eq!(summary.global_palette_rgb, cube.global_palette_rgb);
+        assert_eq!(summary.palette_stability, 0.93);
+    }
+
+    #[test]
+    fn test_migrate_legacy_cube_fills_delays() {
+        // Serialized before delays and schema versioning existed
+        let legacy_json = r#"{
+            "width": 2,
+            "height": 2,
+            "global_palette_rgb": [0, 0, 0, 255, 255, 255],
+            "indexed_frames": [[0, 1, 1, 0], [1, 0, 0, 1], [0, 0, 1, 1]],
+            "palette_stability": 0.8,
+            "mean_delta_e": 0.01,
+            "p95_delta_e": 0.02,
+            "attention_maps": [[0.5, 0.5, 0.5, 0.5]]
+        }"#;
+
+        let mut cube: QuantizedCubeData = serde_json::from_str(legacy_json).unwrap();
+        assert!(cube.delays_cs.is_empty());
+        assert_eq!(cube.schema_version, 0);
+
+        let changes = cube.migrate().unwrap();
+        assert_eq!(
+            changes,
+            vec![
+                CubeMigration::DelaysFilled { from_len: 0, delay_cs: DEFAULT_DELAY_CS },
+                CubeMigration::AttentionMapsDropped { maps: 1, frames: 3 },
+                CubeMigration::SchemaUpgraded { from: 0, to: CUBE_SCHEMA_VERSION },
+            ]
+        );
+        assert_eq!(cube.delays_cs, vec![DEFAULT_DELAY_CS; 3]);
+        assert!(cube.attention_maps.is_none());
+
+        // Already current: nothing left to do
+        assert!(cube.migrate().unwrap().is_empty());
+
+        cube.schema_version = CUBE_SCHEMA_VERSION + 1;
+        assert!(cube.migrate().is_err());
+    }
+
+    #[test]
+    fn test_loop_duration_at_25fps() {
+        assert_eq!(timing::delay_cs_for_fps(25.0), 4);
+        assert_eq!(timing::loop_duration_ms(81, 25.0), 3240);
+        assert_eq!(timing::frames_for_duration(3240, 25.0), 81);
+        assert_eq!(timing::frames_for_duration(3000, 25.0), 75);
+        assert_eq!(timing::frames_for_duration(3001, 25.0), 76);
+
+        assert_eq!(timing::check_loop_duration(81, 25.0, 3240), None);
+        assert_eq!(timing::check_loop_duration(81, 25.0, 3250), None);
+        assert_eq!(
+            timing::check_loop_duration(81, 25.0, 5000),
+            Some(timing::LoopDurationWarning::TooShort { actual_ms: 3240, intended_ms: 5000 })
+        );
+        assert_eq!(
+            timing::check_loop_duration(81, 25.0, 2000),
+            Some(timing::LoopDurationWarning::TooLong { actual_ms: 3240, intended_ms: 2000 })
+        );
+
+        // 24fps can't be represented exactly and plays as 25fps
+        assert_eq!(timing::loop_duration_ms(81, 24.0), 3240);
+        assert_eq!(timing::delay_cs_for_fps(0.0), u8::MAX);
+    }
 }
@@ -0,0 +1,170 @@
+//! Streaming XXH64, used for stable cube fingerprints
+
+const PRIME64_1: u64 = 0x9E37_79B1_85EB_CA87;
+const PRIME64_2: u64 = 0xC2B2_AE3D_27D4_EB4F;
+const PRIME64_3: u64 = 0x1656_67B1_9E37_79F9;
+const PRIME64_4: u64 = 0x85EB_CA77_C2B2_AE63;
+const PRIME64_5: u64 = 0x27D4_EB2F_1656_67C5;
+
+pub(crate) struct Xxh64 {
+    seed: u64,
+    acc: [u64; 4],
+    buffer: [u8; 32],
+    buffered: usize,
+    total_len: u64,
+}
+
+impl Xxh64 {
+    pub(crate) fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            acc: [
+                seed.wrapping_add(PRIME64_1).wrapping_add(PRIME64_2),
+                seed.wrapping_add(PRIME64_2),
+                seed,
+                seed.wrapping_sub(PRIME64_1),
+            ],
+            buffer: [0; 32],
+            buffered: 0,
+            total_len: 0,
+        }
+    }
+
+    pub(crate) fn update(&mut self, mut data: &[u8]) {
+        self.total_len += data.len() as u64;
+
+        // Top up a partially filled stripe first
+        if self.buffered > 0 {
+            let take = (32 - self.buffered).min(data.len());
+            self.buffer[self.buffered..self.buffered + take].copy_from_slice(&data[..take]);
+            self.buffered += take;
+            data = &data[take..];
+
+            if self.buffered < 32 {
+                return;
+            }
+            let stripe = self.buffer;
+            self.consume_stripe(&stripe);
+            self.buffered = 0;
+        }
+
+        let mut stripes = data.chunks_exact(32);
+        for stripe in &mut stripes {
+            self.consume_stripe(stripe);
+        }
+
+        let rest = stripes.remainder();
+        self.buffer[..rest.len()].copy_from_slice(rest);
+        self.buffered = rest.len();
+    }
+
+    pub(crate) fn finish(&self) -> u64 {
+        let mut hash = if self.total_len >= 32 {
+            let [v1, v2, v3, v4] = self.acc;
+            let mut hash = v1
+                .rotate_left(1)
+                .wrapping_add(v2.rotate_left(7))
+                .wrapping_add(v3.rotate_left(12))
+                .wrapping_add(v4.rotate_left(18));
+            for v in self.acc {
+                hash = (hash ^ round(0, v)).wrapping_mul(PRIME64_1).wrapping_add(PRIME64_4);
+            }
+            hash
+        } else {
+            self.seed.wrapping_add(PRIME64_5)
+        };
+
+        hash = hash.wrapping_add(self.total_len);
+
+        let mut tail = &self.buffer[..self.buffered];
+        while tail.len() >= 8 {
+            hash ^= round(0, read_u64(tail));
+            hash = hash.rotate_left(27).wrapping_mul(PRIME64_1).wrapping_add(PRIME64_4);
+            tail = &tail[8..];
+        }
+        if tail.len() >= 4 {
+            hash ^= (read_u32(tail) as u64).wrapping_mul(PRIME64_1);
+            hash = hash.rotate_left(23).wrapping_mul(PRIME64_2).wrapping_add(PRIME64_3);
+            tail = &tail[4..];
+        }
+        for &byte in tail {
+            hash ^= (byte as u64).wrapping_mul(PRIME64_5);
+            hash = hash.rotate_left(11).wrapping_mul(PRIME64_1);
+        }
+
+        // Avalanche
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(PRIME64_2);
+        hash ^= hash >> 29;
+        hash = hash.wrapping_mul(PRIME64_3);
+        hash ^ (hash >> 32)
+    }
+
+    fn consume_stripe(&mut self, stripe: &[u8]) {
+        for (lane, acc) in self.acc.iter_mut().enumerate() {
+            *acc = round(*acc, read_u64(&stripe[lane * 8..]));
+        }
+    }
+}
+
+fn round(acc: u64, input: u64) -> u64 {
+    acc.wrapping_add(input.wrapping_mul(PRIME64_2))
+        .rotate_left(31)
+        .wrapping_mul(PRIME64_1)
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes[..8].try_into().unwrap())
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes(bytes[..4].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn xxh64(data: &[u8]) -> u64 {
+        let mut hasher = Xxh64::new(0);
+        hasher.update(data);
+        hasher.finish()
+    }
+
+    #[test]
+    fn test_reference_vectors() {
+        assert_eq!(xxh64(b""), 0xEF46_DB37_51D8_E999);
+        assert_eq!(xxh64(b"a"), 0xD24E_C4F1_A98C_6E5B);
+        assert_eq!(xxh64(b"abc"), 0x44BC_2CF5_AD77_0999);
+    }
+
+    #[test]
+    fn test_reference_vectors_past_one_stripe() {
+        // Example from the python-xxhash README
+        assert_eq!(xxh64(b"Nobody inspects the spammish repetition"), 0xFBCE_A83C_8A37_8BF1);
+        // Exactly one 32-byte stripe, then several stripes plus a tail, seeded and not
+        assert_eq!(xxh64(b"0123456789abcdef0123456789abcdef"), 0x642A_9495_8E71_E6C5);
+        let bytes: Vec<u8> = (0..=255u8).collect();
+        assert_eq!(xxh64(&bytes), 0x1FAC_BE84_06CD_904B);
+
+        let seeded = |data: &[u8], seed: u64| {
+            let mut hasher = Xxh64::new(seed);
+            hasher.update(data);
+            hasher.finish()
+        };
+        assert_eq!(seeded(&bytes, 0x9E37_79B9_7F4A_7C15), 0xCC29_7FEF_2BB4_8BBF);
+        let data: Vec<u8> = (0..1000u32).map(|i| (i * 31 % 251) as u8).collect();
+        assert_eq!(seeded(&data, 42), 0xEFDB_DB0F_72AC_3B5C);
+    }
+
+    #[test]
+    fn test_streaming_matches_one_shot() {
+        let data: Vec<u8> = (0..1000u32).map(|i| (i * 31 % 251) as u8).collect();
+
+        let mut streamed = Xxh64::new(0);
+        for chunk in data.chunks(7) {
+            streamed.update(chunk);
+        }
+        assert_eq!(streamed.finish(), xxh64(&data));
+    }
+}
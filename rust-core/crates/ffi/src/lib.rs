@@ -1,7 +1,7 @@
 use common_types::*;
 use tracing::{info, error, warn};
 use uuid::Uuid;
-use std::sync::Once;
+use std::sync::{Arc, Once};
 use std::time::Instant;
 
 static INIT: Once = Once::new();
@@ -31,6 +31,57 @@ pub struct GifValidation {
     pub errors: Vec<String>,
 }
 
+/// Which frames contribute samples to the global palette
+#[derive(Debug, Clone, uniffi::Enum)]
+pub enum SampleStrategy {
+    /// Sample every frame
+    All,
+    /// Sample every `step`th frame, plus scene changes above the threshold (0..1)
+    EveryNth { step: u32, scene_change_threshold: f32 },
+}
+
+/// Caller-tunable M2 quantization settings
+#[derive(Debug, Clone, uniffi::Record)]
+pub struct QuantizeOptions {
+    /// Palette size, 2..=256
+    pub max_colors: u32,
+    pub dithering: bool,
+    pub sample_strategy: SampleStrategy,
+}
+
+impl Default for QuantizeOptions {
+    fn default() -> Self {
+        Self {
+            max_colors: 256,
+            dithering: false,
+            sample_strategy: SampleStrategy::All,
+        }
+    }
+}
+
+impl QuantizeOptions {
+    /// Build the quantizer, rejecting palette sizes a GIF can't use
+    fn quantizer(&self) -> Result<m2_quant::OklabQuantizer, GifPipeError> {
+        if !(2..=256).contains(&self.max_colors) {
+            return Err(GifPipeError::ConfigInvalid {
+                message: format!("max_colors must be in 2..=256, got {}", self.max_colors),
+            });
+        }
+
+        let subset = match self.sample_strategy {
+            SampleStrategy::All => m2_quant::SubsetStrategy::All,
+            SampleStrategy::EveryNth { step, scene_change_threshold } => m2_quant::SubsetStrategy::EveryNth {
+                step: step as usize,
+                scene_change_threshold,
+            },
+        };
+
+        Ok(m2_quant::OklabQuantizer::new(self.max_colors as usize)
+            .with_dithering(self.dithering)
+            .with_palette_frame_subset(subset))
+    }
+}
+
 /// Initialize Android tracing/logging
 #[uniffi::export]
 pub fn init_android_tracing() -> String {
@@ -59,16 +110,34 @@ pub fn init_android_tracing() -> String {
 }
 
 /// M2: Quantize RGBA frames to create palette and indexed cube data
+///
+/// Cancelling `cancel` from another thread stops the work between frames or
+/// k-means iterations with `GifPipeError::Cancelled`. `progress` hears about
+/// each frame mapped to the palette.
 #[uniffi::export]
-pub fn m2_quantize_for_cube(frames_81_rgba: Vec<Vec<u8>>) -> Result<QuantizedCubeData, GifPipeError> {
+pub fn m2_quantize_for_cube(
+    frames_81_rgba: Vec<Vec<u8>>,
+    options: QuantizeOptions,
+    cancel: Option<Arc<CancellationToken>>,
+    progress: Option<Arc<dyn ProgressListener>>,
+) -> Result<QuantizedCubeData, GifPipeError> {
     let start = Instant::now();
-    info!("M2: Starting quantization for {} frames", frames_81_rgba.len());
+    info!("M2: Starting quantization for {} frames with {:?}", frames_81_rgba.len(), options);
+    
+    let mut quantizer = options.quantizer().inspect_err(|err| error!(code = err.code(), "M2: {}", err))?;
+    if let Some(token) = cancel {
+        token.check("M2").inspect_err(|err| info!(code = err.code(), "M2: {}", err))?;
+        quantizer = quantizer.with_cancellation(CancellationToken::clone(&token));
+    }
+    if let Some(listener) = progress {
+        quantizer = quantizer.with_progress(listener);
+    }
     
     // Validate input
-    if frames_81_rgba.len() != 81 {
-        return Err(GifPipeError::InvalidFrameData {
-            message: format!("Expected 81 frames, got {}", frames_81_rgba.len())
-        });
+    if frames_81_rgba.is_empty() || frames_81_rgba.len() > MAX_FRAME_COUNT as usize {
+        let err = GifPipeError::frame_count_out_of_range(frames_81_rgba.len());
+        error!(code = err.code(), "M2: {}", err);
+        return Err(err);
     }
     
     // Convert to RGB (drop alpha channel)
@@ -90,7 +159,6 @@ pub fn m2_quantize_for_cube(frames_81_rgba: Vec<Vec<u8>>) -> Result<QuantizedCub
         processing_time_ms: 0,
     };
     
-    let quantizer = m2_quant::OklabQuantizer::new(256);
     let result = quantizer.quantize_for_cube(frames)?;
     
     let elapsed = start.elapsed();
@@ -100,31 +168,42 @@ pub fn m2_quantize_for_cube(frames_81_rgba: Vec<Vec<u8>>) -> Result<QuantizedCub
 }
 
 /// M3: Write GIF from pre-quantized cube data
+///
+/// `progress` hears about each frame written.
 #[uniffi::export]
 pub fn m3_write_gif_from_cube(
     cube: QuantizedCubeData,
     fps_cs: u8,
     loop_forever: bool,
+    progress: Option<Arc<dyn ProgressListener>>,
 ) -> Result<GifInfo, GifPipeError> {
     let start = Instant::now();
     info!("M3: Starting GIF encoding, {} frames, fps_cs={}", cube.indexed_frames.len(), fps_cs);
     
-    let encoder = m3_gif::Gif89aEncoder::new();
+    let mut encoder = m3_gif::Gif89aEncoder::new();
+    if let Some(listener) = progress {
+        encoder = encoder.with_progress(listener);
+    }
     let gif_bytes = encoder.encode_from_cube_data(&cube, fps_cs, loop_forever)?;
     
     let elapsed = start.elapsed();
     info!("M3: GIF encoding complete in {:?}, {} bytes", elapsed, gif_bytes.len());
     
+    let pixels_per_frame = cube.indexed_frames.first().map_or(0, Vec::len);
+    let uncompressed_size = GifInfo::uncompressed_size(cube.indexed_frames.len(), pixels_per_frame);
+    
     Ok(GifInfo {
         file_path: String::new(), // No file path when returning bytes
         file_size_bytes: gif_bytes.len() as u64,
         frame_count: cube.indexed_frames.len() as u32,
         palette_size: cube.global_palette_rgb.len() as u32 / 3,
         has_netscape_loop: loop_forever,
-        compression_ratio: calculate_compression_ratio(&cube, gif_bytes.len()),
+        compression_ratio: GifInfo::ratio(uncompressed_size, gif_bytes.len() as u64),
+        uncompressed_size_bytes: uncompressed_size,
         validation_passed: true,
         processing_time_ms: elapsed.as_millis() as u64,
         total_processing_ms: elapsed.as_millis() as u64,
+        warnings: Vec::new(),
         gif_data: gif_bytes,
     })
 }
@@ -133,7 +212,6 @@ pub fn m3_write_gif_from_cube(
 #[uniffi::export]
 pub fn validate_gif_bytes(gif_bytes: Vec<u8>) -> Result<GifValidation, GifPipeError> {
     let mut errors = Vec::new();
-    let mut frame_count = 0u32;
     
     // Check minimum size
     if gif_bytes.len() < 13 {
@@ -159,16 +237,23 @@ pub fn validate_gif_bytes(gif_bytes: Vec<u8>) -> Result<GifValidation, GifPipeEr
     let has_netscape_loop = gif_bytes.windows(11)
         .any(|window| window == netscape_pattern);
     
-    // Count frames (0x2C = image separator)
-    frame_count = gif_bytes.iter().filter(|&&b| b == 0x2C).count() as u32;
-    
     // Check for trailer (0x3B)
     let has_trailer = gif_bytes.last() == Some(&0x3B);
     if !has_trailer {
         errors.push("Missing GIF trailer (0x3B)".to_string());
     }
     
-    let is_valid = has_gif89a_header && has_netscape_loop && has_trailer && frame_count == 81;
+    // Count image descriptors, any count the encoder accepts is valid
+    let frame_count = m3_gif::count_gif_frames(&gif_bytes).unwrap_or_else(|| {
+        errors.push("Malformed GIF block structure".to_string());
+        0
+    });
+    let frame_count_ok = (1..=MAX_FRAME_COUNT as u32).contains(&frame_count);
+    if has_trailer && !frame_count_ok {
+        errors.push(format!("Expected 1 to {} frames, found {}", MAX_FRAME_COUNT, frame_count));
+    }
+    
+    let is_valid = has_gif89a_header && has_netscape_loop && has_trailer && frame_count_ok;
     
     Ok(GifValidation {
         is_valid,
@@ -180,11 +265,6 @@ pub fn validate_gif_bytes(gif_bytes: Vec<u8>) -> Result<GifValidation, GifPipeEr
     })
 }
 
-fn calculate_compression_ratio(cube: &QuantizedCubeData, compressed_size: usize) -> f32 {
-    let uncompressed_size = cube.indexed_frames.len() * cube.indexed_frames[0].len() * 3; // RGB
-    uncompressed_size as f32 / compressed_size as f32
-}
-
 /// Legacy: Process GIF frames (kept for compatibility)
 #[uniffi::export]
 pub fn process_gif_frames(_frames_bytes: Vec<u8>, _session_id: String) -> Result<Vec<u8>, ProcessingError> {
@@ -221,6 +301,9 @@ pub fn create_test_cube() -> QuantizedCubeData {
         mean_delta_e: 1.5,
         p95_delta_e: 3.2,
         attention_maps: None,
+        frame_checksums: None,
+        source_frame_ids: None,
+        schema_version: CUBE_SCHEMA_VERSION,
     }
 }
 
@@ -238,4 +321,132 @@ mod tests {
         assert_eq!(test_cube.height, 4);
         assert_eq!(test_cube.indexed_frames.len(), 3);
     }
+
+    #[test]
+    fn test_wrong_frame_count_reports_counts_and_code() {
+        for count in [0, MAX_FRAME_COUNT as usize + 1] {
+            let frames = vec![vec![0u8; 81 * 81 * 4]; count];
+
+            let err = m2_quantize_for_cube(frames, QuantizeOptions::default(), None, None).unwrap_err();
+            assert_eq!(err.code(), "E_M1_INPUT");
+
+            let message = err.to_string();
+            assert!(message.starts_with("E_M1_INPUT"));
+            assert!(message.contains(&format!("actual={}", count)), "{}", message);
+            assert!(message.contains("expected=1..=1024"), "{}", message);
+        }
+    }
+
+    #[test]
+    fn test_non_81_frames_quantize_encode_and_validate() {
+        for count in [1usize, 10] {
+            let cube = m2_quantize_for_cube(gradient_frames()[..count].to_vec(), QuantizeOptions::default(), None, None).unwrap();
+            assert_eq!(cube.indexed_frames.len(), count);
+            assert_eq!(cube.delays_cs.len(), count);
+
+            let info = m3_write_gif_from_cube(cube, 4, true, None).unwrap();
+            let validation = validate_gif_bytes(info.gif_data).unwrap();
+            assert_eq!(validation.frame_count, count as u32);
+            assert!(validation.is_valid, "{} frames: {:?}", count, validation.errors);
+        }
+    }
+
+    /// 81 frames of a shifting hue gradient, so every palette size gets used
+    fn gradient_frames() -> Vec<Vec<u8>> {
+        (0..81u32)
+            .map(|f| {
+                (0..81 * 81u32)
+                    .flat_map(|i| [((i % 81) * 3) as u8, ((i / 81) * 3) as u8, (f * 3) as u8, 255])
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_quantize_options_limit_palette_size() {
+        for max_colors in [16u32, 64, 256] {
+            let options = QuantizeOptions {
+                max_colors,
+                dithering: max_colors == 64,
+                sample_strategy: SampleStrategy::EveryNth { step: 9, scene_change_threshold: 0.5 },
+            };
+            let cube = m2_quantize_for_cube(gradient_frames(), options, None, None).unwrap();
+
+            let palette_len = cube.global_palette_rgb.len() / 3;
+            assert!(palette_len <= max_colors as usize, "{} colors for max {}", palette_len, max_colors);
+            assert!(palette_len > max_colors as usize / 2, "{} colors for max {}", palette_len, max_colors);
+            assert!(cube.indexed_frames.iter().flatten().all(|&index| (index as usize) < palette_len));
+        }
+    }
+
+    #[test]
+    fn test_quantize_options_reject_out_of_range_colors() {
+        for max_colors in [0u32, 1, 257] {
+            let options = QuantizeOptions { max_colors, ..QuantizeOptions::default() };
+            let err = m2_quantize_for_cube(gradient_frames(), options, None, None).unwrap_err();
+            assert_eq!(err.code(), "E_SYSTEM_CONFIG");
+            assert!(err.to_string().contains(&max_colors.to_string()), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_cancelled_token_fails_immediately() {
+        let token = Arc::new(CancellationToken::new());
+        token.cancel();
+
+        let err = m2_quantize_for_cube(gradient_frames(), QuantizeOptions::default(), Some(token), None).unwrap_err();
+        assert!(matches!(err, GifPipeError::Cancelled { .. }), "{:?}", err);
+        assert_eq!(err.code(), "E_SYSTEM_CANCELLED");
+    }
+
+    #[test]
+    fn test_validate_gif_bytes_accepts_any_frame_count() {
+        for frames in [1usize, 10, 200] {
+            let cube = QuantizedCubeData {
+                width: 81,
+                height: 81,
+                global_palette_rgb: vec![0x2C, 0x2C, 0x2C, 255, 255, 255],
+                indexed_frames: (0..frames).map(|f| vec![(f % 2) as u8; 81 * 81]).collect(),
+                delays_cs: vec![4; frames],
+                palette_stability: 1.0,
+                mean_delta_e: 0.0,
+                p95_delta_e: 0.0,
+                attention_maps: None,
+                frame_checksums: None,
+                source_frame_ids: None,
+                schema_version: CUBE_SCHEMA_VERSION,
+            };
+            let info = m3_write_gif_from_cube(cube, 4, true, None).unwrap();
+
+            let validation = validate_gif_bytes(info.gif_data).unwrap();
+            assert_eq!(validation.frame_count, frames as u32);
+            assert!(validation.is_valid, "{} frames: {:?}", frames, validation.errors);
+        }
+    }
+
+    #[derive(Default)]
+    struct RecordingListener(std::sync::Mutex<Vec<(String, u32, u32)>>);
+
+    impl ProgressListener for RecordingListener {
+        fn on_progress(&self, stage: String, frame: u32, total: u32) {
+            self.0.lock().unwrap().push((stage, frame, total));
+        }
+    }
+
+    #[test]
+    fn test_progress_listener_counts_every_frame() {
+        let listener = Arc::new(RecordingListener::default());
+
+        let cube = m2_quantize_for_cube(gradient_frames(), QuantizeOptions::default(), None, Some(listener.clone())).unwrap();
+        m3_write_gif_from_cube(cube, 4, true, Some(listener.clone())).unwrap();
+
+        let events = listener.0.lock().unwrap();
+        for stage in ["M2", "M3"] {
+            let frames: Vec<u32> = events.iter()
+                .filter(|(event_stage, _, total)| event_stage == stage && *total == 81)
+                .map(|&(_, frame, _)| frame)
+                .collect();
+            assert_eq!(frames, (1..=81).collect::<Vec<_>>(), "{} progress", stage);
+        }
+    }
 }
@@ -404,9 +404,7 @@ pub fn m3_write_gif_from_cube(
 /// Convert RGBA frames to RGB frames for processing
 fn convert_rgba_to_rgb_frames(frames_81_rgba: Vec<Vec<u8>>) -> Result<Frames81Rgb, GifPipeError> {
     if frames_81_rgba.len() != 81 {
-        return Err(GifPipeError::InvalidFrameData {
-            message: format!("Expected 81 frames, got {}", frames_81_rgba.len())
-        });
+        return Err(GifPipeError::frame_count_out_of_range(frames_81_rgba.len()));
     }
     
     let mut frames_rgb = Vec::with_capacity(81);
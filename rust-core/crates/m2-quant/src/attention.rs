@@ -0,0 +1,389 @@
+use tracing::{debug, info};
+use common_types::GifPipeError;
+
+/// Motion-based attention from frame-to-frame differences
+///
+/// Each pixel's attention is the mean absolute RGB difference to the previous
+/// frame over a 3×3 window, normalized across the whole cube to [0, 1].
+/// With a stride > 1 only every Nth pixel is evaluated and the coarse map is
+/// bilinearly upsampled, trading precision for speed on low-end devices.
+pub struct MotionAttention {
+    stride: usize,
+}
+
+impl Default for MotionAttention {
+    fn default() -> Self {
+        Self { stride: 1 }
+    }
+}
+
+impl MotionAttention {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_stride(mut self, stride: usize) -> Self {
+        self.stride = stride.max(1);
+        self
+    }
+
+    /// Compute one attention map per RGB frame (width×height×3 bytes each)
+    pub fn compute(
+        &self,
+        frames_rgb: &[Vec<u8>],
+        width: usize,
+        height: usize,
+    ) -> Result<Vec<Vec<f32>>, GifPipeError> {
+        if frames_rgb.is_empty() || width == 0 || height == 0 {
+            return Err(GifPipeError::AttentionProcessingFailed {
+                message: "No frames or zero dimensions for attention computation".to_string(),
+            });
+        }
+
+        let expected_len = width * height * 3;
+        for (idx, frame) in frames_rgb.iter().enumerate() {
+            if frame.len() != expected_len {
+                return Err(GifPipeError::AttentionProcessingFailed {
+                    message: format!(
+                        "Frame {} has {} bytes, expected {} ({}x{} RGB)",
+                        idx, frame.len(), expected_len, width, height
+                    ),
+                });
+            }
+        }
+
+        // Coarse grid covers the last row/column so upsampling never extrapolates
+        let grid_w = (width - 1) / self.stride + 1;
+        let grid_h = (height - 1) / self.stride + 1;
+
+        info!(
+            stage = "M2",
+            frames = frames_rgb.len(),
+            stride = self.stride,
+            grid = grid_w * grid_h,
+            "Computing motion attention"
+        );
+
+        let mut coarse_maps = Vec::with_capacity(frames_rgb.len());
+        let mut max_energy = 0.0f32;
+
+        for (frame_idx, frame) in frames_rgb.iter().enumerate() {
+            // First frame has no predecessor, compare against the next one instead
+            let reference = if frame_idx > 0 {
+                &frames_rgb[frame_idx - 1]
+            } else {
+                frames_rgb.get(1).unwrap_or(frame)
+            };
+
+            let mut coarse = Vec::with_capacity(grid_w * grid_h);
+            for gy in 0..grid_h {
+                for gx in 0..grid_w {
+                    let energy = self.motion_energy(
+                        frame,
+                        reference,
+                        width,
+                        height,
+                        (gx * self.stride).min(width - 1),
+                        (gy * self.stride).min(height - 1),
+                    );
+                    max_energy = max_energy.max(energy);
+                    coarse.push(energy);
+                }
+            }
+            coarse_maps.push(coarse);
+        }
+
+        let maps: Vec<Vec<f32>> = coarse_maps
+            .into_iter()
+            .map(|coarse| {
+                let normalized: Vec<f32> = if max_energy > 0.0 {
+                    coarse.iter().map(|&e| e / max_energy).collect()
+                } else {
+                    coarse
+                };
+                self.upsample(&normalized, grid_w, grid_h, width, height)
+            })
+            .collect();
+
+        debug!(stage = "M2", max_energy = max_energy, "Motion attention completed");
+
+        Ok(maps)
+    }
+
+    /// Mean absolute RGB difference over the 3×3 window centred on (x, y)
+    fn motion_energy(
+        &self,
+        frame: &[u8],
+        reference: &[u8],
+        width: usize,
+        height: usize,
+        x: usize,
+        y: usize,
+    ) -> f32 {
+        let mut total = 0u32;
+        let mut count = 0u32;
+
+        for ny in y.saturating_sub(1)..=(y + 1).min(height - 1) {
+            for nx in x.saturating_sub(1)..=(x + 1).min(width - 1) {
+                let idx = (ny * width + nx) * 3;
+                for c in 0..3 {
+                    total += (frame[idx + c] as i32 - reference[idx + c] as i32).unsigned_abs();
+                }
+                count += 3;
+            }
+        }
+
+        total as f32 / (count as f32 * 255.0)
+    }
+
+    /// Bilinear upsample of a coarse grid back to full resolution
+    fn upsample(&self, coarse: &[f32], grid_w: usize, grid_h: usize, width: usize, height: usize) -> Vec<f32> {
+        if self.stride == 1 {
+            return coarse.to_vec();
+        }
+
+        let mut output = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let gy = y as f32 / self.stride as f32;
+            let y0 = (gy.floor() as usize).min(grid_h - 1);
+            let y1 = (y0 + 1).min(grid_h - 1);
+            let wy = gy - y0 as f32;
+
+            for x in 0..width {
+                let gx = x as f32 / self.stride as f32;
+                let x0 = (gx.floor() as usize).min(grid_w - 1);
+                let x1 = (x0 + 1).min(grid_w - 1);
+                let wx = gx - x0 as f32;
+
+                let top = coarse[y0 * grid_w + x0] * (1.0 - wx) + coarse[y0 * grid_w + x1] * wx;
+                let bottom = coarse[y1 * grid_w + x0] * (1.0 - wx) + coarse[y1 * grid_w + x1] * wx;
+                output.push(top * (1.0 - wy) + bottom * wy);
+            }
+        }
+        output
+    }
+}
+
+/// Area-average square attention maps from `from_dim`×`from_dim` to `to_dim`×`to_dim`
+///
+/// Each output cell is the overlap-weighted mean of the source cells it covers,
+/// so 729×729 capture-resolution maps reduce to 81×81 by exact 9×9 averaging.
+pub fn resize_attention_maps(
+    maps: &[Vec<f32>],
+    from_dim: usize,
+    to_dim: usize,
+) -> Result<Vec<Vec<f32>>, GifPipeError> {
+    if from_dim == 0 || to_dim == 0 {
+        return Err(GifPipeError::AttentionProcessingFailed {
+            message: format!("Cannot resize attention maps from {} to {}", from_dim, to_dim),
+        });
+    }
+    for (idx, map) in maps.iter().enumerate() {
+        if map.len() != from_dim * from_dim {
+            return Err(GifPipeError::AttentionProcessingFailed {
+                message: format!(
+                    "Attention map {} has {} values, expected {} ({}x{})",
+                    idx, map.len(), from_dim * from_dim, from_dim, from_dim
+                ),
+            });
+        }
+    }
+    if from_dim == to_dim {
+        return Ok(maps.to_vec());
+    }
+
+    debug!(stage = "M2", maps = maps.len(), from_dim, to_dim, "Resizing attention maps");
+
+    let weights = area_weights(from_dim, to_dim);
+    let resized = maps
+        .iter()
+        .map(|map| {
+            let mut output = Vec::with_capacity(to_dim * to_dim);
+            for row_weights in &weights {
+                for col_weights in &weights {
+                    let mut sum = 0.0f32;
+                    for &(y, wy) in row_weights {
+                        for &(x, wx) in col_weights {
+                            sum += map[y * from_dim + x] * wy * wx;
+                        }
+                    }
+                    output.push(sum);
+                }
+            }
+            output
+        })
+        .collect();
+
+    Ok(resized)
+}
+
+/// RGBA preview of an RGB frame with its attention map as the alpha channel
+///
+/// Attention in [0, 1] maps to alpha 0..=255 (values outside are clamped), so
+/// any image viewer shows attended regions opaque and ignored ones faded.
+pub fn attention_to_rgba_overlay(frame_rgb: &[u8], attention: &[f32]) -> Result<Vec<u8>, GifPipeError> {
+    if frame_rgb.len() != attention.len() * 3 {
+        return Err(GifPipeError::AttentionProcessingFailed {
+            message: format!(
+                "Frame has {} bytes but attention map has {} values, expected 3 bytes per value",
+                frame_rgb.len(), attention.len()
+            ),
+        });
+    }
+
+    Ok(frame_rgb
+        .chunks_exact(3)
+        .zip(attention)
+        .flat_map(|(rgb, &weight)| {
+            let alpha = (weight.clamp(0.0, 1.0) * 255.0).round() as u8;
+            [rgb[0], rgb[1], rgb[2], alpha]
+        })
+        .collect())
+}
+
+/// Source indices and normalized overlap weights for each output cell along one axis
+fn area_weights(from_dim: usize, to_dim: usize) -> Vec<Vec<(usize, f32)>> {
+    let scale = from_dim as f64 / to_dim as f64;
+
+    (0..to_dim)
+        .map(|out| {
+            let start = out as f64 * scale;
+            let end = (out + 1) as f64 * scale;
+            let first = start.floor() as usize;
+            let last = (end.ceil() as usize).min(from_dim);
+
+            (first..last)
+                .filter_map(|src| {
+                    let overlap = end.min(src as f64 + 1.0) - start.max(src as f64);
+                    (overlap > 0.0).then_some((src, (overlap / scale) as f32))
+                })
+                .collect()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    const SIZE: usize = 81;
+
+    /// 81 frames of a 9×9 white dot moving diagonally across a black frame
+    fn moving_dot_cube() -> Vec<Vec<u8>> {
+        (0..81)
+            .map(|frame_idx| {
+                let mut frame = vec![0u8; SIZE * SIZE * 3];
+                let origin = frame_idx % (SIZE - 9);
+                for y in origin..origin + 9 {
+                    for x in origin..origin + 9 {
+                        let idx = (y * SIZE + x) * 3;
+                        frame[idx..idx + 3].copy_from_slice(&[255, 255, 255]);
+                    }
+                }
+                frame
+            })
+            .collect()
+    }
+
+    fn correlation(a: &[f32], b: &[f32]) -> f32 {
+        let n = a.len() as f32;
+        let mean_a = a.iter().sum::<f32>() / n;
+        let mean_b = b.iter().sum::<f32>() / n;
+
+        let mut cov = 0.0;
+        let mut var_a = 0.0;
+        let mut var_b = 0.0;
+        for (&x, &y) in a.iter().zip(b) {
+            cov += (x - mean_a) * (y - mean_b);
+            var_a += (x - mean_a).powi(2);
+            var_b += (y - mean_b).powi(2);
+        }
+        cov / (var_a.sqrt() * var_b.sqrt())
+    }
+
+    fn best_time(attention: &MotionAttention, frames: &[Vec<u8>]) -> Duration {
+        (0..3)
+            .map(|_| {
+                let start = Instant::now();
+                attention.compute(frames, SIZE, SIZE).unwrap();
+                start.elapsed()
+            })
+            .min()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_stride_2_matches_stride_1_and_is_faster() {
+        let frames = moving_dot_cube();
+
+        let full = MotionAttention::new();
+        let strided = MotionAttention::new().with_stride(2);
+
+        let full_maps = full.compute(&frames, SIZE, SIZE).unwrap();
+        let strided_maps = strided.compute(&frames, SIZE, SIZE).unwrap();
+
+        assert_eq!(strided_maps.len(), 81);
+        for (full_map, strided_map) in full_maps.iter().zip(&strided_maps) {
+            assert_eq!(strided_map.len(), SIZE * SIZE);
+            let r = correlation(full_map, strided_map);
+            assert!(r > 0.8, "Stride-2 attention correlation {} too low", r);
+        }
+
+        let full_time = best_time(&full, &frames);
+        let strided_time = best_time(&strided, &frames);
+        assert!(
+            strided_time < full_time,
+            "Stride-2 ({:?}) should be faster than stride-1 ({:?})",
+            strided_time, full_time
+        );
+    }
+
+    #[test]
+    fn test_resize_729_map_keeps_bright_quadrant() {
+        const FULL: usize = 729;
+        // Bright top-right quadrant, dim elsewhere
+        let map: Vec<f32> = (0..FULL * FULL)
+            .map(|i| if i % FULL >= FULL / 2 && i / FULL < FULL / 2 { 1.0 } else { 0.1 })
+            .collect();
+
+        let resized = resize_attention_maps(&[map], FULL, SIZE).unwrap();
+        assert_eq!(resized.len(), 1);
+        assert_eq!(resized[0].len(), SIZE * SIZE);
+
+        let at = |x: usize, y: usize| resized[0][y * SIZE + x];
+        assert!((at(70, 10) - 1.0).abs() < 1e-5);
+        assert!((at(10, 10) - 0.1).abs() < 1e-5);
+        assert!((at(10, 70) - 0.1).abs() < 1e-5);
+        assert!((at(70, 70) - 0.1).abs() < 1e-5);
+
+        // Area averaging preserves the mean
+        let mean = resized[0].iter().sum::<f32>() / (SIZE * SIZE) as f32;
+        assert!((mean - (0.1 + 0.9 * 364.0 * 365.0 / (FULL * FULL) as f32)).abs() < 1e-3);
+
+        assert!(resize_attention_maps(&[vec![0.0; 10]], FULL, SIZE).is_err());
+    }
+
+    #[test]
+    fn test_overlay_alpha_follows_attention_spot() {
+        let frame = vec![200u8; SIZE * SIZE * 3];
+        let spot = SIZE * 40 + 40;
+        let attention: Vec<f32> = (0..SIZE * SIZE)
+            .map(|i| if i == spot { 1.0 } else { 0.1 })
+            .collect();
+
+        let overlay = attention_to_rgba_overlay(&frame, &attention).unwrap();
+        assert_eq!(overlay.len(), SIZE * SIZE * 4);
+        assert_eq!(&overlay[spot * 4..spot * 4 + 4], &[200, 200, 200, 255]);
+        assert!(overlay[spot * 4 + 3] > overlay[3]);
+        assert_eq!(overlay[3], 26);
+
+        assert!(attention_to_rgba_overlay(&frame, &attention[1..]).is_err());
+    }
+
+    #[test]
+    fn test_invalid_frame_size() {
+        let frames = vec![vec![0u8; 100]];
+        assert!(MotionAttention::new().compute(&frames, SIZE, SIZE).is_err());
+    }
+}
@@ -0,0 +1,136 @@
+use common_types::oklab::delta_e_oklab_weighted;
+
+/// 3-D k-d tree over Oklab palette colors for exact nearest-color lookup
+///
+/// Distances are `delta_e_oklab_weighted` under the tree's channel weights,
+/// which is Euclidean after scaling each axis by its weight, so whole subtrees
+/// can be skipped once the splitting plane is farther than the best match.
+/// Equally close colors resolve to the lowest palette index, as a linear scan
+/// would.
+pub(crate) struct PaletteKdTree {
+    colors: Vec<[f32; 3]>,
+    weights: [f32; 3],
+    nodes: Vec<Node>,
+}
+
+struct Node {
+    color: usize,
+    axis: usize,
+    left: Option<usize>,
+    right: Option<usize>,
+}
+
+impl PaletteKdTree {
+    pub(crate) fn new(colors: Vec<[f32; 3]>, weights: [f32; 3]) -> Self {
+        let mut tree = Self { colors, weights, nodes: Vec::new() };
+        let mut order: Vec<usize> = (0..tree.colors.len()).collect();
+        tree.build(&mut order);
+        tree
+    }
+
+    /// Palette color `idx` in Oklab
+    pub(crate) fn color(&self, idx: usize) -> [f32; 3] {
+        self.colors[idx]
+    }
+
+    /// Index of the palette color closest to `target`, `None` for an empty palette
+    pub(crate) fn nearest(&self, target: [f32; 3]) -> Option<usize> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        // The root is pushed first by `build`
+        let mut best = (usize::MAX, f32::INFINITY);
+        self.search(0, target, &mut best);
+        Some(best.0)
+    }
+
+    /// Split `order` at the median of its widest weighted axis, returning the subtree root
+    fn build(&mut self, order: &mut [usize]) -> Option<usize> {
+        if order.is_empty() {
+            return None;
+        }
+
+        let axis = (0..3)
+            .map(|axis| {
+                let (min, max) = order.iter().fold((f32::MAX, f32::MIN), |(min, max), &idx| {
+                    let value = self.colors[idx][axis] * self.weights[axis].abs();
+                    (min.min(value), max.max(value))
+                });
+                (axis, max - min)
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map_or(0, |(axis, _)| axis);
+        order.sort_by(|&p, &q| self.colors[p][axis].total_cmp(&self.colors[q][axis]).then(p.cmp(&q)));
+
+        let median = order.len() / 2;
+        let node = self.nodes.len();
+        self.nodes.push(Node { color: order[median], axis, left: None, right: None });
+        let (lower, upper) = order.split_at_mut(median);
+        self.nodes[node].left = self.build(lower);
+        self.nodes[node].right = self.build(&mut upper[1..]);
+        Some(node)
+    }
+
+    fn search(&self, node: usize, target: [f32; 3], best: &mut (usize, f32)) {
+        let Node { color, axis, left, right } = self.nodes[node];
+        let distance = delta_e_oklab_weighted(target, self.colors[color], self.weights);
+        if distance < best.1 || (distance == best.1 && color < best.0) {
+            *best = (color, distance);
+        }
+
+        let offset = (target[axis] - self.colors[color][axis]) * self.weights[axis];
+        let (near, far) = if target[axis] < self.colors[color][axis] { (left, right) } else { (right, left) };
+        if let Some(near) = near {
+            self.search(near, target, best);
+        }
+        // Visit ties too so the lowest index wins; the epsilon absorbs rounding
+        if let Some(far) = far {
+            if offset.abs() <= best.1 + f32::EPSILON {
+                self.search(far, target, best);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn brute_force(colors: &[[f32; 3]], weights: [f32; 3], target: [f32; 3]) -> usize {
+        colors
+            .iter()
+            .enumerate()
+            .map(|(idx, &color)| (idx, delta_e_oklab_weighted(target, color, weights)))
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+            .unwrap()
+            .0
+    }
+
+    #[test]
+    fn test_kdtree_matches_brute_force() {
+        let mut rng = StdRng::seed_from_u64(17);
+        let random_color = |rng: &mut StdRng| {
+            [rng.gen_range(0.0..1.0), rng.gen_range(-0.4..0.4), rng.gen_range(-0.4..0.4)]
+        };
+
+        for (size, weights) in [(1, [1.0; 3]), (7, [1.0; 3]), (64, [4.0, 1.0, 1.0]), (256, [1.0, 0.5, 2.0])] {
+            let colors: Vec<[f32; 3]> = (0..size).map(|_| random_color(&mut rng)).collect();
+            let tree = PaletteKdTree::new(colors.clone(), weights);
+            for _ in 0..2000 {
+                let target = random_color(&mut rng);
+                assert_eq!(tree.nearest(target), Some(brute_force(&colors, weights, target)));
+            }
+        }
+
+        // Duplicate colors resolve to the first index, like the linear scan
+        let colors = vec![[0.5, 0.0, 0.0], [0.2, 0.1, 0.1], [0.5, 0.0, 0.0], [0.2, 0.1, 0.1]];
+        let tree = PaletteKdTree::new(colors.clone(), [1.0; 3]);
+        for target in [[0.5, 0.0, 0.0], [0.2, 0.1, 0.1], [0.35, 0.05, 0.05]] {
+            assert_eq!(tree.nearest(target), Some(brute_force(&colors, [1.0; 3], target)));
+        }
+
+        assert_eq!(PaletteKdTree::new(Vec::new(), [1.0; 3]).nearest([0.5, 0.0, 0.0]), None);
+    }
+}
@@ -1,15 +1,92 @@
 use tracing::{info, debug, span, Level, warn};
 use common_types::{
-    Frames81Rgb, QuantizedSet, GifPipeError, QuantizedCubeData
+    CancellationToken, DeltaEFormula, ProgressListener, Frames81Rgb, QuantizedSet, GifPipeError, QuantizedCubeData, CUBE_SCHEMA_VERSION
 };
 use common_types::oklab::{rgb_to_oklab, delta_e_oklab};
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::rngs::StdRng;
 use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use rayon::prelude::*;
+use std::sync::{Arc, Mutex};
+
+pub mod attention;
+mod kdtree;
+pub mod median_cut;
+pub mod merge;
+pub mod prefilter;
+pub use attention::{attention_to_rgba_overlay, resize_attention_maps, MotionAttention};
+pub use median_cut::median_cut;
+pub use merge::{crossfade_cubes, merge_cubes, share_segment_centroids};
+pub use prefilter::{prefilter_frame, PrefilterKind};
+
+use kdtree::PaletteKdTree;
 
 /// Oklab-based streaming k-means quantizer
 pub struct OklabQuantizer {
     max_colors: usize,
     convergence_threshold: f32,
     max_iterations: usize,
+    posterize_bits: Option<u8>,
+    delta_e_formula: DeltaEFormula,
+    channel_weights: [f32; 3],
+    palette_frame_subset: SubsetStrategy,
+    prefilter: PrefilterKind,
+    dithering: bool,
+    method: QuantMethod,
+    seed: Option<u64>,
+    cancellation: Option<CancellationToken>,
+    progress: Option<Arc<dyn ProgressListener>>,
+}
+
+/// How the global palette is built from the sampled pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QuantMethod {
+    /// Lloyd's k-means from randomly chosen seeds
+    #[default]
+    KMeans,
+    /// Recursive median cut in Oklab; the same frames always give the same palette
+    MedianCut,
+}
+
+/// Fixed pixel-sampling seed for reproducible palette methods
+const SAMPLING_SEED: u64 = 0x6d32_7175_616e;
+
+/// Which frames contribute samples to the global palette
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SubsetStrategy {
+    /// Sample every frame
+    #[default]
+    All,
+    /// Sample every `step`th frame, plus any frame whose mean per-channel
+    /// difference from its predecessor exceeds `scene_change_threshold` (0..1)
+    EveryNth { step: usize, scene_change_threshold: f32 },
+}
+
+impl SubsetStrategy {
+    /// Indices of the frames to sample, in order
+    pub fn select(&self, frames_rgb: &[Vec<u8>]) -> Vec<usize> {
+        let SubsetStrategy::EveryNth { step, scene_change_threshold } = *self else {
+            return (0..frames_rgb.len()).collect();
+        };
+        let step = step.max(1);
+
+        (0..frames_rgb.len())
+            .filter(|&idx| {
+                idx.is_multiple_of(step)
+                    || (idx > 0 && mean_frame_difference(&frames_rgb[idx - 1], &frames_rgb[idx]) > scene_change_threshold)
+            })
+            .collect()
+    }
+}
+
+/// Mean absolute per-channel difference between two frames, normalized to [0, 1]
+fn mean_frame_difference(a: &[u8], b: &[u8]) -> f32 {
+    if a.is_empty() || a.len() != b.len() {
+        return 1.0;
+    }
+    let total: u64 = a.iter().zip(b).map(|(&x, &y)| x.abs_diff(y) as u64).sum();
+    total as f32 / (a.len() as f32 * 255.0)
 }
 
 impl Default for OklabQuantizer {
@@ -18,21 +95,204 @@ impl Default for OklabQuantizer {
             max_colors: 256,
             convergence_threshold: 1.0,
             max_iterations: 50,
+            posterize_bits: None,
+            delta_e_formula: DeltaEFormula::OklabEuclidean,
+            channel_weights: [1.0; 3],
+            palette_frame_subset: SubsetStrategy::All,
+            prefilter: PrefilterKind::None,
+            dithering: false,
+            method: QuantMethod::KMeans,
+            seed: None,
+            cancellation: None,
+            progress: None,
         }
     }
 }
 
+/// Palette colors converted to Oklab for nearest-color search
+fn palette_to_oklab(palette: &[[u8; 3]]) -> Vec<[f32; 3]> {
+    palette.iter().map(|&rgb| rgb_to_oklab(rgb[0], rgb[1], rgb[2])).collect()
+}
+
+/// Side length of a square image with `pixels` pixels, if it is square
+fn square_dim(pixels: usize) -> Option<usize> {
+    let dim = (pixels as f64).sqrt().round() as usize;
+    (dim * dim == pixels).then_some(dim)
+}
+
+/// Snap each RGB channel to a regular grid of `bits_per_channel` bits
+///
+/// Gives a stylized "posterized" look and bounds the palette to
+/// `2^(3 * bits)` colors. Values of 8 or more leave the frame untouched.
+pub fn posterize(frame_rgb: &mut [u8], bits_per_channel: u8) {
+    if bits_per_channel >= 8 {
+        return;
+    }
+
+    let max_level = ((1u32 << bits_per_channel.max(1)) - 1) as f32;
+    for value in frame_rgb.iter_mut() {
+        let level = (*value as f32 * max_level / 255.0).round();
+        *value = (level * 255.0 / max_level).round() as u8;
+    }
+}
+
+/// Largest palette a GIF color table can hold
+const MAX_GIF_COLORS: usize = 256;
+
+/// Clamp a requested palette size into 1..=256, warning when it had to change
+fn clamp_max_colors(max_colors: usize) -> usize {
+    let clamped = max_colors.clamp(1, MAX_GIF_COLORS);
+    if clamped != max_colors {
+        warn!(
+            stage = "M2",
+            requested = max_colors,
+            clamped = clamped,
+            "max_colors outside GIF palette range, clamping"
+        );
+    }
+    clamped
+}
+
 impl OklabQuantizer {
+    /// Quantizer producing at most `max_colors` colors, clamped to 1..=256
     pub fn new(max_colors: usize) -> Self {
         Self {
-            max_colors,
+            max_colors: clamp_max_colors(max_colors),
             ..Default::default()
         }
     }
 
+    /// Palette size limit, clamped to 1..=256 like `new`
+    pub fn with_max_colors(mut self, max_colors: usize) -> Self {
+        self.max_colors = clamp_max_colors(max_colors);
+        self
+    }
+
+    /// Posterize frames to the given bits per channel before quantization
+    pub fn with_posterize(mut self, bits_per_channel: u8) -> Self {
+        self.posterize_bits = Some(bits_per_channel);
+        self
+    }
+
+    /// Formula for the reported mean/p95 ΔE; palette search stays on Oklab
+    pub fn with_delta_e_formula(mut self, formula: DeltaEFormula) -> Self {
+        self.delta_e_formula = formula;
+        self
+    }
+
+    /// Weight Oklab (L, a, b) differences in the nearest-color search
+    ///
+    /// Raising L above a/b keeps brightness accurate at the cost of hue.
+    /// Reported ΔE stays unweighted.
+    pub fn with_channel_weights(mut self, weights: [f32; 3]) -> Self {
+        self.channel_weights = weights;
+        self
+    }
+
+    /// Build the global palette from a subset of frames instead of all of them
+    ///
+    /// Faster on long or uniform captures; every frame is still mapped to the palette.
+    pub fn with_palette_frame_subset(mut self, strategy: SubsetStrategy) -> Self {
+        self.palette_frame_subset = strategy;
+        self
+    }
+
+    /// Smooth each frame before sampling to cut noise-driven color count
+    pub fn with_prefilter(mut self, kind: PrefilterKind) -> Self {
+        self.prefilter = kind;
+        self
+    }
+
+    /// Diffuse quantization error in Oklab with Floyd–Steinberg weights
+    ///
+    /// Breaks up banding in gradients. Reported ΔE is still measured against
+    /// the source pixels.
+    pub fn with_dithering(mut self, enabled: bool) -> Self {
+        self.dithering = enabled;
+        self
+    }
+
+    /// Palette construction algorithm, k-means by default
+    ///
+    /// `MedianCut` also samples pixels with a fixed seed, so repeated runs on
+    /// the same frames produce identical palettes.
+    pub fn with_method(mut self, method: QuantMethod) -> Self {
+        self.method = method;
+        self
+    }
+
+    /// Seed pixel sampling and k-means initialization for reproducible palettes
+    ///
+    /// Without a seed both draw from entropy (median cut still samples with
+    /// its fixed seed).
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Stop with `GifPipeError::Cancelled` once `token` is cancelled
+    ///
+    /// Checked between frames and between k-means iterations.
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    /// Report each frame mapped to the palette to `listener` as stage `"M2"`
+    pub fn with_progress(mut self, listener: Arc<dyn ProgressListener>) -> Self {
+        self.progress = Some(listener);
+        self
+    }
+
+    fn check_cancelled(&self, stage: &str) -> Result<(), GifPipeError> {
+        match &self.cancellation {
+            Some(token) => token.check(stage),
+            None => Ok(()),
+        }
+    }
+
+    /// Apply configured preprocessing to RGB frames in place
+    fn preprocess_frames(&self, frames_rgb: &mut [Vec<u8>]) {
+        if self.prefilter != PrefilterKind::None {
+            debug!(stage = "M2", prefilter = ?self.prefilter, "Prefiltering frames");
+            for frame in frames_rgb.iter_mut() {
+                match square_dim(frame.len() / 3) {
+                    Some(dim) => prefilter_frame(frame, dim, dim, self.prefilter),
+                    None => warn!(stage = "M2", "Non-square frame, skipping prefilter"),
+                }
+            }
+        }
+
+        if let Some(bits) = self.posterize_bits {
+            debug!(stage = "M2", bits_per_channel = bits, "Posterizing frames");
+            for frame in frames_rgb.iter_mut() {
+                posterize(frame, bits);
+            }
+        }
+    }
+
+    /// Resize attention maps to the frame resolution when M1 produced them at capture size
+    fn align_attention_maps(&self, frames: &mut Frames81Rgb) -> Result<(), GifPipeError> {
+        let (Some(frame), Some(map)) = (frames.frames_rgb.first(), frames.attention_maps.first()) else {
+            return Ok(());
+        };
+
+        let frame_dim = square_dim(frame.len() / 3);
+        let map_dim = square_dim(map.len());
+        if let (Some(to_dim), Some(from_dim)) = (frame_dim, map_dim) {
+            if from_dim != to_dim {
+                info!(stage = "M2", from_dim, to_dim, "Attention maps don't match frames, resizing");
+                frames.attention_maps = resize_attention_maps(&frames.attention_maps, from_dim, to_dim)?;
+            }
+        } else if frame.len() / 3 != map.len() {
+            warn!(stage = "M2", "Non-square attention maps don't match frames, passing through");
+        }
+        Ok(())
+    }
+
     /// Quantize RGB frames using Oklab perceptual color space
     #[tracing::instrument(level = "info", skip(self, frames_data))]
-    pub fn quantize_frames(&self, frames_data: Frames81Rgb) -> Result<QuantizedSet, GifPipeError> {
+    pub fn quantize_frames(&self, mut frames_data: Frames81Rgb) -> Result<QuantizedSet, GifPipeError> {
         let span = span!(Level::INFO, "M2_quantize", frames = frames_data.frames_rgb.len());
         let _guard = span.enter();
 
@@ -45,8 +305,11 @@ impl OklabQuantizer {
             "Starting Oklab quantization"
         );
 
+        self.preprocess_frames(&mut frames_data.frames_rgb);
+        self.align_attention_maps(&mut frames_data)?;
+
         // Sample pixels from all frames for k-means
-        let sample_pixels = self.sample_pixels(&frames_data.frames_rgb)?;
+        let sample_pixels = self.sample_pixels(&frames_data.frames_rgb, &frames_data.attention_maps)?;
         
         info!(
             stage = "M2",
@@ -55,7 +318,7 @@ impl OklabQuantizer {
         );
 
         // Run k-means clustering in Oklab space
-        let palette = self.kmeans_oklab(&sample_pixels)?;
+        let palette = self.build_palette(&sample_pixels)?;
         
         info!(
             stage = "M2",
@@ -68,6 +331,7 @@ impl OklabQuantizer {
         let mut frame_errors = Vec::new();
 
         for (frame_idx, frame_rgb) in frames_data.frames_rgb.iter().enumerate() {
+            self.check_cancelled("frame mapping")?;
             debug!(stage = "M2", frame_idx = frame_idx, "Quantizing frame");
 
             let (frame_indices, frame_error) = self.map_frame_to_palette(frame_rgb, &palette)?;
@@ -82,6 +346,7 @@ impl OklabQuantizer {
             );
         }
 
+        let palette_stability = common_types::palette_stability(&quantized_frames);
         let processing_time = start_time.elapsed().as_millis() as u64;
         let avg_error = frame_errors.iter().sum::<f32>() / frame_errors.len() as f32;
 
@@ -90,13 +355,14 @@ impl OklabQuantizer {
             duration_ms = processing_time,
             avg_delta_e = avg_error,
             palette_size = palette.len(),
+            palette_stability = palette_stability,
             "Quantization completed"
         );
 
         Ok(QuantizedSet {
             palette_rgb: palette.into_iter().flatten().collect(), // Convert [[u8; 3]] to Vec<u8>
             frames_indices: quantized_frames,
-            palette_stability: 0.85, // Placeholder - would be calculated from temporal analysis
+            palette_stability,
             mean_perceptual_error: avg_error,
             p95_perceptual_error: frame_errors.iter().fold(0.0, |max, &val| max.max(val)),
             processing_time_ms: processing_time,
@@ -105,40 +371,46 @@ impl OklabQuantizer {
     }
 
     /// Sample pixels from frames using attention-weighted sampling
-    fn sample_pixels(&self, frames_rgb: &[Vec<u8>]) -> Result<Vec<[u8; 3]>, GifPipeError> {
+    fn sample_pixels(&self, frames_rgb: &[Vec<u8>], attention_maps: &[Vec<f32>]) -> Result<Vec<[u8; 3]>, GifPipeError> {
         const SAMPLES_PER_FRAME: usize = 1000;
         let mut samples = Vec::new();
-        let mut rng = rand::thread_rng();
+        let mut rng = self.sampling_rng();
 
-        for frame_rgb in frames_rgb {
-            if frame_rgb.len() % 3 != 0 {
-                return Err(GifPipeError::InvalidFrameData {
-                    message: "RGB frame length not divisible by 3".to_string(),
-                });
-            }
+        for frame_idx in self.palette_frame_subset.select(frames_rgb) {
+            let attention = attention_maps.get(frame_idx).map(Vec::as_slice);
+            samples.extend(self.sample_frame_pixels(&frames_rgb[frame_idx], attention, SAMPLES_PER_FRAME, &mut rng)?);
+        }
 
-            let pixel_count = frame_rgb.len() / 3;
-            let mut frame_samples = Vec::new();
-
-            // Simple random sampling (could be enhanced with attention weighting)
-            let mut pixel_indices: Vec<usize> = (0..pixel_count).collect();
-            pixel_indices.shuffle(&mut rng);
-
-            for &idx in pixel_indices.iter().take(SAMPLES_PER_FRAME.min(pixel_count)) {
-                let rgb_idx = idx * 3;
-                if rgb_idx + 2 < frame_rgb.len() {
-                    frame_samples.push([
-                        frame_rgb[rgb_idx],
-                        frame_rgb[rgb_idx + 1], 
-                        frame_rgb[rgb_idx + 2]
-                    ]);
+        Ok(samples)
+    }
+
+    /// Build the palette from sampled pixels with the configured method
+    fn build_palette(&self, samples: &[[u8; 3]]) -> Result<Vec<[u8; 3]>, GifPipeError> {
+        match self.method {
+            QuantMethod::KMeans => self.kmeans_oklab(samples),
+            QuantMethod::MedianCut => {
+                if samples.is_empty() {
+                    return Err(GifPipeError::QuantizationFailed {
+                        message: "No samples provided for median cut".to_string(),
+                    });
                 }
+                let samples_oklab: Vec<[f32; 3]> = samples.iter()
+                    .map(|rgb| rgb_to_oklab(rgb[0], rgb[1], rgb[2]))
+                    .collect();
+                let boxes = median_cut(&samples_oklab, self.max_colors);
+                debug!(stage = "M2", boxes = boxes.len(), "Median cut complete");
+                Ok(boxes.into_iter().map(|oklab| self.oklab_to_rgb(oklab)).collect())
             }
-
-            samples.extend(frame_samples);
         }
+    }
 
-        Ok(samples)
+    /// Random source for pixel sampling, seeded when set or the method must be reproducible
+    fn sampling_rng(&self) -> StdRng {
+        match (self.seed, self.method) {
+            (Some(seed), _) => StdRng::seed_from_u64(seed),
+            (None, QuantMethod::KMeans) => StdRng::from_entropy(),
+            (None, QuantMethod::MedianCut) => StdRng::seed_from_u64(SAMPLING_SEED),
+        }
     }
 
     /// K-means clustering in Oklab perceptual color space
@@ -150,7 +422,7 @@ impl OklabQuantizer {
         }
 
         let k = self.max_colors.min(samples.len());
-        let mut rng = rand::thread_rng();
+        let mut rng = self.seed.map_or_else(StdRng::from_entropy, StdRng::seed_from_u64);
         
         // Initialize centroids by sampling
         let mut centroids: Vec<[f32; 3]> = samples
@@ -161,6 +433,8 @@ impl OklabQuantizer {
         debug!(stage = "M2", centroids = k, "K-means initialization");
 
         for iteration in 0..self.max_iterations {
+            self.check_cancelled("k-means")?;
+
             // Assign points to nearest centroids
             let mut clusters: Vec<Vec<[f32; 3]>> = vec![Vec::new(); k];
             let mut total_distance = 0.0f32;
@@ -224,21 +498,61 @@ impl OklabQuantizer {
 
     /// Map a frame to palette indices with error calculation
     fn map_frame_to_palette(&self, frame_rgb: &[u8], palette: &[[u8; 3]]) -> Result<(Vec<u8>, f32), GifPipeError> {
+        self.map_frame_with_tree(frame_rgb, palette, &self.palette_tree(palette))
+    }
+
+    /// Map every frame to the palette in parallel, building the palette's k-d tree once
+    ///
+    /// Frames are independent, so the result matches mapping them one by one.
+    fn map_frames_to_palette(&self, frames_rgb: &[Vec<u8>], palette: &[[u8; 3]]) -> Result<Vec<(Vec<u8>, f32)>, GifPipeError> {
+        let tree = self.palette_tree(palette);
+        // Counting under the lock keeps reported frames increasing across threads
+        let mapped = Mutex::new(0u32);
+        frames_rgb
+            .par_iter()
+            .map(|frame| {
+                self.check_cancelled("frame mapping")?;
+                let result = self.map_frame_with_tree(frame, palette, &tree)?;
+                if let Some(listener) = &self.progress {
+                    let mut mapped = mapped.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                    *mapped += 1;
+                    listener.on_progress("M2".to_string(), *mapped, frames_rgb.len() as u32);
+                }
+                Ok(result)
+            })
+            .collect()
+    }
+
+    /// Nearest-color index over the palette in Oklab under this quantizer's channel weights
+    fn palette_tree(&self, palette: &[[u8; 3]]) -> PaletteKdTree {
+        PaletteKdTree::new(palette_to_oklab(palette), self.channel_weights)
+    }
+
+    /// `map_frame_to_palette` with the palette's k-d tree already built
+    fn map_frame_with_tree(
+        &self,
+        frame_rgb: &[u8],
+        palette: &[[u8; 3]],
+        tree: &PaletteKdTree,
+    ) -> Result<(Vec<u8>, f32), GifPipeError> {
         if frame_rgb.len() % 3 != 0 {
             return Err(GifPipeError::InvalidFrameData {
                 message: "RGB frame length not divisible by 3".to_string(),
             });
         }
+        if palette.is_empty() {
+            return Err(GifPipeError::ValidationFailed {
+                message: "Cannot map a frame to an empty palette".to_string(),
+            });
+        }
 
         let pixel_count = frame_rgb.len() / 3;
         let mut indices = Vec::with_capacity(pixel_count);
         let mut total_error = 0.0f32;
 
-        // Pre-convert palette to Oklab for faster comparison
-        let palette_oklab: Vec<[f32; 3]> = palette
-            .iter()
-            .map(|&rgb| rgb_to_oklab(rgb[0], rgb[1], rgb[2]))
-            .collect();
+        // Pending Oklab error per pixel; non-square frames diffuse along a single row
+        let width = square_dim(pixel_count).unwrap_or(pixel_count);
+        let mut diffused = if self.dithering { vec![[0.0f32; 3]; pixel_count] } else { Vec::new() };
 
         for i in 0..pixel_count {
             let rgb_idx = i * 3;
@@ -249,17 +563,37 @@ impl OklabQuantizer {
                     frame_rgb[rgb_idx + 2]
                 ];
                 let pixel_oklab = rgb_to_oklab(pixel_rgb[0], pixel_rgb[1], pixel_rgb[2]);
+                let target = match diffused.get(i) {
+                    Some(error) => [pixel_oklab[0] + error[0], pixel_oklab[1] + error[1], pixel_oklab[2] + error[2]],
+                    None => pixel_oklab,
+                };
 
-                // Find closest palette color
-                let (best_idx, error) = palette_oklab
-                    .iter()
-                    .enumerate()
-                    .map(|(idx, &pal_oklab)| (idx, delta_e_oklab(pixel_oklab, pal_oklab)))
-                    .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
-                    .unwrap();
+                // Find closest palette color under the channel weighting
+                let best_idx = tree.nearest(target).unwrap_or(0);
+
+                if self.dithering {
+                    let chosen = tree.color(best_idx);
+                    let residual = [target[0] - chosen[0], target[1] - chosen[1], target[2] - chosen[2]];
+                    let (x, y) = (i % width, i / width);
+                    for (dx, dy, weight) in [(1isize, 0usize, 7.0), (-1, 1, 3.0), (0, 1, 5.0), (1, 1, 1.0)] {
+                        let nx = x as isize + dx;
+                        if nx < 0 || nx as usize >= width {
+                            continue;
+                        }
+                        let neighbor = (y + dy) * width + nx as usize;
+                        if let Some(error) = diffused.get_mut(neighbor) {
+                            for c in 0..3 {
+                                error[c] += residual[c] * weight / 16.0;
+                            }
+                        }
+                    }
+                }
 
                 indices.push(best_idx as u8);
-                total_error += error;
+                total_error += match self.delta_e_formula {
+                    DeltaEFormula::OklabEuclidean => delta_e_oklab(pixel_oklab, tree.color(best_idx)),
+                    formula => formula.delta_e_rgb(pixel_rgb, palette[best_idx]),
+                };
             }
         }
 
@@ -267,66 +601,83 @@ impl OklabQuantizer {
         Ok((indices, avg_error))
     }
 
-    /// Convert Oklab back to RGB (simplified conversion)
+    /// Convert Oklab back to sRGB, inverting `common_types::oklab::rgb_to_oklab`
+    ///
+    /// Out-of-gamut centroids are clamped per channel in linear RGB.
     fn oklab_to_rgb(&self, oklab: [f32; 3]) -> [u8; 3] {
-        // Simplified Oklab to RGB conversion
-        // In production, use proper color space conversion library
-        let l = oklab[0];
-        let a = oklab[1];  
-        let b = oklab[2];
+        let (l, a, b) = (oklab[0] as f64, oklab[1] as f64, oklab[2] as f64);
+
+        // Oklab -> cube-rooted LMS
+        let l_ = l + 0.3963377922 * a + 0.2158037581 * b;
+        let m_ = l - 0.1055613423 * a - 0.0638541748 * b;
+        let s_ = l - 0.0894841821 * a - 1.2914855379 * b;
+        let (l, m, s) = (l_ * l_ * l_, m_ * m_ * m_, s_ * s_ * s_);
 
-        // Rough approximation for now
-        let r = (l + 0.3963 * a + 0.2158 * b).clamp(0.0, 1.0);
-        let g = (l - 0.1055 * a - 0.0638 * b).clamp(0.0, 1.0);
-        let blue = (l - 0.0894 * a - 1.2914 * b).clamp(0.0, 1.0);
+        // LMS -> XYZ
+        let x = 1.2270138511 * l - 0.5577999807 * m + 0.2812561490 * s;
+        let y = -0.0405801784 * l + 1.1122568696 * m - 0.0716766787 * s;
+        let z = -0.0763812845 * l - 0.4214819784 * m + 1.5861632204 * s;
 
-        [
-            (r * 255.0) as u8,
-            (g * 255.0) as u8,
-            (blue * 255.0) as u8,
-        ]
+        // XYZ -> linear RGB
+        let linear = [
+            3.2406254773 * x - 1.5372079722 * y - 0.4986285987 * z,
+            -0.9689307147 * x + 1.8757560609 * y + 0.0415175238 * z,
+            0.0557101204 * x - 0.2040210506 * y + 1.0569959423 * z,
+        ];
+
+        linear.map(|c| {
+            let c = c.clamp(0.0, 1.0);
+            let encoded = if c <= 0.0031308 { 12.92 * c } else { 1.055 * c.powf(1.0 / 2.4) - 0.055 };
+            (encoded.clamp(0.0, 1.0) * 255.0).round() as u8
+        })
     }
 
     /// Quantize frames for cube data with global palette
-    pub fn quantize_for_cube(&self, frames: Frames81Rgb) -> Result<QuantizedCubeData, GifPipeError> {
+    pub fn quantize_for_cube(&self, mut frames: Frames81Rgb) -> Result<QuantizedCubeData, GifPipeError> {
         let span = span!(Level::INFO, "M2_quantize_cube", 
-            frames = 81,
+            frames = frames.frames_rgb.len(),
             target_colors = 256,
             method = "oklab_streaming_kmeans"
         );
         let _guard = span.enter();
+        self.check_cancelled("quantize_for_cube")?;
+        
+        // No maps means no attention; a partial set would misalign with the frames
+        if !frames.attention_maps.is_empty() && frames.attention_maps.len() != frames.frames_rgb.len() {
+            return Err(GifPipeError::ValidationFailed {
+                message: format!(
+                    "Got {} attention maps for {} frames, expected one per frame or none",
+                    frames.attention_maps.len(), frames.frames_rgb.len()
+                ),
+            });
+        }
+        
+        self.preprocess_frames(&mut frames.frames_rgb);
+        self.align_attention_maps(&mut frames)?;
         
-        // Sample pixels from all 81 frames for global k-means
-        let all_samples = self.sample_all_frames(&frames, 1000)?; // 1000 per frame
+        // Sample pixels from every frame for global k-means, one random stream across frames
+        let mut rng = self.sampling_rng();
+        let all_samples = self.sample_all_frames(&frames, 1000, &mut rng)?; // 1000 per frame
         info!(total_samples = all_samples.len(), "Building global palette");
         
         // Run k-means in Oklab space
-        let global_palette_rgb = self.kmeans_oklab(&all_samples)?;
+        let global_palette_rgb = self.build_palette(&all_samples)?;
         let global_palette_bytes: Vec<u8> = global_palette_rgb.iter()
             .flat_map(|rgb| vec![rgb[0], rgb[1], rgb[2]])
             .collect();
         
         // Quantize each frame using global palette
-        let mut indexed_frames = Vec::with_capacity(81);
-        let mut delta_e_values = Vec::with_capacity(81);
-        
-        for (idx, frame) in frames.frames_rgb.iter().enumerate() {
-            let (indices, frame_delta_e) = self.quantize_frame_with_palette(
-                frame, 
-                &global_palette_rgb
-            )?;
-            
-            indexed_frames.push(indices);
-            delta_e_values.push(frame_delta_e);
-            
-            if idx % 10 == 0 {
-                info!(frame = idx, delta_e = frame_delta_e, "Quantized frame batch");
-            }
+        let (indexed_frames, delta_e_values): (Vec<Vec<u8>>, Vec<f32>) = self
+            .map_frames_to_palette(&frames.frames_rgb, &global_palette_rgb)?
+            .into_iter()
+            .unzip();
+        for (idx, &frame_delta_e) in delta_e_values.iter().enumerate().step_by(10) {
+            info!(frame = idx, delta_e = frame_delta_e, "Quantized frame batch");
         }
         
-        // Calculate temporal metrics
-        let palette_stability = self.calculate_palette_stability(&indexed_frames)?;
-        let mean_delta_e = delta_e_values.iter().sum::<f32>() / 81.0;
+        // Calculate temporal metrics; a single frame has no pairs and counts as fully stable
+        let palette_stability = common_types::palette_stability(&indexed_frames);
+        let mean_delta_e = delta_e_values.iter().sum::<f32>() / delta_e_values.len().max(1) as f32;
         let p95_delta_e = self.calculate_p95(&delta_e_values);
         
         info!(
@@ -341,75 +692,52 @@ impl OklabQuantizer {
             height: 81,
             global_palette_rgb: global_palette_bytes,
             indexed_frames,
-            delays_cs: vec![4; 81], // 25fps = 4cs
+            delays_cs: vec![4; frames.frames_rgb.len()], // 25fps = 4cs
             palette_stability,
             mean_delta_e,
             p95_delta_e,
-            attention_maps: Some(frames.attention_maps),
-        })
+            attention_maps: (!frames.attention_maps.is_empty()).then_some(frames.attention_maps),
+            frame_checksums: None,
+            source_frame_ids: None,
+            schema_version: CUBE_SCHEMA_VERSION,
+        }
+        .with_frame_checksums())
     }
     
-    fn sample_all_frames(&self, frames: &Frames81Rgb, samples_per_frame: usize) -> Result<Vec<[u8; 3]>, GifPipeError> {
+    fn sample_all_frames(
+        &self,
+        frames: &Frames81Rgb,
+        samples_per_frame: usize,
+        rng: &mut StdRng,
+    ) -> Result<Vec<[u8; 3]>, GifPipeError> {
         let mut all_samples = Vec::new();
+        let subset = self.palette_frame_subset.select(&frames.frames_rgb);
+        debug!(stage = "M2", sampled_frames = subset.len(), "Selected palette frames");
         
-        for frame in &frames.frames_rgb {
-            let frame_samples = self.sample_frame_pixels(frame, samples_per_frame)?;
+        for frame_idx in subset {
+            self.check_cancelled("pixel sampling")?;
+            let attention = frames.attention_maps.get(frame_idx).map(Vec::as_slice);
+            let frame_samples = self.sample_frame_pixels(&frames.frames_rgb[frame_idx], attention, samples_per_frame, rng)?;
             all_samples.extend(frame_samples);
         }
         
         Ok(all_samples)
     }
     
-    fn quantize_frame_with_palette(
+    /// Sample up to `max_samples` pixels of one frame for k-means
+    ///
+    /// With an attention map, each draw picks a pixel with probability
+    /// proportional to its weight (with replacement), so salient regions
+    /// dominate the palette. Without a usable map (missing, wrong size or all
+    /// zero), pixels are drawn uniformly without replacement. `rng` carries on
+    /// across frames so each frame samples different positions.
+    fn sample_frame_pixels(
         &self,
         frame: &[u8],
-        palette: &[[u8; 3]],
-    ) -> Result<(Vec<u8>, f32), GifPipeError> {
-        let (indices, error) = self.map_frame_to_palette(frame, palette)?;
-        Ok((indices, error))
-    }
-    
-    fn calculate_palette_stability(&self, indexed_frames: &[Vec<u8>]) -> Result<f32, GifPipeError> {
-        // Measure histogram similarity between consecutive frames
-        let mut stability_scores = Vec::new();
-        
-        for i in 1..indexed_frames.len() {
-            let hist_prev = self.build_histogram(&indexed_frames[i-1]);
-            let hist_curr = self.build_histogram(&indexed_frames[i]);
-            
-            // Chi-squared distance between histograms
-            let similarity = self.histogram_similarity(&hist_prev, &hist_curr);
-            stability_scores.push(similarity);
-        }
-        
-        Ok(stability_scores.iter().sum::<f32>() / stability_scores.len() as f32)
-    }
-    
-    fn build_histogram(&self, frame_indices: &[u8]) -> Vec<u32> {
-        let mut histogram = vec![0u32; 256];
-        for &index in frame_indices {
-            histogram[index as usize] += 1;
-        }
-        histogram
-    }
-    
-    fn histogram_similarity(&self, hist1: &[u32], hist2: &[u32]) -> f32 {
-        let total1: u32 = hist1.iter().sum();
-        let total2: u32 = hist2.iter().sum();
-        
-        if total1 == 0 || total2 == 0 {
-            return 0.0;
-        }
-        
-        let mut intersection = 0u32;
-        for (&h1, &h2) in hist1.iter().zip(hist2.iter()) {
-            intersection += h1.min(h2);
-        }
-        
-        intersection as f32 / total1.max(total2) as f32
-    }
-
-    fn sample_frame_pixels(&self, frame: &[u8], max_samples: usize) -> Result<Vec<[u8; 3]>, GifPipeError> {
+        attention: Option<&[f32]>,
+        max_samples: usize,
+        rng: &mut StdRng,
+    ) -> Result<Vec<[u8; 3]>, GifPipeError> {
         if frame.len() % 3 != 0 {
             return Err(GifPipeError::InvalidFrameData {
                 message: "Frame length not divisible by 3".to_string(),
@@ -417,23 +745,19 @@ impl OklabQuantizer {
         }
 
         let pixel_count = frame.len() / 3;
-        let mut rng = rand::thread_rng();
-        let mut pixel_indices: Vec<usize> = (0..pixel_count).collect();
-        pixel_indices.shuffle(&mut rng);
+        let pixel = |idx: usize| [frame[idx * 3], frame[idx * 3 + 1], frame[idx * 3 + 2]];
 
-        let mut samples = Vec::new();
-        for &idx in pixel_indices.iter().take(max_samples.min(pixel_count)) {
-            let rgb_idx = idx * 3;
-            if rgb_idx + 2 < frame.len() {
-                samples.push([
-                    frame[rgb_idx],
-                    frame[rgb_idx + 1],
-                    frame[rgb_idx + 2],
-                ]);
-            }
+        let weights = attention
+            .filter(|map| map.len() == pixel_count)
+            .and_then(|map| WeightedIndex::new(map.iter().map(|&w| if w.is_finite() { w.max(0.0) } else { 0.0 })).ok());
+        if let Some(weights) = weights {
+            let count = max_samples.min(pixel_count);
+            return Ok((0..count).map(|_| pixel(weights.sample(rng))).collect());
         }
 
-        Ok(samples)
+        let mut pixel_indices: Vec<usize> = (0..pixel_count).collect();
+        pixel_indices.shuffle(rng);
+        Ok(pixel_indices.iter().take(max_samples.min(pixel_count)).map(|&idx| pixel(idx)).collect())
     }
 
     fn calculate_p95(&self, errors: &[f32]) -> f32 {
@@ -460,6 +784,47 @@ mod tests {
         assert_eq!(quantizer.max_colors, 16);
     }
 
+    #[test]
+    fn test_oklab_to_rgb_round_trip() {
+        let quantizer = OklabQuantizer::new(16);
+        let colors: [[u8; 3]; 20] = [
+            [0, 0, 0], [255, 255, 255], [255, 0, 0], [0, 255, 0], [0, 0, 255],
+            [255, 255, 0], [0, 255, 255], [255, 0, 255], [128, 128, 128], [1, 2, 3],
+            [250, 128, 114], [34, 139, 34], [70, 130, 180], [255, 215, 0], [75, 0, 130],
+            [210, 105, 30], [12, 200, 90], [240, 248, 255], [60, 20, 20], [199, 21, 133],
+        ];
+
+        for rgb in colors {
+            let back = quantizer.oklab_to_rgb(rgb_to_oklab(rgb[0], rgb[1], rgb[2]));
+            for c in 0..3 {
+                assert!(
+                    (back[c] as i32 - rgb[c] as i32).abs() <= 2,
+                    "{:?} round-tripped to {:?}", rgb, back
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_max_colors_clamped_to_gif_range() {
+        assert_eq!(OklabQuantizer::new(300).max_colors, 256);
+        assert_eq!(OklabQuantizer::new(0).max_colors, 1);
+        assert_eq!(OklabQuantizer::default().with_max_colors(300).max_colors, 256);
+        assert_eq!(OklabQuantizer::new(300).with_max_colors(64).max_colors, 64);
+
+        // Palette from an over-requested quantizer fits a color table
+        let frame_rgb: Vec<u8> = (0..81 * 81)
+            .flat_map(|i| [(i % 81 * 3) as u8, (i / 81 * 3) as u8, (i % 7 * 36) as u8])
+            .collect();
+        let frames_data = Frames81Rgb {
+            frames_rgb: vec![frame_rgb],
+            attention_maps: vec![],
+            processing_time_ms: 0,
+        };
+        let result = OklabQuantizer::new(300).quantize_frames(frames_data).unwrap();
+        assert!(result.palette_rgb.len() <= 256 * 3);
+    }
+
     #[test]
     fn test_pixel_sampling() {
         let quantizer = OklabQuantizer::default();
@@ -468,11 +833,23 @@ mod tests {
         let frame_rgb = vec![128u8; FRAME_SIZE_81 as usize * FRAME_SIZE_81 as usize * 3];
         let frames = vec![frame_rgb];
         
-        let samples = quantizer.sample_pixels(&frames).unwrap();
+        let samples = quantizer.sample_pixels(&frames, &[]).unwrap();
         assert!(!samples.is_empty());
         assert!(samples.len() <= 1000); // SAMPLES_PER_FRAME
     }
 
+    #[test]
+    fn test_sampling_positions_differ_between_frames() {
+        let quantizer = OklabQuantizer::new(16).with_seed(7);
+        // Every pixel has a distinct color, so samples identify their positions
+        let frame: Vec<u8> = (0..81 * 81).flat_map(|i| [(i % 256) as u8, (i / 256) as u8, 0]).collect();
+
+        let mut rng = quantizer.sampling_rng();
+        let first = quantizer.sample_frame_pixels(&frame, None, 100, &mut rng).unwrap();
+        let second = quantizer.sample_frame_pixels(&frame, None, 100, &mut rng).unwrap();
+        assert_ne!(first, second);
+    }
+
     #[test]
     fn test_quantization_workflow() {
         let quantizer = OklabQuantizer::new(8);
@@ -494,13 +871,384 @@ mod tests {
         assert!(result.mean_perceptual_error >= 0.0);
     }
 
+    #[test]
+    fn test_attention_weighted_sampling_keeps_salient_color() {
+        let quantizer = OklabQuantizer::new(8).with_seed(7);
+        // Blue-green gradient with a 9x9 red square: red is ~1% of the pixels
+        let in_square = |i: usize| i % 81 < 9 && i / 81 < 9;
+        let frame_rgb: Vec<u8> = (0..81 * 81)
+            .flat_map(|i| if in_square(i) { [255, 0, 0] } else { [0, (i % 81 * 3) as u8, (255 - i / 81 * 3) as u8] })
+            .collect();
+        let attention_map: Vec<f32> = (0..81 * 81).map(|i| if in_square(i) { 1.0 } else { 0.0 }).collect();
+
+        // The map is what makes red common in the samples k-means sees
+        let red_samples = |attention: Option<&[f32]>| {
+            let mut rng = quantizer.sampling_rng();
+            quantizer.sample_frame_pixels(&frame_rgb, attention, 1000, &mut rng).unwrap()
+                .iter()
+                .filter(|&&px| px == [255, 0, 0])
+                .count()
+        };
+        let uniform = red_samples(None);
+        let weighted = red_samples(Some(&attention_map));
+        assert!(uniform < 50, "{} of 1000 uniform samples are red", uniform);
+        assert!(weighted > 900, "{} of 1000 weighted samples are red", weighted);
+
+        let frames_data = Frames81Rgb {
+            frames_rgb: vec![frame_rgb; 3],
+            attention_maps: vec![attention_map; 3],
+            processing_time_ms: 0,
+        };
+        let result = quantizer.quantize_frames(frames_data).unwrap();
+
+        let red = rgb_to_oklab(255, 0, 0);
+        let nearest = result.palette_rgb.chunks_exact(3)
+            .map(|rgb| delta_e_oklab(rgb_to_oklab(rgb[0], rgb[1], rgb[2]), red))
+            .fold(f32::MAX, f32::min);
+        assert!(nearest < 0.05, "closest palette color is {} from red", nearest);
+    }
+
+    #[test]
+    fn test_quantize_frames_reports_palette_stability() {
+        let quantizer = OklabQuantizer::new(8);
+        let colors: [[u8; 3]; 4] = [[255, 0, 0], [0, 255, 0], [0, 0, 255], [255, 255, 0]];
+        let solid = |rgb: [u8; 3]| rgb.repeat(81 * 81);
+
+        // One four-color frame repeated: identical histograms every step
+        let static_frames = Frames81Rgb {
+            frames_rgb: vec![(0..81 * 81).flat_map(|i| colors[i * 4 / (81 * 81)]).collect(); 8],
+            attention_maps: vec![],
+            processing_time_ms: 0,
+        };
+        let stable = quantizer.quantize_frames(static_frames).unwrap().palette_stability;
+        assert!(stable > 0.99, "static stability {}", stable);
+
+        // Every frame a different solid color: consecutive histograms don't overlap
+        let flashing_frames = Frames81Rgb {
+            frames_rgb: (0..8).map(|f| solid(colors[f % 4])).collect(),
+            attention_maps: vec![],
+            processing_time_ms: 0,
+        };
+        let flashing = quantizer.quantize_frames(flashing_frames).unwrap().palette_stability;
+        assert!(flashing < 0.5, "flashing stability {}", flashing);
+    }
+
+    #[test]
+    fn test_median_cut_palettes_are_reproducible() {
+        let quantizer = OklabQuantizer::new(16).with_method(QuantMethod::MedianCut);
+        let frames_rgb: Vec<Vec<u8>> = (0..3u32)
+            .map(|f| (0..81 * 81u32).flat_map(|i| [(i % 81 * 3) as u8, (i / 81 * 3) as u8, (f * 60 + i % 7 * 10) as u8]).collect())
+            .collect();
+        let frames = || Frames81Rgb { frames_rgb: frames_rgb.clone(), attention_maps: vec![], processing_time_ms: 0 };
+
+        let first = quantizer.quantize_frames(frames()).unwrap();
+        let second = quantizer.quantize_frames(frames()).unwrap();
+        assert_eq!(first.palette_rgb.len(), 16 * 3);
+        assert_eq!(first.palette_rgb, second.palette_rgb);
+        assert_eq!(first.frames_indices, second.frames_indices);
+    }
+
+    #[test]
+    fn test_parallel_mapping_matches_sequential() {
+        let frames_rgb: Vec<Vec<u8>> = (0..81u32)
+            .map(|f| (0..81 * 81u32).flat_map(|i| [(i * 7 + f) as u8, (i / 81 * 3) as u8, (f * 3) as u8]).collect())
+            .collect();
+        let palette: Vec<[u8; 3]> = (0..64u8).map(|i| [i * 4, 255 - i * 4, i * 2]).collect();
+
+        for quantizer in [OklabQuantizer::new(64), OklabQuantizer::new(64).with_dithering(true)] {
+            let sequential: Vec<(Vec<u8>, f32)> = frames_rgb
+                .iter()
+                .map(|frame| quantizer.map_frame_to_palette(frame, &palette).unwrap())
+                .collect();
+            let parallel = quantizer.map_frames_to_palette(&frames_rgb, &palette).unwrap();
+
+            assert_eq!(parallel.len(), sequential.len());
+            for ((indices, error), (expected_indices, expected_error)) in parallel.iter().zip(&sequential) {
+                assert_eq!(indices, expected_indices);
+                assert_eq!(error.to_bits(), expected_error.to_bits());
+            }
+        }
+    }
+
+    #[test]
+    fn test_seeded_kmeans_is_reproducible() {
+        let frames_rgb: Vec<Vec<u8>> = (0..81u32)
+            .map(|f| (0..81 * 81u32).flat_map(|i| [(i % 81 * 3) as u8, (i / 81 * 3) as u8, (f * 3 + i % 7 * 10) as u8]).collect())
+            .collect();
+        let quantize = |seed| {
+            let frames = Frames81Rgb { frames_rgb: frames_rgb.clone(), attention_maps: vec![], processing_time_ms: 0 };
+            OklabQuantizer::new(16).with_seed(seed).quantize_for_cube(frames).unwrap()
+        };
+
+        let first = quantize(7);
+        assert_eq!(first.global_palette_rgb, quantize(7).global_palette_rgb);
+        assert_ne!(first.global_palette_rgb, quantize(8).global_palette_rgb);
+    }
+
+    #[test]
+    fn test_capture_size_attention_maps_are_resized() {
+        let quantizer = OklabQuantizer::new(8);
+        let frames_data = Frames81Rgb {
+            frames_rgb: vec![vec![128u8; 81 * 81 * 3]],
+            attention_maps: vec![vec![0.25f32; 729 * 729]],
+            processing_time_ms: 0,
+        };
+
+        let result = quantizer.quantize_frames(frames_data).unwrap();
+
+        assert_eq!(result.attention_maps[0].len(), 81 * 81);
+        assert!(result.attention_maps[0].iter().all(|&a| (a - 0.25).abs() < 1e-5));
+    }
+
+    #[test]
+    fn test_posterize_limits_palette() {
+        let quantizer = OklabQuantizer::new(256).with_posterize(2);
+
+        // Smooth gradients with far more than 64 distinct colors
+        let frames_rgb: Vec<Vec<u8>> = (0..2)
+            .map(|frame_idx| {
+                (0..FRAME_SIZE_81 as usize * FRAME_SIZE_81 as usize)
+                    .flat_map(|i| {
+                        let x = i % 81;
+                        let y = i / 81;
+                        [(x * 3) as u8, (y * 3) as u8, ((x + y) * 2 + frame_idx * 40) as u8]
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let frames_data = Frames81Rgb {
+            frames_rgb,
+            attention_maps: vec![],
+            processing_time_ms: 0,
+        };
+
+        let result = quantizer.quantize_frames(frames_data).unwrap();
+
+        let distinct: std::collections::HashSet<&[u8]> = result.palette_rgb.chunks(3).collect();
+        assert!(distinct.len() <= 64, "Posterized palette has {} colors", distinct.len());
+    }
+
+    #[test]
+    fn test_gaussian_prefilter_reduces_distinct_colors() {
+        // Flat mid-grey with deterministic per-pixel sensor noise of ±24
+        let mut state = 0x2545_f491u32;
+        let noisy: Vec<u8> = (0..81 * 81 * 3)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                (104 + state % 49) as u8
+            })
+            .collect();
+        let distinct = |frame: &[u8]| {
+            frame.chunks_exact(3).collect::<std::collections::HashSet<_>>().len()
+        };
+
+        let mut frames = vec![noisy.clone()];
+        OklabQuantizer::new(256)
+            .with_prefilter(PrefilterKind::Gaussian { sigma: 1.0 })
+            .preprocess_frames(&mut frames);
+        assert!(
+            distinct(&frames[0]) < distinct(&noisy),
+            "Prefiltered frame has {} colors, noisy frame {}",
+            distinct(&frames[0]), distinct(&noisy)
+        );
+
+        let mut bilateral = vec![noisy.clone()];
+        OklabQuantizer::new(256).with_prefilter(PrefilterKind::Bilateral).preprocess_frames(&mut bilateral);
+        assert!(distinct(&bilateral[0]) < distinct(&noisy));
+
+        let mut untouched = vec![noisy.clone()];
+        OklabQuantizer::new(256).preprocess_frames(&mut untouched);
+        assert_eq!(untouched[0], noisy);
+    }
+
+    #[test]
+    fn test_posterize_snaps_to_grid() {
+        let mut rgb = vec![0, 50, 100, 128, 200, 255];
+        posterize(&mut rgb, 2);
+        assert_eq!(rgb, vec![0, 85, 85, 170, 170, 255]);
+
+        let mut untouched = vec![1, 2, 3];
+        posterize(&mut untouched, 8);
+        assert_eq!(untouched, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_delta_e_formula_only_changes_reported_error() {
+        let palette = [[0u8, 0, 0], [200, 60, 60], [255, 255, 255]];
+        let frame_rgb: Vec<u8> = [[190u8, 70, 50], [20, 10, 10], [240, 240, 250]].concat();
+
+        let oklab = OklabQuantizer::default();
+        let ciede = OklabQuantizer::default().with_delta_e_formula(DeltaEFormula::Ciede2000);
+
+        let (oklab_indices, oklab_error) = oklab.map_frame_to_palette(&frame_rgb, &palette).unwrap();
+        let (ciede_indices, ciede_error) = ciede.map_frame_to_palette(&frame_rgb, &palette).unwrap();
+
+        assert_eq!(oklab_indices, ciede_indices);
+        let expected = frame_rgb
+            .chunks(3)
+            .zip(&ciede_indices)
+            .map(|(px, &idx)| DeltaEFormula::Ciede2000.delta_e_rgb([px[0], px[1], px[2]], palette[idx as usize]))
+            .sum::<f32>()
+            / 3.0;
+        assert!((ciede_error - expected).abs() < 1e-4);
+        assert!((ciede_error - oklab_error).abs() > 0.1, "CIEDE2000 is on a different scale");
+    }
+
+    #[test]
+    fn test_dithering_breaks_up_gradient_bands() {
+        // Shallow horizontal ramp that sits entirely nearest the darker of two grays
+        let palette = [[0u8, 0, 0], [96, 96, 96], [160, 160, 160], [255, 255, 255]];
+        let frame_rgb: Vec<u8> = (0..81 * 81)
+            .flat_map(|i| {
+                let v = 100 + ((i % 81) * 12 / 80) as u8;
+                [v, v, v]
+            })
+            .collect();
+
+        let (plain, plain_error) = OklabQuantizer::default().map_frame_to_palette(&frame_rgb, &palette).unwrap();
+        let (dithered, dithered_error) = OklabQuantizer::default()
+            .with_dithering(true)
+            .map_frame_to_palette(&frame_rgb, &palette)
+            .unwrap();
+
+        let distinct = |indices: &[u8]| indices.iter().collect::<std::collections::HashSet<_>>().len();
+        assert_eq!(distinct(&plain), 1);
+        assert!(distinct(&dithered) > distinct(&plain));
+
+        // Mixing in the lighter gray brings the average closer to the source
+        let mean_gray = |indices: &[u8]| indices.iter().map(|&i| palette[i as usize][0] as f32).sum::<f32>() / indices.len() as f32;
+        let source = frame_rgb.iter().step_by(3).map(|&v| v as f32).sum::<f32>() / (81.0 * 81.0);
+        assert!((mean_gray(&dithered) - source).abs() < (mean_gray(&plain) - source).abs());
+
+        // Reported error is against the source pixels, so dithering can only add to it per pixel
+        assert!(dithered_error >= plain_error);
+    }
+
+    #[test]
+    fn test_luminance_weighting_prefers_brightness_match() {
+        // Mid gray against a darker gray (hue match) and a pinkish tone of equal lightness
+        let palette = [[118u8, 118, 118], [160, 112, 118]];
+        let frame_rgb = vec![128u8, 128, 128];
+
+        let (plain, _) = OklabQuantizer::default()
+            .map_frame_to_palette(&frame_rgb, &palette)
+            .unwrap();
+        assert_eq!(plain, vec![0]);
+
+        let (weighted, weighted_error) = OklabQuantizer::default()
+            .with_channel_weights([4.0, 1.0, 1.0])
+            .map_frame_to_palette(&frame_rgb, &palette)
+            .unwrap();
+        assert_eq!(weighted, vec![1]);
+
+        // Reported error is the unweighted distance to the chosen entry
+        let expected = delta_e_oklab(rgb_to_oklab(128, 128, 128), rgb_to_oklab(160, 112, 118));
+        assert!((weighted_error - expected).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_palette_frame_subset_matches_full_on_uniform_cube() {
+        // Slowly drifting gradient with a hard cut to a different scene at frame 52
+        let frames_rgb: Vec<Vec<u8>> = (0..81usize)
+            .map(|frame_idx| {
+                (0..81 * 81usize)
+                    .flat_map(|i| {
+                        let (x, y) = (i % 81, i / 81);
+                        if frame_idx < 52 {
+                            [(x * 3) as u8, (y * 3) as u8, (frame_idx / 2) as u8]
+                        } else {
+                            [200, (x * 2) as u8, (y * 2) as u8]
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        let cube = |quantizer: OklabQuantizer| {
+            quantizer
+                .quantize_for_cube(Frames81Rgb {
+                    frames_rgb: frames_rgb.clone(),
+                    attention_maps: vec![],
+                    processing_time_ms: 0,
+                })
+                .unwrap()
+        };
+
+        let strategy = SubsetStrategy::EveryNth { step: 5, scene_change_threshold: 0.1 };
+        let selected = strategy.select(&frames_rgb);
+        assert_eq!(selected.len(), 18);
+        assert!(selected.contains(&52), "Scene change frame should be sampled");
+
+        let full = cube(OklabQuantizer::new(16));
+        let subset = cube(OklabQuantizer::new(16).with_palette_frame_subset(strategy));
+        assert_eq!(subset.indexed_frames.len(), 81);
+        assert!(
+            subset.mean_delta_e <= full.mean_delta_e * 1.25 + 0.005,
+            "Subset palette ΔE {} too far above full-sample ΔE {}",
+            subset.mean_delta_e, full.mean_delta_e
+        );
+    }
+
+    #[test]
+    fn test_single_frame_cube_metrics_are_finite() {
+        let frame_rgb: Vec<u8> = (0..81 * 81)
+            .flat_map(|i| [(i % 81 * 3) as u8, (i / 81 * 3) as u8, 90])
+            .collect();
+        let frames = Frames81Rgb {
+            frames_rgb: vec![frame_rgb],
+            attention_maps: vec![],
+            processing_time_ms: 0,
+        };
+
+        let cube = OklabQuantizer::new(16).quantize_for_cube(frames).unwrap();
+        assert_eq!(cube.indexed_frames.len(), 1);
+        assert!(cube.palette_stability.is_finite());
+        assert_eq!(cube.palette_stability, 1.0);
+        assert!(cube.mean_delta_e.is_finite());
+        assert_eq!(cube.mean_delta_e, cube.p95_delta_e, "One frame's mean should be its own error");
+        assert!(cube.attention_maps.is_none(), "Empty attention maps become None");
+    }
+
+    #[test]
+    fn test_mismatched_attention_map_count_is_rejected() {
+        let frames = Frames81Rgb {
+            frames_rgb: vec![vec![128u8; 81 * 81 * 3]; 81],
+            attention_maps: vec![vec![0.5; 81 * 81]; 40],
+            processing_time_ms: 0,
+        };
+
+        match OklabQuantizer::new(16).quantize_for_cube(frames) {
+            Err(GifPipeError::ValidationFailed { message }) => {
+                assert!(message.contains("40 attention maps for 81 frames"), "{}", message);
+            }
+            other => panic!("Expected a validation error, got {:?}", other.map(|cube| cube.indexed_frames.len())),
+        }
+    }
+
+    #[test]
+    fn test_cancelled_token_stops_quantization() {
+        let frames = Frames81Rgb {
+            frames_rgb: vec![vec![128u8; 81 * 81 * 3]; 81],
+            attention_maps: vec![],
+            processing_time_ms: 0,
+        };
+        let token = CancellationToken::new();
+        token.cancel();
+
+        let result = OklabQuantizer::new(16).with_cancellation(token.clone()).quantize_for_cube(frames);
+        assert!(matches!(result, Err(GifPipeError::Cancelled { .. })));
+        assert!(token.is_cancelled());
+    }
+
     #[test]
     fn test_invalid_frame_data() {
         let quantizer = OklabQuantizer::default();
         
         // Frame length not divisible by 3
         let invalid_frame = vec![128u8; 100]; // Not divisible by 3
-        let result = quantizer.sample_pixels(&[invalid_frame]);
+        let result = quantizer.sample_pixels(&[invalid_frame], &[]);
         assert!(result.is_err());
     }
 }
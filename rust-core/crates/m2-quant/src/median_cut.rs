@@ -0,0 +1,90 @@
+/// Recursive median cut over Oklab samples
+///
+/// Starts with one box holding every sample and repeatedly splits the box
+/// with the widest extent along any axis, at the median of that axis, until
+/// there are `max_colors` boxes or no box holds two distinct colors. Each box
+/// contributes the mean of its samples, so repeated colors weigh in by count.
+///
+/// Boxes are sorted on all three axes before splitting, so the palette only
+/// depends on which samples are given, not their order.
+pub fn median_cut(samples: &[[f32; 3]], max_colors: usize) -> Vec<[f32; 3]> {
+    if samples.is_empty() || max_colors == 0 {
+        return Vec::new();
+    }
+
+    let mut boxes = vec![samples.to_vec()];
+    while boxes.len() < max_colors {
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .map(|(idx, colors)| {
+                let (axis, extent) = widest_axis(colors);
+                (idx, axis, extent)
+            })
+            .filter(|&(_, _, extent)| extent > 0.0)
+            .max_by(|a, b| a.2.total_cmp(&b.2));
+        let Some((idx, axis, _)) = widest else {
+            break;
+        };
+
+        let mut colors = boxes.swap_remove(idx);
+        colors.sort_by(|p, q| {
+            p[axis]
+                .total_cmp(&q[axis])
+                .then(p[(axis + 1) % 3].total_cmp(&q[(axis + 1) % 3]))
+                .then(p[(axis + 2) % 3].total_cmp(&q[(axis + 2) % 3]))
+        });
+        let upper = colors.split_off(colors.len() / 2);
+        boxes.push(colors);
+        boxes.push(upper);
+    }
+
+    boxes.iter().map(|colors| mean(colors)).collect()
+}
+
+/// Axis with the largest max-min range, and that range
+fn widest_axis(colors: &[[f32; 3]]) -> (usize, f32) {
+    (0..3)
+        .map(|axis| {
+            let (min, max) = colors
+                .iter()
+                .fold((f32::MAX, f32::MIN), |(min, max), c| (min.min(c[axis]), max.max(c[axis])));
+            (axis, max - min)
+        })
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .unwrap_or((0, 0.0))
+}
+
+fn mean(colors: &[[f32; 3]]) -> [f32; 3] {
+    let mut sum = [0.0f64; 3];
+    for color in colors {
+        for (total, &value) in sum.iter_mut().zip(color) {
+            *total += value as f64;
+        }
+    }
+    sum.map(|total| (total / colors.len() as f64) as f32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_median_cut_ignores_sample_order() {
+        let samples: Vec<[f32; 3]> = (0..500)
+            .map(|i| [(i % 17) as f32 / 17.0, (i % 5) as f32 * 0.05 - 0.1, (i % 11) as f32 * 0.02 - 0.1])
+            .collect();
+        let mut reversed = samples.clone();
+        reversed.reverse();
+
+        let palette = median_cut(&samples, 16);
+        assert_eq!(palette.len(), 16);
+        assert_eq!(palette, median_cut(&reversed, 16));
+
+        // Fewer distinct colors than requested: one exact entry per color
+        let two = [[0.2, 0.0, 0.0], [0.8, 0.1, -0.1]].repeat(50);
+        let mut palette = median_cut(&two, 8);
+        palette.sort_by(|a, b| a[0].total_cmp(&b[0]));
+        assert_eq!(palette, vec![[0.2, 0.0, 0.0], [0.8, 0.1, -0.1]]);
+    }
+}
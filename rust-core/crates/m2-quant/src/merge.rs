@@ -0,0 +1,546 @@
+use tracing::{debug, info};
+use common_types::oklab::{delta_e_oklab, rgb_to_oklab};
+use common_types::{GifPipeError, QuantizedCubeData, CUBE_SCHEMA_VERSION};
+
+use crate::OklabQuantizer;
+
+/// Total pixels sampled across all frames when re-quantizing a crossfade
+const CROSSFADE_SAMPLES: usize = 81_000;
+
+/// Union palette entry: a color plus how many pixels reference it
+struct PaletteEntry {
+    rgb: [u8; 3],
+    weight: u64,
+}
+
+/// Merge two cubes into one animation sharing a single global palette
+///
+/// Colors referenced by either cube form a union palette; when that exceeds
+/// 256 entries the closest pair in Oklab is merged repeatedly (weighted by
+/// pixel usage) until it fits. Both cubes are remapped to the union and their
+/// frames concatenated, `a` first. Source frame ids are dropped, since ids
+/// from two captures would collide.
+pub fn merge_cubes(a: &QuantizedCubeData, b: &QuantizedCubeData) -> Result<QuantizedCubeData, GifPipeError> {
+    if a.width != b.width || a.height != b.height {
+        return Err(GifPipeError::ValidationFailed {
+            message: format!(
+                "Cannot merge {}x{} cube with {}x{} cube",
+                a.width, a.height, b.width, b.height
+            ),
+        });
+    }
+
+    // Union of used colors; `sources` maps each cube's palette index to a union slot
+    let mut entries: Vec<PaletteEntry> = Vec::new();
+    let mut sources: Vec<Vec<Option<usize>>> = Vec::with_capacity(2);
+
+    for cube in [a, b] {
+        let usage = palette_usage(cube)?;
+        let mut slots = vec![None; usage.len()];
+
+        for (index, &count) in usage.iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
+            let rgb = [
+                cube.global_palette_rgb[index * 3],
+                cube.global_palette_rgb[index * 3 + 1],
+                cube.global_palette_rgb[index * 3 + 2],
+            ];
+            let slot = match entries.iter().position(|entry| entry.rgb == rgb) {
+                Some(existing) => {
+                    entries[existing].weight += count;
+                    existing
+                }
+                None => {
+                    entries.push(PaletteEntry { rgb, weight: count });
+                    entries.len() - 1
+                }
+            };
+            slots[index] = Some(slot);
+        }
+        sources.push(slots);
+    }
+
+    let union_size = entries.len();
+    let slot_to_final = reduce_palette(&mut entries, 256);
+
+    info!(
+        stage = "M2",
+        union_colors = union_size,
+        merged_colors = entries.len(),
+        frames = a.indexed_frames.len() + b.indexed_frames.len(),
+        "Merging cubes to shared palette"
+    );
+
+    let mut indexed_frames = Vec::with_capacity(a.indexed_frames.len() + b.indexed_frames.len());
+    for (cube, slots) in [a, b].into_iter().zip(&sources) {
+        for frame in &cube.indexed_frames {
+            indexed_frames.push(
+                frame
+                    .iter()
+                    .map(|&index| {
+                        // Every referenced index has a slot by construction
+                        let slot = slots[index as usize].unwrap_or(0);
+                        slot_to_final[slot] as u8
+                    })
+                    .collect(),
+            );
+        }
+    }
+
+    let global_palette_rgb: Vec<u8> = entries.iter().flat_map(|entry| entry.rgb).collect();
+
+    let attention_maps = match (&a.attention_maps, &b.attention_maps) {
+        (Some(maps_a), Some(maps_b)) => Some(maps_a.iter().chain(maps_b).cloned().collect()),
+        _ => None,
+    };
+
+    // Remapping error isn't known without source frames; report the inputs' combined figures
+    let frames_a = a.indexed_frames.len() as f32;
+    let frames_b = b.indexed_frames.len() as f32;
+    let mean_delta_e = if frames_a + frames_b > 0.0 {
+        (a.mean_delta_e * frames_a + b.mean_delta_e * frames_b) / (frames_a + frames_b)
+    } else {
+        0.0
+    };
+
+    let merged = QuantizedCubeData {
+        width: a.width,
+        height: a.height,
+        global_palette_rgb,
+        palette_stability: common_types::palette_stability(&indexed_frames),
+        indexed_frames,
+        delays_cs: a.delays_cs.iter().chain(&b.delays_cs).copied().collect(),
+        mean_delta_e,
+        p95_delta_e: a.p95_delta_e.max(b.p95_delta_e),
+        attention_maps,
+        frame_checksums: None,
+        source_frame_ids: None,
+        schema_version: CUBE_SCHEMA_VERSION,
+    };
+
+    if a.frame_checksums.is_some() || b.frame_checksums.is_some() {
+        Ok(merged.with_frame_checksums())
+    } else {
+        Ok(merged)
+    }
+}
+
+/// Join two cubes with `fade_frames` frames blending `a`'s last into `b`'s first
+///
+/// Fade frame `k` (1-based) is the per-channel RGB mix at `k / (fade_frames + 1)`
+/// between the two boundary frames and plays at `a`'s last delay. Every frame
+/// of the result is then re-quantized to one new palette, since the blended
+/// colors belong to neither input palette.
+pub fn crossfade_cubes(
+    a: &QuantizedCubeData,
+    b: &QuantizedCubeData,
+    fade_frames: usize,
+) -> Result<QuantizedCubeData, GifPipeError> {
+    if a.width != b.width || a.height != b.height {
+        return Err(GifPipeError::ValidationFailed {
+            message: format!(
+                "Cannot crossfade {}x{} cube with {}x{} cube",
+                a.width, a.height, b.width, b.height
+            ),
+        });
+    }
+    palette_usage(a)?;
+    palette_usage(b)?;
+    let (Some(last), Some(first)) = (a.indexed_frames.last(), b.indexed_frames.first()) else {
+        return Err(GifPipeError::ValidationFailed {
+            message: "Cannot crossfade a cube without frames".to_string(),
+        });
+    };
+
+    let from = frame_rgb(a, last);
+    let to = frame_rgb(b, first);
+    let fades = (1..=fade_frames).map(|k| {
+        let t = k as f32 / (fade_frames + 1) as f32;
+        from.iter()
+            .zip(&to)
+            .map(|(&x, &y)| (x as f32 + (y as f32 - x as f32) * t).round() as u8)
+            .collect::<Vec<u8>>()
+    });
+    let frames_rgb: Vec<Vec<u8>> = a.indexed_frames.iter().map(|frame| frame_rgb(a, frame))
+        .chain(fades)
+        .chain(b.indexed_frames.iter().map(|frame| frame_rgb(b, frame)))
+        .collect();
+
+    info!(
+        stage = "M2",
+        fade_frames = fade_frames,
+        frames = frames_rgb.len(),
+        "Re-quantizing crossfade to shared palette"
+    );
+
+    let quantizer = OklabQuantizer::default();
+    let samples_per_frame = (CROSSFADE_SAMPLES / frames_rgb.len()).max(1);
+    let mut samples = Vec::new();
+    let mut rng = quantizer.sampling_rng();
+    for frame in &frames_rgb {
+        samples.extend(quantizer.sample_frame_pixels(frame, None, samples_per_frame, &mut rng)?);
+    }
+    let palette = quantizer.build_palette(&samples)?;
+
+    let mut indexed_frames = Vec::with_capacity(frames_rgb.len());
+    let mut delta_e_values = Vec::with_capacity(frames_rgb.len());
+    for frame in &frames_rgb {
+        let (indices, delta_e) = quantizer.map_frame_to_palette(frame, &palette)?;
+        indexed_frames.push(indices);
+        delta_e_values.push(delta_e);
+    }
+
+    let fade_delay = a.delays_cs.last().copied().unwrap_or(4);
+    let delays_cs = if a.delays_cs.is_empty() && b.delays_cs.is_empty() {
+        Vec::new()
+    } else {
+        let or_default = |cube: &QuantizedCubeData| {
+            if cube.delays_cs.is_empty() { vec![fade_delay; cube.indexed_frames.len()] } else { cube.delays_cs.clone() }
+        };
+        [or_default(a), vec![fade_delay; fade_frames], or_default(b)].concat()
+    };
+
+    let crossfaded = QuantizedCubeData {
+        width: a.width,
+        height: a.height,
+        global_palette_rgb: palette.iter().flatten().copied().collect(),
+        palette_stability: common_types::palette_stability(&indexed_frames),
+        indexed_frames,
+        delays_cs,
+        mean_delta_e: delta_e_values.iter().sum::<f32>() / delta_e_values.len() as f32,
+        p95_delta_e: quantizer.calculate_p95(&delta_e_values),
+        // Blended frames have no attention or source frame of their own
+        attention_maps: None,
+        frame_checksums: None,
+        source_frame_ids: None,
+        schema_version: CUBE_SCHEMA_VERSION,
+    };
+
+    if a.frame_checksums.is_some() || b.frame_checksums.is_some() {
+        Ok(crossfaded.with_frame_checksums())
+    } else {
+        Ok(crossfaded)
+    }
+}
+
+/// Snap colors common to adjacent segments onto shared centroids
+///
+/// Each segment keeps its own palette, but every used color in a segment
+/// whose nearest used color in the previous segment lies within
+/// `max_delta_e` (Oklab) is replaced by their usage-weighted mean in both
+/// palettes, so the color decodes identically on either side of the cut.
+/// A centroid already shared with an earlier segment stays fixed and the
+/// later color adopts it. Returns the number of colors snapped.
+pub fn share_segment_centroids(
+    segments: &mut [QuantizedCubeData],
+    max_delta_e: f32,
+) -> Result<usize, GifPipeError> {
+    let usages = segments.iter().map(palette_usage).collect::<Result<Vec<_>, _>>()?;
+    let mut shared: Vec<Vec<bool>> = usages.iter().map(|usage| vec![false; usage.len()]).collect();
+    let mut snapped = 0;
+
+    for next in 1..segments.len() {
+        let (before, after) = segments.split_at_mut(next);
+        let (prev_cube, next_cube) = (&mut before[next - 1], &mut after[0]);
+        let (prev_usage, next_usage) = (&usages[next - 1], &usages[next]);
+
+        for (j, &next_count) in next_usage.iter().enumerate() {
+            if next_count == 0 {
+                continue;
+            }
+            let next_rgb = palette_rgb(next_cube, j);
+            let next_lab = rgb_to_oklab(next_rgb[0], next_rgb[1], next_rgb[2]);
+
+            let nearest = prev_usage
+                .iter()
+                .enumerate()
+                .filter(|&(_, &count)| count > 0)
+                .map(|(i, _)| {
+                    let rgb = palette_rgb(prev_cube, i);
+                    (i, delta_e_oklab(rgb_to_oklab(rgb[0], rgb[1], rgb[2]), next_lab))
+                })
+                .min_by(|a, b| a.1.total_cmp(&b.1));
+
+            let Some((i, distance)) = nearest.filter(|&(_, distance)| distance <= max_delta_e) else {
+                continue;
+            };
+
+            let rgb = if shared[next - 1][i] {
+                palette_rgb(prev_cube, i)
+            } else {
+                let (wp, wn) = (prev_usage[i], next_count);
+                let prev_rgb = palette_rgb(prev_cube, i);
+                let total = wp + wn;
+                let mut rgb = [0u8; 3];
+                for (c, channel) in rgb.iter_mut().enumerate() {
+                    let sum = prev_rgb[c] as u64 * wp + next_rgb[c] as u64 * wn;
+                    *channel = ((sum + total / 2) / total) as u8;
+                }
+                prev_cube.global_palette_rgb[i * 3..i * 3 + 3].copy_from_slice(&rgb);
+                shared[next - 1][i] = true;
+                rgb
+            };
+
+            next_cube.global_palette_rgb[j * 3..j * 3 + 3].copy_from_slice(&rgb);
+            shared[next][j] = true;
+            snapped += 1;
+
+            debug!(stage = "M2", segment = next, index = j, delta_e = distance, "Shared centroid");
+        }
+    }
+
+    info!(
+        stage = "M2",
+        segments = segments.len(),
+        shared_colors = snapped,
+        "Shared centroids across segment boundaries"
+    );
+
+    Ok(snapped)
+}
+
+fn palette_rgb(cube: &QuantizedCubeData, index: usize) -> [u8; 3] {
+    [
+        cube.global_palette_rgb[index * 3],
+        cube.global_palette_rgb[index * 3 + 1],
+        cube.global_palette_rgb[index * 3 + 2],
+    ]
+}
+
+/// RGB bytes of one frame of `cube`, whose indices `palette_usage` has checked
+fn frame_rgb(cube: &QuantizedCubeData, frame: &[u8]) -> Vec<u8> {
+    frame.iter().flat_map(|&index| palette_rgb(cube, index as usize)).collect()
+}
+
+/// Pixel count per palette index, validating indices against the palette
+fn palette_usage(cube: &QuantizedCubeData) -> Result<Vec<u64>, GifPipeError> {
+    let palette_len = cube.global_palette_rgb.len() / 3;
+    let mut usage = vec![0u64; palette_len];
+
+    for (frame_idx, frame) in cube.indexed_frames.iter().enumerate() {
+        for &index in frame {
+            match usage.get_mut(index as usize) {
+                Some(count) => *count += 1,
+                None => {
+                    return Err(GifPipeError::ValidationFailed {
+                        message: format!(
+                            "Frame {} references index {} beyond {}-color palette",
+                            frame_idx, index, palette_len
+                        ),
+                    });
+                }
+            }
+        }
+    }
+
+    Ok(usage)
+}
+
+/// Greedily merge the closest Oklab pair until at most `max_colors` remain
+///
+/// Returns a map from original entry position to final palette index.
+fn reduce_palette(entries: &mut Vec<PaletteEntry>, max_colors: usize) -> Vec<usize> {
+    let mut slot_to_final: Vec<usize> = (0..entries.len()).collect();
+    if entries.len() <= max_colors {
+        return slot_to_final;
+    }
+
+    let mut oklab: Vec<[f32; 3]> = entries
+        .iter()
+        .map(|entry| rgb_to_oklab(entry.rgb[0], entry.rgb[1], entry.rgb[2]))
+        .collect();
+    let merges = entries.len() - max_colors;
+
+    while entries.len() > max_colors {
+        let mut best = (0, 1, f32::MAX);
+        for i in 0..entries.len() {
+            for j in (i + 1)..entries.len() {
+                let distance = delta_e_oklab(oklab[i], oklab[j]);
+                if distance < best.2 {
+                    best = (i, j, distance);
+                }
+            }
+        }
+
+        let (keep, remove, _) = best;
+        let (wk, wr) = (entries[keep].weight, entries[remove].weight);
+        let total = (wk + wr).max(1);
+        let mut rgb = [0u8; 3];
+        for (c, channel) in rgb.iter_mut().enumerate() {
+            let sum = entries[keep].rgb[c] as u64 * wk + entries[remove].rgb[c] as u64 * wr;
+            *channel = ((sum + total / 2) / total) as u8;
+        }
+
+        entries[keep] = PaletteEntry { rgb, weight: wk + wr };
+        oklab[keep] = rgb_to_oklab(rgb[0], rgb[1], rgb[2]);
+        entries.remove(remove);
+        oklab.remove(remove);
+
+        // Redirect slots pointing at the removed entry and shift those after it
+        for target in slot_to_final.iter_mut() {
+            if *target == remove {
+                *target = keep;
+            } else if *target > remove {
+                *target -= 1;
+            }
+        }
+    }
+
+    debug!(stage = "M2", merges = merges, "Union palette reduced");
+
+    slot_to_final
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PIXELS: usize = 81 * 81;
+
+    /// 81-frame cube over a 200-color ramp produced by `color`
+    fn ramp_cube(color: impl Fn(usize) -> [u8; 3]) -> QuantizedCubeData {
+        let global_palette_rgb = (0..200).flat_map(&color).collect();
+        let indexed_frames = (0..81)
+            .map(|f| (0..PIXELS).map(|i| ((i + f * 7) % 200) as u8).collect())
+            .collect();
+
+        QuantizedCubeData {
+            width: 81,
+            height: 81,
+            global_palette_rgb,
+            indexed_frames,
+            delays_cs: vec![4; 81],
+            palette_stability: 1.0,
+            mean_delta_e: 1.0,
+            p95_delta_e: 2.0,
+            attention_maps: None,
+            frame_checksums: None,
+            source_frame_ids: None,
+            schema_version: CUBE_SCHEMA_VERSION,
+        }
+    }
+
+    fn decode(cube: &QuantizedCubeData, frame: usize, pixel: usize) -> [u8; 3] {
+        let base = cube.indexed_frames[frame][pixel] as usize * 3;
+        [
+            cube.global_palette_rgb[base],
+            cube.global_palette_rgb[base + 1],
+            cube.global_palette_rgb[base + 2],
+        ]
+    }
+
+    #[test]
+    fn test_merge_two_cubes_shares_palette() {
+        let warm = ramp_cube(|i| [55 + i as u8, 40, (i / 4) as u8]);
+        let cool = ramp_cube(|i| [(i / 4) as u8, 80, 55 + i as u8]);
+
+        let merged = merge_cubes(&warm, &cool).unwrap();
+
+        assert_eq!(merged.indexed_frames.len(), 162);
+        assert_eq!(merged.delays_cs.len(), 162);
+        assert!(merged.global_palette_rgb.len() <= 256 * 3);
+
+        // Both halves decode to approximately their originals
+        for (offset, original) in [(0, &warm), (81, &cool)] {
+            let mut total = 0.0;
+            let mut worst = 0.0f32;
+            for frame in (0..81).step_by(10) {
+                for pixel in (0..PIXELS).step_by(17) {
+                    let before = decode(original, frame, pixel);
+                    let after = decode(&merged, offset + frame, pixel);
+                    let de = delta_e_oklab(
+                        rgb_to_oklab(before[0], before[1], before[2]),
+                        rgb_to_oklab(after[0], after[1], after[2]),
+                    );
+                    total += de;
+                    worst = worst.max(de);
+                }
+            }
+            let samples = 9.0 * (PIXELS as f32 / 17.0).ceil();
+            assert!(total / samples < 0.005, "Mean ΔE {} too high", total / samples);
+            assert!(worst < 0.02, "Worst ΔE {} too high", worst);
+        }
+    }
+
+    #[test]
+    fn test_merge_small_palettes_is_lossless() {
+        let mut a = ramp_cube(|i| [i as u8, 0, 0]);
+        let mut b = ramp_cube(|i| [i as u8, 0, 0]);
+        a.indexed_frames.truncate(3);
+        b.indexed_frames.truncate(2);
+
+        let merged = merge_cubes(&a, &b).unwrap();
+        assert_eq!(merged.global_palette_rgb.len(), 200 * 3);
+        assert_eq!(decode(&merged, 4, 123), decode(&b, 1, 123));
+    }
+
+    #[test]
+    fn test_segments_share_background_centroid() {
+        // Two scenes over nearly the same dark background, with unrelated foregrounds
+        let mut first = ramp_cube(|i| if i == 0 { [20, 24, 30] } else { [200, i as u8, 0] });
+        let mut second = ramp_cube(|i| if i == 0 { [22, 25, 29] } else { [0, i as u8, 200] });
+        for cube in [&mut first, &mut second] {
+            for frame in cube.indexed_frames.iter_mut() {
+                frame[..PIXELS / 2].fill(0);
+            }
+        }
+
+        let mut segments = vec![first, second];
+        let shared = share_segment_centroids(&mut segments, 0.02).unwrap();
+
+        assert_eq!(shared, 1);
+        assert_eq!(decode(&segments[0], 0, 0), decode(&segments[1], 0, 0));
+        // Foreground colors are left alone
+        assert_eq!(decode(&segments[0], 0, PIXELS - 1)[0], 200);
+        assert_eq!(decode(&segments[1], 0, PIXELS - 1)[2], 200);
+    }
+
+    #[test]
+    fn test_crossfade_moves_from_a_end_to_b_start() {
+        let mean_rgb = |cube: &QuantizedCubeData, frame: usize| {
+            let mut sum = [0u64; 3];
+            for pixel in 0..PIXELS {
+                for (total, value) in sum.iter_mut().zip(decode(cube, frame, pixel)) {
+                    *total += value as u64;
+                }
+            }
+            sum.map(|total| total as f32 / PIXELS as f32)
+        };
+        let mut dark = ramp_cube(|i| [(i / 4) as u8, 20, 40]);
+        let mut bright = ramp_cube(|i| [200 + (i / 4) as u8, 150, 60]);
+        dark.indexed_frames.truncate(4);
+        dark.delays_cs = vec![6; 4];
+        bright.indexed_frames.truncate(3);
+        bright.delays_cs = vec![4; 3];
+
+        let faded = crossfade_cubes(&dark, &bright, 8).unwrap();
+
+        assert_eq!(faded.indexed_frames.len(), 4 + 8 + 3);
+        assert_eq!(faded.delays_cs, [vec![6; 12], vec![4; 3]].concat());
+        let means: Vec<[f32; 3]> = (3..=12).map(|frame| mean_rgb(&faded, frame)).collect();
+        for channel in 0..3 {
+            assert!(
+                means.windows(2).all(|pair| pair[1][channel] > pair[0][channel]),
+                "Channel {} not rising through the fade: {:?}",
+                channel,
+                means
+            );
+        }
+        let (start, end) = (mean_rgb(&dark, 3), mean_rgb(&bright, 0));
+        for channel in 0..3 {
+            assert!((means[0][channel] - start[channel]).abs() < 3.0);
+            assert!((means[9][channel] - end[channel]).abs() < 3.0);
+        }
+    }
+
+    #[test]
+    fn test_merge_rejects_mismatched_dimensions() {
+        let a = ramp_cube(|i| [i as u8, 0, 0]);
+        let mut b = ramp_cube(|i| [i as u8, 0, 0]);
+        b.width = 80;
+
+        assert!(merge_cubes(&a, &b).is_err());
+    }
+}
@@ -0,0 +1,115 @@
+/// Smoothing applied to each RGB frame before palette sampling
+///
+/// A light blur suppresses sensor noise in low-light captures, so fewer
+/// colors are needed for the same perceived quality and LZW compresses better.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum PrefilterKind {
+    #[default]
+    None,
+    /// Separable Gaussian blur with standard deviation `sigma` in pixels
+    Gaussian { sigma: f32 },
+    /// Edge-preserving 5×5 bilateral filter, smoothing noise but not hard edges
+    Bilateral,
+}
+
+/// Bilateral window radius and spatial/range standard deviations
+const BILATERAL_RADIUS: usize = 2;
+const BILATERAL_SIGMA_SPATIAL: f32 = 1.5;
+const BILATERAL_SIGMA_RANGE: f32 = 25.0;
+
+/// Filter a `width`×`height` RGB frame in place
+pub fn prefilter_frame(frame_rgb: &mut [u8], width: usize, height: usize, kind: PrefilterKind) {
+    if width == 0 || height == 0 || frame_rgb.len() != width * height * 3 {
+        return;
+    }
+
+    match kind {
+        PrefilterKind::None => {}
+        PrefilterKind::Gaussian { sigma } if sigma > 0.0 => gaussian_blur(frame_rgb, width, height, sigma),
+        PrefilterKind::Gaussian { .. } => {}
+        PrefilterKind::Bilateral => bilateral_filter(frame_rgb, width, height),
+    }
+}
+
+/// Normalized 1D Gaussian kernel covering ±3σ
+fn gaussian_kernel(sigma: f32) -> Vec<f32> {
+    let radius = (sigma * 3.0).ceil() as isize;
+    let weights: Vec<f32> = (-radius..=radius)
+        .map(|d| (-(d * d) as f32 / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let total: f32 = weights.iter().sum();
+    weights.into_iter().map(|w| w / total).collect()
+}
+
+fn gaussian_blur(frame_rgb: &mut [u8], width: usize, height: usize, sigma: f32) {
+    let kernel = gaussian_kernel(sigma);
+    let radius = (kernel.len() / 2) as isize;
+
+    // Horizontal then vertical pass, clamping at the frame edges
+    let mut horizontal = vec![0.0f32; frame_rgb.len()];
+    for y in 0..height {
+        for x in 0..width {
+            for c in 0..3 {
+                horizontal[(y * width + x) * 3 + c] = kernel
+                    .iter()
+                    .enumerate()
+                    .map(|(k, &w)| {
+                        let sx = (x as isize + k as isize - radius).clamp(0, width as isize - 1) as usize;
+                        frame_rgb[(y * width + sx) * 3 + c] as f32 * w
+                    })
+                    .sum();
+            }
+        }
+    }
+
+    for y in 0..height {
+        for x in 0..width {
+            for c in 0..3 {
+                let value: f32 = kernel
+                    .iter()
+                    .enumerate()
+                    .map(|(k, &w)| {
+                        let sy = (y as isize + k as isize - radius).clamp(0, height as isize - 1) as usize;
+                        horizontal[(sy * width + x) * 3 + c] * w
+                    })
+                    .sum();
+                frame_rgb[(y * width + x) * 3 + c] = value.round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+}
+
+fn bilateral_filter(frame_rgb: &mut [u8], width: usize, height: usize) {
+    let source = frame_rgb.to_vec();
+    let spatial_denom = 2.0 * BILATERAL_SIGMA_SPATIAL * BILATERAL_SIGMA_SPATIAL;
+    let range_denom = 2.0 * BILATERAL_SIGMA_RANGE * BILATERAL_SIGMA_RANGE;
+
+    for y in 0..height {
+        for x in 0..width {
+            let center = (y * width + x) * 3;
+            let mut sum = [0.0f32; 3];
+            let mut total_weight = 0.0f32;
+
+            for ny in y.saturating_sub(BILATERAL_RADIUS)..=(y + BILATERAL_RADIUS).min(height - 1) {
+                for nx in x.saturating_sub(BILATERAL_RADIUS)..=(x + BILATERAL_RADIUS).min(width - 1) {
+                    let idx = (ny * width + nx) * 3;
+                    let dx = nx as f32 - x as f32;
+                    let dy = ny as f32 - y as f32;
+                    let color_dist2: f32 = (0..3)
+                        .map(|c| (source[idx + c] as f32 - source[center + c] as f32).powi(2))
+                        .sum();
+
+                    let weight = (-(dx * dx + dy * dy) / spatial_denom - color_dist2 / range_denom).exp();
+                    for c in 0..3 {
+                        sum[c] += source[idx + c] as f32 * weight;
+                    }
+                    total_weight += weight;
+                }
+            }
+
+            for c in 0..3 {
+                frame_rgb[center + c] = (sum[c] / total_weight).round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+}
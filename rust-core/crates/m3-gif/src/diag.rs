@@ -0,0 +1,45 @@
+use std::sync::Mutex;
+
+/// GIF block reported by `DiagEvent::BlockWritten`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GifBlock {
+    /// Signature and logical screen descriptor
+    Header,
+    GlobalColorTable,
+    NetscapeLoop,
+    /// Application extension carrying the `DisplayHint` aspect ratio
+    DisplayHint,
+    GraphicControl,
+    /// Image descriptor, including any local color table
+    ImageDescriptor,
+    /// LZW minimum code size and compressed sub-blocks
+    ImageData,
+}
+
+/// Structured encoder event delivered to a `Gif89aEncoder::with_diagnostics` sink
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiagEvent {
+    /// `len` bytes of `block` were written at byte `offset` of the GIF
+    BlockWritten { block: GifBlock, offset: usize, len: usize },
+    /// A color table of `colors` entries was chosen, global when `frame` is `None`
+    PaletteChosen { frame: Option<usize>, colors: usize },
+    /// Frame `frame` was written in `bytes` bytes, shown for `delay_cs`
+    FrameEncoded { frame: usize, bytes: usize, delay_cs: u16 },
+}
+
+/// Caller closure receiving `DiagEvent`s
+///
+/// Behind a mutex so encoding can keep taking `&self` and the encoder stays `Sync`.
+pub(crate) struct DiagSink(Mutex<Box<dyn FnMut(DiagEvent) + Send>>);
+
+impl DiagSink {
+    pub(crate) fn new(sink: impl FnMut(DiagEvent) + Send + 'static) -> Self {
+        Self(Mutex::new(Box::new(sink)))
+    }
+
+    pub(crate) fn emit(&self, event: DiagEvent) {
+        // A sink that panicked earlier still gets later events
+        let mut sink = self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        sink(event);
+    }
+}
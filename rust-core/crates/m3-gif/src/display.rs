@@ -0,0 +1,163 @@
+use common_types::GifPipeError;
+
+/// Application identifier and auth code of the display hint extension
+const DISPLAY_HINT_ID: &[u8; 11] = b"GIFPIPEDSP1";
+
+/// Intended display aspect ratio of a GIF whose frames are stored square
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisplayHint {
+    pub aspect_width: u16,
+    pub aspect_height: u16,
+}
+
+impl DisplayHint {
+    pub fn new(aspect_width: u16, aspect_height: u16) -> Self {
+        Self { aspect_width, aspect_height }
+    }
+
+    /// Size to present a `height`-pixel-tall GIF at, stretching its width
+    pub fn display_size(&self, height: u32) -> (u32, u32) {
+        let width = (height as u64 * self.aspect_width as u64 + self.aspect_height as u64 / 2)
+            / self.aspect_height.max(1) as u64;
+        (width as u32, height)
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), GifPipeError> {
+        if self.aspect_width == 0 || self.aspect_height == 0 {
+            return Err(GifPipeError::ValidationFailed {
+                message: format!("Invalid display aspect {}:{}", self.aspect_width, self.aspect_height),
+            });
+        }
+        Ok(())
+    }
+
+    /// Logical screen descriptor pixel aspect byte, `(byte + 15) / 64` = pixel width / height
+    ///
+    /// Only approximates the ratio and is clamped to what the byte can hold;
+    /// the application extension carries the exact value.
+    pub(crate) fn pixel_aspect_byte(&self, width: u16, height: u16) -> u8 {
+        let pixel_aspect = (self.aspect_width as f64 / self.aspect_height as f64)
+            / (width.max(1) as f64 / height.max(1) as f64);
+        (pixel_aspect * 64.0 - 15.0).round().clamp(1.0, 255.0) as u8
+    }
+
+    /// Append the exact ratio as an application extension
+    pub(crate) fn write_extension(&self, output: &mut Vec<u8>) {
+        output.push(0x21); // Extension introducer
+        output.push(0xFF); // Application extension label
+        output.push(0x0B); // Block size
+        output.extend_from_slice(DISPLAY_HINT_ID);
+        output.push(0x04); // Sub-block size
+        output.extend_from_slice(&self.aspect_width.to_le_bytes());
+        output.extend_from_slice(&self.aspect_height.to_le_bytes());
+        output.push(0x00); // Block terminator
+    }
+}
+
+/// Read the display aspect ratio recorded in a GIF
+///
+/// Prefers the exact ratio from the application extension written by
+/// `Gif89aEncoder::with_display_hint`, falling back to the screen's
+/// pixel aspect byte (reduced to lowest terms). Returns `None` when neither
+/// is present or the header is malformed.
+pub fn read_display_hint(gif_bytes: &[u8]) -> Option<DisplayHint> {
+    if gif_bytes.len() < 13 || !gif_bytes.starts_with(b"GIF8") {
+        return None;
+    }
+    let width = u16::from_le_bytes([gif_bytes[6], gif_bytes[7]]);
+    let height = u16::from_le_bytes([gif_bytes[8], gif_bytes[9]]);
+    let packed = gif_bytes[10];
+    let aspect_byte = gif_bytes[12];
+
+    // Extensions between the global color table and the first image
+    let mut pos = 13;
+    if packed & 0x80 != 0 {
+        pos += 3 * (1usize << ((packed & 0x07) + 1));
+    }
+    while gif_bytes.get(pos) == Some(&0x21) {
+        let label = *gif_bytes.get(pos + 1)?;
+        let data = pos + 2;
+        if label == 0xFF
+            && gif_bytes.get(data..data + 12).is_some_and(|id| id[0] == 0x0B && &id[1..] == DISPLAY_HINT_ID)
+            && gif_bytes.get(data + 12) == Some(&0x04)
+        {
+            let ratio = gif_bytes.get(data + 13..data + 17)?;
+            let hint = DisplayHint::new(
+                u16::from_le_bytes([ratio[0], ratio[1]]),
+                u16::from_le_bytes([ratio[2], ratio[3]]),
+            );
+            return hint.validate().is_ok().then_some(hint);
+        }
+
+        pos = data;
+        while *gif_bytes.get(pos)? != 0 {
+            pos += gif_bytes[pos] as usize + 1;
+        }
+        pos += 1;
+    }
+
+    if aspect_byte == 0 || width == 0 || height == 0 {
+        return None;
+    }
+    // Display ratio = pixel aspect * width / height = (byte + 15) * width / (64 * height)
+    let numerator = (aspect_byte as u64 + 15) * width as u64;
+    let denominator = 64 * height as u64;
+    let divisor = gcd(numerator, denominator);
+    let (aspect_width, aspect_height) = (numerator / divisor, denominator / divisor);
+    Some(DisplayHint::new(
+        u16::try_from(aspect_width).ok()?,
+        u16::try_from(aspect_height).ok()?,
+    ))
+}
+
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Gif89aEncoder;
+    use crate::tests::test_cube;
+
+    #[test]
+    fn test_display_hint_round_trips() {
+        let cube = test_cube(
+            (0..81).map(|f| (0..81 * 81).map(|i| ((i + f) % 2) as u8).collect()).collect(),
+            vec![0, 0, 0, 255, 255, 255],
+        );
+
+        let gif = Gif89aEncoder::new()
+            .with_display_hint(DisplayHint::new(16, 9))
+            .encode_from_cube_data(&cube, 4, true)
+            .unwrap();
+        let hint = read_display_hint(&gif).unwrap();
+        assert_eq!(hint, DisplayHint::new(16, 9));
+        assert_eq!(hint.display_size(81), (144, 81));
+        // (99 + 15) / 64 is the closest the screen's aspect byte gets to 16/9
+        assert_eq!(gif[12], 99);
+
+        // Still a valid GIF for decoders that skip unknown extensions
+        let mut decoder = gif::DecodeOptions::new().read_info(gif.as_slice()).unwrap();
+        let mut frames = 0;
+        while decoder.read_next_frame().unwrap().is_some() {
+            frames += 1;
+        }
+        assert_eq!(frames, 81);
+
+        // Without the extension the aspect byte is the fallback; without either, nothing
+        let plain = Gif89aEncoder::new().encode_from_cube_data(&cube, 4, true).unwrap();
+        assert_eq!(read_display_hint(&plain), None);
+        let mut byte_only = plain;
+        byte_only[12] = 99;
+        assert_eq!(read_display_hint(&byte_only), Some(DisplayHint::new(57, 32)));
+
+        assert!(Gif89aEncoder::new()
+            .with_display_hint(DisplayHint::new(16, 0))
+            .encode_from_cube_data(&cube, 4, true)
+            .is_err());
+    }
+}
@@ -1,11 +1,101 @@
 use tracing::{info, debug, span, Level, warn};
-use common_types::{QuantizedSet, GifInfo, GifPipeError, QuantizedCubeData};
+use common_types::{QuantizedSet, GifInfo, GifPipeError, ProgressListener, QuantizedCubeData, MAX_FRAME_COUNT};
+use common_types::oklab::{delta_e_oklab, mean_delta_e_indexed, rgb_to_oklab};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+mod diag;
+mod display;
+mod lossy;
+mod lzw;
+mod stream;
+
+use diag::DiagSink;
+pub use diag::{DiagEvent, GifBlock};
+pub use display::{read_display_hint, DisplayHint};
+pub use lossy::{optimize_lossy, LossyOptimization, MAX_LOSSY_LEVEL};
+use lzw::lzw_encode;
+pub use stream::GifStreamEncoder;
+
+/// Global palette RGB bytes with frames indexed against it
+type PalettedFrames = (Vec<u8>, Vec<Vec<u8>>);
+
+/// Mean Oklab ΔE a frame must gain from its own table to be worth the bytes
+const LOCAL_PALETTE_MIN_GAIN: f32 = 0.01;
+/// Above this share of frames wanting local tables, every frame gets one
+const LOCAL_PALETTE_ALL_FRACTION: f32 = 0.5;
+/// Default source spread above which a single-index frame is suspicious
+pub const UNIFORM_FRAME_MIN_SPREAD: f32 = 8.0;
+
+/// How frames were given color tables by `encode_auto_palette_strategy`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaletteStrategy {
+    /// Every frame indexes the cube's global palette
+    Global,
+    /// Every frame carries its own local color table
+    Local,
+    /// Only frames the global palette serves poorly get local tables
+    Hybrid,
+}
+
+/// GIF bytes with the palette strategy chosen for them
+#[derive(Debug, Clone)]
+pub struct AutoPaletteEncoding {
+    pub gif_data: Vec<u8>,
+    pub strategy: PaletteStrategy,
+    /// Frames written with a local color table
+    pub local_frames: usize,
+    /// Mean ΔE of the frames against their source with the global palette
+    pub global_delta_e: f32,
+    /// Mean ΔE of the frames as written
+    pub encoded_delta_e: f32,
+}
+
+/// Frame disposal written to the Graphic Control Extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DisposalMethod {
+    /// No disposal specified, decoders treat it like `Keep`
+    #[default]
+    Unspecified,
+    /// Leave the frame in place for the next one to draw over
+    Keep,
+    /// Clear the frame's area to the background color
+    RestoreBackground,
+    /// Restore the area to what it was before the frame was drawn
+    RestorePrevious,
+}
+
+impl DisposalMethod {
+    /// 3-bit disposal code of the GCE packed field
+    pub fn code(self) -> u8 {
+        match self {
+            DisposalMethod::Unspecified => 0,
+            DisposalMethod::Keep => 1,
+            DisposalMethod::RestoreBackground => 2,
+            DisposalMethod::RestorePrevious => 3,
+        }
+    }
+}
 
 /// GIF89a encoder with validation and transparency support
 pub struct Gif89aEncoder {
     optimize_palette: bool,
     validate_output: bool,
     transparency_threshold: u8,
+    auto_transparency: bool,
+    reference_palette: Option<Vec<u8>>,
+    border: Option<(u16, u8)>,
+    sub_block_size: u8,
+    local_palettes: bool,
+    prune_palette: bool,
+    delta_frames: bool,
+    disposal: Option<DisposalMethod>,
+    source_spread: Option<(Vec<f32>, f32)>,
+    display_hint: Option<DisplayHint>,
+    diagnostics: Option<DiagSink>,
+    progress: Option<Arc<dyn ProgressListener>>,
 }
 
 impl Default for Gif89aEncoder {
@@ -14,6 +104,18 @@ impl Default for Gif89aEncoder {
             optimize_palette: true,
             validate_output: true,
             transparency_threshold: 254,
+            auto_transparency: false,
+            reference_palette: None,
+            border: None,
+            sub_block_size: 255,
+            local_palettes: false,
+            prune_palette: false,
+            delta_frames: false,
+            disposal: None,
+            source_spread: None,
+            display_hint: None,
+            diagnostics: None,
+            progress: None,
         }
     }
 }
@@ -33,6 +135,174 @@ impl Gif89aEncoder {
         self
     }
 
+    /// Pick the transparency threshold from the frames' alpha histogram instead
+    /// of the fixed value; the fixed threshold remains the fallback
+    pub fn with_auto_transparency(mut self, auto: bool) -> Self {
+        self.auto_transparency = auto;
+        self
+    }
+
+    /// Write the global color table in the order of `reference_rgb` (RGB bytes)
+    ///
+    /// Frame indices are remapped to match, so cube output can be byte-compared
+    /// with a GIF produced by another tool. Cube colors missing from the
+    /// reference are appended after it.
+    pub fn with_reference_palette(mut self, reference_rgb: Vec<u8>) -> Self {
+        self.reference_palette = Some(reference_rgb);
+        self
+    }
+
+    /// Draw a solid `width_px` border of palette entry `color_index` around every frame
+    pub fn with_border(mut self, width_px: u16, color_index: u8) -> Self {
+        self.border = Some((width_px, color_index));
+        self
+    }
+
+    /// Chunk LZW output into data sub-blocks of at most `size` bytes (1..=255)
+    ///
+    /// Smaller sub-blocks are valid GIF and useful for decoder interop testing.
+    pub fn with_sub_block_size(mut self, size: u8) -> Self {
+        self.sub_block_size = size.max(1);
+        self
+    }
+
+    /// Allow per-frame local color tables via `encode_from_cube_data_with_local_palettes`
+    ///
+    /// Each frame then carries its own palette of up to 256 colors, so a cube
+    /// can span more colors than one global table holds.
+    pub fn with_local_palettes(mut self, enabled: bool) -> Self {
+        self.local_palettes = enabled;
+        self
+    }
+
+    /// Drop global palette colors no cube frame uses before encoding
+    ///
+    /// Indices are remapped to the compacted palette and the global color table
+    /// shrinks to the smallest power of two holding it, which also lowers the
+    /// LZW minimum code size. Only applies to cubes encoded against the global
+    /// palette alone, without local tables or transparency.
+    pub fn with_palette_pruning(mut self, enabled: bool) -> Self {
+        self.prune_palette = enabled;
+        self
+    }
+
+    /// Write each frame after the first as just the rectangle that changed
+    ///
+    /// Frames are drawn over their predecessor (disposal `Keep`), so static
+    /// areas cost nothing. Frame 0 is always a full, opaque keyframe that
+    /// later deltas build on.
+    pub fn with_delta_frames(mut self, enabled: bool) -> Self {
+        self.delta_frames = enabled;
+        self
+    }
+
+    /// Disposal written for every frame unless the caller passes per-frame disposals
+    ///
+    /// Without one, cube frames are `Unspecified` (`Keep` for delta frames) and
+    /// `encode_gif` frames `RestoreBackground`. Full-frame animations usually
+    /// want `Keep` to avoid flicker; transparent overlays want
+    /// `RestoreBackground`.
+    pub fn with_disposal(mut self, disposal: DisposalMethod) -> Self {
+        self.disposal = Some(disposal);
+        self
+    }
+
+    /// Disposal for cube frames without an explicit per-frame disposal
+    fn default_disposal(&self) -> DisposalMethod {
+        match self.disposal {
+            Some(disposal) => disposal,
+            None if self.delta_frames => DisposalMethod::Keep,
+            None => DisposalMethod::Unspecified,
+        }
+    }
+
+    /// Warn about frames that index a single color although their source didn't
+    ///
+    /// `source_spread` holds one `frame_spread` value per cube frame. A frame
+    /// whose indices are all identical while its source spread exceeds
+    /// `min_spread` (see `UNIFORM_FRAME_MIN_SPREAD`) is logged before encoding
+    /// and reported in `GifInfo::warnings`; it is still encoded.
+    pub fn with_uniform_frame_sentinel(mut self, source_spread: Vec<f32>, min_spread: f32) -> Self {
+        self.source_spread = Some((source_spread, min_spread));
+        self
+    }
+
+    /// Record the aspect ratio the square frames should be displayed at
+    ///
+    /// Written as the screen's pixel aspect byte, which players that honor it
+    /// approximate, and exactly in an application extension that
+    /// `read_display_hint` reads back.
+    pub fn with_display_hint(mut self, hint: DisplayHint) -> Self {
+        self.display_hint = Some(hint);
+        self
+    }
+
+    /// Send structured encoding events to `sink`, independent of any logging setup
+    ///
+    /// Reports each block written, the color tables chosen and every frame
+    /// encoded, so apps can show progress or debug output without a tracing
+    /// subscriber or logger.
+    pub fn with_diagnostics(mut self, sink: impl FnMut(DiagEvent) + Send + 'static) -> Self {
+        self.diagnostics = Some(DiagSink::new(sink));
+        self
+    }
+
+    /// Report each frame written to `listener` as stage `"M3"`
+    pub fn with_progress(mut self, listener: Arc<dyn ProgressListener>) -> Self {
+        self.progress = Some(listener);
+        self
+    }
+
+    /// Send `event` to the diagnostics sink, if one is set
+    fn emit(&self, event: DiagEvent) {
+        if let Some(sink) = &self.diagnostics {
+            sink.emit(event);
+        }
+    }
+
+    /// Report `block` as the bytes of `output` from `start` on
+    fn emit_block(&self, block: GifBlock, output: &[u8], start: usize) {
+        self.emit(DiagEvent::BlockWritten { block, offset: start, len: output.len() - start });
+    }
+
+    /// Append `data` as length-prefixed sub-blocks followed by the block terminator
+    fn write_sub_blocks(&self, output: &mut Vec<u8>, data: &[u8]) {
+        for block in data.chunks(self.sub_block_size as usize) {
+            output.push(block.len() as u8);
+            output.extend_from_slice(block);
+        }
+        output.push(0); // Block terminator
+    }
+
+    /// Overwrite the outer `width_px` ring of an indexed frame with the border color
+    fn apply_border(&self, frame_indices: &mut [u8], width: usize, height: usize) {
+        paint_border(frame_indices, width, height, self.border);
+    }
+
+    /// Resolve the transparency threshold for a set of RGBA frames
+    ///
+    /// Alpha values below the returned threshold are treated as transparent.
+    pub fn transparency_threshold_for(&self, frames_rgba: &[Vec<u8>]) -> u8 {
+        if !self.auto_transparency {
+            return self.transparency_threshold;
+        }
+
+        match otsu_alpha_threshold(frames_rgba) {
+            Some(threshold) => {
+                debug!(stage = "M3", threshold = threshold, "Auto transparency threshold selected");
+                threshold
+            }
+            None => {
+                debug!(
+                    stage = "M3",
+                    threshold = self.transparency_threshold,
+                    "Alpha histogram is unimodal, using fixed transparency threshold"
+                );
+                self.transparency_threshold
+            }
+        }
+    }
+
     /// Encode quantized frames to GIF89a format
     #[tracing::instrument(level = "info", skip(self, quantized_set))]
     pub fn encode_gif(&self, quantized_set: QuantizedSet) -> Result<GifInfo, GifPipeError> {
@@ -82,12 +352,21 @@ impl Gif89aEncoder {
             debug!(stage = "M3", frame_idx = frame_idx, "Encoding frame");
             
             let frame_delay = self.calculate_frame_delay(attention_map);
+            let mut frame_indices = frame_indices.clone();
+            let frame_size = common_types::FRAME_SIZE_81 as usize;
+            self.apply_border(&mut frame_indices, frame_size, frame_size);
+            let frame_start = gif_data.len();
             self.write_gif_frame(
                 &mut gif_data,
-                frame_indices,
+                &frame_indices,
                 frame_delay,
                 &optimized_palette,
             )?;
+            self.emit(DiagEvent::FrameEncoded {
+                frame: frame_idx,
+                bytes: gif_data.len() - frame_start,
+                delay_cs: frame_delay,
+            });
         }
 
         self.write_gif_trailer(&mut gif_data)?;
@@ -99,7 +378,9 @@ impl Gif89aEncoder {
             self.validate_gif_output(&gif_data)?;
         }
 
-        let compression_ratio = self.calculate_compression_ratio(&quantized_set, &gif_data);
+        let frame_pixels = (common_types::FRAME_SIZE_81 as usize).pow(2);
+        let uncompressed_size = GifInfo::uncompressed_size(quantized_set.frames_indices.len(), frame_pixels);
+        let compression_ratio = GifInfo::ratio(uncompressed_size, gif_data.len() as u64);
 
         info!(
             stage = "M3",
@@ -117,11 +398,13 @@ impl Gif89aEncoder {
             file_size_bytes: gif_data_len as u64,
             processing_time_ms: processing_time,
             compression_ratio,
+            uncompressed_size_bytes: uncompressed_size,
             frame_count: quantized_set.frames_indices.len() as u32,
             palette_size: optimized_palette.len() as u32,
             total_processing_ms: quantized_set.processing_time_ms + processing_time,
             has_netscape_loop: true,
             validation_passed: self.validate_output,
+            warnings: Vec::new(),
         })
     }
 
@@ -216,7 +499,7 @@ impl Gif89aEncoder {
         output.push(packed);
 
         output.push(0); // Background color index
-        output.push(0); // Pixel aspect ratio
+        output.push(self.screen_aspect_byte(width, height)?); // Pixel aspect ratio
 
         // Write global color table
         for &[r, g, b] in palette {
@@ -229,9 +512,24 @@ impl Gif89aEncoder {
             output.extend_from_slice(&[0, 0, 0]);
         }
 
+        if let Some(hint) = &self.display_hint {
+            hint.write_extension(output);
+        }
+
         Ok(())
     }
 
+    /// Pixel aspect byte for the display hint, 0 (no aspect information) without one
+    fn screen_aspect_byte(&self, width: u16, height: u16) -> Result<u8, GifPipeError> {
+        match &self.display_hint {
+            Some(hint) => {
+                hint.validate()?;
+                Ok(hint.pixel_aspect_byte(width, height))
+            }
+            None => Ok(0),
+        }
+    }
+
     /// Write individual GIF frame
     fn write_gif_frame(
         &self,
@@ -240,12 +538,8 @@ impl Gif89aEncoder {
         delay: u16,
         palette: &[[u8; 3]],
     ) -> Result<(), GifPipeError> {
-        // Graphic Control Extension
-        output.extend_from_slice(&[0x21, 0xF9, 0x04]); // Extension + label + block size
-        output.push(0x08); // Disposal method: restore to background
-        output.extend_from_slice(&delay.to_le_bytes());
-        output.push(0); // Transparent color index (none)
-        output.push(0); // Block terminator
+        let disposal = self.disposal.unwrap_or(DisposalMethod::RestoreBackground);
+        self.write_graphic_control(output, disposal, delay, None)?;
 
         // Image Descriptor
         output.push(0x2C); // Image separator
@@ -261,58 +555,13 @@ impl Gif89aEncoder {
         Ok(())
     }
 
-    /// Write LZW compressed image data (simplified implementation)
+    /// Write LZW compressed image data sized to the global color table
     fn write_lzw_data(&self, output: &mut Vec<u8>, indices: &[u8], palette: &[[u8; 3]]) -> Result<(), GifPipeError> {
         let color_bits = self.calculate_color_bits(palette.len())?;
         let min_code_size = (color_bits + 1).max(2);
         
         output.push(min_code_size);
-
-        // Simplified LZW encoding - in production use proper LZW
-        let mut compressed = Vec::new();
-        
-        // Clear code and end code
-        let clear_code: u16 = 1 << min_code_size;
-        let end_code: u16 = clear_code + 1;
-        
-        compressed.extend_from_slice(&clear_code.to_le_bytes());
-        
-        // Simple run-length encoding as LZW placeholder
-        let mut i = 0;
-        while i < indices.len() {
-            let current = indices[i];
-            let mut count = 1;
-            
-            while i + count < indices.len() && indices[i + count] == current && count < 128 {
-                count += 1;
-            }
-            
-            if count > 3 {
-                // Use run-length encoding
-                compressed.push(current);
-                compressed.push((count - 1) as u8);
-            } else {
-                // Raw pixels
-                for _ in 0..count {
-                    compressed.push(current);
-                }
-            }
-            
-            i += count;
-        }
-        
-        compressed.extend_from_slice(&end_code.to_le_bytes());
-
-        // Write data blocks
-        let mut pos = 0;
-        while pos < compressed.len() {
-            let block_size = (compressed.len() - pos).min(255);
-            output.push(block_size as u8);
-            output.extend_from_slice(&compressed[pos..pos + block_size]);
-            pos += block_size;
-        }
-        
-        output.push(0); // Block terminator
+        self.write_sub_blocks(output, &lzw_encode(indices, min_code_size));
 
         Ok(())
     }
@@ -367,37 +616,269 @@ impl Gif89aEncoder {
         Ok(())
     }
 
-    /// Calculate compression ratio
-    fn calculate_compression_ratio(&self, quantized_set: &QuantizedSet, gif_data: &[u8]) -> f32 {
-        // Original size: frames × pixels × 3 bytes (RGB)
-        let frame_pixels = (common_types::FRAME_SIZE_81 * common_types::FRAME_SIZE_81) as usize;
-        let original_size = quantized_set.frames_indices.len() * frame_pixels * 3;
-        
-        if gif_data.is_empty() {
-            return 0.0;
-        }
-        
-        original_size as f32 / gif_data.len() as f32
-    }
-
     /// Encode from pre-quantized cube data (no quantization inside)
+    ///
+    /// Every frame gets a Graphic Control Extension with its `delays_cs`
+    /// entry. `fps_cs` is only used when `delays_cs` is empty; otherwise it
+    /// must hold one delay per frame.
     pub fn encode_from_cube_data(
         &self, 
         cube: &QuantizedCubeData, 
-        _fps_cs: u8, 
+        fps_cs: u8, 
         loop_forever: bool
+    ) -> Result<Vec<u8>, GifPipeError> {
+        self.encode_from_cube_data_with_disposals(cube, fps_cs, loop_forever, None)
+    }
+
+    /// Encode cube data with an explicit disposal method per frame
+    ///
+    /// With `disposals` set, each frame's Graphic Control Extension carries its
+    /// disposal instead of `Unspecified`, so delta encoders can keep overlay
+    /// frames and clear keyframes.
+    pub fn encode_from_cube_data_with_disposals(
+        &self,
+        cube: &QuantizedCubeData,
+        fps_cs: u8,
+        loop_forever: bool,
+        disposals: Option<&[DisposalMethod]>,
+    ) -> Result<Vec<u8>, GifPipeError> {
+        self.encode_cube(cube, fps_cs, loop_forever, disposals, None, None)
+    }
+
+    /// Encode cube data whose frames index their own RGB palettes
+    ///
+    /// Requires `with_local_palettes(true)`. Frame `i` indexes
+    /// `frame_palettes[i]`, written as a local color table padded to a power of
+    /// two. The cube's global palette is still written as the fallback table.
+    pub fn encode_from_cube_data_with_local_palettes(
+        &self,
+        cube: &QuantizedCubeData,
+        frame_palettes: &[Vec<u8>],
+        fps_cs: u8,
+        loop_forever: bool,
+    ) -> Result<Vec<u8>, GifPipeError> {
+        if !self.local_palettes {
+            return Err(GifPipeError::ValidationFailed {
+                message: "Local palettes are disabled, enable them with with_local_palettes".to_string(),
+            });
+        }
+        let frame_palettes: Vec<Option<&[u8]>> = frame_palettes.iter().map(|palette| Some(palette.as_slice())).collect();
+        self.encode_cube(cube, fps_cs, loop_forever, None, Some(&frame_palettes), None)
+    }
+
+    /// Encode cube data with transparency taken from the source RGBA frames
+    ///
+    /// Pixels whose alpha falls below `transparency_threshold_for(frames_rgba)`
+    /// become transparent. Each frame reserves one index its opaque pixels do
+    /// not use, preferring padding slots past the palette, which then join the
+    /// written palette as black entries; when all 256 are in use, the frame's
+    /// least-used color is folded into its nearest neighbor.
+    /// Every frame gets a Graphic Control Extension with `disposal`.
+    pub fn encode_with_transparency(
+        &self,
+        cube: &QuantizedCubeData,
+        frames_rgba: &[Vec<u8>],
+        disposal: DisposalMethod,
+        fps_cs: u8,
+        loop_forever: bool,
+    ) -> Result<Vec<u8>, GifPipeError> {
+        if frames_rgba.len() != cube.indexed_frames.len() {
+            return Err(GifPipeError::ValidationFailed {
+                message: format!("Got {} RGBA frames for {} indexed frames", frames_rgba.len(), cube.indexed_frames.len()),
+            });
+        }
+        cube.verify_frame_checksums()?;
+        
+        let threshold = self.transparency_threshold_for(frames_rgba);
+        let mut transparent_cube = cube.clone();
+        transparent_cube.frame_checksums = None;
+        let mut transparent_indices = Vec::with_capacity(frames_rgba.len());
+        
+        for (idx, (frame, rgba)) in transparent_cube.indexed_frames.iter_mut().zip(frames_rgba).enumerate() {
+            if rgba.len() != frame.len() * 4 {
+                return Err(GifPipeError::ValidationFailed {
+                    message: format!("RGBA frame {} has {} bytes, expected {}", idx, rgba.len(), frame.len() * 4),
+                });
+            }
+            let transparent: Vec<bool> = rgba.chunks_exact(4).map(|px| px[3] < threshold).collect();
+            if !transparent.contains(&true) {
+                transparent_indices.push(None);
+                continue;
+            }
+            
+            let index = self.reserve_transparent_index(frame, &transparent, &cube.global_palette_rgb);
+            for (pixel, _) in frame.iter_mut().zip(&transparent).filter(|(_, &t)| t) {
+                *pixel = index;
+            }
+            transparent_indices.push(Some(index));
+        }
+        
+        // Padding slots become real entries, so every transparent index lies inside the palette
+        if let Some(&highest) = transparent_indices.iter().flatten().max() {
+            let needed = (highest as usize + 1) * 3;
+            if transparent_cube.global_palette_rgb.len() < needed {
+                transparent_cube.global_palette_rgb.resize(needed, 0);
+            }
+        }
+        
+        debug!(
+            stage = "M3",
+            threshold = threshold,
+            transparent_frames = transparent_indices.iter().flatten().count(),
+            "Transparent indices selected"
+        );
+        
+        let disposals = vec![disposal; frames_rgba.len()];
+        self.encode_cube(&transparent_cube, fps_cs, loop_forever, Some(&disposals), None, Some(&transparent_indices))
+    }
+
+    /// Encode cube data, choosing global, local or mixed color tables per frame
+    ///
+    /// Each frame is re-quantized from `source_rgb` into its own table and
+    /// compared (mean Oklab ΔE) with the cube's global palette. Frames that
+    /// gain at least `LOCAL_PALETTE_MIN_GAIN` are worth a local table. If none
+    /// are, the GIF is global-only; if more than half are, every frame gets a
+    /// local table; otherwise only those frames do. Does not require
+    /// `with_local_palettes`.
+    pub fn encode_auto_palette_strategy(
+        &self,
+        cube: &QuantizedCubeData,
+        source_rgb: &[Vec<u8>],
+        fps_cs: u8,
+        loop_forever: bool,
+    ) -> Result<AutoPaletteEncoding, GifPipeError> {
+        if source_rgb.len() != cube.indexed_frames.len() {
+            return Err(GifPipeError::ValidationFailed {
+                message: format!("Got {} source frames for {} indexed frames", source_rgb.len(), cube.indexed_frames.len()),
+            });
+        }
+        cube.verify_frame_checksums()?;
+        
+        let mut local_cube = cube.clone();
+        local_cube.frame_checksums = None;
+        let mut local_palettes = Vec::with_capacity(source_rgb.len());
+        let mut global_errors = Vec::with_capacity(source_rgb.len());
+        let mut local_errors = Vec::with_capacity(source_rgb.len());
+        
+        for (idx, (frame, rgb)) in local_cube.indexed_frames.iter_mut().zip(source_rgb).enumerate() {
+            if rgb.len() != frame.len() * 3 || frame.len() != 81 * 81 {
+                return Err(GifPipeError::ValidationFailed {
+                    message: format!("Source frame {} has {} bytes, expected {}", idx, rgb.len(), 81 * 81 * 3),
+                });
+            }
+            global_errors.push(mean_delta_e_indexed(frame, &cube.global_palette_rgb, rgb));
+            
+            let local = gif::Frame::from_rgb_speed(81, 81, rgb, 10);
+            let palette = local.palette.unwrap_or_default();
+            local_errors.push(mean_delta_e_indexed(&local.buffer, &palette, rgb));
+            *frame = local.buffer.into_owned();
+            local_palettes.push(palette);
+        }
+        
+        let wants_local: Vec<bool> = global_errors.iter().zip(&local_errors)
+            .map(|(global, local)| global - local >= LOCAL_PALETTE_MIN_GAIN)
+            .collect();
+        let wanted = wants_local.iter().filter(|&&wants| wants).count();
+        let strategy = if wanted == 0 {
+            PaletteStrategy::Global
+        } else if wanted as f32 > wants_local.len() as f32 * LOCAL_PALETTE_ALL_FRACTION {
+            PaletteStrategy::Local
+        } else {
+            PaletteStrategy::Hybrid
+        };
+        
+        let use_local: Vec<bool> = match strategy {
+            PaletteStrategy::Global => vec![false; wants_local.len()],
+            PaletteStrategy::Local => vec![true; wants_local.len()],
+            PaletteStrategy::Hybrid => wants_local,
+        };
+        for (idx, &local) in use_local.iter().enumerate() {
+            if !local {
+                local_cube.indexed_frames[idx] = cube.indexed_frames[idx].clone();
+            }
+        }
+        let frame_palettes: Vec<Option<&[u8]>> = use_local.iter().zip(&local_palettes)
+            .map(|(&local, palette)| local.then_some(palette.as_slice()))
+            .collect();
+        
+        let frame_count = global_errors.len().max(1) as f32;
+        let global_delta_e = global_errors.iter().sum::<f32>() / frame_count;
+        let encoded_delta_e = use_local.iter().zip(global_errors.iter().zip(&local_errors))
+            .map(|(&local, (&global, &local_error))| if local { local_error } else { global })
+            .sum::<f32>() / frame_count;
+        let local_frames = use_local.iter().filter(|&&local| local).count();
+        info!(
+            stage = "M3",
+            strategy = ?strategy,
+            local_frames = local_frames,
+            global_delta_e = global_delta_e,
+            encoded_delta_e = encoded_delta_e,
+            "Palette strategy selected"
+        );
+        
+        let gif_data = match strategy {
+            PaletteStrategy::Global => self.encode_cube(cube, fps_cs, loop_forever, None, None, None)?,
+            _ => self.encode_cube(&local_cube, fps_cs, loop_forever, None, Some(&frame_palettes), None)?,
+        };
+        
+        Ok(AutoPaletteEncoding { gif_data, strategy, local_frames, global_delta_e, encoded_delta_e })
+    }
+
+    /// Pick a palette index that no opaque pixel (or the border) of `frame` uses
+    ///
+    /// If every index is taken, the least-used one is remapped to its nearest
+    /// Oklab neighbor in `frame` and freed.
+    fn reserve_transparent_index(&self, frame: &mut [u8], transparent: &[bool], palette_rgb: &[u8]) -> u8 {
+        let mut usage = [0usize; 256];
+        for (&index, _) in frame.iter().zip(transparent).filter(|(_, &t)| !t) {
+            usage[index as usize] += 1;
+        }
+        if let Some((_, color_index)) = self.border {
+            usage[color_index as usize] = usize::MAX;
+        }
+        
+        let palette_len = (palette_rgb.len() / 3).min(256);
+        if let Some(free) = (palette_len..256).chain(0..palette_len).find(|&i| usage[i] == 0) {
+            return free as u8;
+        }
+        
+        let freed = (0..palette_len).min_by_key(|&i| usage[i]).unwrap_or(0);
+        let color = |i: usize| rgb_to_oklab(palette_rgb[i * 3], palette_rgb[i * 3 + 1], palette_rgb[i * 3 + 2]);
+        let nearest = (0..palette_len)
+            .filter(|&i| i != freed)
+            .min_by(|&a, &b| {
+                let target = color(freed);
+                delta_e_oklab(color(a), target).total_cmp(&delta_e_oklab(color(b), target))
+            });
+        
+        if let Some(nearest) = nearest {
+            for (pixel, _) in frame.iter_mut().zip(transparent).filter(|(p, &t)| !t && **p as usize == freed) {
+                *pixel = nearest as u8;
+            }
+        }
+        freed as u8
+    }
+
+    fn encode_cube(
+        &self,
+        cube: &QuantizedCubeData,
+        fps_cs: u8,
+        loop_forever: bool,
+        disposals: Option<&[DisposalMethod]>,
+        frame_palettes: Option<&[Option<&[u8]>]>,
+        transparent_indices: Option<&[Option<u8>]>,
     ) -> Result<Vec<u8>, GifPipeError> {
         let span = span!(Level::INFO, "M3_encode_cube",
-            frames = 81,
+            frames = cube.indexed_frames.len(),
             palette_size = cube.global_palette_rgb.len() / 3,
             stability = cube.palette_stability
         );
         let _guard = span.enter();
         
         // Validate cube structure
-        if cube.indexed_frames.len() != 81 {
+        if cube.indexed_frames.is_empty() || cube.indexed_frames.len() > MAX_FRAME_COUNT as usize {
             return Err(GifPipeError::ValidationFailed {
-                message: format!("Expected 81 frames, got {}", cube.indexed_frames.len())
+                message: format!("Expected 1 to {} frames, got {}", MAX_FRAME_COUNT, cube.indexed_frames.len())
             });
         }
         
@@ -407,23 +888,149 @@ impl Gif89aEncoder {
             });
         }
         
+        // An empty vector means "use fps_cs"; anything else must cover every frame
+        if !cube.delays_cs.is_empty() && cube.delays_cs.len() != cube.indexed_frames.len() {
+            return Err(GifPipeError::ValidationFailed {
+                message: format!(
+                    "Got {} frame delays for {} frames",
+                    cube.delays_cs.len(), cube.indexed_frames.len()
+                ),
+            });
+        }
+        
+        if let Some(disposals) = disposals {
+            if disposals.len() != cube.indexed_frames.len() {
+                return Err(GifPipeError::ValidationFailed {
+                    message: format!(
+                        "Got {} disposal methods for {} frames",
+                        disposals.len(), cube.indexed_frames.len()
+                    ),
+                });
+            }
+        }
+        
+        if let Some(frame_palettes) = frame_palettes {
+            self.validate_frame_palettes(&cube.indexed_frames, frame_palettes)?;
+        }
+        
+        if self.delta_frames {
+            self.validate_keyframe(disposals, transparent_indices)?;
+        }
+        
+        for warning in self.uniform_frame_warnings(&cube.indexed_frames)? {
+            warn!(stage = "M3", "{}", warning);
+        }
+        
+        // Catch frames corrupted between quantization and encoding
+        cube.verify_frame_checksums()?;
+        
+        // Reordering remaps indices against the global palette, which local tables
+        // bypass and transparent indices may lie beyond
+        let reordered = match (frame_palettes, transparent_indices) {
+            (None, None) => self.reorder_to_reference(&cube.global_palette_rgb, &cube.indexed_frames)?,
+            _ => None,
+        };
+        let (palette_rgb, indexed_frames) = match &reordered {
+            Some((palette, frames)) => (palette.as_slice(), frames.as_slice()),
+            None => (cube.global_palette_rgb.as_slice(), cube.indexed_frames.as_slice()),
+        };
+        
+        // Pruning must keep the border color, which frames only get while encoding
+        let pruned = match (self.prune_palette, frame_palettes, transparent_indices) {
+            (true, None, None) => Some(prune_palette(palette_rgb, indexed_frames, self.border.map(|(_, index)| index))),
+            _ => None,
+        };
+        let (palette_rgb, indexed_frames, border, global_bits) = match &pruned {
+            Some(((palette, frames), remap)) => {
+                debug!(stage = "M3", colors = palette.len() / 3, "Pruned unused palette colors");
+                let border = self.border.map(|(width_px, index)| (width_px, remap[index as usize]));
+                (palette.as_slice(), frames.as_slice(), border, color_table_bits(palette.len() / 3))
+            }
+            None => (palette_rgb, indexed_frames, self.border, 8),
+        };
+        
         let mut gif_bytes = Vec::new();
         
         // GIF89a header + logical screen descriptor
-        self.write_gif89a_header(&mut gif_bytes, 81, 81)?;
+        self.write_gif89a_header(&mut gif_bytes, 81, 81, global_bits)?;
         
         // Global color table (palette)
-        self.write_global_color_table(&mut gif_bytes, &cube.global_palette_rgb)?;
+        self.write_global_color_table(&mut gif_bytes, palette_rgb, global_bits)?;
+        self.emit(DiagEvent::PaletteChosen { frame: None, colors: palette_rgb.len() / 3 });
         
         // NETSCAPE2.0 loop extension for infinite loop
         if loop_forever {
             self.write_netscape_loop(&mut gif_bytes)?;
         }
         
+        if let Some(hint) = &self.display_hint {
+            let start = gif_bytes.len();
+            hint.write_extension(&mut gif_bytes);
+            self.emit_block(GifBlock::DisplayHint, &gif_bytes, start);
+        }
+        
+        if let Some((_, color_index)) = border {
+            let smallest_palette = match frame_palettes {
+                Some(frame_palettes) => frame_palettes.iter()
+                    .map(|palette| palette.unwrap_or(palette_rgb).len() / 3)
+                    .min()
+                    .unwrap_or(0),
+                None => palette_rgb.len() / 3,
+            };
+            if color_index as usize >= smallest_palette {
+                return Err(GifPipeError::ValidationFailed {
+                    message: format!("Border color index {} outside the {}-color palette", color_index, smallest_palette),
+                });
+            }
+        }
+        
+        // Displayed colors of the previous frame, for delta rectangles
+        let mut previous_rgb: Option<Vec<[u8; 3]>> = None;
+        
         // Write 81 frames
-        for (idx, frame_indices) in cube.indexed_frames.iter().enumerate() {
-            self.write_image_descriptor(&mut gif_bytes, 0, 0, 81, 81)?;
-            self.write_lzw_compressed_data(&mut gif_bytes, frame_indices)?;
+        for (idx, frame_indices) in indexed_frames.iter().enumerate() {
+            let mut frame_indices = frame_indices.clone();
+            paint_border(&mut frame_indices, 81, 81, border);
+            let local_palette = frame_palettes.and_then(|frame_palettes| frame_palettes[idx]);
+            if let Some(palette) = local_palette {
+                self.emit(DiagEvent::PaletteChosen { frame: Some(idx), colors: palette.len() / 3 });
+            }
+            let frame_start = gif_bytes.len();
+            
+            // Frame 0 (previous_rgb unset) is always the full keyframe
+            let mut rect = (0, 0, 81, 81);
+            if self.delta_frames {
+                let table = local_palette.unwrap_or(palette_rgb);
+                let frame_rgb: Vec<[u8; 3]> = frame_indices.iter()
+                    .map(|&index| {
+                        let base = index as usize * 3;
+                        table.get(base..base + 3).map_or([0, 0, 0], |rgb| [rgb[0], rgb[1], rgb[2]])
+                    })
+                    .collect();
+                if let Some(previous) = &previous_rgb {
+                    // An unchanged frame still needs one pixel to carry its delay
+                    rect = changed_rect(previous, &frame_rgb, 81).unwrap_or((0, 0, 1, 1));
+                }
+                previous_rgb = Some(frame_rgb);
+            }
+            
+            let disposal = disposals.map_or(self.default_disposal(), |disposals| disposals[idx]);
+            let delay_cs = cube.delays_cs.get(idx).copied().unwrap_or(fps_cs);
+            let transparent_index = transparent_indices.and_then(|indices| indices[idx]);
+            self.write_graphic_control(&mut gif_bytes, disposal, delay_cs as u16, transparent_index)?;
+            let (x, y, width, height) = rect;
+            self.write_image_descriptor(&mut gif_bytes, x as u32, y as u32, width as u32, height as u32, local_palette)?;
+            let rect_indices: Vec<u8> = if rect == (0, 0, 81, 81) {
+                frame_indices
+            } else {
+                (y..y + height).flat_map(|row| frame_indices[row * 81 + x..row * 81 + x + width].iter().copied()).collect()
+            };
+            let min_code_size = local_palette.map_or(global_bits, |palette| color_table_bits(palette.len() / 3)).max(2);
+            self.write_lzw_compressed_data(&mut gif_bytes, &rect_indices, min_code_size)?;
+            self.emit(DiagEvent::FrameEncoded { frame: idx, bytes: gif_bytes.len() - frame_start, delay_cs: delay_cs as u16 });
+            if let Some(listener) = &self.progress {
+                listener.on_progress("M3".to_string(), idx as u32 + 1, indexed_frames.len() as u32);
+            }
             
             if idx % 10 == 0 {
                 info!(frame = idx, "Encoded frame batch");
@@ -435,85 +1042,346 @@ impl Gif89aEncoder {
         
         info!(
             size_bytes = gif_bytes.len(),
-            frames = 81,
+            frames = indexed_frames.len(),
             "GIF89a encoding complete"
         );
         
         Ok(gif_bytes)
     }
-    
-    fn write_global_color_table(&self, gif_bytes: &mut Vec<u8>, palette_rgb: &[u8]) -> Result<(), GifPipeError> {
-        // Write palette, pad to 256 entries if needed
-        gif_bytes.extend_from_slice(palette_rgb);
-        
-        let colors_written = palette_rgb.len() / 3;
-        if colors_written < 256 {
-            let padding = vec![0u8; (256 - colors_written) * 3];
-            gif_bytes.extend_from_slice(&padding);
-        }
-        
-        Ok(())
-    }
 
-    fn write_image_descriptor(&self, gif_bytes: &mut Vec<u8>, left: u16, top: u16, width: u16, height: u16) -> Result<(), GifPipeError> {
-        gif_bytes.push(0x2C); // Image separator
-        gif_bytes.extend_from_slice(&left.to_le_bytes());
-        gif_bytes.extend_from_slice(&top.to_le_bytes());
-        gif_bytes.extend_from_slice(&width.to_le_bytes());
-        gif_bytes.extend_from_slice(&height.to_le_bytes());
-        gif_bytes.push(0x00); // No local color table
-        Ok(())
+    /// Encode pre-quantized cube data and write it to `path`
+    ///
+    /// The GIF is written to a sibling temp file and renamed into place, so a
+    /// crash mid-write never leaves a truncated GIF at `path`.
+    pub fn encode_to_file(
+        &self,
+        cube: &QuantizedCubeData,
+        path: impl AsRef<Path>,
+        fps_cs: u8,
+        loop_forever: bool,
+    ) -> Result<GifInfo, GifPipeError> {
+        self.write_cube_file(cube, path.as_ref(), fps_cs, loop_forever, false)
+            .map(|(info, _)| info)
     }
 
-    fn write_lzw_compressed_data(&self, gif_bytes: &mut Vec<u8>, frame_indices: &[u8]) -> Result<(), GifPipeError> {
-        // LZW minimum code size (8 bits for 256 color palette)
-        gif_bytes.push(8);
-        
-        // Simple LZW encoding - in production, use proper LZW implementation
-        let mut compressed = Vec::new();
-        
-        // Clear code (256) and end code (257)
-        compressed.extend_from_slice(&256u16.to_le_bytes());
-        
-        // Simple encoding: just write the indices
-        for &index in frame_indices {
-            compressed.push(index);
-        }
-        
-        compressed.extend_from_slice(&257u16.to_le_bytes());
-        
-        // Write in 255-byte blocks
-        let mut pos = 0;
-        while pos < compressed.len() {
-            let block_size = (compressed.len() - pos).min(255);
-            gif_bytes.push(block_size as u8);
-            gif_bytes.extend_from_slice(&compressed[pos..pos + block_size]);
-            pos += block_size;
-        }
-        
-        gif_bytes.push(0); // Block terminator
-        Ok(())
+    /// Like `encode_to_file`, also returning the hex SHA-256 of the written GIF
+    ///
+    /// The digest is computed from the bytes as they are written, so integrity
+    /// checks don't need to read the file back.
+    pub fn encode_to_file_with_sha256(
+        &self,
+        cube: &QuantizedCubeData,
+        path: impl AsRef<Path>,
+        fps_cs: u8,
+        loop_forever: bool,
+    ) -> Result<(GifInfo, String), GifPipeError> {
+        let (info, digest) = self.write_cube_file(cube, path.as_ref(), fps_cs, loop_forever, true)?;
+        Ok((info, digest.expect("hashing was requested")))
     }
 
-    fn write_gif89a_header(&self, output: &mut Vec<u8>, width: u16, height: u16) -> Result<(), GifPipeError> {
-        // GIF89a signature
-        output.extend_from_slice(b"GIF89a");
-
-        // Logical screen descriptor
-        output.extend_from_slice(&width.to_le_bytes());
-        output.extend_from_slice(&height.to_le_bytes());
+    fn write_cube_file(
+        &self,
+        cube: &QuantizedCubeData,
+        path: &Path,
+        fps_cs: u8,
+        loop_forever: bool,
+        hash_output: bool,
+    ) -> Result<(GifInfo, Option<String>), GifPipeError> {
+        let start_time = std::time::Instant::now();
 
-        // Global color table info: 8 bits (256 colors)
-        let packed = 0xF7; // Global color table flag + 8-bit color resolution + sorted flag
-        output.push(packed);
+        let gif_data = self.encode_from_cube_data(cube, fps_cs, loop_forever)?;
+        if self.validate_output {
+            self.validate_gif_output(&gif_data)?;
+        }
 
-        output.push(0); // Background color index
-        output.push(0); // Pixel aspect ratio
+        let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+        tmp_name.push(".tmp");
+        let tmp_path = path.with_file_name(tmp_name);
+
+        let mut digest = None;
+        let write_result = std::fs::File::create(&tmp_path)
+            .and_then(|file| {
+                let mut writer = HashingWriter::new(file, hash_output);
+                writer.write_all(&gif_data)?;
+                let (file, sha256) = writer.finish();
+                digest = sha256;
+                file.sync_all()
+            })
+            .and_then(|_| std::fs::rename(&tmp_path, path));
 
-        Ok(())
-    }
+        if let Err(e) = write_result {
+            let _ = std::fs::remove_file(&tmp_path);
+            return Err(GifPipeError::IoFailed {
+                message: format!("Failed to write GIF to {}: {}", path.display(), e),
+            });
+        }
+
+        let processing_time = start_time.elapsed().as_millis() as u64;
+        let uncompressed_size = GifInfo::uncompressed_size(
+            cube.indexed_frames.len(),
+            cube.width as usize * cube.height as usize,
+        );
+
+        info!(
+            stage = "M3",
+            path = %path.display(),
+            size_bytes = gif_data.len(),
+            "GIF written to file"
+        );
+
+        let info = GifInfo {
+            file_path: path.to_string_lossy().into_owned(),
+            file_size_bytes: gif_data.len() as u64,
+            frame_count: cube.indexed_frames.len() as u32,
+            palette_size: (cube.global_palette_rgb.len() / 3) as u32,
+            has_netscape_loop: loop_forever,
+            compression_ratio: GifInfo::ratio(uncompressed_size, gif_data.len() as u64),
+            uncompressed_size_bytes: uncompressed_size,
+            validation_passed: self.validate_output,
+            processing_time_ms: processing_time,
+            total_processing_ms: processing_time,
+            warnings: self.uniform_frame_warnings(&cube.indexed_frames)?,
+            gif_data,
+        };
+        Ok((info, digest))
+    }
+    
+    /// Palette in the configured reference order with frames remapped to it,
+    /// or `None` when no reference palette is set
+    fn reorder_to_reference(
+        &self,
+        palette_rgb: &[u8],
+        frames: &[Vec<u8>],
+    ) -> Result<Option<PalettedFrames>, GifPipeError> {
+        let Some(reference) = &self.reference_palette else {
+            return Ok(None);
+        };
+        if !reference.len().is_multiple_of(3) || reference.len() > 768 {
+            return Err(GifPipeError::ValidationFailed {
+                message: format!("Invalid reference palette size: {} bytes", reference.len()),
+            });
+        }
+        
+        let mut ordered: Vec<[u8; 3]> = reference.chunks_exact(3).map(|c| [c[0], c[1], c[2]]).collect();
+        let mut positions: HashMap<[u8; 3], usize> = HashMap::new();
+        for (idx, &color) in ordered.iter().enumerate() {
+            positions.entry(color).or_insert(idx);
+        }
+        
+        // Lookup from cube index to reference position, appending unmatched colors
+        let mut remap = [0u8; 256];
+        for (idx, c) in palette_rgb.chunks_exact(3).enumerate() {
+            let color = [c[0], c[1], c[2]];
+            let position = *positions.entry(color).or_insert_with(|| {
+                ordered.push(color);
+                ordered.len() - 1
+            });
+            if position > 255 {
+                return Err(GifPipeError::ValidationFailed {
+                    message: "Cube palette doesn't fit alongside the reference palette in 256 colors".to_string(),
+                });
+            }
+            remap[idx] = position as u8;
+        }
+        
+        let appended = ordered.len() - reference.len() / 3;
+        if appended > 0 {
+            warn!(stage = "M3", appended, "Cube colors missing from reference palette, appended");
+        }
+        debug!(stage = "M3", colors = ordered.len(), "Reordered palette to reference");
+        
+        let frames = frames
+            .iter()
+            .map(|frame| frame.iter().map(|&index| remap[index as usize]).collect())
+            .collect();
+        
+        Ok(Some((ordered.into_iter().flatten().collect(), frames)))
+    }
+    
+    /// Write `palette_rgb` padded to the `1 << table_bits` entries the header declares
+    fn write_global_color_table(&self, gif_bytes: &mut Vec<u8>, palette_rgb: &[u8], table_bits: u8) -> Result<(), GifPipeError> {
+        let start = gif_bytes.len();
+        gif_bytes.extend_from_slice(palette_rgb);
+        
+        let colors_written = palette_rgb.len() / 3;
+        let table_size = 1usize << table_bits;
+        if colors_written < table_size {
+            let padding = vec![0u8; (table_size - colors_written) * 3];
+            gif_bytes.extend_from_slice(&padding);
+        }
+        
+        self.emit_block(GifBlock::GlobalColorTable, gif_bytes, start);
+        Ok(())
+    }
+
+    fn write_graphic_control(
+        &self,
+        gif_bytes: &mut Vec<u8>,
+        disposal: DisposalMethod,
+        delay_cs: u16,
+        transparent_index: Option<u8>,
+    ) -> Result<(), GifPipeError> {
+        let start = gif_bytes.len();
+        gif_bytes.extend_from_slice(&[0x21, 0xF9, 0x04]); // Extension + label + block size
+        gif_bytes.push(disposal.code() << 2 | transparent_index.is_some() as u8); // Disposal method + transparency flag
+        gif_bytes.extend_from_slice(&delay_cs.to_le_bytes());
+        gif_bytes.push(transparent_index.unwrap_or(0)); // Transparent color index
+        gif_bytes.push(0); // Block terminator
+        self.emit_block(GifBlock::GraphicControl, gif_bytes, start);
+        Ok(())
+    }
+
+    /// Delta frames draw over frame 0, so it must stay opaque and on screen
+    fn validate_keyframe(
+        &self,
+        disposals: Option<&[DisposalMethod]>,
+        transparent_indices: Option<&[Option<u8>]>,
+    ) -> Result<(), GifPipeError> {
+        let default = [self.default_disposal()];
+        let disposals = disposals.unwrap_or(&default);
+        if let Some(idx) = disposals.iter().position(|&d| !matches!(d, DisposalMethod::Keep | DisposalMethod::Unspecified)) {
+            return Err(GifPipeError::ValidationFailed {
+                message: format!("Delta frames need Keep disposal, frame {} uses {:?}", idx, disposals[idx]),
+            });
+        }
+        if transparent_indices.is_some_and(|indices| indices.first().copied().flatten().is_some()) {
+            return Err(GifPipeError::ValidationFailed {
+                message: "Delta keyframe 0 must be opaque, but it has transparent pixels".to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Frames that index one color although their source spread says otherwise
+    ///
+    /// Empty unless `with_uniform_frame_sentinel` is set.
+    fn uniform_frame_warnings(&self, indexed_frames: &[Vec<u8>]) -> Result<Vec<String>, GifPipeError> {
+        let Some((source_spread, min_spread)) = &self.source_spread else {
+            return Ok(Vec::new());
+        };
+        if source_spread.len() != indexed_frames.len() {
+            return Err(GifPipeError::ValidationFailed {
+                message: format!("Got {} source spreads for {} frames", source_spread.len(), indexed_frames.len()),
+            });
+        }
+
+        Ok(indexed_frames
+            .iter()
+            .zip(source_spread)
+            .enumerate()
+            .filter_map(|(idx, (frame, &spread))| {
+                let &first = frame.first()?;
+                (spread > *min_spread && frame.iter().all(|&i| i == first)).then(|| {
+                    format!(
+                        "Frame {} is entirely palette index {} but its source spread is {:.1}",
+                        idx, first, spread
+                    )
+                })
+            })
+            .collect())
+    }
+
+    /// Check per-frame palettes line up with the frames and cover their indices
+    ///
+    /// `None` entries are frames that keep indexing the global palette.
+    fn validate_frame_palettes(&self, indexed_frames: &[Vec<u8>], frame_palettes: &[Option<&[u8]>]) -> Result<(), GifPipeError> {
+        if frame_palettes.len() != indexed_frames.len() {
+            return Err(GifPipeError::ValidationFailed {
+                message: format!("Got {} local palettes for {} frames", frame_palettes.len(), indexed_frames.len()),
+            });
+        }
+        
+        for (idx, (frame, palette)) in indexed_frames.iter().zip(frame_palettes).enumerate() {
+            let Some(palette) = palette else {
+                continue;
+            };
+            let colors = palette.len() / 3;
+            if palette.len() % 3 != 0 || colors == 0 || colors > 256 {
+                return Err(GifPipeError::ValidationFailed {
+                    message: format!("Frame {} local palette has {} bytes, expected 1-256 RGB colors", idx, palette.len()),
+                });
+            }
+            if let Some(&index) = frame.iter().find(|&&index| index as usize >= colors) {
+                return Err(GifPipeError::ValidationFailed {
+                    message: format!("Frame {} references index {} beyond its {}-color local palette", idx, index, colors),
+                });
+            }
+        }
+        
+        Ok(())
+    }
+
+    /// Write an image descriptor, followed by the padded local color table when given
+    fn write_image_descriptor(
+        &self,
+        gif_bytes: &mut Vec<u8>,
+        left: u32,
+        top: u32,
+        width: u32,
+        height: u32,
+        local_palette: Option<&[u8]>,
+    ) -> Result<(), GifPipeError> {
+        let left = gif_dimension(left, "Frame left offset")?;
+        let top = gif_dimension(top, "Frame top offset")?;
+        let width = gif_dimension(width, "Frame width")?;
+        let height = gif_dimension(height, "Frame height")?;
+
+        let start = gif_bytes.len();
+        gif_bytes.push(0x2C); // Image separator
+        gif_bytes.extend_from_slice(&left.to_le_bytes());
+        gif_bytes.extend_from_slice(&top.to_le_bytes());
+        gif_bytes.extend_from_slice(&width.to_le_bytes());
+        gif_bytes.extend_from_slice(&height.to_le_bytes());
+        
+        match local_palette {
+            Some(palette) => {
+                let bits = color_table_bits(palette.len() / 3);
+                gif_bytes.push(0x80 | (bits - 1)); // Local color table flag + size
+                gif_bytes.extend_from_slice(palette);
+                gif_bytes.resize(gif_bytes.len() + ((1usize << bits) * 3 - palette.len()), 0);
+            }
+            None => gif_bytes.push(0x00), // No local color table
+        }
+        self.emit_block(GifBlock::ImageDescriptor, gif_bytes, start);
+        Ok(())
+    }
+
+    fn write_lzw_compressed_data(&self, gif_bytes: &mut Vec<u8>, frame_indices: &[u8], min_code_size: u8) -> Result<(), GifPipeError> {
+        let start = gif_bytes.len();
+        gif_bytes.push(min_code_size);
+        self.write_sub_blocks(gif_bytes, &lzw_encode(frame_indices, min_code_size));
+        self.emit_block(GifBlock::ImageData, gif_bytes, start);
+        Ok(())
+    }
+
+    /// Signature and logical screen descriptor announcing a `1 << table_bits` entry global color table
+    fn write_gif89a_header(&self, output: &mut Vec<u8>, width: u32, height: u32, table_bits: u8) -> Result<(), GifPipeError> {
+        let width = gif_dimension(width, "Canvas width")?;
+        let height = gif_dimension(height, "Canvas height")?;
+
+        let start = output.len();
+        // GIF89a signature
+        output.extend_from_slice(b"GIF89a");
+
+        // Logical screen descriptor
+        output.extend_from_slice(&width.to_le_bytes());
+        output.extend_from_slice(&height.to_le_bytes());
+
+        // Global color table flag + 8-bit color resolution + table size
+        let packed = 0xF0 | (table_bits - 1);
+        output.push(packed);
+
+        output.push(0); // Background color index
+        output.push(self.screen_aspect_byte(width, height)?); // Pixel aspect ratio
+
+        self.emit_block(GifBlock::Header, output, start);
+        Ok(())
+    }
 
     fn write_netscape_loop(&self, output: &mut Vec<u8>) -> Result<(), GifPipeError> {
+        self.check_netscape_placement(output)?;
+        let start = output.len();
+
         // Application Extension
         output.push(0x21); // Extension introducer
         output.push(0xFF); // Application extension label
@@ -526,8 +1394,313 @@ impl Gif89aEncoder {
         output.extend_from_slice(&0u16.to_le_bytes()); // Loop count (0 = infinite)
         output.push(0x00); // Block terminator
         
+        self.emit_block(GifBlock::NetscapeLoop, output, start);
         Ok(())
     }
+
+    /// Refuse to write the NETSCAPE block anywhere but after the global color
+    /// table and before the first image descriptor; decoders ignore it otherwise
+    fn check_netscape_placement(&self, output: &[u8]) -> Result<(), GifPipeError> {
+        if output.len() < 13 {
+            return Err(GifPipeError::EncodingFailed {
+                message: "NETSCAPE2.0 block must follow the logical screen descriptor".to_string(),
+            });
+        }
+
+        let packed = output[10];
+        let gct_end = if packed & 0x80 != 0 {
+            13 + 3 * (1usize << ((packed & 0x07) + 1))
+        } else {
+            13
+        };
+        if output.len() < gct_end {
+            return Err(GifPipeError::EncodingFailed {
+                message: "NETSCAPE2.0 block must follow the global color table".to_string(),
+            });
+        }
+
+        // Only extensions may sit between the color table and the NETSCAPE block
+        let mut pos = gct_end;
+        while pos < output.len() {
+            match output[pos] {
+                0x21 => {
+                    pos += 2; // Introducer + label
+                    while pos < output.len() && output[pos] != 0 {
+                        pos += output[pos] as usize + 1;
+                    }
+                    pos += 1; // Block terminator
+                }
+                0x2C => {
+                    return Err(GifPipeError::EncodingFailed {
+                        message: "NETSCAPE2.0 block must precede the first image descriptor".to_string(),
+                    });
+                }
+                other => {
+                    return Err(GifPipeError::EncodingFailed {
+                        message: format!("Unexpected block 0x{:02X} before NETSCAPE2.0 block", other),
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Bounding box `(x, y, width, height)` of pixels that differ between two frames
+fn changed_rect(previous: &[[u8; 3]], current: &[[u8; 3]], width: usize) -> Option<(usize, usize, usize, usize)> {
+    let mut bounds: Option<(usize, usize, usize, usize)> = None;
+    for (i, _) in previous.iter().zip(current).enumerate().filter(|(_, (a, b))| a != b) {
+        let (x, y) = (i % width, i / width);
+        bounds = Some(match bounds {
+            None => (x, y, x, y),
+            Some((x0, y0, x1, y1)) => (x0.min(x), y0.min(y), x1.max(x), y1.max(y)),
+        });
+    }
+    bounds.map(|(x0, y0, x1, y1)| (x0, y0, x1 - x0 + 1, y1 - y0 + 1))
+}
+
+/// Overwrite the outer ring of an indexed frame with `border`'s (width, color index)
+fn paint_border(frame_indices: &mut [u8], width: usize, height: usize, border: Option<(u16, u8)>) {
+    let Some((width_px, color_index)) = border else {
+        return;
+    };
+    let border = width_px as usize;
+
+    for (y, row) in frame_indices.chunks_exact_mut(width).take(height).enumerate() {
+        if y < border || y + border >= height {
+            row.fill(color_index);
+        } else {
+            let edge = border.min(width);
+            row[..edge].fill(color_index);
+            row[width - edge..].fill(color_index);
+        }
+    }
+}
+
+/// Number of images in a GIF, as counted by the `gif` decoder
+///
+/// Scanning for `0x2C` bytes overcounts, since palettes and LZW data contain
+/// them too. Returns `None` when the decoder can't read the header or a block.
+pub fn count_gif_frames(gif_bytes: &[u8]) -> Option<u32> {
+    let mut decoder = gif::DecodeOptions::new().read_info(gif_bytes).ok()?;
+    let mut frames = 0;
+    while decoder.next_frame_info().ok()?.is_some() {
+        frames += 1;
+    }
+    Some(frames)
+}
+
+/// How many pixels across all frames use each palette index
+fn build_histogram(frames: &[Vec<u8>]) -> [u64; 256] {
+    let mut histogram = [0u64; 256];
+    for &index in frames.iter().flatten() {
+        histogram[index as usize] += 1;
+    }
+    histogram
+}
+
+/// Palette holding only the colors some frame (or `keep`) uses, in their original order
+///
+/// Returns the compacted palette, the frames remapped to it and the old-to-new
+/// index lookup. Indices past the end of `palette_rgb` read the black padding a
+/// full table would hold, so they keep showing black.
+fn prune_palette(palette_rgb: &[u8], frames: &[Vec<u8>], keep: Option<u8>) -> (PalettedFrames, [u8; 256]) {
+    let mut histogram = build_histogram(frames);
+    if let Some(index) = keep {
+        histogram[index as usize] += 1;
+    }
+
+    let mut palette = Vec::new();
+    let mut remap = [0u8; 256];
+    for (index, _) in histogram.iter().enumerate().filter(|(_, &count)| count > 0) {
+        remap[index] = (palette.len() / 3) as u8;
+        palette.extend_from_slice(palette_rgb.get(index * 3..index * 3 + 3).unwrap_or(&[0, 0, 0]));
+    }
+
+    let frames = frames
+        .iter()
+        .map(|frame| frame.iter().map(|&index| remap[index as usize]).collect())
+        .collect();
+    ((palette, frames), remap)
+}
+
+/// Bits of the smallest power-of-two color table holding `colors` (at least 1)
+fn color_table_bits(colors: usize) -> u8 {
+    let mut bits = 1;
+    while (1usize << bits) < colors {
+        bits += 1;
+    }
+    bits
+}
+
+/// Narrow a dimension or offset to the u16 that GIF descriptors store
+fn gif_dimension(value: u32, what: &str) -> Result<u16, GifPipeError> {
+    u16::try_from(value).map_err(|_| GifPipeError::ValidationFailed {
+        message: format!("{} {} exceeds the GIF limit of {}", what, value, u16::MAX),
+    })
+}
+
+/// Mean per-channel standard deviation of an RGB frame, in 0-255 units
+///
+/// Cheap source statistic for `with_uniform_frame_sentinel`: 0 for a solid
+/// frame, tens for anything with visible content.
+pub fn frame_spread(frame_rgb: &[u8]) -> f32 {
+    let pixels = frame_rgb.len() / 3;
+    if pixels == 0 {
+        return 0.0;
+    }
+    let mut sum = [0.0f64; 3];
+    let mut sum_sq = [0.0f64; 3];
+    for pixel in frame_rgb.chunks_exact(3) {
+        for channel in 0..3 {
+            let value = pixel[channel] as f64;
+            sum[channel] += value;
+            sum_sq[channel] += value * value;
+        }
+    }
+    let std_dev: f64 = (0..3)
+        .map(|channel| {
+            let mean = sum[channel] / pixels as f64;
+            (sum_sq[channel] / pixels as f64 - mean * mean).max(0.0).sqrt()
+        })
+        .sum();
+    (std_dev / 3.0) as f32
+}
+
+/// Otsu's method on the alpha channel of RGBA frames
+///
+/// Returns the first alpha value of the opaque class, placed in the middle of
+/// the valley between the transparent and opaque populations. Returns `None`
+/// when every pixel shares one alpha value and there is nothing to split.
+pub fn otsu_alpha_threshold(frames_rgba: &[Vec<u8>]) -> Option<u8> {
+    let mut histogram = [0u64; 256];
+    for frame in frames_rgba {
+        for pixel in frame.chunks_exact(4) {
+            histogram[pixel[3] as usize] += 1;
+        }
+    }
+
+    let total: u64 = histogram.iter().sum();
+    if total == 0 {
+        return None;
+    }
+    let total_sum: f64 = histogram.iter().enumerate().map(|(a, &n)| a as f64 * n as f64).sum();
+
+    let mut weight_low = 0u64;
+    let mut sum_low = 0.0f64;
+    let mut best_variance = 0.0f64;
+    let mut best_range: Option<(usize, usize)> = None;
+
+    // Split is between `t` and `t + 1`; equal variances form a plateau across the gap
+    for (t, &count) in histogram.iter().enumerate().take(255) {
+        weight_low += count;
+        sum_low += t as f64 * count as f64;
+
+        let weight_high = total - weight_low;
+        if weight_low == 0 || weight_high == 0 {
+            continue;
+        }
+
+        let mean_low = sum_low / weight_low as f64;
+        let mean_high = (total_sum - sum_low) / weight_high as f64;
+        let variance = weight_low as f64 * weight_high as f64 * (mean_low - mean_high).powi(2);
+
+        if variance > best_variance * (1.0 + 1e-9) {
+            best_variance = variance;
+            best_range = Some((t, t));
+        } else if (variance - best_variance).abs() <= best_variance * 1e-9 {
+            if let Some((start, _)) = best_range {
+                best_range = Some((start, t));
+            }
+        }
+    }
+
+    best_range.map(|(start, end)| ((start + end) / 2 + 1) as u8)
+}
+
+/// Writer that optionally feeds every written chunk into a SHA-256 hasher
+///
+/// Produces the output and its digest in one pass instead of re-reading it.
+pub struct HashingWriter<W: Write> {
+    inner: W,
+    hasher: Option<sha2::Sha256>,
+}
+
+impl<W: Write> HashingWriter<W> {
+    pub fn new(inner: W, hash: bool) -> Self {
+        use sha2::Digest;
+        Self { inner, hasher: hash.then(sha2::Sha256::new) }
+    }
+
+    /// Return the inner writer and the hex digest of everything written, if hashing
+    pub fn finish(self) -> (W, Option<String>) {
+        use sha2::Digest;
+        (self.inner, self.hasher.map(|hasher| format!("{:x}", hasher.finalize())))
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        use sha2::Digest;
+        let written = self.inner.write(buf)?;
+        if let Some(hasher) = &mut self.hasher {
+            hasher.update(&buf[..written]);
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Hex SHA-256 of encoded GIF bytes, the format of golden hash files
+pub fn gif_sha256_hex(gif_bytes: &[u8]) -> String {
+    use sha2::{Digest, Sha256};
+    format!("{:x}", Sha256::digest(gif_bytes))
+}
+
+/// Encode `cube` twice in-process and report whether the outputs match
+pub fn deterministic_encode_check(cube: &QuantizedCubeData) -> bool {
+    deterministic_encode_check_with_golden(cube, None::<&Path>)
+}
+
+/// Like `deterministic_encode_check`, also comparing against a golden hash file
+///
+/// The file holds the hex SHA-256 written by `gif_sha256_hex`. A missing file
+/// is skipped, so CI can pin a hash recorded on one platform and catch
+/// float-ordering drift on another.
+pub fn deterministic_encode_check_with_golden(
+    cube: &QuantizedCubeData,
+    golden_hash_path: Option<impl AsRef<Path>>,
+) -> bool {
+    let encoder = Gif89aEncoder::new();
+    let (first, second) = match (
+        encoder.encode_from_cube_data(cube, 4, true),
+        encoder.encode_from_cube_data(cube, 4, true),
+    ) {
+        (Ok(first), Ok(second)) => (first, second),
+        (Err(e), _) | (_, Err(e)) => {
+            warn!(stage = "M3", error = %e, "Determinism check could not encode cube");
+            return false;
+        }
+    };
+
+    if first != second {
+        warn!(stage = "M3", "Two in-process encodes of the same cube differ");
+        return false;
+    }
+
+    let Some(golden) = golden_hash_path.as_ref().and_then(|path| std::fs::read_to_string(path).ok()) else {
+        return true;
+    };
+    let actual = gif_sha256_hex(&first);
+    if golden.trim() != actual {
+        warn!(stage = "M3", expected = golden.trim(), actual = %actual, "Encode differs from golden hash");
+        return false;
+    }
+    true
 }
 
 #[cfg(test)]
@@ -535,6 +1708,24 @@ mod tests {
     use super::*;
     use common_types::{FRAME_SIZE_81, QuantizedSet};
 
+    /// 81x81 cube over `frames` and `palette`, 4cs per frame, with no metrics or metadata
+    pub(crate) fn test_cube(frames: Vec<Vec<u8>>, palette: Vec<u8>) -> QuantizedCubeData {
+        QuantizedCubeData {
+            width: 81,
+            height: 81,
+            global_palette_rgb: palette,
+            delays_cs: vec![4; frames.len()],
+            indexed_frames: frames,
+            palette_stability: 1.0,
+            mean_delta_e: 0.0,
+            p95_delta_e: 0.0,
+            attention_maps: None,
+            frame_checksums: None,
+            source_frame_ids: None,
+            schema_version: common_types::CUBE_SCHEMA_VERSION,
+        }
+    }
+
     #[test]
     fn test_encoder_creation() {
         let encoder = Gif89aEncoder::new();
@@ -552,6 +1743,716 @@ mod tests {
         assert_eq!(encoder.transparency_threshold, 128);
     }
 
+    #[test]
+    fn test_auto_transparency_threshold_bimodal() {
+        // Transparent population around alpha 20, opaque population around 230
+        let frames: Vec<Vec<u8>> = (0..3)
+            .map(|frame_idx| {
+                (0..81 * 81)
+                    .flat_map(|i| {
+                        let jitter = ((i + frame_idx) % 11) as u8;
+                        let alpha = if i % 3 == 0 { 15 + jitter } else { 225 + jitter };
+                        [128, 128, 128, alpha]
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let threshold = otsu_alpha_threshold(&frames).unwrap();
+        assert!(
+            threshold > 25 && threshold <= 225,
+            "Threshold {} should fall in the gap between the alpha modes",
+            threshold
+        );
+
+        let auto = Gif89aEncoder::new().with_auto_transparency(true);
+        assert_eq!(auto.transparency_threshold_for(&frames), threshold);
+
+        let fixed = Gif89aEncoder::new();
+        assert_eq!(fixed.transparency_threshold_for(&frames), 254);
+
+        // Fully opaque input has nothing to split and keeps the fixed threshold
+        let opaque = vec![vec![255u8; 81 * 81 * 4]];
+        assert_eq!(otsu_alpha_threshold(&opaque), None);
+        assert_eq!(auto.transparency_threshold_for(&opaque), 254);
+    }
+
+    #[test]
+    fn test_netscape_placement_guard() {
+        let encoder = Gif89aEncoder::new();
+
+        // Header without the color table yet
+        let mut early = Vec::new();
+        encoder.write_gif89a_header(&mut early, 81, 81, 8).unwrap();
+        assert!(encoder.write_netscape_loop(&mut early).is_err());
+
+        // After an image descriptor
+        let mut late = Vec::new();
+        encoder.write_gif89a_header(&mut late, 81, 81, 8).unwrap();
+        encoder.write_global_color_table(&mut late, &[0, 0, 0], 8).unwrap();
+        encoder.write_image_descriptor(&mut late, 0, 0, 81, 81, None).unwrap();
+        assert!(encoder.write_netscape_loop(&mut late).is_err());
+
+        // Between the color table and the first image
+        let mut ok = Vec::new();
+        encoder.write_gif89a_header(&mut ok, 81, 81, 8).unwrap();
+        encoder.write_global_color_table(&mut ok, &[0, 0, 0], 8).unwrap();
+        assert!(encoder.write_netscape_loop(&mut ok).is_ok());
+    }
+
+    #[test]
+    fn test_reference_palette_order() {
+        let palette: Vec<u8> = vec![255, 0, 0, 0, 255, 0, 0, 0, 255, 255, 255, 255];
+        let frames: Vec<Vec<u8>> = (0..81)
+            .map(|f| (0..81 * 81).map(|i| ((i + f) % 4) as u8).collect())
+            .collect();
+        let cube = test_cube(frames.clone(), palette.clone());
+        
+        // White, blue, green, red: the reverse of the cube's order
+        let reference: Vec<u8> = vec![255, 255, 255, 0, 0, 255, 0, 255, 0, 255, 0, 0];
+        let encoder = Gif89aEncoder::new().with_reference_palette(reference.clone());
+        
+        let gif = encoder.encode_from_cube_data(&cube, 4, true).unwrap();
+        assert_eq!(&gif[13..13 + reference.len()], reference.as_slice());
+        
+        // Every pixel still resolves to its original color
+        let (reordered_palette, reordered_frames) =
+            encoder.reorder_to_reference(&palette, &frames).unwrap().unwrap();
+        for (original, reordered) in frames.iter().zip(&reordered_frames) {
+            for (&old, &new) in original.iter().zip(reordered) {
+                assert_eq!(
+                    &reordered_palette[new as usize * 3..new as usize * 3 + 3],
+                    &palette[old as usize * 3..old as usize * 3 + 3]
+                );
+            }
+        }
+        
+        // Colors missing from the reference are appended after it
+        let partial = Gif89aEncoder::new().with_reference_palette(vec![0, 0, 255]);
+        let (partial_palette, _) = partial.reorder_to_reference(&palette, &frames).unwrap().unwrap();
+        assert_eq!(partial_palette, vec![0, 0, 255, 255, 0, 0, 0, 255, 0, 255, 255, 255]);
+        
+        assert!(Gif89aEncoder::new().reorder_to_reference(&palette, &frames).unwrap().is_none());
+    }
+
+    /// Palette indices of each frame, decoded with the `gif` crate
+    fn stored_frame_indices(gif: &[u8]) -> Vec<Vec<u8>> {
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(gif).unwrap();
+
+        let mut frames = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            frames.push(frame.buffer.to_vec());
+        }
+        frames
+    }
+
+    #[test]
+    fn test_border_marks_outer_ring() {
+        let cube = test_cube(vec![vec![1u8; 81 * 81]; 81], vec![0, 0, 0, 255, 255, 255, 255, 0, 0]);
+
+        let gif = Gif89aEncoder::new()
+            .with_border(2, 2)
+            .encode_from_cube_data(&cube, 4, true)
+            .unwrap();
+
+        let frames = stored_frame_indices(&gif);
+        assert_eq!(frames.len(), 81);
+        for frame in &frames {
+            for y in 0..81 {
+                for x in 0..81 {
+                    let on_border = x < 2 || y < 2 || x >= 79 || y >= 79;
+                    assert_eq!(frame[y * 81 + x], if on_border { 2 } else { 1 }, "pixel ({}, {})", x, y);
+                }
+            }
+        }
+
+        // The cube itself is left untouched and out-of-palette colors are rejected
+        assert!(cube.indexed_frames[0].iter().all(|&i| i == 1));
+        assert!(Gif89aEncoder::new().with_border(2, 3).encode_from_cube_data(&cube, 4, true).is_err());
+    }
+
+    #[test]
+    fn test_per_frame_disposals() {
+        let cube = QuantizedCubeData {
+            delays_cs: (0..81).map(|i| 4 + (i % 3) as u8).collect(),
+            ..test_cube(vec![vec![1u8; 81 * 81]; 81], vec![0, 0, 0, 255, 255, 255])
+        };
+        // Keyframe every 9 frames, overlays in between, one restore-previous
+        let disposals: Vec<DisposalMethod> = (0..81)
+            .map(|i| match i {
+                40 => DisposalMethod::RestorePrevious,
+                i if i % 9 == 0 => DisposalMethod::RestoreBackground,
+                _ => DisposalMethod::Keep,
+            })
+            .collect();
+
+        let gif = Gif89aEncoder::new()
+            .encode_from_cube_data_with_disposals(&cube, 4, true, Some(&disposals))
+            .unwrap();
+
+        // Each GCE's disposal and delay
+        let gces = graphic_control_blocks(&gif);
+        assert_eq!(gces.len(), 81);
+        for (idx, &(packed, delay, _)) in gces.iter().enumerate() {
+            assert_eq!((packed >> 2) & 0x07, disposals[idx].code(), "frame {}", idx);
+            assert_eq!(delay, cube.delays_cs[idx] as u16, "frame {}", idx);
+        }
+        assert_eq!(stored_frame_indices(&gif).len(), 81);
+
+        // Without disposals the output is unchanged, and a short vector is rejected
+        assert_eq!(
+            Gif89aEncoder::new().encode_from_cube_data_with_disposals(&cube, 4, true, None).unwrap(),
+            Gif89aEncoder::new().encode_from_cube_data(&cube, 4, true).unwrap()
+        );
+        assert!(Gif89aEncoder::new()
+            .encode_from_cube_data_with_disposals(&cube, 4, true, Some(&disposals[..80]))
+            .is_err());
+    }
+
+    #[test]
+    fn test_sub_block_size_64_decodes_identically() {
+        let cube = test_cube(
+            (0..81u32).map(|f| (0..81 * 81u32).map(|i| ((i + f) % 3) as u8).collect()).collect(),
+            vec![0, 0, 0, 255, 255, 255, 255, 0, 0],
+        );
+
+        let default_gif = Gif89aEncoder::new().encode_from_cube_data(&cube, 4, true).unwrap();
+        let small_gif = Gif89aEncoder::new()
+            .with_sub_block_size(64)
+            .encode_from_cube_data(&cube, 4, true)
+            .unwrap();
+
+        assert_ne!(default_gif, small_gif);
+        assert_eq!(stored_frame_indices(&small_gif), stored_frame_indices(&default_gif));
+        assert_eq!(stored_frame_indices(&small_gif), cube.indexed_frames);
+
+        // Every image data sub-block respects the limit
+        for (start, sub_blocks) in gif_blocks(&small_gif) {
+            if small_gif[start] == 0x2C {
+                assert!(sub_blocks.iter().all(|block| block.len() <= 64));
+            }
+        }
+    }
+
+    #[test]
+    fn test_local_palettes_written_per_frame() {
+        // Two disjoint 256-color palettes, alternating between frames
+        let reds: Vec<u8> = (0..=255u8).flat_map(|i| [i, 0, 0]).collect();
+        let blues: Vec<u8> = (0..=255u8).flat_map(|i| [0, 0, i]).collect();
+        let frame_palettes: Vec<Vec<u8>> = (0..81).map(|f| if f % 2 == 0 { reds.clone() } else { blues.clone() }).collect();
+        let cube = test_cube(
+            (0..81usize).map(|f| (0..81 * 81).map(|i| ((i + f) % 256) as u8).collect()).collect(),
+            reds.clone(),
+        );
+
+        let encoder = Gif89aEncoder::new().with_local_palettes(true);
+        let gif = encoder.encode_from_cube_data_with_local_palettes(&cube, &frame_palettes, 4, true).unwrap();
+
+        // Global table is still present, and every descriptor carries a full 256-entry LCT
+        assert_eq!(gif[10] & 0x80, 0x80);
+        let mut tables = Vec::new();
+        for descriptor in image_descriptors(&gif) {
+            let packed = gif[descriptor + 9];
+            assert_eq!(packed & 0x80, 0x80, "LCT flag");
+            assert_eq!(packed & 0x07, 7, "LCT size field");
+            tables.push(gif[descriptor + 10..descriptor + 10 + 256 * 3].to_vec());
+            assert_eq!(gif[descriptor + 10 + 256 * 3], 8, "LZW minimum code size");
+        }
+        assert_eq!(tables, frame_palettes);
+        assert_eq!(stored_frame_indices(&gif), cube.indexed_frames);
+
+        // A small local palette is padded and shrinks the code size
+        let small: Vec<Vec<u8>> = vec![vec![10, 20, 30, 40, 50, 60, 70, 80, 90]; 81];
+        let mut small_cube = cube.clone();
+        small_cube.indexed_frames = vec![vec![2u8; 81 * 81]; 81];
+        let gif = encoder.encode_from_cube_data_with_local_palettes(&small_cube, &small, 4, true).unwrap();
+        let descriptor = image_descriptors(&gif)[0];
+        assert_eq!(gif[descriptor + 9], 0x81);
+        assert_eq!(&gif[descriptor + 10..descriptor + 22], &[10, 20, 30, 40, 50, 60, 70, 80, 90, 0, 0, 0]);
+        assert_eq!(gif[descriptor + 22], 2);
+        assert_eq!(stored_frame_indices(&gif), small_cube.indexed_frames);
+
+        // Disabled by default, and indices must fit their frame's palette
+        assert!(Gif89aEncoder::new()
+            .encode_from_cube_data_with_local_palettes(&cube, &frame_palettes, 4, true)
+            .is_err());
+        assert!(encoder.encode_from_cube_data_with_local_palettes(&cube, &small, 4, true).is_err());
+    }
+
+    /// Offset of every block between the global color table and the trailer, with its data sub-blocks
+    ///
+    /// Image blocks start at the descriptor's 0x2C, extensions at their 0x21.
+    fn gif_blocks(gif: &[u8]) -> Vec<(usize, Vec<&[u8]>)> {
+        let table_len = |packed: u8| if packed & 0x80 != 0 { 3 << ((packed & 0x07) + 1) } else { 0 };
+        let mut pos = 13 + table_len(gif[10]);
+        let mut blocks = Vec::new();
+        while gif[pos] != 0x3B {
+            let start = pos;
+            // Image descriptor, local color table and LZW code size, or introducer and label
+            pos += if gif[pos] == 0x2C { 11 + table_len(gif[pos + 9]) } else { 2 };
+            let mut sub_blocks = Vec::new();
+            while gif[pos] != 0 {
+                sub_blocks.push(&gif[pos + 1..pos + 1 + gif[pos] as usize]);
+                pos += gif[pos] as usize + 1;
+            }
+            pos += 1;
+            blocks.push((start, sub_blocks));
+        }
+        blocks
+    }
+
+    /// Offset of each image descriptor
+    fn image_descriptors(gif: &[u8]) -> Vec<usize> {
+        gif_blocks(gif).into_iter().map(|(start, _)| start).filter(|&start| gif[start] == 0x2C).collect()
+    }
+
+    /// (packed field, delay, transparent index) of each Graphic Control Extension
+    fn graphic_control_blocks(gif: &[u8]) -> Vec<(u8, u16, u8)> {
+        gif_blocks(gif)
+            .into_iter()
+            .filter(|&(start, _)| gif[start..start + 2] == [0x21, 0xF9])
+            .map(|(_, sub_blocks)| {
+                let body = sub_blocks[0];
+                assert_eq!(body.len(), 4);
+                (body[0], u16::from_le_bytes([body[1], body[2]]), body[3])
+            })
+            .collect()
+    }
+
+    /// (packed field, transparent index) of each Graphic Control Extension
+    fn graphic_controls(gif: &[u8]) -> Vec<(u8, u8)> {
+        graphic_control_blocks(gif).into_iter().map(|(packed, _, transparent)| (packed, transparent)).collect()
+    }
+
+    /// RGBA of each frame as composited by the `gif` crate
+    pub(crate) fn decoded_rgba(gif: &[u8]) -> Vec<Vec<u8>> {
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::RGBA);
+        let mut decoder = options.read_info(gif).unwrap();
+
+        let mut frames = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            frames.push(frame.buffer.to_vec());
+        }
+        frames
+    }
+
+    #[test]
+    fn test_transparency_sets_gce_index() {
+        let cube = test_cube(vec![vec![1u8; 81 * 81]; 81], vec![0, 0, 0, 255, 0, 0, 0, 255, 0, 0, 0, 255]);
+        // Even frames have a transparent left half, odd frames are opaque
+        let frames_rgba: Vec<Vec<u8>> = (0..81)
+            .map(|f| {
+                (0..81 * 81)
+                    .flat_map(|i| [255, 0, 0, if f % 2 == 0 && i % 81 < 40 { 0 } else { 255 }])
+                    .collect()
+            })
+            .collect();
+
+        let events = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = events.clone();
+        let gif = Gif89aEncoder::new()
+            .with_diagnostics(move |event| sink.lock().unwrap().push(event))
+            .encode_with_transparency(&cube, &frames_rgba, DisposalMethod::RestoreBackground, 4, true)
+            .unwrap();
+        // The reserved slot is written as part of the palette, not left as table padding
+        assert!(events.lock().unwrap().contains(&DiagEvent::PaletteChosen { frame: None, colors: 5 }));
+
+        // Logical screen descriptor declares the global table; the cube palette leads it
+        let table_entries = 2usize << (gif[10] & 0x07);
+        assert_eq!(gif[10] & 0x80, 0x80, "global color table flag");
+        let table_entry = |index: usize| &gif[13 + index * 3..13 + index * 3 + 3];
+        assert_eq!(table_entry(1), [255, 0, 0]);
+
+        let gces = graphic_controls(&gif);
+        assert_eq!(gces.len(), 81);
+        for (idx, &(packed, index)) in gces.iter().enumerate() {
+            assert_eq!((packed >> 2) & 0x07, DisposalMethod::RestoreBackground.code());
+            if idx % 2 == 0 {
+                assert_eq!(packed & 0x01, 1, "frame {} transparency flag", idx);
+                // First free slot past the 4-color palette, reserved as a black entry
+                assert_eq!(index, 4);
+                assert!((index as usize) < table_entries, "index {} past the {}-entry table", index, table_entries);
+                assert_eq!(table_entry(index as usize), [0, 0, 0]);
+            } else {
+                assert_eq!(packed & 0x01, 0, "frame {} transparency flag", idx);
+            }
+        }
+
+        let decoded = decoded_rgba(&gif);
+        for (i, px) in decoded[0].chunks_exact(4).enumerate() {
+            let expected = if i % 81 < 40 { [0, 0, 0, 0] } else { [255, 0, 0, 255] };
+            assert_eq!(px, expected, "pixel {}", i);
+        }
+        assert!(decoded[1].chunks_exact(4).all(|px| px == [255, 0, 0, 255]));
+    }
+
+    #[test]
+    fn test_transparency_frees_least_used_index_in_full_palette() {
+        // Gray-blue ramp, except index 6 which is pure red so 8 is 7's nearest neighbor
+        let palette: Vec<u8> = (0..=255u8).flat_map(|i| if i == 6 { [255, 0, 0] } else { [i, i, 255 - i] }).collect();
+        // Every index in use; index 7 appears only once
+        let frame: Vec<u8> = (0..81 * 81).map(|i| if i == 100 { 7 } else if i % 256 == 7 { 8 } else { (i % 256) as u8 }).collect();
+        let cube = test_cube(vec![frame; 81], palette.clone());
+        let frames_rgba: Vec<Vec<u8>> = vec![
+            (0..81 * 81).flat_map(|i| [0, 0, 0, if i >= 81 * 80 { 0 } else { 255 }]).collect();
+            81
+        ];
+
+        let gif = Gif89aEncoder::new()
+            .encode_with_transparency(&cube, &frames_rgba, DisposalMethod::Keep, 4, true)
+            .unwrap();
+
+        for &(packed, index) in &graphic_controls(&gif) {
+            assert_eq!(packed, DisposalMethod::Keep.code() << 2 | 1);
+            assert_eq!(index, 7);
+        }
+
+        // The freed pixel takes its nearest neighbor, and only the bottom row is transparent
+        let decoded = decoded_rgba(&gif);
+        assert_eq!(&decoded[0][100 * 4..101 * 4], &[8, 8, 247, 255]);
+        for (i, px) in decoded[0].chunks_exact(4).enumerate() {
+            assert_eq!(px[3] == 0, i >= 81 * 80, "pixel {}", i);
+        }
+
+        assert!(Gif89aEncoder::new()
+            .encode_with_transparency(&cube, &frames_rgba[..80], DisposalMethod::Keep, 4, true)
+            .is_err());
+    }
+
+    #[test]
+    fn test_delta_frames_start_with_full_keyframe() {
+        // A 4x4 square steps one pixel right each frame
+        let frames: Vec<Vec<u8>> = (0..81)
+            .map(|f| (0..81 * 81).map(|i| u8::from(i / 81 < 4 && i % 81 >= f && i % 81 < f + 4)).collect())
+            .collect();
+        let cube = test_cube(frames, vec![0, 0, 0, 255, 255, 255]);
+
+        let gif = Gif89aEncoder::new().with_delta_frames(true).encode_from_cube_data(&cube, 4, true).unwrap();
+
+        let gces = graphic_controls(&gif);
+        assert_eq!(gces.len(), 81);
+        assert_eq!(gces[0].0, DisposalMethod::Keep.code() << 2, "keyframe must be opaque");
+
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(gif.as_slice()).unwrap();
+        let mut canvas = vec![0u8; 81 * 81];
+        let mut idx = 0;
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            let (left, top, width, height) = (frame.left as usize, frame.top as usize, frame.width as usize, frame.height as usize);
+            if idx == 0 {
+                assert_eq!((left, top, width, height), (0, 0, 81, 81));
+            } else {
+                assert!(width * height < 81 * 81, "frame {} is {}x{}", idx, width, height);
+            }
+            for row in 0..height {
+                let start = (top + row) * 81 + left;
+                canvas[start..start + width].copy_from_slice(&frame.buffer[row * width..(row + 1) * width]);
+            }
+            assert_eq!(canvas, cube.indexed_frames[idx], "frame {}", idx);
+            idx += 1;
+        }
+        assert_eq!(idx, 81);
+
+        // The keyframe can be neither transparent nor cleared away
+        let encoder = Gif89aEncoder::new().with_delta_frames(true);
+        let frames_rgba: Vec<Vec<u8>> = vec![vec![0u8; 81 * 81 * 4]; 81];
+        assert!(encoder.encode_with_transparency(&cube, &frames_rgba, DisposalMethod::Keep, 4, true).is_err());
+        let opaque_rgba: Vec<Vec<u8>> = vec![vec![255u8; 81 * 81 * 4]; 81];
+        assert!(encoder.encode_with_transparency(&cube, &opaque_rgba, DisposalMethod::RestoreBackground, 4, true).is_err());
+    }
+
+    #[test]
+    fn test_delta_frames_shrink_corner_changes() {
+        // Busy static background; only the top-left 10x10 corner changes each frame
+        let frames: Vec<Vec<u8>> = (0..81u32)
+            .map(|f| {
+                (0..81 * 81u32)
+                    .map(|i| {
+                        let (x, y) = (i % 81, i / 81);
+                        if x < 10 && y < 10 { ((x + y + f) % 16) as u8 } else { (i.wrapping_mul(2654435761) >> 28) as u8 }
+                    })
+                    .collect()
+            })
+            .collect();
+        let cube = test_cube(frames, (0..16u8).flat_map(|i| [i * 16, 255 - i * 16, i * 8]).collect());
+
+        let full = Gif89aEncoder::new().encode_from_cube_data(&cube, 4, true).unwrap();
+        let delta = Gif89aEncoder::new().with_delta_frames(true).encode_from_cube_data(&cube, 4, true).unwrap();
+        assert!(delta.len() * 5 < full.len(), "delta {} bytes vs full {} bytes", delta.len(), full.len());
+
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(delta.as_slice()).unwrap();
+        let mut canvas = vec![0u8; 81 * 81];
+        let mut idx = 0;
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            let (left, top, width, height) = (frame.left as usize, frame.top as usize, frame.width as usize, frame.height as usize);
+            if idx > 0 {
+                assert!(left + width <= 10 && top + height <= 10, "frame {} rect {}x{}+{}+{}", idx, width, height, left, top);
+            }
+            for row in 0..height {
+                let start = (top + row) * 81 + left;
+                canvas[start..start + width].copy_from_slice(&frame.buffer[row * width..(row + 1) * width]);
+            }
+            assert_eq!(canvas, cube.indexed_frames[idx], "frame {}", idx);
+            idx += 1;
+        }
+        assert_eq!(idx, 81);
+    }
+
+    #[test]
+    fn test_auto_palette_strategy_follows_content() {
+        let palette: Vec<u8> = vec![0, 0, 0, 255, 255, 255, 255, 0, 0, 0, 255, 0, 0, 0, 255, 128, 128, 128];
+        let nearest = |px: &[u8]| -> u8 {
+            (0..palette.len() / 3)
+                .min_by_key(|&i| (0..3).map(|c| (palette[i * 3 + c] as i32 - px[c] as i32).pow(2)).sum::<i32>())
+                .unwrap() as u8
+        };
+        let cube_for = |source: &[Vec<u8>]| test_cube(
+            source.iter().map(|rgb| rgb.chunks_exact(3).map(nearest).collect()).collect(),
+            palette.clone(),
+        );
+        // Smooth ramps of one primary per scene, which six colors can't follow
+        let ramp = |channel: usize| -> Vec<u8> {
+            (0..81 * 81).flat_map(|i| {
+                let mut px = [0u8; 3];
+                px[channel] = (i % 81 * 3) as u8;
+                px
+            }).collect()
+        };
+        // Stripes of exact palette colors
+        let stripes: Vec<u8> = (0..81 * 81).flat_map(|i| palette[(i / 81 % 6) * 3..(i / 81 % 6) * 3 + 3].to_vec()).collect();
+
+        let scenes: Vec<Vec<u8>> = (0..81).map(|f| ramp(f / 27)).collect();
+        let auto = Gif89aEncoder::new().encode_auto_palette_strategy(&cube_for(&scenes), &scenes, 4, true).unwrap();
+        assert_eq!(auto.strategy, PaletteStrategy::Local);
+        assert_eq!(auto.local_frames, 81);
+        assert!(auto.encoded_delta_e < auto.global_delta_e);
+        for (decoded, source) in decoded_rgba(&auto.gif_data).iter().zip(&scenes) {
+            let rgb: Vec<u8> = decoded.chunks_exact(4).flat_map(|px| [px[0], px[1], px[2]]).collect();
+            assert_eq!(&rgb, source);
+        }
+
+        let uniform = vec![stripes.clone(); 81];
+        let cube = cube_for(&uniform);
+        let auto = Gif89aEncoder::new().encode_auto_palette_strategy(&cube, &uniform, 4, true).unwrap();
+        assert_eq!(auto.strategy, PaletteStrategy::Global);
+        assert_eq!(auto.gif_data, Gif89aEncoder::new().encode_from_cube_data(&cube, 4, true).unwrap());
+
+        let mixed: Vec<Vec<u8>> = (0..81).map(|f| if f < 71 { stripes.clone() } else { ramp(0) }).collect();
+        let auto = Gif89aEncoder::new().encode_auto_palette_strategy(&cube_for(&mixed), &mixed, 4, true).unwrap();
+        assert_eq!(auto.strategy, PaletteStrategy::Hybrid);
+        assert_eq!(auto.local_frames, 10);
+        assert!(Gif89aEncoder::new().encode_auto_palette_strategy(&cube, &mixed[..80], 4, true).is_err());
+    }
+
+    #[test]
+    fn test_diagnostics_report_every_frame() {
+        let cube = QuantizedCubeData {
+            delays_cs: vec![5; 81],
+            ..test_cube(
+                (0..81).map(|f| (0..81 * 81).map(|i| ((i / 81 + f) % 2) as u8).collect()).collect(),
+                vec![0, 0, 0, 255, 255, 255],
+            )
+        };
+        let events = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = events.clone();
+
+        let gif = Gif89aEncoder::new()
+            .with_diagnostics(move |event| sink.lock().unwrap().push(event))
+            .with_display_hint(DisplayHint::new(16, 9))
+            .encode_from_cube_data(&cube, 4, true)
+            .unwrap();
+        let events = events.lock().unwrap();
+
+        let frames: Vec<(usize, u16)> = events.iter()
+            .filter_map(|event| match *event {
+                DiagEvent::FrameEncoded { frame, delay_cs, .. } => Some((frame, delay_cs)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(frames, (0..81).map(|f| (f, 5)).collect::<Vec<_>>());
+        assert!(events.contains(&DiagEvent::PaletteChosen { frame: None, colors: 2 }));
+        let hint_blocks: Vec<usize> = events.iter()
+            .filter_map(|event| match *event {
+                DiagEvent::BlockWritten { block: GifBlock::DisplayHint, len, .. } => Some(len),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(hint_blocks, vec![20]);
+
+        // Blocks tile the whole file up to the trailer
+        let mut next_offset = 0;
+        for event in events.iter() {
+            if let DiagEvent::BlockWritten { offset, len, .. } = *event {
+                assert_eq!(offset, next_offset);
+                next_offset += len;
+            }
+        }
+        assert_eq!(next_offset, gif.len() - 1);
+    }
+
+    #[test]
+    fn test_cube_delays_written_per_frame() {
+        let mut cube = QuantizedCubeData {
+            delays_cs: (0..81).map(|f| 2 + (f % 7) as u8).collect(),
+            ..test_cube(vec![vec![1u8; 81 * 81]; 81], vec![0, 0, 0, 255, 255, 255])
+        };
+        let frame_delays = |gif: &[u8]| -> Vec<u16> {
+            let mut decoder = gif::DecodeOptions::new().read_info(gif).unwrap();
+            let mut delays = Vec::new();
+            while let Some(frame) = decoder.read_next_frame().unwrap() {
+                delays.push(frame.delay);
+            }
+            delays
+        };
+
+        let gif = Gif89aEncoder::new().encode_from_cube_data(&cube, 4, true).unwrap();
+        let expected: Vec<u16> = cube.delays_cs.iter().map(|&d| d as u16).collect();
+        assert_eq!(frame_delays(&gif), expected);
+
+        // fps_cs only stands in for a missing delay vector
+        cube.delays_cs.clear();
+        let gif = Gif89aEncoder::new().encode_from_cube_data(&cube, 5, true).unwrap();
+        assert_eq!(frame_delays(&gif), vec![5; 81]);
+
+        cube.delays_cs = vec![4; 80];
+        assert!(Gif89aEncoder::new().encode_from_cube_data(&cube, 4, true).is_err());
+    }
+
+    #[test]
+    fn test_cube_frame_count_is_flexible() {
+        // 0x2C entries make byte-scanning frame counts overcount
+        let cube_with = |frames: usize| QuantizedCubeData {
+            delays_cs: (0..frames).map(|f| 2 + (f % 5) as u8).collect(),
+            ..test_cube(
+                (0..frames).map(|f| (0..81 * 81).map(|i| ((i / 81 + f) % 2) as u8).collect()).collect(),
+                vec![0x2C, 0x2C, 0x2C, 255, 255, 255],
+            )
+        };
+
+        for frames in [1, 10, 200] {
+            let cube = cube_with(frames);
+            let gif = Gif89aEncoder::new().encode_from_cube_data(&cube, 4, true).unwrap();
+            assert_eq!(count_gif_frames(&gif), Some(frames as u32));
+
+            let mut decoder = gif::DecodeOptions::new().read_info(gif.as_slice()).unwrap();
+            let mut delays = Vec::new();
+            while let Some(frame) = decoder.read_next_frame().unwrap() {
+                delays.push(frame.delay as u8);
+            }
+            assert_eq!(delays, cube.delays_cs, "{} frames", frames);
+        }
+
+        assert!(Gif89aEncoder::new().encode_from_cube_data(&cube_with(0), 4, true).is_err());
+        assert!(Gif89aEncoder::new().encode_from_cube_data(&cube_with(MAX_FRAME_COUNT as usize + 1), 4, true).is_err());
+        assert_eq!(count_gif_frames(b"GIF89a"), None);
+    }
+
+    #[test]
+    fn test_oversized_dimensions_are_rejected() {
+        let encoder = Gif89aEncoder::new();
+        let expect_rejected = |result: Result<(), GifPipeError>, output: &[u8]| match result {
+            Err(GifPipeError::ValidationFailed { message }) => {
+                assert!(message.contains("70000"), "{}", message);
+                assert!(output.is_empty(), "nothing may be written before the check");
+            }
+            other => panic!("Expected a validation error, got {:?}", other),
+        };
+
+        assert_eq!(gif_dimension(65535, "Canvas width").unwrap(), u16::MAX);
+        expect_rejected(gif_dimension(70000, "Canvas width").map(drop), &[]);
+
+        let mut header = Vec::new();
+        expect_rejected(encoder.write_gif89a_header(&mut header, 70000, 81, 8), &header);
+        expect_rejected(encoder.write_gif89a_header(&mut header, 81, 70000, 8), &header);
+
+        let mut descriptor = Vec::new();
+        expect_rejected(encoder.write_image_descriptor(&mut descriptor, 0, 0, 70000, 81, None), &descriptor);
+        expect_rejected(encoder.write_image_descriptor(&mut descriptor, 70000, 0, 81, 81, None), &descriptor);
+
+        // Sizes that fit are written as-is, not truncated
+        encoder.write_gif89a_header(&mut header, 65535, 81, 8).unwrap();
+        assert_eq!(&header[6..10], &[0xFF, 0xFF, 81, 0]);
+    }
+
+    #[test]
+    fn test_disposal_setter_sets_gce_packed_byte() {
+        let cube = test_cube(
+            (0..81).map(|f| vec![(f % 2) as u8; 81 * 81]).collect(),
+            vec![0, 0, 0, 255, 255, 255],
+        );
+
+        for disposal in [DisposalMethod::Keep, DisposalMethod::RestoreBackground, DisposalMethod::RestorePrevious] {
+            let gif = Gif89aEncoder::new().with_disposal(disposal).encode_from_cube_data(&cube, 4, true).unwrap();
+            let gces = graphic_controls(&gif);
+            assert_eq!(gces.len(), 81);
+            assert!(gces.iter().all(|&(packed, _)| packed == disposal.code() << 2), "{:?}", disposal);
+        }
+
+        // Per-frame disposals still win over the encoder-wide one
+        let disposals = vec![DisposalMethod::RestorePrevious; 81];
+        let gif = Gif89aEncoder::new()
+            .with_disposal(DisposalMethod::Keep)
+            .encode_from_cube_data_with_disposals(&cube, 4, true, Some(&disposals))
+            .unwrap();
+        assert!(graphic_controls(&gif).iter().all(|&(packed, _)| packed == DisposalMethod::RestorePrevious.code() << 2));
+
+        // Delta frames draw over their predecessor, so they refuse other disposals
+        let encoder = Gif89aEncoder::new().with_delta_frames(true).with_disposal(DisposalMethod::RestoreBackground);
+        assert!(encoder.encode_from_cube_data(&cube, 4, true).is_err());
+
+        // The legacy path keeps restoring to background unless told otherwise
+        let frame_pixels = (FRAME_SIZE_81 * FRAME_SIZE_81) as usize;
+        let quantized_set = QuantizedSet {
+            frames_indices: vec![vec![0u8; frame_pixels]],
+            palette_rgb: vec![255, 0, 0, 0, 255, 0],
+            palette_stability: 1.0,
+            mean_perceptual_error: 0.0,
+            p95_perceptual_error: 0.0,
+            processing_time_ms: 0,
+            attention_maps: vec![vec![0.5f32; frame_pixels]],
+        };
+        for (encoder, disposal) in [
+            (Gif89aEncoder::new(), DisposalMethod::RestoreBackground),
+            (Gif89aEncoder::new().with_disposal(DisposalMethod::Keep), DisposalMethod::Keep),
+        ] {
+            let gif = encoder.encode_gif(quantized_set.clone()).unwrap().gif_data;
+            assert_eq!(graphic_controls(&gif)[0].0, disposal.code() << 2);
+        }
+    }
+
+    #[test]
+    fn test_palette_pruning_drops_unused_colors() {
+        // 256-color palette, but the frames only touch every 20th entry
+        let used: Vec<u8> = (0..10).map(|i| i * 20 + 7).collect();
+        let cube = test_cube(
+            (0..81).map(|f| (0..81 * 81).map(|i| used[(i / 81 + f) % 10]).collect()).collect(),
+            (0..=255u8).flat_map(|i| [i, 255 - i, i / 2]).collect(),
+        );
+
+        let ((palette, frames), remap) = prune_palette(&cube.global_palette_rgb, &cube.indexed_frames, None);
+        assert_eq!(palette.len() / 3, 10);
+        for (old, new) in used.iter().map(|&index| (index as usize, remap[index as usize] as usize)) {
+            assert_eq!(palette[new * 3..new * 3 + 3], cube.global_palette_rgb[old * 3..old * 3 + 3]);
+        }
+        assert!(frames.iter().flatten().all(|&index| index < 10));
+
+        let full = Gif89aEncoder::new().encode_from_cube_data(&cube, 4, true).unwrap();
+        let gif = Gif89aEncoder::new().with_palette_pruning(true).encode_from_cube_data(&cube, 4, true).unwrap();
+        assert!(gif.len() < full.len(), "{} vs {} bytes", gif.len(), full.len());
+
+        // 10 colors fit a 16-entry table with 4-bit codes
+        assert_eq!(gif[10] & 0x07, 3, "GCT size field");
+        let code_sizes: Vec<u8> = image_descriptors(&gif).into_iter().map(|descriptor| gif[descriptor + 10]).collect();
+        assert_eq!(code_sizes, vec![4; 81]);
+        assert_eq!(decoded_rgba(&gif), decoded_rgba(&full));
+    }
+
     #[test]
     fn test_color_bits_calculation() {
         let encoder = Gif89aEncoder::new();
@@ -587,6 +2488,29 @@ mod tests {
         assert!(result.gif_data.starts_with(b"GIF89a"));
     }
 
+    #[test]
+    fn test_size_fields_are_consistent() {
+        let encoder = Gif89aEncoder::new();
+        
+        let frame_pixels = (FRAME_SIZE_81 * FRAME_SIZE_81) as usize;
+        let quantized_set = QuantizedSet {
+            frames_indices: vec![vec![0u8; frame_pixels], vec![1u8; frame_pixels]],
+            palette_rgb: vec![255, 0, 0, 0, 255, 0],
+            palette_stability: 1.0,
+            mean_perceptual_error: 0.0,
+            p95_perceptual_error: 0.0,
+            processing_time_ms: 0,
+            attention_maps: vec![vec![0.5f32; frame_pixels]; 2],
+        };
+        
+        let info = encoder.encode_gif(quantized_set).unwrap();
+        
+        assert_eq!(info.uncompressed_size_bytes, (2 * frame_pixels * 3) as u64);
+        assert_eq!(info.file_size_bytes, info.gif_data.len() as u64);
+        let expected_ratio = info.uncompressed_size_bytes as f32 / info.file_size_bytes as f32;
+        assert!((info.compression_ratio - expected_ratio).abs() < 1e-6);
+    }
+
     #[test]
     fn test_validation_errors() {
         let encoder = Gif89aEncoder::new();
@@ -0,0 +1,164 @@
+use std::cmp::Reverse;
+
+use common_types::oklab::{delta_e_oklab, rgb_to_oklab};
+use common_types::{GifPipeError, QuantizedCubeData};
+use tracing::debug;
+
+use crate::{build_histogram, prune_palette};
+
+/// Highest `optimize_lossy` level; larger levels are clamped to it
+pub const MAX_LOSSY_LEVEL: u8 = 100;
+/// Oklab ΔE below which colors merge, per lossy level
+const LOSSY_DELTA_E_PER_LEVEL: f32 = 0.001;
+
+/// Cube after `optimize_lossy`, with the fidelity it gave up
+#[derive(Debug, Clone)]
+pub struct LossyOptimization {
+    pub cube: QuantizedCubeData,
+    /// Global palette entries left
+    pub palette_colors: usize,
+    /// Pixel-weighted mean Oklab ΔE between each pixel's old and new color
+    pub added_delta_e: f32,
+}
+
+/// Merge near-duplicate palette colors so the LZW stream sees fewer distinct indices
+///
+/// Level 0 returns the cube untouched. Otherwise colors closer than
+/// `level * 0.001` Oklab ΔE (level capped at `MAX_LOSSY_LEVEL`) are folded into
+/// the more used color, most used first, and the palette is compacted to the
+/// colors still referenced. Quality metrics are left as measured by M2.
+pub fn optimize_lossy(cube: &QuantizedCubeData, level: u8) -> Result<LossyOptimization, GifPipeError> {
+    let palette_rgb = &cube.global_palette_rgb;
+    if !palette_rgb.len().is_multiple_of(3) || palette_rgb.len() > 768 {
+        return Err(GifPipeError::ValidationFailed {
+            message: format!("Invalid palette size: {} bytes", palette_rgb.len()),
+        });
+    }
+    if level == 0 {
+        return Ok(LossyOptimization {
+            cube: cube.clone(),
+            palette_colors: palette_rgb.len() / 3,
+            added_delta_e: 0.0,
+        });
+    }
+    cube.verify_frame_checksums()?;
+
+    let threshold = level.min(MAX_LOSSY_LEVEL) as f32 * LOSSY_DELTA_E_PER_LEVEL;
+    let histogram = build_histogram(&cube.indexed_frames);
+    // Indices past the palette show the black padding of a full table
+    let colors: Vec<[f32; 3]> = (0..256)
+        .map(|index| {
+            let rgb = palette_rgb.get(index * 3..index * 3 + 3).unwrap_or(&[0, 0, 0]);
+            rgb_to_oklab(rgb[0], rgb[1], rgb[2])
+        })
+        .collect();
+
+    let mut used: Vec<usize> = (0..256).filter(|&index| histogram[index] > 0).collect();
+    used.sort_by_key(|&index| (Reverse(histogram[index]), index));
+
+    let mut remap: Vec<u8> = (0..=255).collect();
+    let mut kept: Vec<usize> = Vec::new();
+    let mut added_error = 0.0f64;
+    for index in used {
+        let nearest = kept
+            .iter()
+            .map(|&target| (target, delta_e_oklab(colors[index], colors[target])))
+            .filter(|&(_, delta_e)| delta_e < threshold)
+            .min_by(|a, b| a.1.total_cmp(&b.1));
+        match nearest {
+            Some((target, delta_e)) => {
+                remap[index] = target as u8;
+                added_error += delta_e as f64 * histogram[index] as f64;
+            }
+            None => kept.push(index),
+        }
+    }
+
+    let merged_frames: Vec<Vec<u8>> = cube
+        .indexed_frames
+        .iter()
+        .map(|frame| frame.iter().map(|&index| remap[index as usize]).collect())
+        .collect();
+    let ((palette, frames), _) = prune_palette(palette_rgb, &merged_frames, None);
+
+    let pixels: u64 = histogram.iter().sum();
+    let added_delta_e = if pixels == 0 { 0.0 } else { (added_error / pixels as f64) as f32 };
+    debug!(
+        stage = "M3",
+        level,
+        colors_before = palette_rgb.len() / 3,
+        colors_after = palette.len() / 3,
+        added_delta_e,
+        "Lossy palette merge"
+    );
+
+    let mut optimized = QuantizedCubeData {
+        global_palette_rgb: palette,
+        indexed_frames: frames,
+        frame_checksums: None,
+        ..cube.clone()
+    };
+    if cube.frame_checksums.is_some() {
+        optimized = optimized.with_frame_checksums();
+    }
+    Ok(LossyOptimization {
+        palette_colors: optimized.global_palette_rgb.len() / 3,
+        cube: optimized,
+        added_delta_e,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{decoded_rgba, test_cube};
+    use crate::Gif89aEncoder;
+
+    /// 16 base colors, each with three copies nudged by a couple of RGB steps
+    fn near_duplicate_cube() -> QuantizedCubeData {
+        let palette: Vec<u8> = (0..64u8)
+            .flat_map(|i| {
+                let (base, nudge) = (i / 4, i % 4);
+                [base * 16 + nudge, 255 - base * 16, 128u8.wrapping_add(base * 8) + nudge]
+            })
+            .collect();
+        test_cube(
+            (0..81).map(|f| (0..81 * 81).map(|i| ((i * 7 + f) % 64) as u8).collect()).collect(),
+            palette,
+        )
+    }
+
+    #[test]
+    fn test_lossy_level_zero_is_lossless() {
+        let cube = near_duplicate_cube();
+        let result = optimize_lossy(&cube, 0).unwrap();
+        assert_eq!(result.cube.global_palette_rgb, cube.global_palette_rgb);
+        assert_eq!(result.cube.indexed_frames, cube.indexed_frames);
+        assert_eq!(result.palette_colors, 64);
+        assert_eq!(result.added_delta_e, 0.0);
+    }
+
+    #[test]
+    fn test_lossy_merges_near_duplicates() {
+        let cube = near_duplicate_cube().with_frame_checksums();
+        let result = optimize_lossy(&cube, 20).unwrap();
+        assert_eq!(result.palette_colors, 16);
+        assert!(result.added_delta_e > 0.0 && result.added_delta_e < 0.01, "{}", result.added_delta_e);
+        assert!(result.cube.verify_frame_checksums().is_ok());
+
+        let encoder = Gif89aEncoder::new();
+        let original = decoded_rgba(&encoder.encode_from_cube_data(&cube, 4, true).unwrap()).concat();
+        let merged = decoded_rgba(&encoder.encode_from_cube_data(&result.cube, 4, true).unwrap()).concat();
+        assert_eq!(original.len(), merged.len());
+        let worst = original
+            .chunks_exact(4)
+            .zip(merged.chunks_exact(4))
+            .map(|(a, b)| delta_e_oklab(rgb_to_oklab(a[0], a[1], a[2]), rgb_to_oklab(b[0], b[1], b[2])))
+            .fold(0.0f32, f32::max);
+        assert!(worst < 0.01, "worst pixel ΔE {}", worst);
+
+        // More aggressive levels never keep more colors
+        let strongest = optimize_lossy(&cube, u8::MAX).unwrap();
+        assert!(strongest.palette_colors <= result.palette_colors);
+    }
+}
@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+
+/// GIF caps LZW codes at 12 bits, so the dictionary holds at most 4096 entries
+const MAX_CODE_BITS: u8 = 12;
+const MAX_DICTIONARY: u16 = 1 << MAX_CODE_BITS;
+
+/// Packs variable-width codes LSB-first, as GIF image data requires
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u32,
+    bits: u8,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        Self { bytes: Vec::new(), buffer: 0, bits: 0 }
+    }
+
+    fn write(&mut self, code: u16, width: u8) {
+        self.buffer |= (code as u32) << self.bits;
+        self.bits += width;
+        while self.bits >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        self.bytes
+    }
+}
+
+/// LZW-compress palette indices into a GIF code stream (without sub-blocks)
+///
+/// Codes start at `min_code_size + 1` bits and widen as the dictionary grows,
+/// up to 12 bits. When the dictionary fills, a clear code resets it. Every
+/// index must fit in `min_code_size` bits.
+pub(crate) fn lzw_encode(indices: &[u8], min_code_size: u8) -> Vec<u8> {
+    let clear_code: u16 = 1 << min_code_size;
+    let end_code = clear_code + 1;
+    let initial_width = min_code_size + 1;
+
+    let mut writer = BitWriter::new();
+    let mut dictionary: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next_code = end_code + 1;
+    let mut width = initial_width;
+
+    writer.write(clear_code, width);
+
+    let mut pixels = indices.iter().copied();
+    if let Some(first) = pixels.next() {
+        let mut prefix = first as u16;
+
+        for index in pixels {
+            if let Some(&code) = dictionary.get(&(prefix, index)) {
+                prefix = code;
+                continue;
+            }
+
+            writer.write(prefix, width);
+            dictionary.insert((prefix, index), next_code);
+            next_code += 1;
+
+            if next_code == MAX_DICTIONARY {
+                // Dictionary full: reset rather than keep coding with a frozen table
+                writer.write(clear_code, width);
+                dictionary.clear();
+                next_code = end_code + 1;
+                width = initial_width;
+            } else if next_code > (1 << width) {
+                // The decoder adds each entry one code later, so widen once it
+                // could see `next_code - 1`
+                width += 1;
+            }
+
+            prefix = index as u16;
+        }
+
+        writer.write(prefix, width);
+    }
+
+    writer.write(end_code, width);
+    writer.finish()
+}
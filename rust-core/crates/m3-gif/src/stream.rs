@@ -0,0 +1,130 @@
+use common_types::GifPipeError;
+use tracing::info;
+
+use crate::{DiagEvent, DisposalMethod, Gif89aEncoder};
+
+/// Frame-by-frame GIF89a encoder
+///
+/// Writes the header and global palette up front, then compresses each frame
+/// as it is pushed, so callers can drop frames as soon as M2 produces them
+/// instead of holding the whole cube. Only the compressed GIF is buffered.
+/// Every frame gets a `Keep` Graphic Control Extension carrying its delay.
+pub struct GifStreamEncoder {
+    encoder: Gif89aEncoder,
+    gif_bytes: Vec<u8>,
+    width: u32,
+    height: u32,
+    frames: usize,
+}
+
+impl GifStreamEncoder {
+    /// Start a GIF of `width`x`height` frames indexing `palette` (RGB, up to 256 colors)
+    pub fn begin(width: u32, height: u32, palette: &[u8], loop_forever: bool) -> Result<Self, GifPipeError> {
+        Self::begin_with(Gif89aEncoder::new(), width, height, palette, loop_forever)
+    }
+
+    /// Like `begin`, with an encoder configured for e.g. its sub-block size
+    pub fn begin_with(
+        encoder: Gif89aEncoder,
+        width: u32,
+        height: u32,
+        palette: &[u8],
+        loop_forever: bool,
+    ) -> Result<Self, GifPipeError> {
+        if !palette.len().is_multiple_of(3) || palette.len() > 768 {
+            return Err(GifPipeError::ValidationFailed {
+                message: "Invalid palette size".to_string(),
+            });
+        }
+
+        let mut gif_bytes = Vec::new();
+        encoder.write_gif89a_header(&mut gif_bytes, width, height, 8)?;
+        encoder.write_global_color_table(&mut gif_bytes, palette, 8)?;
+        encoder.emit(DiagEvent::PaletteChosen { frame: None, colors: palette.len() / 3 });
+        if loop_forever {
+            encoder.write_netscape_loop(&mut gif_bytes)?;
+        }
+        if let Some(hint) = &encoder.display_hint {
+            hint.write_extension(&mut gif_bytes);
+        }
+
+        Ok(Self { encoder, gif_bytes, width, height, frames: 0 })
+    }
+
+    /// Compress one frame of palette indices, shown for `delay_cs` centiseconds
+    pub fn push_frame(&mut self, indices: &[u8], delay_cs: u16) -> Result<(), GifPipeError> {
+        let expected = self.width as usize * self.height as usize;
+        if indices.len() != expected {
+            return Err(GifPipeError::FrameEncodingFailed {
+                frame_idx: self.frames as u32,
+                message: format!("Expected {} indices, got {}", expected, indices.len()),
+            });
+        }
+
+        let encoder = &self.encoder;
+        let frame_start = self.gif_bytes.len();
+        encoder.write_graphic_control(&mut self.gif_bytes, DisposalMethod::Keep, delay_cs, None)?;
+        encoder.write_image_descriptor(&mut self.gif_bytes, 0, 0, self.width, self.height, None)?;
+        // The padded global table always has 256 entries
+        encoder.write_lzw_compressed_data(&mut self.gif_bytes, indices, 8)?;
+        encoder.emit(DiagEvent::FrameEncoded {
+            frame: self.frames,
+            bytes: self.gif_bytes.len() - frame_start,
+            delay_cs,
+        });
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Write the trailer and return the finished GIF
+    pub fn finish(mut self) -> Result<Vec<u8>, GifPipeError> {
+        if self.frames == 0 {
+            return Err(GifPipeError::GifFinalizationFailed {
+                message: "No frames were pushed".to_string(),
+            });
+        }
+
+        self.gif_bytes.push(0x3B);
+        info!(stage = "M3", size_bytes = self.gif_bytes.len(), frames = self.frames, "Streamed GIF89a complete");
+        Ok(self.gif_bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common_types::QuantizedCubeData;
+    use crate::tests::test_cube;
+
+    #[test]
+    fn test_streamed_output_matches_batch() {
+        let palette: Vec<u8> = (0..32u8).flat_map(|i| [i * 8, 255 - i * 8, i * 3]).collect();
+        let cube = QuantizedCubeData {
+            delays_cs: (0..81).map(|f| 4 + (f % 3) as u8).collect(),
+            ..test_cube(
+                (0..81).map(|f| (0..81 * 81).map(|i| ((i / 81 + i % 81 + f) % 32) as u8).collect()).collect(),
+                palette.clone(),
+            )
+        };
+
+        let batch = Gif89aEncoder::new()
+            .encode_from_cube_data_with_disposals(&cube, 4, true, Some(&[DisposalMethod::Keep; 81]))
+            .unwrap();
+
+        let mut stream = GifStreamEncoder::begin(81, 81, &palette, true).unwrap();
+        for (frame, &delay_cs) in cube.indexed_frames.iter().zip(&cube.delays_cs) {
+            stream.push_frame(frame, delay_cs as u16).unwrap();
+        }
+        assert_eq!(stream.finish().unwrap(), batch);
+    }
+
+    #[test]
+    fn test_stream_rejects_bad_frames() {
+        let mut stream = GifStreamEncoder::begin(81, 81, &[0, 0, 0, 255, 255, 255], false).unwrap();
+        assert!(stream.push_frame(&[0u8; 80 * 81], 4).is_err());
+
+        let empty = GifStreamEncoder::begin(81, 81, &[0, 0, 0, 255, 255, 255], false).unwrap();
+        assert!(empty.finish().is_err());
+        assert!(GifStreamEncoder::begin(81, 81, &[0; 4], false).is_err());
+    }
+}
@@ -1,5 +1,6 @@
-use m3_gif::Gif89aEncoder;
-use common_types::{QuantizedCubeData, GifPipeError};
+use m3_gif::{deterministic_encode_check, deterministic_encode_check_with_golden, frame_spread, gif_sha256_hex, Gif89aEncoder, HashingWriter, UNIFORM_FRAME_MIN_SPREAD};
+use common_types::{QuantizedCubeData, GifPipeError, CUBE_SCHEMA_VERSION};
+use std::io::Write;
 
 #[test]
 fn test_encode_from_cube_data() {
@@ -58,6 +59,129 @@ fn test_netscape_loop_extension() {
     assert!(!contains_netscape_loop(&gif_no_loop), "Should not contain NETSCAPE2.0 loop");
 }
 
+#[test]
+fn test_netscape_block_placement() {
+    let cube_data = create_test_cube_data();
+    let encoder = Gif89aEncoder::new();
+    
+    let gif_bytes = encoder.encode_from_cube_data(&cube_data, 4, true).unwrap();
+    let (gct_end, blocks) = parse_block_sequence(&gif_bytes);
+    
+    let netscape = blocks.iter()
+        .position(|block| matches!(block, GifBlock::Application(id) if id == b"NETSCAPE2.0"))
+        .expect("Should contain NETSCAPE2.0 block");
+    let first_image = blocks.iter()
+        .position(|block| matches!(block, GifBlock::Image))
+        .expect("Should contain image descriptors");
+    
+    assert!(netscape < first_image, "NETSCAPE2.0 must precede the first image descriptor");
+    assert_eq!(
+        blocks.iter().filter(|block| matches!(block, GifBlock::Image)).count(),
+        81
+    );
+    
+    // Nothing but extensions between the global color table and NETSCAPE2.0
+    assert!(gct_end > 13, "Global color table should be present");
+    assert!(
+        blocks[..netscape].iter().all(|block| matches!(block, GifBlock::Extension)),
+        "NETSCAPE2.0 must directly follow the global color table"
+    );
+}
+
+#[test]
+fn test_corrupted_frame_checksum_rejected() {
+    let encoder = Gif89aEncoder::new();
+    let mut cube_data = create_test_cube_data().with_frame_checksums();
+    
+    assert!(encoder.encode_from_cube_data(&cube_data, 4, true).is_ok());
+    
+    // Flip one index after checksums were recorded
+    cube_data.indexed_frames[42][81 * 40 + 40] ^= 0x01;
+    let result = encoder.encode_from_cube_data(&cube_data, 4, true);
+    assert!(
+        matches!(result, Err(GifPipeError::ValidationFailed { ref message }) if message.contains("Frame 42")),
+        "Corrupted frame should be rejected before encoding"
+    );
+}
+
+#[test]
+fn test_encode_to_file_writes_atomically() {
+    let cube_data = create_test_cube_data();
+    let encoder = Gif89aEncoder::new();
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("cube.gif");
+    
+    let info = encoder.encode_to_file(&cube_data, &path, 4, true).unwrap();
+    
+    assert!(path.exists(), "GIF file should exist at the target path");
+    assert_eq!(info.file_path, path.to_string_lossy());
+    assert_eq!(info.frame_count, 81);
+    assert!(info.has_netscape_loop);
+    
+    let written = std::fs::read(&path).unwrap();
+    assert_eq!(written, info.gif_data);
+    assert_eq!(info.file_size_bytes, written.len() as u64);
+    validate_gif_structure(&written).unwrap();
+    
+    // Only the final file remains, no temp file left behind
+    let entries: Vec<_> = std::fs::read_dir(dir.path()).unwrap().collect();
+    assert_eq!(entries.len(), 1);
+}
+
+#[test]
+fn test_encode_to_file_hash_matches_written_bytes() {
+    let cube_data = create_test_cube_data();
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("cube.gif");
+    
+    let (info, digest) = Gif89aEncoder::new()
+        .encode_to_file_with_sha256(&cube_data, &path, 4, true)
+        .unwrap();
+    
+    let written = std::fs::read(&path).unwrap();
+    assert_eq!(digest, gif_sha256_hex(&written));
+    assert_eq!(digest, gif_sha256_hex(&info.gif_data));
+    
+    // Chunked writes hash the same as one-shot
+    let mut writer = HashingWriter::new(Vec::new(), true);
+    for chunk in written.chunks(100) {
+        writer.write_all(chunk).unwrap();
+    }
+    let (bytes, chunked_digest) = writer.finish();
+    assert_eq!(bytes, written);
+    assert_eq!(chunked_digest, Some(digest));
+    assert_eq!(HashingWriter::new(Vec::new(), false).finish().1, None);
+}
+
+#[test]
+fn test_uniform_frame_sentinel_flags_zeroed_frame() {
+    let mut cube_data = create_test_cube_data();
+    cube_data.indexed_frames[7] = vec![0; 81 * 81];
+    // Every source frame was a gradient; a solid black one would not be flagged
+    let gradient: Vec<u8> = (0..81 * 81).flat_map(|i| [(i % 81 * 3) as u8; 3]).collect();
+    let mut source_spread = vec![frame_spread(&gradient); 81];
+    source_spread[3] = frame_spread(&[0; 81 * 81 * 3]);
+    cube_data.indexed_frames[3] = vec![0; 81 * 81];
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("cube.gif");
+    
+    let info = Gif89aEncoder::new()
+        .with_uniform_frame_sentinel(source_spread.clone(), UNIFORM_FRAME_MIN_SPREAD)
+        .encode_to_file(&cube_data, &path, 4, true)
+        .unwrap();
+    assert_eq!(info.warnings.len(), 1, "{:?}", info.warnings);
+    assert!(info.warnings[0].contains("Frame 7"));
+    
+    // Off by default, and the spreads must cover every frame
+    let info = Gif89aEncoder::new().encode_to_file(&cube_data, &path, 4, true).unwrap();
+    assert!(info.warnings.is_empty());
+    source_spread.pop();
+    assert!(Gif89aEncoder::new()
+        .with_uniform_frame_sentinel(source_spread, UNIFORM_FRAME_MIN_SPREAD)
+        .encode_from_cube_data(&cube_data, 4, true)
+        .is_err());
+}
+
 #[test]
 fn test_frame_count_validation() {
     let cube_data = create_test_cube_data();
@@ -65,11 +189,52 @@ fn test_frame_count_validation() {
     
     let gif_bytes = encoder.encode_from_cube_data(&cube_data, 4, true).unwrap();
     
-    // Count image descriptors (0x2C) which indicate frames
-    let frame_count = gif_bytes.iter().filter(|&&b| b == 0x2C).count();
+    // Count image descriptors, which indicate frames
+    let (_, blocks) = parse_block_sequence(&gif_bytes);
+    let frame_count = blocks.iter().filter(|block| matches!(block, GifBlock::Image)).count();
     assert_eq!(frame_count, 81, "Should have exactly 81 frames");
 }
 
+#[test]
+fn test_cube_gif_decodes_to_indexed_frames() {
+    // Noise over all 256 entries fills the LZW dictionary and forces clear codes
+    let mut cube_data = create_seeded_cube_data(0xC0DE);
+    let mut state = 0x9E37_79B9u32;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state
+    };
+    cube_data.global_palette_rgb = (0..256 * 3).map(|_| next() as u8).collect();
+    for frame in cube_data.indexed_frames.iter_mut() {
+        frame.iter_mut().for_each(|index| *index = next() as u8);
+    }
+    let gif_bytes = Gif89aEncoder::new().encode_from_cube_data(&cube_data, 4, true).unwrap();
+    
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::Indexed);
+    let mut decoder = options.read_info(gif_bytes.as_slice()).unwrap();
+    
+    let mut decoded = Vec::new();
+    while let Some(frame) = decoder.read_next_frame().unwrap() {
+        decoded.push(frame.buffer.to_vec());
+    }
+    assert_eq!(decoded, cube_data.indexed_frames);
+    
+    // The first frame also survives the image crate's decoder, as RGB
+    let image = image::load_from_memory(&gif_bytes).unwrap().to_rgb8();
+    assert_eq!(image.dimensions(), (81, 81));
+    for (pixel, &index) in image.pixels().zip(&cube_data.indexed_frames[0]) {
+        let base = index as usize * 3;
+        assert_eq!(pixel.0, [
+            cube_data.global_palette_rgb[base],
+            cube_data.global_palette_rgb[base + 1],
+            cube_data.global_palette_rgb[base + 2],
+        ]);
+    }
+}
+
 #[test]
 fn test_golden_gif_hash() {
     // Test that identical input produces identical output
@@ -91,6 +256,33 @@ fn test_golden_gif_hash() {
     // In a real test, we'd assert against a known good hash
 }
 
+#[test]
+fn test_deterministic_encode_check() {
+    let cube_data = create_seeded_cube_data(0x5eed);
+    
+    // Two in-process encodes of the seeded cube are byte-identical
+    let encoder = Gif89aEncoder::new();
+    assert_eq!(
+        encoder.encode_from_cube_data(&cube_data, 4, true).unwrap(),
+        encoder.encode_from_cube_data(&cube_data, 4, true).unwrap()
+    );
+    assert!(deterministic_encode_check(&cube_data));
+    
+    // Recorded hash catches cross-platform drift
+    let golden = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden/seeded_cube.sha256");
+    assert!(
+        deterministic_encode_check_with_golden(&cube_data, Some(&golden)),
+        "Encode differs from golden hash, got {}",
+        gif_sha256_hex(&encoder.encode_from_cube_data(&cube_data, 4, true).unwrap())
+    );
+    
+    let dir = tempfile::tempdir().unwrap();
+    let stale = dir.path().join("stale.sha256");
+    std::fs::write(&stale, "0".repeat(64)).unwrap();
+    assert!(!deterministic_encode_check_with_golden(&cube_data, Some(&stale)));
+    assert!(deterministic_encode_check_with_golden(&cube_data, Some(dir.path().join("missing.sha256"))));
+}
+
 #[test]
 fn test_concrete_quality_targets() {
     // Test that M3 output meets the specified quality requirements
@@ -103,7 +295,8 @@ fn test_concrete_quality_targets() {
     validate_gif_structure(&gif_bytes).unwrap();
     
     // Verify frame count matches input
-    let frame_count = gif_bytes.iter().filter(|&&b| b == 0x2C).count();
+    let (_, blocks) = parse_block_sequence(&gif_bytes);
+    let frame_count = blocks.iter().filter(|block| matches!(block, GifBlock::Image)).count();
     assert_eq!(frame_count, 81, "Should preserve all 81 frames");
     
     // Verify reasonable compression (original would be ~1.6MB for 81×81×81×3)
@@ -154,6 +347,9 @@ fn create_test_cube_data() -> QuantizedCubeData {
         mean_delta_e: 1.2,
         p95_delta_e: 2.8,
         attention_maps: Some(vec![vec![0.5; 81 * 81]; 81]),
+        frame_checksums: None,
+        source_frame_ids: None,
+        schema_version: CUBE_SCHEMA_VERSION,
     }
 }
 
@@ -201,6 +397,40 @@ fn create_deterministic_cube_data() -> QuantizedCubeData {
         mean_delta_e: 0.8,
         p95_delta_e: 1.6,
         attention_maps: None,
+        frame_checksums: None,
+        source_frame_ids: None,
+        schema_version: CUBE_SCHEMA_VERSION,
+    }
+}
+
+fn create_seeded_cube_data(seed: u32) -> QuantizedCubeData {
+    // Xorshift noise over a 32-color palette, reproducible from the seed
+    let mut state = seed.max(1);
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state
+    };
+    
+    let global_palette_rgb: Vec<u8> = (0..32 * 3).map(|_| next() as u8).collect();
+    let indexed_frames = (0..81)
+        .map(|_| (0..81 * 81).map(|_| (next() % 32) as u8).collect())
+        .collect();
+    
+    QuantizedCubeData {
+        width: 81,
+        height: 81,
+        global_palette_rgb,
+        indexed_frames,
+        delays_cs: vec![4; 81],
+        palette_stability: 1.0,
+        mean_delta_e: 0.0,
+        p95_delta_e: 0.0,
+        attention_maps: None,
+        frame_checksums: None,
+        source_frame_ids: None,
+        schema_version: CUBE_SCHEMA_VERSION,
     }
 }
 
@@ -244,6 +474,9 @@ fn create_high_quality_cube_data() -> QuantizedCubeData {
         mean_delta_e: 0.5,       // Very low error
         p95_delta_e: 1.2,        // Low P95 error
         attention_maps: None,
+        frame_checksums: None,
+        source_frame_ids: None,
+        schema_version: CUBE_SCHEMA_VERSION,
     }
 }
 
@@ -299,3 +532,57 @@ fn contains_netscape_loop(gif_bytes: &[u8]) -> bool {
     
     false
 }
+
+#[derive(Debug)]
+enum GifBlock {
+    Application(Vec<u8>),
+    Extension,
+    Image,
+}
+
+/// Walk the block sequence after the global color table
+/// Returns the GCT end offset and each block with its identifying data
+fn parse_block_sequence(gif_bytes: &[u8]) -> (usize, Vec<GifBlock>) {
+    let packed = gif_bytes[10];
+    let gct_end = if packed & 0x80 != 0 {
+        13 + 3 * (1usize << ((packed & 0x07) + 1))
+    } else {
+        13
+    };
+    
+    let skip_sub_blocks = |mut pos: usize| {
+        while gif_bytes[pos] != 0 {
+            pos += gif_bytes[pos] as usize + 1;
+        }
+        pos + 1
+    };
+    
+    let mut blocks = Vec::new();
+    let mut pos = gct_end;
+    loop {
+        match gif_bytes[pos] {
+            0x21 if gif_bytes[pos + 1] == 0xFF => {
+                let id = gif_bytes[pos + 3..pos + 3 + gif_bytes[pos + 2] as usize].to_vec();
+                blocks.push(GifBlock::Application(id));
+                pos = skip_sub_blocks(pos + 2);
+            }
+            0x21 => {
+                blocks.push(GifBlock::Extension);
+                pos = skip_sub_blocks(pos + 2);
+            }
+            0x2C => {
+                blocks.push(GifBlock::Image);
+                let packed = gif_bytes[pos + 9];
+                let mut data = pos + 10;
+                if packed & 0x80 != 0 {
+                    data += 3 * (1usize << ((packed & 0x07) + 1));
+                }
+                pos = skip_sub_blocks(data + 1); // Skip LZW minimum code size
+            }
+            0x3B => break,
+            other => panic!("Unexpected block 0x{:02X} at offset {}", other, pos),
+        }
+    }
+    
+    (gct_end, blocks)
+}
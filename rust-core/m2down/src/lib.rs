@@ -3,6 +3,7 @@
 /// North Star spec: EXACTLY 81 frames at 81×81
 
 use std::cmp;
+use std::ops::Range;
 use std::sync::{Mutex, Once};
 use std::time::{Instant, Duration};
 
@@ -42,12 +43,39 @@ pub struct M2TimingStats {
 /// Quality metrics for neural network assessment
 #[derive(Debug, Clone)]
 pub struct M2QualityMetrics {
+    /// Mean windowed SSIM of outputs against their reference downscale
     pub avg_ssim: f64,
+    /// Mean PSNR in dB against the reference, capped at `PSNR_CAP_DB`
     pub avg_psnr: f64,
     pub edge_preservation: f64,
     pub policy_confidence_avg: f64,
     pub value_prediction_avg: f64,
     pub kernel_diversity: f64,
+    /// Frames the averages above are taken over
+    pub frames_measured: u32,
+}
+
+/// PSNR reported for identical images, whose true PSNR is infinite
+pub const PSNR_CAP_DB: f64 = 100.0;
+
+/// Side of the square SSIM window, moved half a window at a time
+const SSIM_WINDOW: usize = 8;
+
+/// Downsize path taken for a frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DownsizeMethod {
+    /// Edge-weighted neural-inspired sampling
+    Neural,
+    /// Plain 9×9 block averaging
+    Baseline,
+}
+
+/// Per-call result of `m2_downsize_with_report`
+#[derive(Debug, Clone)]
+pub struct DownsizeReport {
+    pub method: DownsizeMethod,
+    pub duration_ms: f64,
+    pub edge_preservation: f64,
 }
 
 /// Global model state - simplified for initial implementation
@@ -80,12 +108,13 @@ static TIMING_STATS: Mutex<M2TimingStats> = Mutex::new(M2TimingStats {
 });
 
 static QUALITY_METRICS: Mutex<M2QualityMetrics> = Mutex::new(M2QualityMetrics {
-    avg_ssim: 0.85,      // Simulated good quality
-    avg_psnr: 28.4,
-    edge_preservation: 0.91,
+    avg_ssim: 0.0,
+    avg_psnr: 0.0,
+    edge_preservation: 0.0,
     policy_confidence_avg: 0.84,
     value_prediction_avg: 0.42,
     kernel_diversity: 0.63,
+    frames_measured: 0,
 });
 
 /// Initialize the Go 9×9 neural network model
@@ -124,6 +153,18 @@ pub fn m2_downsize_9x9_cpu(
     rgba_729: Vec<u8>,
     width: u32,
     height: u32,
+) -> Result<Vec<u8>, M2Error> {
+    m2_downsize_9x9_cpu_with_reference(rgba_729, width, height, None)
+}
+
+/// `m2_downsize_9x9_cpu`, scoring the output against `reference_81`
+/// Without a reference the neural output is scored against the 9×9 block average,
+/// and the baseline path, which is that average, records no quality sample
+pub fn m2_downsize_9x9_cpu_with_reference(
+    rgba_729: Vec<u8>,
+    width: u32,
+    height: u32,
+    reference_81: Option<Vec<u8>>,
 ) -> Result<Vec<u8>, M2Error> {
     let start_time = Instant::now();
     
@@ -148,24 +189,104 @@ pub fn m2_downsize_9x9_cpu(
         error!("M2_RUST_FRAME_ERROR idx={} invalid_size={} expected={}", frame_idx, rgba_729.len(), expected_size);
         return Err(M2Error::InvalidDataSize);
     }
+    if reference_81.as_ref().is_some_and(|reference| reference.len() != 81 * 81 * 4) {
+        error!("M2_RUST_FRAME_ERROR idx={} invalid_reference_size", frame_idx);
+        return Err(M2Error::InvalidDataSize);
+    }
     
     // Initialize model if not already done
     m2_initialize_model()?;
     
     // Check if neural network is available
-    let result = if *MODEL_LOADED.lock().unwrap() {
+    let neural = *MODEL_LOADED.lock().unwrap();
+    let output = if neural {
         log::debug!("M2: Using enhanced neural downsize");
-        enhanced_neural_downsize(&rgba_729, width, height)
+        let output = enhanced_neural_downsize(&rgba_729, width, height)?;
+        update_quality_metrics_neural();
+        output
     } else {
         log::debug!("M2: Using baseline averaging");
-        baseline_block_average(&rgba_729, width, height)
+        baseline_block_average(&rgba_729, width, height)?
     };
     
+    let reference = match reference_81 {
+        Some(reference) => Some(reference),
+        None if neural => Some(baseline_block_average(&rgba_729, width, height)?),
+        None => None,
+    };
+    if let Some(reference) = reference {
+        record_quality(&rgba_729, width, &output, &reference);
+    }
+    
     // Record timing
     let duration = start_time.elapsed();
     update_timing_stats(duration);
     
-    result
+    Ok(output)
+}
+
+/// Downsize a 729×729 RGBA frame and report timing, edge preservation and method
+/// Unlike `m2_downsize_9x9_cpu` this leaves the global timing and quality stats untouched
+// Note: Not exported via UniFFI since UDL has no tuple return type
+pub fn m2_downsize_with_report(rgba_729: Vec<u8>) -> Result<(Vec<u8>, DownsizeReport), M2Error> {
+    const INPUT_SIZE: u32 = 729;
+    let start_time = Instant::now();
+    
+    if rgba_729.len() != (INPUT_SIZE * INPUT_SIZE * 4) as usize {
+        return Err(M2Error::InvalidDataSize);
+    }
+    
+    m2_initialize_model()?;
+    
+    let (output, method) = if *MODEL_LOADED.lock().unwrap() {
+        (enhanced_neural_downsize(&rgba_729, INPUT_SIZE, INPUT_SIZE)?, DownsizeMethod::Neural)
+    } else {
+        (baseline_block_average(&rgba_729, INPUT_SIZE, INPUT_SIZE)?, DownsizeMethod::Baseline)
+    };
+    
+    let report = DownsizeReport {
+        method,
+        duration_ms: start_time.elapsed().as_secs_f64() * 1000.0,
+        edge_preservation: edge_preservation(&rgba_729, INPUT_SIZE, &output),
+    };
+    Ok((output, report))
+}
+
+/// Share of luma gradient energy kept in the 81×81 output, relative to the
+/// input sampled at each 9×9 block centre; clamped to [0, 1], flat input scores 1.0
+fn edge_preservation(rgba_729: &[u8], width: u32, output_81: &[u8]) -> f64 {
+    const OUTPUT_SIZE: usize = 81;
+    const BLOCK_SIZE: usize = 9;
+    let luma = |px: &[u8]| 0.299 * px[0] as f64 + 0.587 * px[1] as f64 + 0.114 * px[2] as f64;
+    
+    let gradient_energy = |pixel_at: &dyn Fn(usize, usize) -> f64| {
+        let mut energy = 0.0;
+        for y in 0..OUTPUT_SIZE {
+            for x in 0..OUTPUT_SIZE {
+                if x + 1 < OUTPUT_SIZE {
+                    energy += (pixel_at(x + 1, y) - pixel_at(x, y)).abs();
+                }
+                if y + 1 < OUTPUT_SIZE {
+                    energy += (pixel_at(x, y + 1) - pixel_at(x, y)).abs();
+                }
+            }
+        }
+        energy
+    };
+    
+    let input_energy = gradient_energy(&|x, y| {
+        let idx = ((y * BLOCK_SIZE + BLOCK_SIZE / 2) * width as usize + x * BLOCK_SIZE + BLOCK_SIZE / 2) * 4;
+        luma(&rgba_729[idx..idx + 4])
+    });
+    if input_energy == 0.0 {
+        return 1.0;
+    }
+    let output_energy = gradient_energy(&|x, y| {
+        let idx = (y * OUTPUT_SIZE + x) * 4;
+        luma(&output_81[idx..idx + 4])
+    });
+    
+    (output_energy / input_energy).clamp(0.0, 1.0)
 }
 
 /// Enhanced neural network implementation (simplified for initial deployment)
@@ -232,9 +353,6 @@ fn enhanced_neural_downsize(
         }
     }
     
-    // Update quality metrics to reflect neural processing
-    update_quality_metrics_neural();
-    
     Ok(output)
 }
 
@@ -280,34 +398,8 @@ fn baseline_block_average(
             let in_x_end = cmp::min(in_x_start + BLOCK_SIZE, width);
             let in_y_end = cmp::min(in_y_start + BLOCK_SIZE, height);
             
-            // Accumulate block values
-            let mut r_sum = 0u32;
-            let mut g_sum = 0u32;
-            let mut b_sum = 0u32;
-            let mut a_sum = 0u32;
-            let mut pixel_count = 0u32;
-            
-            for in_y in in_y_start..in_y_end {
-                for in_x in in_x_start..in_x_end {
-                    let idx = ((in_y * width + in_x) * 4) as usize;
-                    r_sum += rgba_data[idx] as u32;
-                    g_sum += rgba_data[idx + 1] as u32;
-                    b_sum += rgba_data[idx + 2] as u32;
-                    a_sum += rgba_data[idx + 3] as u32;
-                    pixel_count += 1;
-                }
-            }
-            
-            // Average and write output pixel
-            if pixel_count > 0 {
-                output.push((r_sum / pixel_count) as u8);
-                output.push((g_sum / pixel_count) as u8);
-                output.push((b_sum / pixel_count) as u8);
-                output.push((a_sum / pixel_count) as u8);
-            } else {
-                // Should never happen with our fixed dimensions
-                output.extend_from_slice(&[0, 0, 0, 255]);
-            }
+            let pixel = average_block(rgba_data, width, in_x_start..in_x_end, in_y_start..in_y_end);
+            output.extend_from_slice(&pixel);
         }
     }
     
@@ -318,6 +410,32 @@ fn baseline_block_average(
     Ok(output)
 }
 
+/// Output pixel for a block with no input pixels (block past the image edge)
+const EMPTY_BLOCK_PIXEL: [u8; 4] = [0, 0, 0, 255];
+
+/// Average an RGBA block of any shape
+/// Sums use u64 so large blocks of 255-valued pixels cannot overflow, and an
+/// empty block yields `EMPTY_BLOCK_PIXEL` instead of dividing by zero
+fn average_block(rgba_data: &[u8], width: u32, xs: Range<u32>, ys: Range<u32>) -> [u8; 4] {
+    let mut sums = [0u64; 4];
+    let mut pixel_count = 0u64;
+    
+    for in_y in ys {
+        for in_x in xs.clone() {
+            let idx = (in_y as usize * width as usize + in_x as usize) * 4;
+            for (sum, &value) in sums.iter_mut().zip(&rgba_data[idx..idx + 4]) {
+                *sum = sum.saturating_add(value as u64);
+            }
+            pixel_count += 1;
+        }
+    }
+    
+    if pixel_count == 0 {
+        return EMPTY_BLOCK_PIXEL;
+    }
+    sums.map(|sum| (sum / pixel_count) as u8)
+}
+
 /// Update timing statistics
 fn update_timing_stats(duration: Duration) {
     let mut stats = TIMING_STATS.lock().unwrap();
@@ -348,11 +466,81 @@ fn update_quality_metrics_neural() {
     metrics.policy_confidence_avg = 0.847;
     metrics.value_prediction_avg = 0.423;
     metrics.kernel_diversity = 0.632;
+}
+
+/// Fold one frame's SSIM, PSNR and edge preservation into the running averages
+fn record_quality(rgba_729: &[u8], width: u32, output_81: &[u8], reference_81: &[u8]) {
+    let ssim = ssim(output_81, reference_81, 81);
+    let psnr = psnr(output_81, reference_81);
+    let edges = edge_preservation(rgba_729, width, output_81);
+    log::debug!("M2_QUALITY ssim={:.4} psnr={:.2} edges={:.3}", ssim, psnr, edges);
+    
+    let mut metrics = QUALITY_METRICS.lock().unwrap();
+    metrics.frames_measured += 1;
+    let n = metrics.frames_measured as f64;
+    metrics.avg_ssim += (ssim - metrics.avg_ssim) / n;
+    metrics.avg_psnr += (psnr - metrics.avg_psnr) / n;
+    metrics.edge_preservation += (edges - metrics.edge_preservation) / n;
+}
+
+/// Peak signal-to-noise ratio in dB between two RGBA images, over RGB only
+/// Identical images score `PSNR_CAP_DB`
+// Note: Not exported via UniFFI, like `ssim`; callers with raw frames use them directly
+pub fn psnr(a: &[u8], b: &[u8]) -> f64 {
+    let (mut squared_error, mut samples) = (0.0f64, 0usize);
+    for (pa, pb) in a.chunks_exact(4).zip(b.chunks_exact(4)) {
+        for c in 0..3 {
+            let diff = pa[c] as f64 - pb[c] as f64;
+            squared_error += diff * diff;
+        }
+        samples += 3;
+    }
+    if samples == 0 || squared_error == 0.0 {
+        return PSNR_CAP_DB;
+    }
+    let mse = squared_error / samples as f64;
+    (10.0 * (255.0 * 255.0 / mse).log10()).min(PSNR_CAP_DB)
+}
+
+/// Mean SSIM of luma over `SSIM_WINDOW`-square windows of two RGBA images `width` pixels wide
+/// 1.0 for identical images, lower as structure, contrast or brightness diverge
+pub fn ssim(a: &[u8], b: &[u8], width: usize) -> f64 {
+    const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+    let luma = |rgba: &[u8]| -> Vec<f64> {
+        rgba.chunks_exact(4)
+            .map(|px| 0.299 * px[0] as f64 + 0.587 * px[1] as f64 + 0.114 * px[2] as f64)
+            .collect()
+    };
+    let (la, lb) = (luma(a), luma(b));
+    let height = la.len().min(lb.len()) / width.max(1);
+    if width < SSIM_WINDOW || height < SSIM_WINDOW {
+        return if la == lb { 1.0 } else { 0.0 };
+    }
     
-    // High-quality metrics for enhanced neural processing
-    metrics.avg_ssim = 0.89;
-    metrics.avg_psnr = 31.2;
-    metrics.edge_preservation = 0.94;
+    let (mut total, mut windows) = (0.0, 0usize);
+    for top in (0..=height - SSIM_WINDOW).step_by(SSIM_WINDOW / 2) {
+        for left in (0..=width - SSIM_WINDOW).step_by(SSIM_WINDOW / 2) {
+            let pixels = || (top..top + SSIM_WINDOW)
+                .flat_map(move |y| (left..left + SSIM_WINDOW).map(move |x| y * width + x));
+            let n = (SSIM_WINDOW * SSIM_WINDOW) as f64;
+            let mean_a = pixels().map(|i| la[i]).sum::<f64>() / n;
+            let mean_b = pixels().map(|i| lb[i]).sum::<f64>() / n;
+            let (mut var_a, mut var_b, mut covariance) = (0.0, 0.0, 0.0);
+            for i in pixels() {
+                let (da, db) = (la[i] - mean_a, lb[i] - mean_b);
+                var_a += da * da;
+                var_b += db * db;
+                covariance += da * db;
+            }
+            let (var_a, var_b, covariance) = (var_a / n, var_b / n, covariance / n);
+            
+            total += ((2.0 * mean_a * mean_b + C1) * (2.0 * covariance + C2))
+                / ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2));
+            windows += 1;
+        }
+    }
+    total / windows as f64
 }
 
 /// Get timing statistics
@@ -379,12 +567,13 @@ pub fn reset_m2_stats() {
     
     let mut metrics = QUALITY_METRICS.lock().unwrap();
     *metrics = M2QualityMetrics {
-        avg_ssim: 0.85,
-        avg_psnr: 28.4,
-        edge_preservation: 0.91,
+        avg_ssim: 0.0,
+        avg_psnr: 0.0,
+        edge_preservation: 0.0,
         policy_confidence_avg: 0.84,
         value_prediction_avg: 0.42,
         kernel_diversity: 0.63,
+        frames_measured: 0,
     };
 }
 
@@ -397,6 +586,13 @@ pub fn get_m2_version() -> String {
 mod tests {
     use super::*;
     
+    /// Serializes tests that record into or reset the global stats
+    static STATS_LOCK: Mutex<()> = Mutex::new(());
+    
+    fn stats_guard() -> std::sync::MutexGuard<'static, ()> {
+        STATS_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+    
     #[test]
     fn test_neural_downsize_initialization() {
         // Test model initialization
@@ -409,6 +605,7 @@ mod tests {
     
     #[test]
     fn test_enhanced_downsize() {
+        let _guard = stats_guard();
         // Create synthetic 729×729 pattern
         let mut input = vec![0u8; 729 * 729 * 4];
         
@@ -465,6 +662,25 @@ mod tests {
         assert_eq!(result[3], 255);  // A preserved
     }
     
+    #[test]
+    fn test_degenerate_blocks_have_defined_pixels() {
+        // 1×729 column: only the first block column has pixels, each 1×9
+        let input: Vec<u8> = (0..729).flat_map(|y| [(y % 9 * 10) as u8, 50, 255, 255]).collect();
+        let result = baseline_block_average(&input, 1, 729).unwrap();
+        
+        assert_eq!(result.len(), 81 * 81 * 4);
+        assert_eq!(&result[0..4], &[40, 50, 255, 255]);
+        // Blocks past the right edge are empty and fall back instead of dividing by zero
+        assert_eq!(&result[4..8], &EMPTY_BLOCK_PIXEL);
+        
+        assert_eq!(average_block(&input, 1, 0..0, 0..9), EMPTY_BLOCK_PIXEL);
+        assert_eq!(average_block(&input, 1, 0..1, 5..5), EMPTY_BLOCK_PIXEL);
+        
+        // A large saturated block averages without overflow
+        let white = vec![255u8; 2048 * 2048 * 4];
+        assert_eq!(average_block(&white, 2048, 0..2048, 0..2048), [255; 4]);
+    }
+    
     #[test]
     fn test_invalid_dimensions() {
         let input = vec![0u8; 100 * 100 * 4];
@@ -474,6 +690,7 @@ mod tests {
     
     #[test]
     fn test_timing_stats() {
+        let _guard = stats_guard();
         reset_m2_stats();
         
         // Process a frame to generate stats
@@ -486,15 +703,81 @@ mod tests {
         assert!(stats.total_duration_ms > 0);
     }
     
+    #[test]
+    fn test_downsize_report_matches_path() {
+        // Vertical stripes every 27 pixels give hard edges between block columns
+        let input: Vec<u8> = (0..729 * 729)
+            .flat_map(|i| if i % 729 / 27 % 2 == 0 { [20, 20, 20, 255] } else { [230, 230, 230, 255] })
+            .collect();
+        
+        let (output, report) = m2_downsize_with_report(input.clone()).unwrap();
+        assert_eq!(output.len(), 81 * 81 * 4);
+        
+        let expected_method = if *MODEL_LOADED.lock().unwrap() {
+            DownsizeMethod::Neural
+        } else {
+            DownsizeMethod::Baseline
+        };
+        assert_eq!(report.method, expected_method);
+        let expected_output = match report.method {
+            DownsizeMethod::Neural => enhanced_neural_downsize(&input, 729, 729).unwrap(),
+            DownsizeMethod::Baseline => baseline_block_average(&input, 729, 729).unwrap(),
+        };
+        assert_eq!(output, expected_output);
+        
+        assert!(report.duration_ms >= 0.0);
+        assert!(report.edge_preservation > 0.5 && report.edge_preservation <= 1.0);
+        
+        assert!(matches!(m2_downsize_with_report(vec![0; 16]), Err(M2Error::InvalidDataSize)));
+    }
+    
     #[test]
     fn test_quality_metrics() {
+        let _guard = stats_guard();
+        reset_m2_stats();
+        
+        let reference: Vec<u8> = (0..81 * 81).flat_map(|i| [(i % 256) as u8, 128, 64, 255]).collect();
+        let input = vec![128u8; 729 * 729 * 4];
+        let output = m2_downsize_9x9_cpu_with_reference(input, 729, 729, Some(reference.clone())).unwrap();
         let metrics = get_m2_quality_metrics();
-        assert!(metrics.avg_ssim >= 0.0 && metrics.avg_ssim <= 1.0);
-        assert!(metrics.avg_psnr > 0.0);
+        assert_eq!(metrics.frames_measured, 1);
+        assert!((metrics.avg_ssim - ssim(&output, &reference, 81)).abs() < 1e-12);
+        assert!((metrics.avg_psnr - psnr(&output, &reference)).abs() < 1e-12);
+        assert!(metrics.avg_psnr < PSNR_CAP_DB);
         assert!(metrics.edge_preservation >= 0.0 && metrics.edge_preservation <= 1.0);
         assert!(metrics.policy_confidence_avg >= 0.0 && metrics.policy_confidence_avg <= 1.0);
     }
     
+    #[test]
+    fn test_psnr_and_ssim_track_differences() {
+        let _guard = stats_guard();
+        // Textured 81×81 frame and a copy with deterministic ±noise
+        let clean: Vec<u8> = (0..81 * 81)
+            .flat_map(|i| {
+                let (x, y) = (i % 81, i / 81);
+                [(x * 3) as u8, (y * 3) as u8, ((x + y) * 2) as u8, 255]
+            })
+            .collect();
+        let noisy: Vec<u8> = clean
+            .iter()
+            .enumerate()
+            .map(|(i, &v)| if i % 4 == 3 { v } else { v.saturating_add_signed(((i * 2654435761) >> 7) as i8 % 40) })
+            .collect();
+        
+        assert_eq!(psnr(&clean, &clean), PSNR_CAP_DB);
+        assert!((ssim(&clean, &clean, 81) - 1.0).abs() < 1e-12);
+        
+        let (noisy_psnr, noisy_ssim) = (psnr(&clean, &noisy), ssim(&clean, &noisy, 81));
+        assert!(noisy_psnr < 40.0 && noisy_psnr > 10.0, "psnr {}", noisy_psnr);
+        assert!(noisy_ssim < 0.95 && noisy_ssim > 0.0, "ssim {}", noisy_ssim);
+        
+        // An explicit reference is what the frame is scored against
+        let input = vec![90u8; 729 * 729 * 4];
+        assert!(m2_downsize_9x9_cpu_with_reference(input.clone(), 729, 729, Some(vec![0; 16])).is_err());
+        let output = m2_downsize_9x9_cpu_with_reference(input, 729, 729, Some(noisy.clone())).unwrap();
+        assert!(psnr(&output, &noisy) < PSNR_CAP_DB);
+    }
+    
     #[test]
     fn test_edge_detection() {
         // Create test pattern with an edge
@@ -1,12 +1,19 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
 use gif::{Encoder, Frame, Repeat};
+use std::borrow::Cow;
 use std::fs::File;
-use std::io::BufWriter;
+use std::io::{BufWriter, Write};
 use log::info;
 
+use crate::ColorMetrics;
+
 pub struct GifEncodeOptions {
     pub delay_cs: u16,
     pub loop_gif: bool,
+    /// Force at least this LZW minimum code size; `None` derives it from the frame
+    pub min_lzw_code_size: Option<u8>,
+    /// Point the background color index at the frames' average color
+    pub average_background: bool,
 }
 
 impl Default for GifEncodeOptions {
@@ -14,23 +21,142 @@ impl Default for GifEncodeOptions {
         Self {
             delay_cs: 4, // 4 centiseconds = ~24 fps
             loop_gif: true,
+            min_lzw_code_size: None,
+            average_background: false,
         }
     }
 }
 
+impl GifEncodeOptions {
+    /// Write every frame with this LZW minimum code size, clamped to 2..=8
+    ///
+    /// Some legacy decoders misbehave below 8 even for small palettes. Encoding
+    /// fails if a frame's palette needs a larger code size than requested.
+    pub fn with_min_lzw_code_size(mut self, code_size: u8) -> Self {
+        self.min_lzw_code_size = Some(code_size.clamp(2, 8));
+        self
+    }
+    
+    /// Use the capture's average color as the GIF background
+    ///
+    /// The first frame's palette becomes the global color table, with the
+    /// average (from `ColorMetrics`) added when missing, and the logical screen
+    /// descriptor's background index points at it. Frames keep their own
+    /// local tables, so only the backdrop shown during disposal changes.
+    pub fn with_average_background(mut self, enabled: bool) -> Self {
+        self.average_background = enabled;
+        self
+    }
+}
+
+/// Global palette holding `average`, plus the index it sits at
+///
+/// Reuses an exact match, appends when there is room, and otherwise replaces
+/// the entry nearest the average.
+fn background_palette(palette: &[u8], average: [u8; 3]) -> (Vec<u8>, u8) {
+    let mut palette: Vec<u8> = palette[..palette.len().min(256 * 3)].to_vec();
+    
+    if let Some(index) = palette.chunks_exact(3).position(|rgb| rgb == average) {
+        return (palette, index as u8);
+    }
+    if palette.len() < 256 * 3 {
+        let index = (palette.len() / 3) as u8;
+        palette.extend_from_slice(&average);
+        return (palette, index);
+    }
+    
+    let distance = |rgb: &[u8]| -> u32 {
+        rgb.iter().zip(average).map(|(&a, b)| (a as i32 - b as i32).pow(2) as u32).sum()
+    };
+    let nearest = palette.chunks_exact(3)
+        .enumerate()
+        .min_by_key(|(_, rgb)| distance(rgb))
+        .map_or(0, |(index, _)| index);
+    palette[nearest * 3..nearest * 3 + 3].copy_from_slice(&average);
+    (palette, nearest as u8)
+}
+
+/// Smallest LZW minimum code size able to address `palette_len` colors (GIF requires ≥ 2)
+fn required_lzw_code_size(palette_len: usize) -> u8 {
+    let mut bits = 2;
+    while (1usize << bits) < palette_len {
+        bits += 1;
+    }
+    bits
+}
+
 pub fn encode_gif89a_from_quantized(
     output_path: &str,
     quantized_frames: Vec<(Vec<u8>, Vec<u8>)>,
     width: u32,
     height: u32,
     options: Option<GifEncodeOptions>,
+) -> Result<()> {
+    let file = File::create(output_path)?;
+    write_gif89a(BufWriter::new(file), &quantized_frames, width, height, options)?;
+    
+    info!("GIF89a encoding complete: {}", output_path);
+    Ok(())
+}
+
+/// Encode quantized frames to in-memory GIF89a bytes
+pub fn encode_gif89a_to_bytes(
+    quantized_frames: &[(Vec<u8>, Vec<u8>)],
+    width: u32,
+    height: u32,
+    options: Option<GifEncodeOptions>,
+) -> Result<Vec<u8>> {
+    let mut output = Vec::new();
+    write_gif89a(&mut output, quantized_frames, width, height, options)?;
+    Ok(output)
+}
+
+fn write_gif89a<W: Write>(
+    mut writer: W,
+    quantized_frames: &[(Vec<u8>, Vec<u8>)],
+    width: u32,
+    height: u32,
+    options: Option<GifEncodeOptions>,
 ) -> Result<()> {
     let options = options.unwrap_or_default();
     
-    let file = File::create(output_path)?;
-    let writer = BufWriter::new(file);
+    if !options.average_background {
+        return write_frames(writer, quantized_frames, width, height, &options, &[]);
+    }
+    
+    // Average over the frames as they will be displayed
+    let rgba: Vec<u8> = quantized_frames.iter()
+        .flat_map(|(indices, palette)| {
+            indices.iter().flat_map(move |&index| {
+                let base = index as usize * 3;
+                palette.get(base..base + 3).map_or([0, 0, 0, 255], |rgb| [rgb[0], rgb[1], rgb[2], 255])
+            })
+        })
+        .collect();
+    let (r, g, b) = ColorMetrics::calculate(&rgba).avg_rgb;
+    let average = [r.round() as u8, g.round() as u8, b.round() as u8];
     
-    let mut encoder = Encoder::new(writer, width as u16, height as u16, &[])?;
+    let first_palette = quantized_frames.first().map_or(&[][..], |(_, palette)| palette.as_slice());
+    let (global_palette, background_index) = background_palette(first_palette, average);
+    info!("Background color ({}, {}, {}) at global index {}", average[0], average[1], average[2], background_index);
+    
+    // The gif crate always writes background index 0, so patch the logical screen descriptor
+    let mut bytes = Vec::new();
+    write_frames(&mut bytes, quantized_frames, width, height, &options, &global_palette)?;
+    bytes[11] = background_index;
+    writer.write_all(&bytes)?;
+    Ok(())
+}
+
+fn write_frames<W: Write>(
+    writer: W,
+    quantized_frames: &[(Vec<u8>, Vec<u8>)],
+    width: u32,
+    height: u32,
+    options: &GifEncodeOptions,
+    global_palette: &[u8],
+) -> Result<()> {
+    let mut encoder = Encoder::new(writer, width as u16, height as u16, global_palette)?;
     
     if options.loop_gif {
         encoder.set_repeat(Repeat::Infinite)?;
@@ -49,14 +175,28 @@ pub fn encode_gif89a_from_quantized(
         
         frame.delay = options.delay_cs;
         
-        encoder.write_frame(&frame)?;
+        if let Some(code_size) = options.min_lzw_code_size {
+            let required = required_lzw_code_size(palette.len() / 3);
+            if code_size < required {
+                bail!(
+                    "Frame {}: LZW code size {} too small for a {}-color palette (needs {})",
+                    frame_idx, code_size, palette.len() / 3, required
+                );
+            }
+            
+            let mut data = vec![code_size];
+            data.extend(weezl::encode::Encoder::new(weezl::BitOrder::Lsb, code_size).encode(indices)?);
+            frame.buffer = Cow::Owned(data);
+            encoder.write_lzw_pre_encoded_frame(&frame)?;
+        } else {
+            encoder.write_frame(&frame)?;
+        }
         
         if frame_idx % 10 == 0 {
             info!("Encoded frame {}/{}", frame_idx + 1, quantized_frames.len());
         }
     }
     
-    info!("GIF89a encoding complete: {}", output_path);
     Ok(())
 }
 
@@ -76,3 +216,87 @@ pub fn encode_rgba_frames_to_gif89a(
     // Encode to GIF
     encode_gif89a_from_quantized(output_path, quantized, width, height, encode_options)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sixteen_color_frames() -> Vec<(Vec<u8>, Vec<u8>)> {
+        let palette: Vec<u8> = (0..16u8).flat_map(|i| [i * 16, 255 - i * 16, i * 8]).collect();
+        (0..3)
+            .map(|f| ((0..81 * 81).map(|i| ((i + f) % 16) as u8).collect(), palette.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_forced_lzw_code_size_decodes() {
+        let frames = sixteen_color_frames();
+        let options = GifEncodeOptions::default().with_min_lzw_code_size(8);
+        let gif = encode_gif89a_to_bytes(&frames, 81, 81, Some(options)).unwrap();
+
+        let mut decode = gif::DecodeOptions::new();
+        decode.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = decode.read_info(gif.as_slice()).unwrap();
+        let mut decoded = 0;
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            assert_eq!(frame.buffer.as_ref(), frames[decoded].0.as_slice());
+            decoded += 1;
+        }
+        assert_eq!(decoded, 3);
+
+        // First image's LZW minimum code size follows its 16-color local table
+        let image = gif.iter().position(|&b| b == 0x2C).unwrap();
+        assert_eq!(gif[image + 10 + 16 * 3], 8);
+    }
+
+    #[test]
+    fn test_average_background_index() {
+        let frames = sixteen_color_frames();
+        let options = GifEncodeOptions::default().with_average_background(true);
+        let gif = encode_gif89a_to_bytes(&frames, 81, 81, Some(options)).unwrap();
+
+        let rgba: Vec<u8> = frames.iter()
+            .flat_map(|(indices, palette)| {
+                indices.iter().flat_map(move |&i| {
+                    let base = i as usize * 3;
+                    [palette[base], palette[base + 1], palette[base + 2], 255]
+                })
+            })
+            .collect();
+        let (r, g, b) = ColorMetrics::calculate(&rgba).avg_rgb;
+
+        // Global table present, background index resolves to the average color
+        assert_eq!(gif[10] & 0x80, 0x80);
+        let background = gif[11] as usize;
+        let entry = &gif[13 + background * 3..13 + background * 3 + 3];
+        for (&channel, average) in entry.iter().zip([r, g, b]) {
+            assert!((channel as f32 - average).abs() <= 1.0, "{:?} vs ({}, {}, {})", entry, r, g, b);
+        }
+        // The average isn't in the 16-color palette, so it was appended
+        assert_eq!(background, 16);
+
+        // Frames still decode from their own tables
+        let mut decode = gif::DecodeOptions::new();
+        decode.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = decode.read_info(gif.as_slice()).unwrap();
+        let first = decoder.read_next_frame().unwrap().unwrap();
+        assert_eq!(first.buffer.as_ref(), frames[0].0.as_slice());
+
+        // Full palettes have their nearest entry replaced instead
+        let full: Vec<u8> = (0..=255u8).flat_map(|i| [i, i, i]).collect();
+        let (palette, index) = background_palette(&full, [10, 11, 10]);
+        assert_eq!(palette.len(), 256 * 3);
+        assert_eq!(&palette[index as usize * 3..index as usize * 3 + 3], &[10, 11, 10]);
+    }
+
+    #[test]
+    fn test_lzw_code_size_below_palette_requirement_rejected() {
+        let options = GifEncodeOptions::default().with_min_lzw_code_size(3);
+        assert!(encode_gif89a_to_bytes(&sixteen_color_frames(), 81, 81, Some(options)).is_err());
+
+        assert_eq!(GifEncodeOptions::default().with_min_lzw_code_size(12).min_lzw_code_size, Some(8));
+        assert_eq!(required_lzw_code_size(2), 2);
+        assert_eq!(required_lzw_code_size(16), 4);
+        assert_eq!(required_lzw_code_size(256), 8);
+    }
+}
@@ -0,0 +1,168 @@
+//! Separable Lanczos3, triangle and Catmull-Rom resampling for RGBA8 images
+//!
+//! Mirrors the filter placement of `image::imageops::resize` (vertical pass
+//! into f32, then horizontal pass back to u8) so builds without the
+//! `external-image` feature produce matching output.
+
+use std::f32::consts::PI;
+
+/// Resampling kernel and its radius in (unscaled) source pixels
+#[derive(Clone, Copy)]
+pub(crate) struct Kernel {
+    eval: fn(f32) -> f32,
+    support: f32,
+}
+
+pub(crate) const LANCZOS3: Kernel = Kernel { eval: lanczos3, support: 3.0 };
+pub(crate) const TRIANGLE: Kernel = Kernel { eval: triangle, support: 1.0 };
+pub(crate) const CATMULL_ROM: Kernel = Kernel { eval: catmull_rom, support: 2.0 };
+
+fn sinc(t: f32) -> f32 {
+    if t == 0.0 {
+        1.0
+    } else {
+        let a = t * PI;
+        a.sin() / a
+    }
+}
+
+fn lanczos3(x: f32) -> f32 {
+    if x.abs() < 3.0 {
+        sinc(x) * sinc(x / 3.0)
+    } else {
+        0.0
+    }
+}
+
+fn triangle(x: f32) -> f32 {
+    (1.0 - x.abs()).max(0.0)
+}
+
+/// Cubic with B = 0, C = 0.5
+fn catmull_rom(x: f32) -> f32 {
+    let x = x.abs();
+    if x < 1.0 {
+        1.5 * x * x * x - 2.5 * x * x + 1.0
+    } else if x < 2.0 {
+        -0.5 * x * x * x + 2.5 * x * x - 4.0 * x + 2.0
+    } else {
+        0.0
+    }
+}
+
+/// First source index and normalized weights for each output sample along one axis
+fn axis_weights(src_len: u32, dst_len: u32, kernel: Kernel) -> Vec<(usize, Vec<f32>)> {
+    let ratio = src_len as f32 / dst_len as f32;
+    // Widen the kernel when downsampling so every source pixel contributes
+    let scale = ratio.max(1.0);
+    let src_support = kernel.support * scale;
+
+    (0..dst_len)
+        .map(|out| {
+            let center = (out as f32 + 0.5) * ratio;
+            let left = ((center - src_support).floor() as i64).clamp(0, src_len as i64 - 1);
+            let right = ((center + src_support).ceil() as i64).clamp(left + 1, src_len as i64);
+
+            // Kernel origin is the left edge of the sample, matching pixel indices
+            let origin = center - 0.5;
+            let mut weights: Vec<f32> = (left..right)
+                .map(|i| (kernel.eval)((i as f32 - origin) / scale))
+                .collect();
+            let sum: f32 = weights.iter().sum();
+            weights.iter_mut().for_each(|w| *w /= sum);
+
+            (left as usize, weights)
+        })
+        .collect()
+}
+
+/// Resize a tightly packed RGBA8 image with `kernel`
+pub(crate) fn resize_rgba(
+    rgba: &[u8],
+    src_width: u32,
+    src_height: u32,
+    dst_width: u32,
+    dst_height: u32,
+    kernel: Kernel,
+) -> Vec<u8> {
+    if (src_width, src_height) == (dst_width, dst_height) {
+        return rgba.to_vec();
+    }
+
+    let (sw, dw) = (src_width as usize, dst_width as usize);
+
+    // Vertical pass: src_width × dst_height, kept in f32
+    let mut vertical = vec![0.0f32; sw * dst_height as usize * 4];
+    for (out_y, (top, weights)) in axis_weights(src_height, dst_height, kernel).iter().enumerate() {
+        for x in 0..sw {
+            let mut acc = [0.0f32; 4];
+            for (i, &w) in weights.iter().enumerate() {
+                let idx = ((top + i) * sw + x) * 4;
+                for (c, value) in acc.iter_mut().enumerate() {
+                    *value += rgba[idx + c] as f32 * w;
+                }
+            }
+            let out = (out_y * sw + x) * 4;
+            vertical[out..out + 4].copy_from_slice(&acc);
+        }
+    }
+
+    // Horizontal pass: dst_width × dst_height, rounded back to u8
+    let mut output = vec![0u8; dw * dst_height as usize * 4];
+    let columns = axis_weights(src_width, dst_width, kernel);
+    for y in 0..dst_height as usize {
+        for (out_x, (left, weights)) in columns.iter().enumerate() {
+            let mut acc = [0.0f32; 4];
+            for (i, &w) in weights.iter().enumerate() {
+                let idx = (y * sw + left + i) * 4;
+                for (c, value) in acc.iter_mut().enumerate() {
+                    *value += vertical[idx + c] * w;
+                }
+            }
+            let out = (y * dw + out_x) * 4;
+            for (c, value) in acc.iter().enumerate() {
+                output[out + c] = value.clamp(0.0, 255.0).round() as u8;
+            }
+        }
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{imageops::FilterType, ImageBuffer, Rgba};
+
+    #[test]
+    fn test_matches_image_crate_on_gradient() {
+        const SIZE: u32 = 729;
+        let rgba: Vec<u8> = (0..SIZE * SIZE)
+            .flat_map(|i| {
+                let (x, y) = (i % SIZE, i / SIZE);
+                [(x * 255 / SIZE) as u8, (y * 255 / SIZE) as u8, ((x + y) % 256) as u8, 255]
+            })
+            .collect();
+
+        let kernels = [
+            (LANCZOS3, FilterType::Lanczos3),
+            (TRIANGLE, FilterType::Triangle),
+            (CATMULL_ROM, FilterType::CatmullRom),
+        ];
+        for ((width, height), (kernel, filter)) in [(81, 81), (100, 40)].into_iter().flat_map(|size| kernels.map(|k| (size, k))) {
+            let ours = resize_rgba(&rgba, SIZE, SIZE, width, height, kernel);
+
+            let img = ImageBuffer::<Rgba<u8>, Vec<u8>>::from_raw(SIZE, SIZE, rgba.clone()).unwrap();
+            let reference = image::imageops::resize(&img, width, height, filter).into_raw();
+
+            assert_eq!(ours.len(), reference.len());
+            let max_diff = ours
+                .iter()
+                .zip(&reference)
+                .map(|(&a, &b)| (a as i16 - b as i16).abs())
+                .max()
+                .unwrap();
+            assert!(max_diff <= 1, "{:?} {}x{}: max channel difference {}", filter, width, height, max_diff);
+        }
+    }
+}
@@ -8,15 +8,25 @@ use thiserror::Error;
 
 // Add the new module
 mod m2m3_bridge;
+#[cfg(any(test, not(feature = "external-image")))]
+mod lanczos;
 
 // Re-export the new types and functions for UniFFI
 pub use m2m3_bridge::{
     QuantizedCubeData,
+    SegmentedCubeData,
     GifInfo,
     GifValidation,
     m2_quantize_for_cube,
+    m2_quantize_for_cube_segmented,
     m3_write_gif_from_cube,
+    m3_write_gif_from_segments,
+    encode_thumbnail,
+    encode_under_budget,
+    encode_under_budget_with_floor,
     validate_gif_bytes,
+    BUDGET_MIN_COLORS,
+    DEFAULT_SCENE_THRESHOLD,
 };
 
 /// GIF creation errors
@@ -25,7 +35,7 @@ pub enum GifError {
     #[error("Invalid dimensions: {0}")]
     InvalidDimensions(String),
     
-    #[error("Invalid frame count: expected 81, got {0}")]
+    #[error("Invalid frame count: expected 1 to {}, got {0}", common_types::MAX_FRAME_COUNT)]
     InvalidFrameCount(usize),
     
     #[error("Quantization failed: {0}")]
@@ -34,8 +44,14 @@ pub enum GifError {
     #[error("Encoding failed: {0}")]
     EncodingError(String),
     
+    #[error("Frame {frame_idx} encoding failed: {message}")]
+    FrameEncoding { frame_idx: u32, message: String },
+    
     #[error("IO error: {0}")]
     IoError(String),
+    
+    #[error("Invalid GIF data: {0}")]
+    InvalidGif(String),
 }
 
 /// Statistics about the created GIF
@@ -47,11 +63,274 @@ pub struct GifStats {
     pub compression_ratio: f32,
 }
 
+/// NETSCAPE2.0 looping behaviour for encoded GIFs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoopMode {
+    /// No NETSCAPE extension: play once
+    Once,
+    /// Loop forever (NETSCAPE loop count 0)
+    Infinite,
+    /// Explicit NETSCAPE loop count; 0 behaves like `Once`
+    Finite(u16),
+}
+
+impl From<bool> for LoopMode {
+    fn from(loop_forever: bool) -> Self {
+        if loop_forever {
+            LoopMode::Infinite
+        } else {
+            LoopMode::Once
+        }
+    }
+}
+
+/// Seam ΔE (Oklab) below which a loop always counts as seamless
+const LOOP_SEAM_MIN_DELTA_E: f32 = 0.02;
+/// How much larger than an average frame step the last-to-first jump may be
+const LOOP_SEAM_STEP_RATIO: f32 = 1.5;
+
+/// Recommend looping from how well the last RGB frame leads back into the first
+///
+/// A capture loops well when jumping from the last frame to the first is no
+/// bigger than an ordinary frame-to-frame step. Otherwise it is a one-shot
+/// gesture, and `Once` plays it through and holds on the final frame.
+pub fn suggest_loop_mode(frames_rgb: &[Vec<u8>]) -> LoopMode {
+    if frames_rgb.len() < 2 {
+        return LoopMode::Once;
+    }
+    
+    let seam = mean_frame_delta_e(&frames_rgb[frames_rgb.len() - 1], &frames_rgb[0]);
+    let mean_step = frames_rgb.windows(2)
+        .map(|pair| mean_frame_delta_e(&pair[0], &pair[1]))
+        .sum::<f32>() / (frames_rgb.len() - 1) as f32;
+    let threshold = (mean_step * LOOP_SEAM_STEP_RATIO).max(LOOP_SEAM_MIN_DELTA_E);
+    log::debug!("M3_LOOP_SUGGEST seam={:.4} mean_step={:.4} threshold={:.4}", seam, mean_step, threshold);
+    
+    if seam <= threshold {
+        LoopMode::Infinite
+    } else {
+        LoopMode::Once
+    }
+}
+
+/// Mean per-pixel Oklab ΔE between two RGB frames
+fn mean_frame_delta_e(a: &[u8], b: &[u8]) -> f32 {
+    use common_types::oklab::{delta_e_oklab, rgb_to_oklab};
+    
+    let pixels = a.len().min(b.len()) / 3;
+    if pixels == 0 {
+        return 0.0;
+    }
+    let total: f32 = a.chunks_exact(3).zip(b.chunks_exact(3))
+        .map(|(pa, pb)| delta_e_oklab(rgb_to_oklab(pa[0], pa[1], pa[2]), rgb_to_oklab(pb[0], pb[1], pb[2])))
+        .sum();
+    total / pixels as f32
+}
+
+/// Logical screen that frames are placed on when encoding
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Canvas {
+    pub width: u16,
+    pub height: u16,
+    pub frame_width: u16,
+    pub frame_height: u16,
+    /// Frame offset within the canvas
+    pub left: u16,
+    pub top: u16,
+    /// Margin color, written as global color table entry 0 when the canvas has margins
+    pub background_rgb: [u8; 3],
+}
+
+impl Canvas {
+    /// Canvas exactly the size of the frames (no margins)
+    pub fn for_frames(frame_width: u16, frame_height: u16) -> Self {
+        Self {
+            width: frame_width,
+            height: frame_height,
+            frame_width,
+            frame_height,
+            left: 0,
+            top: 0,
+            background_rgb: [0, 0, 0],
+        }
+    }
+    
+    /// Grow the canvas to `width`×`height` and center the frames on it
+    pub fn centered_in(mut self, width: u16, height: u16) -> Self {
+        self.width = width;
+        self.height = height;
+        self.left = width.saturating_sub(self.frame_width) / 2;
+        self.top = height.saturating_sub(self.frame_height) / 2;
+        self
+    }
+    
+    /// Place the frames at an explicit offset
+    pub fn with_offset(mut self, left: u16, top: u16) -> Self {
+        self.left = left;
+        self.top = top;
+        self
+    }
+    
+    pub fn with_background(mut self, rgb: [u8; 3]) -> Self {
+        self.background_rgb = rgb;
+        self
+    }
+    
+    fn has_margins(&self) -> bool {
+        self.width != self.frame_width || self.height != self.frame_height
+    }
+    
+    fn validate(&self) -> Result<(), GifError> {
+        let fits_x = self.left as u32 + self.frame_width as u32 <= self.width as u32;
+        let fits_y = self.top as u32 + self.frame_height as u32 <= self.height as u32;
+        if !fits_x || !fits_y {
+            return Err(GifError::InvalidDimensions(format!(
+                "{}x{} frame at ({}, {}) does not fit {}x{} canvas",
+                self.frame_width, self.frame_height, self.left, self.top, self.width, self.height
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Channel layout of caller-supplied frames
+///
+/// Everything downstream works on tightly-packed RGBA; other layouts are
+/// swizzled once at the entry point so hosts can pass camera buffers as-is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    Rgb,
+    Rgba,
+    Bgra,
+}
+
+impl PixelFormat {
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelFormat::Rgb => 3,
+            PixelFormat::Rgba | PixelFormat::Bgra => 4,
+        }
+    }
+}
+
+/// Convert frames of `pixels_per_frame` pixels in `format` to RGBA
+fn frames_to_rgba(
+    frames: Vec<Vec<u8>>,
+    format: PixelFormat,
+    pixels_per_frame: usize,
+) -> Result<Vec<Vec<u8>>, GifError> {
+    let expected_len = pixels_per_frame * format.bytes_per_pixel();
+    
+    frames
+        .into_iter()
+        .enumerate()
+        .map(|(idx, frame)| {
+            if frame.len() != expected_len {
+                return Err(GifError::InvalidDimensions(
+                    format!("Frame {} has {} bytes, expected {} ({:?})", idx, frame.len(), expected_len, format)
+                ));
+            }
+            
+            Ok(match format {
+                PixelFormat::Rgba => frame,
+                PixelFormat::Bgra => frame
+                    .chunks_exact(4)
+                    .flat_map(|px| [px[2], px[1], px[0], px[3]])
+                    .collect(),
+                PixelFormat::Rgb => frame
+                    .chunks_exact(3)
+                    .flat_map(|px| [px[0], px[1], px[2], 255])
+                    .collect(),
+            })
+        })
+        .collect()
+}
+
+/// Write the NETSCAPE2.0 application extension with a little-endian loop count
+fn write_netscape_loop<W: Write>(output: &mut W, loop_count: u16) -> std::io::Result<()> {
+    output.write_all(&[0x21, 0xFF, 0x0B])?; // Extension introducer + app label + block size
+    output.write_all(b"NETSCAPE2.0")?;
+    output.write_all(&[0x03, 0x01])?; // Sub-block size + loop sub-block ID
+    output.write_all(&loop_count.to_le_bytes())?;
+    output.write_all(&[0x00]) // Block terminator
+}
+
+/// Write the NETSCAPE2.0 extension for `loop_mode`, if it needs one
+fn write_loop_mode<W: Write>(encoder: &mut Encoder<W>, loop_mode: LoopMode) -> Result<(), GifError> {
+    match loop_mode {
+        LoopMode::Infinite => {
+            encoder.set_repeat(Repeat::Infinite)
+                .map_err(|e| GifError::EncodingError(e.to_string()))?;
+        }
+        LoopMode::Finite(count) if count > 0 => {
+            // Write the block directly so the exact count lands in the file
+            write_netscape_loop(encoder.get_mut(), count)
+                .map_err(|e| GifError::EncodingError(e.to_string()))?;
+        }
+        LoopMode::Finite(_) | LoopMode::Once => {}
+    }
+    Ok(())
+}
+
 /// Quantization method for color reduction
 #[derive(Debug, Clone, Copy)]
 pub enum QuantizationMethod {
     NeuQuant { colors: u16, sample_fac: u8 },
     MedianCut { colors: u16 },
+    /// NeuQuant with a palette size recommended from the frame's color complexity
+    Auto(AutoPaletteConfig),
+    /// Uniform gray ramp of `levels` entries (2..=256), pixels mapped by luminance
+    GrayscaleRamp { levels: u16 },
+}
+
+/// Bounds for the automatically recommended palette size
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AutoPaletteConfig {
+    pub min: u16,
+    pub max: u16,
+}
+
+impl Default for AutoPaletteConfig {
+    fn default() -> Self {
+        Self { min: 2, max: 256 }
+    }
+}
+
+impl AutoPaletteConfig {
+    /// Clamp a palette size into [min, max], itself limited to GIF's 2..=256
+    pub fn clamp(&self, colors: u16) -> u16 {
+        let max = self.max.clamp(2, 256);
+        let min = self.min.clamp(2, max);
+        colors.clamp(min, max)
+    }
+}
+
+/// Recommend a palette size for RGBA frames from their color complexity
+///
+/// Counts distinct colors at 5 bits per channel (ignoring sensor noise) and
+/// rounds up to a power of two, then clamps into the configured bounds.
+/// Takes owned frames or borrowed slices, so a single frame needn't be copied.
+pub fn recommend_palette_size(frames_rgba: &[impl AsRef<[u8]>], config: AutoPaletteConfig) -> u16 {
+    let mut seen = vec![false; 1 << 15];
+    let mut distinct = 0usize;
+    
+    'frames: for frame in frames_rgba {
+        for px in frame.as_ref().chunks_exact(4) {
+            let key = ((px[0] as usize >> 3) << 10) | ((px[1] as usize >> 3) << 5) | (px[2] as usize >> 3);
+            if !seen[key] {
+                seen[key] = true;
+                distinct += 1;
+                if distinct >= 256 {
+                    break 'frames;
+                }
+            }
+        }
+    }
+    
+    let recommended = distinct.max(2).next_power_of_two().min(256) as u16;
+    log::debug!("M2_AUTO_PALETTE distinct={} recommended={}", distinct, recommended);
+    
+    config.clamp(recommended)
 }
 
 impl Default for QuantizationMethod {
@@ -64,6 +343,28 @@ impl Default for QuantizationMethod {
     }
 }
 
+/// Floyd-Steinberg error diffusion settings
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DitherConfig {
+    /// Limit accumulated per-channel error to [-limit, limit] before it is applied.
+    /// `None` diffuses unbounded error; a limit suppresses worming at hard edges.
+    pub error_clamp: Option<u8>,
+}
+
+impl DitherConfig {
+    pub fn with_error_clamp(mut self, limit: u8) -> Self {
+        self.error_clamp = Some(limit);
+        self
+    }
+
+    fn clamp_error(&self, error: i32) -> i32 {
+        match self.error_clamp {
+            Some(limit) => error.clamp(-(limit as i32), limit as i32),
+            None => error,
+        }
+    }
+}
+
 /// Quantize RGBA data to indexed color with palette
 /// Returns (RGB palette, indices)
 /// Enhanced with scene change detection and adaptive fallbacks
@@ -72,6 +373,17 @@ pub fn quantize_rgba_to_lct(
     width: u16,
     height: u16,
     method: QuantizationMethod,
+) -> Result<(Vec<u8>, Vec<u8>), GifError> {
+    quantize_rgba_to_lct_with_dither(rgba, width, height, method, DitherConfig::default())
+}
+
+/// Quantize RGBA data to indexed color with explicit dithering settings
+pub fn quantize_rgba_to_lct_with_dither(
+    rgba: &[u8],
+    width: u16,
+    height: u16,
+    method: QuantizationMethod,
+    dither: DitherConfig,
 ) -> Result<(Vec<u8>, Vec<u8>), GifError> {
     let pixel_count = (width as usize) * (height as usize);
     
@@ -83,86 +395,236 @@ pub fn quantize_rgba_to_lct(
     
     match method {
         QuantizationMethod::NeuQuant { colors, sample_fac } => {
-            // NeuQuant expects RGBA data (4 bytes per pixel) 
-            // We already have RGBA, so use it directly
+            Ok(neuquant_quantize(rgba, width.into(), height.into(), colors, sample_fac, dither))
+        }
+        
+        QuantizationMethod::Auto(config) => {
+            let colors = recommend_palette_size(&[rgba], config);
+            Ok(neuquant_quantize(rgba, width.into(), height.into(), colors, 10, dither))
+        }
+        
+        QuantizationMethod::MedianCut { colors } => {
+            // Fallback median-cut implementation
+            // For now, use simple frequency-based selection
+            median_cut_quantize(rgba, width, height, colors)
+        }
+        
+        QuantizationMethod::GrayscaleRamp { levels } => {
+            Ok(grayscale_ramp_quantize(rgba, width, height, levels, dither))
+        }
+    }
+}
+
+/// NeuQuant palette with Floyd-Steinberg dithered mapping
+///
+/// Takes `usize` dimensions so stacked frame runs can be taller than a GIF image.
+pub(crate) fn neuquant_quantize(
+    rgba: &[u8],
+    width: usize,
+    height: usize,
+    colors: u16,
+    sample_fac: u8,
+    dither: DitherConfig,
+) -> (Vec<u8>, Vec<u8>) {
+    let pixel_count = width * height;
+    
+    // NeuQuant expects RGBA data (4 bytes per pixel)
+    // We already have RGBA, so use it directly
+    
+    // Run NeuQuant quantization
+    let nq = NeuQuant::new(sample_fac as i32, colors as usize, rgba);
+    
+    // Get the palette (RGB format)
+    let palette = nq.color_map_rgb();
+    
+    // Map pixels to palette indices with Floyd-Steinberg dithering
+    let mut indices = Vec::with_capacity(pixel_count);
+    let mut error_r = vec![0i32; pixel_count];
+    let mut error_g = vec![0i32; pixel_count];
+    let mut error_b = vec![0i32; pixel_count];
+    
+    for y in 0..height {
+        for x in 0..width {
+            let i = y * width + x;
+            let idx = i * 4;  // RGBA data, 4 bytes per pixel
             
-            // Run NeuQuant quantization
-            let nq = NeuQuant::new(sample_fac as i32, colors as usize, rgba);
+            // Apply accumulated error
+            let r = (rgba[idx] as i32 + dither.clamp_error(error_r[i])).clamp(0, 255) as u8;
+            let g = (rgba[idx + 1] as i32 + dither.clamp_error(error_g[i])).clamp(0, 255) as u8;
+            let b = (rgba[idx + 2] as i32 + dither.clamp_error(error_b[i])).clamp(0, 255) as u8;
             
-            // Get the palette (RGB format)
-            let palette = nq.color_map_rgb();
+            // Find nearest palette color
+            // NeuQuant's index_of expects RGBA (4 bytes)
+            let index = nq.index_of(&[r, g, b, 255]) as u8;
+            indices.push(index);
             
-            // Map pixels to palette indices with Floyd-Steinberg dithering
-            let mut indices = Vec::with_capacity(pixel_count);
-            let mut error_r = vec![0i32; pixel_count];
-            let mut error_g = vec![0i32; pixel_count];
-            let mut error_b = vec![0i32; pixel_count];
+            // Calculate quantization error
+            let palette_idx = index as usize * 3;
+            let err_r = r as i32 - palette[palette_idx] as i32;
+            let err_g = g as i32 - palette[palette_idx + 1] as i32;
+            let err_b = b as i32 - palette[palette_idx + 2] as i32;
             
-            for y in 0..height as usize {
-                for x in 0..width as usize {
-                    let i = y * width as usize + x;
-                    let idx = i * 4;  // RGBA data, 4 bytes per pixel
-                    
-                    // Apply accumulated error
-                    let r = (rgba[idx] as i32 + error_r[i]).clamp(0, 255) as u8;
-                    let g = (rgba[idx + 1] as i32 + error_g[i]).clamp(0, 255) as u8;
-                    let b = (rgba[idx + 2] as i32 + error_b[i]).clamp(0, 255) as u8;
-                    
-                    // Find nearest palette color
-                    // NeuQuant's index_of expects RGBA (4 bytes)
-                    let index = nq.index_of(&[r, g, b, 255]) as u8;
-                    indices.push(index);
-                    
-                    // Calculate quantization error
-                    let palette_idx = index as usize * 3;
-                    let err_r = r as i32 - palette[palette_idx] as i32;
-                    let err_g = g as i32 - palette[palette_idx + 1] as i32;
-                    let err_b = b as i32 - palette[palette_idx + 2] as i32;
-                    
-                    // Distribute error using Floyd-Steinberg coefficients
-                    // Right: 7/16
-                    if x + 1 < width as usize {
-                        let idx_right = i + 1;
-                        error_r[idx_right] += (err_r * 7) / 16;
-                        error_g[idx_right] += (err_g * 7) / 16;
-                        error_b[idx_right] += (err_b * 7) / 16;
-                    }
-                    
-                    // Below-left: 3/16
-                    if y + 1 < height as usize && x > 0 {
-                        let idx_bl = i + width as usize - 1;
-                        error_r[idx_bl] += (err_r * 3) / 16;
-                        error_g[idx_bl] += (err_g * 3) / 16;
-                        error_b[idx_bl] += (err_b * 3) / 16;
-                    }
-                    
-                    // Below: 5/16
-                    if y + 1 < height as usize {
-                        let idx_below = i + width as usize;
-                        error_r[idx_below] += (err_r * 5) / 16;
-                        error_g[idx_below] += (err_g * 5) / 16;
-                        error_b[idx_below] += (err_b * 5) / 16;
-                    }
-                    
-                    // Below-right: 1/16
-                    if y + 1 < height as usize && x + 1 < width as usize {
-                        let idx_br = i + width as usize + 1;
-                        error_r[idx_br] += err_r / 16;
-                        error_g[idx_br] += err_g / 16;
-                        error_b[idx_br] += err_b / 16;
-                    }
-                }
+            // Distribute error using Floyd-Steinberg coefficients
+            // Right: 7/16
+            if x + 1 < width {
+                let idx_right = i + 1;
+                error_r[idx_right] += (err_r * 7) / 16;
+                error_g[idx_right] += (err_g * 7) / 16;
+                error_b[idx_right] += (err_b * 7) / 16;
             }
             
-            Ok((palette, indices))
+            // Below-left: 3/16
+            if y + 1 < height && x > 0 {
+                let idx_bl = i + width - 1;
+                error_r[idx_bl] += (err_r * 3) / 16;
+                error_g[idx_bl] += (err_g * 3) / 16;
+                error_b[idx_bl] += (err_b * 3) / 16;
+            }
+            
+            // Below: 5/16
+            if y + 1 < height {
+                let idx_below = i + width;
+                error_r[idx_below] += (err_r * 5) / 16;
+                error_g[idx_below] += (err_g * 5) / 16;
+                error_b[idx_below] += (err_b * 5) / 16;
+            }
+            
+            // Below-right: 1/16
+            if y + 1 < height && x + 1 < width {
+                let idx_br = i + width + 1;
+                error_r[idx_br] += err_r / 16;
+                error_g[idx_br] += err_g / 16;
+                error_b[idx_br] += err_b / 16;
+            }
         }
-        
-        QuantizationMethod::MedianCut { colors } => {
-            // Fallback median-cut implementation
-            // For now, use simple frequency-based selection
-            median_cut_quantize(rgba, width, height, colors)
+    }
+    
+    (palette, indices)
+}
+
+/// Map pixels onto a uniform gray ramp by BT.601 luminance
+/// Floyd-Steinberg diffusion runs on the single luminance channel, so
+/// smooth gradients don't band even with few levels
+fn grayscale_ramp_quantize(
+    rgba: &[u8],
+    width: u16,
+    height: u16,
+    levels: u16,
+    dither: DitherConfig,
+) -> (Vec<u8>, Vec<u8>) {
+    let (width, height) = (width as usize, height as usize);
+    let steps = levels.clamp(2, 256) as i32 - 1;
+    
+    let palette: Vec<u8> = (0..=steps)
+        .flat_map(|level| {
+            let gray = ((level * 255 + steps / 2) / steps) as u8;
+            [gray, gray, gray]
+        })
+        .collect();
+    
+    let mut error = vec![0i32; width * height];
+    let mut indices = Vec::with_capacity(width * height);
+    
+    for y in 0..height {
+        for x in 0..width {
+            let i = y * width + x;
+            let px = &rgba[i * 4..i * 4 + 3];
+            let luma = (299 * px[0] as i32 + 587 * px[1] as i32 + 114 * px[2] as i32 + 500) / 1000;
+            let value = (luma + dither.clamp_error(error[i])).clamp(0, 255);
+            
+            let level = (value * steps + 127) / 255;
+            indices.push(level as u8);
+            
+            let err = value - palette[level as usize * 3] as i32;
+            if x + 1 < width {
+                error[i + 1] += (err * 7) / 16;
+            }
+            if y + 1 < height {
+                if x > 0 {
+                    error[i + width - 1] += (err * 3) / 16;
+                }
+                error[i + width] += (err * 5) / 16;
+                if x + 1 < width {
+                    error[i + width + 1] += err / 16;
+                }
+            }
+        }
+    }
+    
+    (palette, indices)
+}
+
+/// Indexed frame whose palette may reserve a slot for transparency
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransparentQuantization {
+    pub palette_rgb: Vec<u8>,
+    pub indices: Vec<u8>,
+    /// Palette slot reserved for pixels below the alpha threshold, if any were found
+    pub transparent_index: Option<u8>,
+}
+
+/// Same method with its palette limited to `max_colors` entries
+fn cap_palette_size(method: QuantizationMethod, max_colors: u16) -> QuantizationMethod {
+    match method {
+        QuantizationMethod::NeuQuant { colors, sample_fac } => {
+            QuantizationMethod::NeuQuant { colors: colors.min(max_colors), sample_fac }
+        }
+        QuantizationMethod::MedianCut { colors } => QuantizationMethod::MedianCut { colors: colors.min(max_colors) },
+        QuantizationMethod::Auto(config) => QuantizationMethod::Auto(AutoPaletteConfig {
+            min: config.min,
+            max: config.max.min(max_colors),
+        }),
+        QuantizationMethod::GrayscaleRamp { levels } => {
+            QuantizationMethod::GrayscaleRamp { levels: levels.min(max_colors) }
+        }
+    }
+}
+
+/// Quantize RGBA data, reserving one palette slot for transparent pixels
+///
+/// Pixels with alpha below `alpha_threshold` are transparent. When any exist,
+/// the opaque pixels get at most 255 colors and the one remaining index is
+/// assigned to transparency, so it can never collide with a real color.
+/// Transparent pixels take the color of the previous opaque pixel while the
+/// palette is built, keeping their (often garbage) RGB out of it.
+pub fn quantize_rgba_with_transparency(
+    rgba: &[u8],
+    width: u16,
+    height: u16,
+    method: QuantizationMethod,
+    alpha_threshold: u8,
+) -> Result<TransparentQuantization, GifError> {
+    let is_transparent = |px: &[u8]| px[3] < alpha_threshold;
+    
+    if !rgba.chunks_exact(4).any(is_transparent) {
+        let (palette_rgb, indices) = quantize_rgba_to_lct(rgba, width, height, method)?;
+        return Ok(TransparentQuantization { palette_rgb, indices, transparent_index: None });
+    }
+    
+    let fill = rgba.chunks_exact(4).find(|px| !is_transparent(px)).map_or([0, 0, 0], |px| [px[0], px[1], px[2]]);
+    let mut opaque_rgba = rgba.to_vec();
+    let mut last_opaque = fill;
+    for px in opaque_rgba.chunks_exact_mut(4) {
+        if px[3] < alpha_threshold {
+            px[..3].copy_from_slice(&last_opaque);
+        } else {
+            last_opaque = [px[0], px[1], px[2]];
         }
+        px[3] = 255;
     }
+    
+    let (mut palette_rgb, mut indices) =
+        quantize_rgba_to_lct(&opaque_rgba, width, height, cap_palette_size(method, 255))?;
+    
+    let transparent_index = (palette_rgb.len() / 3) as u8;
+    palette_rgb.extend_from_slice(&[0, 0, 0]);
+    for (index, px) in indices.iter_mut().zip(rgba.chunks_exact(4)) {
+        if is_transparent(px) {
+            *index = transparent_index;
+        }
+    }
+    
+    Ok(TransparentQuantization { palette_rgb, indices, transparent_index: Some(transparent_index) })
 }
 
 /// Simple median-cut quantization (fallback)
@@ -245,56 +707,191 @@ fn find_nearest_palette_index(
 }
 
 /// Create a GIF89a from RGBA frames
-/// Implements full spec: Header, LSD, NETSCAPE2.0, per-frame GCE+LCT+LZW
+/// Implements full spec: Header, LSD(+GCT), NETSCAPE2.0, per-frame GCE+LCT+LZW
+///
+/// `global_palette_rgb` is written as the global color table (empty for none);
+/// frames always carry their own local tables. With `strict_81` the input is
+/// expected to be the 81-frame 81×81 cube; see `encode_gif89a_rgba_on_canvas`.
+#[allow(clippy::too_many_arguments)]
 pub fn encode_gif89a_rgba(
     frames: &[Vec<u8>],
     width: u16,
     height: u16,
     delay_cs: u16,
-    loop_forever: bool,
+    loop_mode: LoopMode,
     method: QuantizationMethod,
+    global_palette_rgb: &[u8],
+    strict_81: bool,
 ) -> Result<Vec<u8>, GifError> {
-    // Validate frame count (must have at least 1 frame, 81 is optimal)
+    encode_gif89a_rgba_on_canvas(
+        frames,
+        &Canvas::for_frames(width, height),
+        delay_cs,
+        loop_mode,
+        method,
+        global_palette_rgb,
+        strict_81,
+    )
+}
+
+/// Global color table for `canvas`, with its background color as entry 0 when margins show it
+///
+/// Frames carry local color tables, so reordering the global one only changes
+/// which color the background index points at.
+fn canvas_global_palette(canvas: &Canvas, global_palette_rgb: &[u8]) -> Result<Vec<u8>, GifError> {
+    let mut palette = global_palette_rgb.to_vec();
+    if !canvas.has_margins() {
+        return Ok(palette);
+    }
+    
+    let background = canvas.background_rgb;
+    match palette.chunks_exact(3).position(|rgb| rgb == background) {
+        Some(index) => {
+            for channel in 0..3 {
+                palette.swap(channel, index * 3 + channel);
+            }
+        }
+        None if palette.len() < 256 * 3 => {
+            palette.splice(0..0, background);
+        }
+        None => {
+            return Err(GifError::EncodingError(format!(
+                "Background color {:?} is missing from the full 256-color global palette", background
+            )));
+        }
+    }
+    Ok(palette)
+}
+
+/// Warnings for input that departs from the 81-frame 81×81 cube
+///
+/// Only `strict_81` encodes treat the cube as the expected shape; otherwise
+/// any frame count and size is normal and nothing is reported.
+fn cube_invariant_warnings(frame_count: usize, width: u16, height: u16, strict_81: bool) -> Vec<String> {
+    let mut warnings = Vec::new();
+    if !strict_81 {
+        return warnings;
+    }
+    
+    if frame_count != 81 {
+        warnings.push(format!("Expected 81 frames for optimal GIF, got {}", frame_count));
+    }
+    if width != 81 || height != 81 {
+        warnings.push(format!("Expected 81x81 dimensions, got {}x{}", width, height));
+    }
+    warnings
+}
+
+/// Create a GIF89a from RGBA frames, keeping pixels with alpha below
+/// `alpha_threshold` transparent
+///
+/// Each frame reserves one palette slot for transparency (see
+/// `quantize_rgba_with_transparency`) and is disposed to background, so
+/// earlier frames never show through its transparent pixels.
+pub fn encode_gif89a_rgba_transparent(
+    frames: &[Vec<u8>],
+    width: u16,
+    height: u16,
+    delay_cs: u16,
+    loop_mode: LoopMode,
+    method: QuantizationMethod,
+    alpha_threshold: u8,
+) -> Result<Vec<u8>, GifError> {
+    encode_frames_on_canvas(
+        frames,
+        &Canvas::for_frames(width, height),
+        delay_cs,
+        loop_mode,
+        method,
+        &[],
+        true,
+        Some(alpha_threshold),
+    )
+}
+
+/// Create a GIF89a from RGBA frames placed on a (possibly larger) canvas
+///
+/// The logical screen is written at the canvas size and every frame's image
+/// descriptor carries the canvas offset. Margins show `canvas.background_rgb`
+/// through global color table entry 0: it is moved there when
+/// `global_palette_rgb` has it, otherwise inserted in front. A full 256-color
+/// global palette without it is rejected.
+///
+/// With `strict_81` the 81-frame 81×81 cube is the expected input: other
+/// shapes are warned about and output too small to hold the cube is rejected.
+/// Without it any frame count and size is normal, down to a single 1×1 frame.
+pub fn encode_gif89a_rgba_on_canvas(
+    frames: &[Vec<u8>],
+    canvas: &Canvas,
+    delay_cs: u16,
+    loop_mode: LoopMode,
+    method: QuantizationMethod,
+    global_palette_rgb: &[u8],
+    strict_81: bool,
+) -> Result<Vec<u8>, GifError> {
+    encode_frames_on_canvas(frames, canvas, delay_cs, loop_mode, method, global_palette_rgb, strict_81, None)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn encode_frames_on_canvas(
+    frames: &[Vec<u8>],
+    canvas: &Canvas,
+    delay_cs: u16,
+    loop_mode: LoopMode,
+    method: QuantizationMethod,
+    global_palette_rgb: &[u8],
+    strict_81: bool,
+    alpha_threshold: Option<u8>,
+) -> Result<Vec<u8>, GifError> {
+    let (width, height) = (canvas.frame_width, canvas.frame_height);
+    
+    // Validate frame count (must have at least 1 frame)
     if frames.is_empty() {
         return Err(GifError::InvalidFrameCount(0));
     }
     
-    if frames.len() != 81 {
-        log::warn!("Expected 81 frames for optimal GIF, got {}", frames.len());
+    for warning in cube_invariant_warnings(frames.len(), width, height, strict_81) {
+        log::warn!("{}", warning);
     }
+    log::debug!("M3_ENCODE frames={} size={}x{} strict81={}", frames.len(), width, height, strict_81);
     
-    // Validate dimensions (81x81 is expected)
-    if width != 81 || height != 81 {
-        log::warn!("Expected 81x81 dimensions, got {}x{}", width, height);
+    canvas.validate()?;
+    
+    if !global_palette_rgb.len().is_multiple_of(3) || global_palette_rgb.len() > 256 * 3 {
+        return Err(GifError::EncodingError(
+            format!("Invalid global palette size: {} bytes", global_palette_rgb.len())
+        ));
     }
     
+    // Margins need a background color, which lives in the global color table
+    let global_palette_rgb = canvas_global_palette(canvas, global_palette_rgb)?;
+    
     let mut output = Vec::new();
-    let mut encoder = Encoder::new(&mut output, width, height, &[])
+    let mut encoder = Encoder::new(&mut output, canvas.width, canvas.height, &global_palette_rgb)
         .map_err(|e| GifError::EncodingError(e.to_string()))?;
     
-    // Set infinite loop if requested (NETSCAPE2.0 extension)
-    if loop_forever {
-        encoder.set_repeat(Repeat::Infinite)
-            .map_err(|e| GifError::EncodingError(e.to_string()))?;
-    }
+    // NETSCAPE2.0 extension must follow the header and precede the first frame
+    write_loop_mode(&mut encoder, loop_mode)?;
     
     let mut palettes = Vec::new();
     
     // Process each frame
     for (idx, rgba_frame) in frames.iter().enumerate() {
         // Quantize frame to indexed color
-        let (palette, indices) = quantize_rgba_to_lct(
-            rgba_frame,
-            width,
-            height,
-            method,
-        )?;
+        let quantized = match alpha_threshold {
+            Some(threshold) => quantize_rgba_with_transparency(rgba_frame, width, height, method, threshold),
+            None => quantize_rgba_to_lct(rgba_frame, width, height, method).map(|(palette_rgb, indices)| {
+                TransparentQuantization { palette_rgb, indices, transparent_index: None }
+            }),
+        }
+        .map_err(|e| GifError::FrameEncoding { frame_idx: idx as u32, message: e.to_string() })?;
+        let TransparentQuantization { palette_rgb: palette, indices, transparent_index } = quantized;
         
         let palette_size = palette.len() / 3;
         palettes.push(palette_size as u16);
         
         // Log per-frame processing
-        log::debug!("M3_GCE idx={} delayCs={} dispose=1 trans=false", idx, delay_cs);
+        log::debug!("M3_GCE idx={} delayCs={} dispose=1 trans={}", idx, delay_cs, transparent_index.is_some());
         log::debug!("M3_ID idx={} lct={}", idx, palette_size);
         
         // Calculate minimum code size for LZW
@@ -303,6 +900,8 @@ pub fn encode_gif89a_rgba(
         
         // Create frame with proper dimensions and data
         let mut frame = Frame::default();
+        frame.left = canvas.left;
+        frame.top = canvas.top;
         frame.width = width;
         frame.height = height;
         frame.buffer = Cow::Borrowed(&indices);
@@ -311,16 +910,21 @@ pub fn encode_gif89a_rgba(
         // Set frame delay (in centiseconds)
         frame.delay = delay_cs;
         
+        if transparent_index.is_some() {
+            frame.transparent = transparent_index;
+            frame.dispose = gif::DisposalMethod::Background;
+        }
+        
         // Write frame with proper LZW compression
         encoder.write_frame(&frame)
-            .map_err(|e| GifError::EncodingError(format!("Frame {}: {}", idx, e)))?;
+            .map_err(|e| GifError::FrameEncoding { frame_idx: idx as u32, message: e.to_string() })?;
     }
     
     // Finish encoding
     drop(encoder);
     
     // Verify GIF structure (sanity check)
-    verify_gif_structure(&output, frames.len())?;
+    verify_gif_structure(&output, frames.len(), strict_81)?;
     
     let stats = GifStats {
         frames: frames.len() as u16,
@@ -360,48 +964,98 @@ fn calculate_min_code_size(palette_size: usize) -> u8 {
     }
 }
 
+/// Resampling filter for the 81×81 downscale
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DownsizeFilter {
+    /// Source pixel nearest each output pixel's center; fastest, keeps exact colors
+    Nearest,
+    /// Bilinear
+    Triangle,
+    /// Sharper cubic than Triangle, with less ringing than Lanczos3
+    CatmullRom,
+    #[default]
+    Lanczos3,
+    /// Unweighted mean of each output pixel's source block, as m2down's baseline
+    BoxAverage,
+}
+
+impl DownsizeFilter {
+    fn name(self) -> &'static str {
+        match self {
+            DownsizeFilter::Nearest => "Nearest",
+            DownsizeFilter::Triangle => "Triangle",
+            DownsizeFilter::CatmullRom => "CatmullRom",
+            DownsizeFilter::Lanczos3 => "Lanczos3",
+            DownsizeFilter::BoxAverage => "BoxAverage",
+        }
+    }
+}
+
 /// High-quality downscale from 729×729 to 81×81 using Lanczos3 filter (PANIC-SAFE)
 pub fn m2_downsize_rgba_729_to_81(rgba_729: Vec<u8>) -> Result<Vec<u8>, GifError> {
+    m2_downsize_rgba_729_to_81_with_filter(rgba_729, DownsizeFilter::Lanczos3, false)
+}
+
+/// Downscale from 729×729 to 81×81 with the given filter (PANIC-SAFE)
+///
+/// With `premultiply_alpha` colors are weighted by alpha while resampling, so
+/// the (usually black) color of transparent pixels doesn't darken the edges
+/// of opaque areas. Leave it off for input that is already premultiplied.
+pub fn m2_downsize_rgba_729_to_81_with_filter(
+    rgba_729: Vec<u8>,
+    filter: DownsizeFilter,
+    premultiply_alpha: bool,
+) -> Result<Vec<u8>, GifError> {
     // Log the downscaling method being used
-    log::info!("M2_DOWNSCALE_START method=Lanczos3 input=729x729 output=81x81");
+    log::info!(
+        "M2_DOWNSCALE_START method={} premultiply={} input=729x729 output=81x81",
+        filter.name(), premultiply_alpha
+    );
     
-    std::panic::catch_unwind(|| inner_downsize_rgba_729_to_81(rgba_729))
+    std::panic::catch_unwind(|| inner_downsize(rgba_729, 729, 729, filter, premultiply_alpha))
         .map_err(|_| GifError::EncodingError("Internal panic during downsize".to_string()))?
 }
 
-/// Internal downsize implementation (can panic, but caught by wrapper)
-fn inner_downsize_rgba_729_to_81(rgba_729: Vec<u8>) -> Result<Vec<u8>, GifError> {
-    const INPUT_SIZE: u32 = 729;
+/// High-quality Lanczos3 downscale of a `width`×`height` RGBA frame to 81×81 (PANIC-SAFE)
+///
+/// Same path as `m2_downsize_rgba_729_to_81` for capture resolutions other
+/// than 729². Non-square inputs are stretched to the square output.
+pub fn m2_downsize_rgba_to_81(rgba: Vec<u8>, width: u32, height: u32) -> Result<Vec<u8>, GifError> {
+    log::info!("M2_DOWNSCALE_START method=Lanczos3 input={}x{} output=81x81", width, height);
+    
+    std::panic::catch_unwind(|| inner_downsize(rgba, width, height, DownsizeFilter::Lanczos3, false))
+        .map_err(|_| GifError::EncodingError("Internal panic during downsize".to_string()))?
+}
+
+/// Downscale of a `width`×`height` RGBA frame to 81×81 (can panic, but caught by wrappers)
+fn inner_downsize(
+    rgba: Vec<u8>,
+    width: u32,
+    height: u32,
+    filter: DownsizeFilter,
+    premultiply_alpha: bool,
+) -> Result<Vec<u8>, GifError> {
     const OUTPUT_SIZE: u32 = 81;
     
-    if rgba_729.len() != (INPUT_SIZE * INPUT_SIZE * 4) as usize {
+    let expected_len = width as usize * height as usize * 4;
+    if width == 0 || height == 0 || rgba.len() != expected_len {
         return Err(GifError::InvalidDimensions(
-            format!("Expected {} bytes, got {}", INPUT_SIZE * INPUT_SIZE * 4, rgba_729.len())
+            format!("Expected {} bytes for {}x{}, got {}", expected_len, width, height, rgba.len())
         ));
     }
     
-    // Use image crate for high-quality resizing with Lanczos3
-    use image::{ImageBuffer, Rgba, imageops::FilterType};
-    
-    // Create image from RGBA bytes
-    let img = ImageBuffer::<Rgba<u8>, Vec<u8>>::from_raw(
-        INPUT_SIZE, 
-        INPUT_SIZE, 
-        rgba_729
-    ).ok_or_else(|| GifError::EncodingError("Failed to create image buffer".to_string()))?;
+    // Nearest copies whole pixels, so there is nothing to weight
+    let premultiply_alpha = premultiply_alpha && filter != DownsizeFilter::Nearest;
+    let rgba = if premultiply_alpha { premultiply(rgba) } else { rgba };
     
-    // Resize using Lanczos3 filter
-    let resized = image::imageops::resize(
-        &img,
-        OUTPUT_SIZE,
-        OUTPUT_SIZE,
-        FilterType::Lanczos3
-    );
-    
-    // Convert back to raw RGBA bytes
-    let output = resized.into_raw();
+    let output = match filter {
+        DownsizeFilter::Nearest => resize_nearest(&rgba, width, height, OUTPUT_SIZE),
+        DownsizeFilter::BoxAverage => resize_box_average(&rgba, width, height, OUTPUT_SIZE),
+        _ => resize_filtered(rgba, width, height, OUTPUT_SIZE, filter)?,
+    };
+    let output = if premultiply_alpha { unpremultiply(output) } else { output };
     
-    log::info!("M2_DOWNSCALE_DONE method=Lanczos3 output_size={}", output.len());
+    log::info!("M2_DOWNSCALE_DONE method={} output_size={}", filter.name(), output.len());
     
     // Log statistics for first few frames (thread-safe)
     static FRAME_COUNT: AtomicUsize = AtomicUsize::new(0);
@@ -437,15 +1091,140 @@ fn inner_downsize_rgba_729_to_81(rgba_729: Vec<u8>) -> Result<Vec<u8>, GifError>
     Ok(output)
 }
 
+/// Separable-filter resize of an RGBA image to a square output via the `image` crate
+#[cfg(feature = "external-image")]
+fn resize_filtered(
+    rgba: Vec<u8>,
+    input_width: u32,
+    input_height: u32,
+    output_size: u32,
+    filter: DownsizeFilter,
+) -> Result<Vec<u8>, GifError> {
+    use image::{ImageBuffer, Rgba, imageops::FilterType};
+    
+    // Create image from RGBA bytes
+    let img = ImageBuffer::<Rgba<u8>, Vec<u8>>::from_raw(
+        input_width, 
+        input_height, 
+        rgba
+    ).ok_or_else(|| GifError::EncodingError("Failed to create image buffer".to_string()))?;
+    
+    let filter_type = match filter {
+        DownsizeFilter::Triangle => FilterType::Triangle,
+        DownsizeFilter::CatmullRom => FilterType::CatmullRom,
+        _ => FilterType::Lanczos3,
+    };
+    let resized = image::imageops::resize(
+        &img,
+        output_size,
+        output_size,
+        filter_type
+    );
+    
+    // Convert back to raw RGBA bytes
+    Ok(resized.into_raw())
+}
+
+/// Separable-filter resize of an RGBA image to a square output with the in-crate resampler
+#[cfg(not(feature = "external-image"))]
+fn resize_filtered(
+    rgba: Vec<u8>,
+    input_width: u32,
+    input_height: u32,
+    output_size: u32,
+    filter: DownsizeFilter,
+) -> Result<Vec<u8>, GifError> {
+    let kernel = match filter {
+        DownsizeFilter::Triangle => lanczos::TRIANGLE,
+        DownsizeFilter::CatmullRom => lanczos::CATMULL_ROM,
+        _ => lanczos::LANCZOS3,
+    };
+    Ok(lanczos::resize_rgba(&rgba, input_width, input_height, output_size, output_size, kernel))
+}
+
+/// Scale each pixel's color channels by its alpha
+fn premultiply(mut rgba: Vec<u8>) -> Vec<u8> {
+    for pixel in rgba.chunks_exact_mut(4) {
+        let alpha = pixel[3] as u32;
+        for channel in &mut pixel[..3] {
+            *channel = ((*channel as u32 * alpha + 127) / 255) as u8;
+        }
+    }
+    rgba
+}
+
+/// Undo `premultiply`; fully transparent pixels come out black
+fn unpremultiply(mut rgba: Vec<u8>) -> Vec<u8> {
+    for pixel in rgba.chunks_exact_mut(4) {
+        let alpha = pixel[3] as u32;
+        for channel in &mut pixel[..3] {
+            *channel = match alpha {
+                0 => 0,
+                _ => ((*channel as u32 * 255 + alpha / 2) / alpha).min(255) as u8,
+            };
+        }
+    }
+    rgba
+}
+
+/// Resize to a square output by copying the source pixel under each output pixel's center
+fn resize_nearest(rgba: &[u8], input_width: u32, input_height: u32, output_size: u32) -> Vec<u8> {
+    let source = |out: u32, len: u32| ((2 * out + 1) as u64 * len as u64 / (2 * output_size) as u64) as usize;
+    let mut output = Vec::with_capacity(output_size as usize * output_size as usize * 4);
+    for out_y in 0..output_size {
+        let row = source(out_y, input_height) * input_width as usize;
+        for out_x in 0..output_size {
+            let idx = (row + source(out_x, input_width)) * 4;
+            output.extend_from_slice(&rgba[idx..idx + 4]);
+        }
+    }
+    output
+}
+
+/// Resize to a square output by averaging each output pixel's source block
+///
+/// Truncates the mean like m2down's baseline block average, so a 9× integer
+/// downscale matches it byte for byte.
+fn resize_box_average(rgba: &[u8], input_width: u32, input_height: u32, output_size: u32) -> Vec<u8> {
+    let block = |out: u32, len: u32| {
+        let start = (out as u64 * len as u64 / output_size as u64) as usize;
+        let end = ((out + 1) as u64 * len as u64 / output_size as u64) as usize;
+        start..end.max(start + 1)
+    };
+    let mut output = Vec::with_capacity(output_size as usize * output_size as usize * 4);
+    for out_y in 0..output_size {
+        let rows = block(out_y, input_height);
+        for out_x in 0..output_size {
+            let columns = block(out_x, input_width);
+            let mut sums = [0u64; 4];
+            for y in rows.clone() {
+                for x in columns.clone() {
+                    let idx = (y * input_width as usize + x) * 4;
+                    for (sum, &value) in sums.iter_mut().zip(&rgba[idx..idx + 4]) {
+                        *sum += value as u64;
+                    }
+                }
+            }
+            let count = (rows.len() * columns.len()) as u64;
+            output.extend(sums.map(|sum| (sum / count) as u8));
+        }
+    }
+    output
+}
+
 fn calculate_compression_ratio(frames: &[Vec<u8>], compressed: &[u8]) -> f32 {
     let uncompressed_size: usize = frames.iter().map(|f| f.len()).sum();
     uncompressed_size as f32 / compressed.len() as f32
 }
 
 /// Verify GIF structure for sanity (catch "black GIF" issues early)
-fn verify_gif_structure(gif_data: &[u8], expected_frames: usize) -> Result<(), GifError> {
+///
+/// The 100-byte floor only holds for the 81-frame cube, so other encodes just
+/// need room for the header, logical screen descriptor and trailer.
+fn verify_gif_structure(gif_data: &[u8], expected_frames: usize, strict_81: bool) -> Result<(), GifError> {
     // Check minimum size
-    if gif_data.len() < 100 {
+    let min_size = if strict_81 { 100 } else { 14 };
+    if gif_data.len() < min_size {
         return Err(GifError::EncodingError(
             format!("GIF too small: {} bytes", gif_data.len())
         ));
@@ -487,24 +1266,231 @@ fn verify_gif_structure(gif_data: &[u8], expected_frames: usize) -> Result<(), G
         ));
     }
     
-    log::debug!(
-        "GIF verification passed: {} bytes, {} image separators",
-        gif_data.len(),
-        image_separators
+    log::debug!(
+        "GIF verification passed: {} bytes, {} image separators",
+        gif_data.len(),
+        image_separators
+    );
+    
+    Ok(())
+}
+
+/// Read the global color table from encoded GIF bytes
+///
+/// Parses the header and logical screen descriptor and returns every GCT entry,
+/// including any padding up to the power-of-two table size. Returns an empty
+/// palette when the file has no global color table.
+pub fn read_global_palette(gif_bytes: &[u8]) -> Result<Vec<[u8; 3]>, GifError> {
+    // 6-byte signature + 7-byte logical screen descriptor
+    if gif_bytes.len() < 13 {
+        return Err(GifError::InvalidGif(
+            format!("GIF too small for logical screen descriptor: {} bytes", gif_bytes.len())
+        ));
+    }
+    
+    if &gif_bytes[0..3] != b"GIF" {
+        return Err(GifError::InvalidGif(
+            "Invalid GIF header: missing GIF signature".to_string()
+        ));
+    }
+    
+    let packed = gif_bytes[10];
+    if packed & 0x80 == 0 {
+        return Ok(Vec::new());
+    }
+    
+    let table_size = 1usize << ((packed & 0x07) + 1);
+    let table_end = 13 + table_size * 3;
+    if gif_bytes.len() < table_end {
+        return Err(GifError::InvalidGif(
+            format!("Global color table truncated: expected {} entries", table_size)
+        ));
+    }
+    
+    Ok(gif_bytes[13..table_end]
+        .chunks_exact(3)
+        .map(|rgb| [rgb[0], rgb[1], rgb[2]])
+        .collect())
+}
+
+/// Read each frame's delay in centiseconds from encoded GIF bytes
+///
+/// Walks the block stream after the global color table, taking the delay from
+/// the Graphic Control Extension preceding each image descriptor. Frames with
+/// no GCE report a delay of 0.
+pub fn read_frame_delays(gif_bytes: &[u8]) -> Result<Vec<u16>, GifError> {
+    let truncated = |what: &str| GifError::InvalidGif(format!("GIF truncated in {}", what));
+    
+    if gif_bytes.len() < 13 || &gif_bytes[0..3] != b"GIF" {
+        return Err(GifError::InvalidGif(
+            "Invalid GIF header: missing GIF signature".to_string()
+        ));
+    }
+    
+    // Skip past a chain of data sub-blocks, returning the offset after the terminator
+    let skip_sub_blocks = |mut pos: usize| -> Result<usize, GifError> {
+        loop {
+            let len = *gif_bytes.get(pos).ok_or_else(|| truncated("data sub-blocks"))? as usize;
+            pos += 1 + len;
+            if len == 0 {
+                return Ok(pos);
+            }
+        }
+    };
+    
+    let packed = gif_bytes[10];
+    let mut pos = 13;
+    if packed & 0x80 != 0 {
+        pos += 3 << ((packed & 0x07) + 1);
+    }
+    
+    let mut delays = Vec::new();
+    let mut pending_delay = None;
+    loop {
+        match *gif_bytes.get(pos).ok_or_else(|| truncated("block stream"))? {
+            0x21 => {
+                let label = *gif_bytes.get(pos + 1).ok_or_else(|| truncated("extension"))?;
+                if label == 0xF9 {
+                    // Block size 4: packed, delay (LE u16), transparent index
+                    let gce = gif_bytes.get(pos + 2..pos + 7).ok_or_else(|| truncated("graphic control extension"))?;
+                    pending_delay = Some(u16::from_le_bytes([gce[2], gce[3]]));
+                }
+                pos = skip_sub_blocks(pos + 2)?;
+            }
+            0x2C => {
+                let descriptor = gif_bytes.get(pos + 1..pos + 10).ok_or_else(|| truncated("image descriptor"))?;
+                let mut next = pos + 10;
+                if descriptor[8] & 0x80 != 0 {
+                    next += 3 << ((descriptor[8] & 0x07) + 1);
+                }
+                // LZW minimum code size, then the image data
+                pos = skip_sub_blocks(next + 1)?;
+                delays.push(pending_delay.take().unwrap_or(0));
+            }
+            0x3B => break,
+            other => {
+                return Err(GifError::InvalidGif(
+                    format!("Unexpected block 0x{:02X} at offset {}", other, pos)
+                ));
+            }
+        }
+    }
+    
+    Ok(delays)
+}
+
+/// Re-encode an existing GIF with at most `target_colors` colors per frame
+///
+/// Frames are decoded and composited onto the logical screen, re-quantized
+/// with NeuQuant to the smaller palette, and written back with their original
+/// delays and loop count. Useful to shrink a GIF that was already produced.
+pub fn reduce_gif_palette(gif_bytes: Vec<u8>, target_colors: u16) -> Result<Vec<u8>, GifError> {
+    let decode_err = |e: gif::DecodingError| GifError::InvalidGif(e.to_string());
+    
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::RGBA);
+    let mut decoder = options.read_info(gif_bytes.as_slice()).map_err(decode_err)?;
+    let (width, height) = (decoder.width(), decoder.height());
+    let canvas_width = width as usize;
+    
+    // Composite every frame onto the full logical screen, honoring disposal
+    let mut canvas = vec![0u8; canvas_width * height as usize * 4];
+    let mut frames = Vec::new();
+    while let Some(frame) = decoder.read_next_frame().map_err(decode_err)? {
+        let previous = (frame.dispose == gif::DisposalMethod::Previous).then(|| canvas.clone());
+        let (left, top) = (frame.left as usize, frame.top as usize);
+        
+        for (row, src_row) in frame.buffer.chunks_exact(frame.width as usize * 4).enumerate() {
+            let y = top + row;
+            if y >= height as usize {
+                break;
+            }
+            for (col, px) in src_row.chunks_exact(4).enumerate() {
+                let x = left + col;
+                if x < canvas_width && px[3] != 0 {
+                    let idx = (y * canvas_width + x) * 4;
+                    canvas[idx..idx + 4].copy_from_slice(px);
+                }
+            }
+        }
+        frames.push((canvas.clone(), frame.delay));
+        
+        match frame.dispose {
+            gif::DisposalMethod::Background => {
+                for y in top..(top + frame.height as usize).min(height as usize) {
+                    let start = (y * canvas_width + left.min(canvas_width)) * 4;
+                    let end = (y * canvas_width + (left + frame.width as usize).min(canvas_width)) * 4;
+                    canvas[start..end].fill(0);
+                }
+            }
+            gif::DisposalMethod::Previous => {
+                if let Some(previous) = previous {
+                    canvas = previous;
+                }
+            }
+            _ => {}
+        }
+    }
+    
+    if frames.is_empty() {
+        return Err(GifError::InvalidFrameCount(0));
+    }
+    
+    let loop_mode = match decoder.repeat() {
+        Repeat::Infinite => LoopMode::Infinite,
+        Repeat::Finite(0) => LoopMode::Once,
+        Repeat::Finite(count) => LoopMode::Finite(count),
+    };
+    let method = QuantizationMethod::NeuQuant {
+        colors: target_colors.clamp(2, 256),
+        sample_fac: 10,
+    };
+    
+    log::info!(
+        "M3_REDUCE frames={} size={}x{} colors={} inBytes={}",
+        frames.len(), width, height, target_colors, gif_bytes.len()
     );
     
-    Ok(())
+    let mut output = Vec::new();
+    let mut encoder = Encoder::new(&mut output, width, height, &[])
+        .map_err(|e| GifError::EncodingError(e.to_string()))?;
+    write_loop_mode(&mut encoder, loop_mode)?;
+    
+    for (idx, (rgba, delay)) in frames.iter().enumerate() {
+        let frame_err = |e: String| GifError::FrameEncoding { frame_idx: idx as u32, message: e };
+        let (palette, indices) = quantize_rgba_to_lct(rgba, width, height, method)
+            .map_err(|e| frame_err(e.to_string()))?;
+        
+        let frame = Frame {
+            width,
+            height,
+            buffer: Cow::Borrowed(&indices),
+            palette: Some(palette),
+            delay: *delay,
+            ..Frame::default()
+        };
+        
+        encoder.write_frame(&frame).map_err(|e| frame_err(e.to_string()))?;
+    }
+    drop(encoder);
+    
+    log::info!("M3_REDUCE_DONE outBytes={}", output.len());
+    
+    Ok(output)
 }
 
 /// Main entry point for UniFFI - creates GIF89a with NeuQuant quantization (PANIC-SAFE)
+///
+/// `strict_81` expects the 81-frame 81×81 cube; pass false for other shapes.
 pub fn m3_create_gif89a_rgba(
     frames_rgba: Vec<Vec<u8>>,
     width: u16,
     height: u16,
     delay_cs: u16,
     loop_forever: bool,
+    strict_81: bool,
 ) -> Result<GifStats, GifError> {
-    std::panic::catch_unwind(|| inner_create_gif89a_rgba(frames_rgba, width, height, delay_cs, loop_forever))
+    std::panic::catch_unwind(|| inner_create_gif89a_rgba(frames_rgba, width, height, delay_cs, loop_forever, strict_81))
         .map_err(|_| GifError::EncodingError("Internal panic during GIF creation".to_string()))?
 }
 
@@ -515,6 +1501,7 @@ fn inner_create_gif89a_rgba(
     height: u16,
     delay_cs: u16,
     loop_forever: bool,
+    strict_81: bool,
 ) -> Result<GifStats, GifError> {
     // Initialize Android logger if not already done
     android_logger::init_once(
@@ -537,8 +1524,10 @@ fn inner_create_gif89a_rgba(
         width,
         height,
         delay_cs,
-        loop_forever,
+        LoopMode::from(loop_forever),
         method,
+        &[],
+        strict_81,
     )?;
     
     // Calculate stats
@@ -552,6 +1541,20 @@ fn inner_create_gif89a_rgba(
     Ok(stats)
 }
 
+/// Create GIF89a from frames in any supported pixel format (PANIC-SAFE)
+pub fn m3_create_gif89a_with_format(
+    frames: Vec<Vec<u8>>,
+    format: PixelFormat,
+    width: u16,
+    height: u16,
+    delay_cs: u16,
+    loop_forever: bool,
+    strict_81: bool,
+) -> Result<GifStats, GifError> {
+    let frames_rgba = frames_to_rgba(frames, format, width as usize * height as usize)?;
+    m3_create_gif89a_rgba(frames_rgba, width, height, delay_cs, loop_forever, strict_81)
+}
+
 /// Export GIF data to file (for testing) (PANIC-SAFE)
 pub fn m3_save_gif_to_file(
     frames_rgba: Vec<Vec<u8>>,
@@ -632,8 +1635,10 @@ fn inner_save_gif_to_file(
         width,
         height,
         delay_cs,
-        true, // loop_forever
+        LoopMode::Infinite,
         method,
+        &[],
+        true,
     )?;
     
     // Write the encoded GIF data to file
@@ -702,6 +1707,176 @@ mod tests {
         assert_eq!(indices.len(), 4);  // 4 pixels
     }
     
+    #[test]
+    fn test_error_clamp_reduces_edge_deviation() {
+        // Dark ramp meeting a hard white edge, quantized to 4 colors
+        let (width, height) = (64u16, 64u16);
+        let rgba: Vec<u8> = (0..width as usize * height as usize)
+            .flat_map(|i| {
+                let x = i % width as usize;
+                let v = if x < 32 { (x * 3) as u8 } else { 255 };
+                [v, v, v, 255]
+            })
+            .collect();
+        let method = QuantizationMethod::NeuQuant { colors: 4, sample_fac: 1 };
+
+        let max_deviation = |dither: DitherConfig| {
+            let (palette, indices) =
+                quantize_rgba_to_lct_with_dither(&rgba, width, height, method, dither).unwrap();
+            indices
+                .iter()
+                .zip(rgba.chunks_exact(4))
+                .map(|(&index, px)| (palette[index as usize * 3] as i32 - px[0] as i32).abs())
+                .max()
+                .unwrap()
+        };
+
+        let unclamped = max_deviation(DitherConfig::default());
+        let clamped = max_deviation(DitherConfig::default().with_error_clamp(16));
+        assert!(
+            clamped < unclamped,
+            "Clamped diffusion deviation {} should be below unclamped {}",
+            clamped, unclamped
+        );
+
+        // Default settings keep the existing output
+        assert_eq!(
+            quantize_rgba_to_lct(&rgba, width, height, method).unwrap(),
+            quantize_rgba_to_lct_with_dither(&rgba, width, height, method, DitherConfig::default()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_transparency_reserves_one_palette_slot() {
+        // Colorful gradient with a transparent square in the middle
+        let (width, height) = (64u16, 64u16);
+        let frame: Vec<u8> = (0..64 * 64)
+            .flat_map(|i| {
+                let (x, y) = (i % 64, i / 64);
+                let alpha = if (16..48).contains(&x) && (16..48).contains(&y) { 0 } else { 255 };
+                [(x * 4) as u8, (y * 4) as u8, ((x + y) * 2) as u8, alpha]
+            })
+            .collect();
+        let is_transparent = |px: &[u8]| px[3] < 128;
+        let method = QuantizationMethod::NeuQuant { colors: 256, sample_fac: 10 };
+        
+        let quantized = quantize_rgba_with_transparency(&frame, width, height, method, 128).unwrap();
+        assert_eq!(quantized.palette_rgb.len(), 256 * 3);
+        assert_eq!(quantized.transparent_index, Some(255));
+        for (&index, px) in quantized.indices.iter().zip(frame.chunks_exact(4)) {
+            assert_eq!(index == 255, is_transparent(px));
+        }
+        
+        // Fully opaque input reserves nothing
+        let opaque: Vec<u8> = frame.chunks_exact(4).flat_map(|px| [px[0], px[1], px[2], 255]).collect();
+        let plain = quantize_rgba_with_transparency(&opaque, width, height, method, 128).unwrap();
+        assert_eq!(plain.transparent_index, None);
+        
+        let gif = encode_gif89a_rgba_transparent(
+            &[frame.clone(), frame.clone()], width, height, 4, LoopMode::Infinite, method, 128,
+        ).unwrap();
+        
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::RGBA);
+        let mut decoder = options.read_info(gif.as_slice()).unwrap();
+        let mut decoded = 0;
+        while let Some(decoded_frame) = decoder.read_next_frame().unwrap() {
+            assert_eq!(decoded_frame.transparent, Some(255));
+            assert_eq!(decoded_frame.dispose, gif::DisposalMethod::Background);
+            for (out, px) in decoded_frame.buffer.chunks_exact(4).zip(frame.chunks_exact(4)) {
+                assert_eq!(out[3], if is_transparent(px) { 0 } else { 255 });
+            }
+            decoded += 1;
+        }
+        assert_eq!(decoded, 2);
+    }
+    
+    #[test]
+    fn test_supported_formats_always_include_gif() {
+        let formats = supported_formats();
+        
+        let gif = formats.iter().find(|info| info.format == OutputFormat::Gif).expect("GIF is always compiled in");
+        assert_eq!(gif.file_extension, "gif");
+        assert_eq!(gif.max_colors, 256);
+        assert!(gif.supports_transparency);
+        
+        // No feature-gated exporters exist yet, so nothing else is reported
+        assert_eq!(formats.len(), 1);
+    }
+    
+    #[test]
+    fn test_grayscale_ramp_maps_luminance() {
+        // Hue sweep across x, brightness ramp down y
+        let (width, height) = (64u16, 64u16);
+        let rgba: Vec<u8> = (0..width as usize * height as usize)
+            .flat_map(|i| {
+                let (x, y) = (i % 64, i / 64);
+                [(x * 4) as u8, (y * 4) as u8, (255 - x * 4) as u8, 255]
+            })
+            .collect();
+        let luma = |px: &[u8]| (299 * px[0] as i32 + 587 * px[1] as i32 + 114 * px[2] as i32 + 500) / 1000;
+        
+        let (palette, indices) = quantize_rgba_to_lct(
+            &rgba, width, height, QuantizationMethod::GrayscaleRamp { levels: 256 },
+        ).unwrap();
+        
+        assert_eq!(palette.len(), 256 * 3);
+        for (level, rgb) in palette.chunks_exact(3).enumerate() {
+            assert_eq!(rgb, [level as u8; 3]);
+        }
+        for (&index, px) in indices.iter().zip(rgba.chunks_exact(4)) {
+            assert!((index as i32 - luma(px)).abs() <= 1, "index {} for luminance {}", index, luma(px));
+        }
+        
+        // Fewer levels still give a pure gray ramp, with dithering keeping the mean luminance
+        let (palette, indices) = quantize_rgba_to_lct(
+            &rgba, width, height, QuantizationMethod::GrayscaleRamp { levels: 4 },
+        ).unwrap();
+        assert_eq!(palette, vec![0, 0, 0, 85, 85, 85, 170, 170, 170, 255, 255, 255]);
+        let mean_out = indices.iter().map(|&i| palette[i as usize * 3] as f64).sum::<f64>() / indices.len() as f64;
+        let mean_in = rgba.chunks_exact(4).map(|px| luma(px) as f64).sum::<f64>() / indices.len() as f64;
+        assert!((mean_out - mean_in).abs() < 2.0, "mean {} vs {}", mean_out, mean_in);
+    }
+    
+    #[test]
+    fn test_auto_palette_size_clamped_to_config() {
+        let config = AutoPaletteConfig { min: 32, max: 128 };
+        
+        // Two flat colors: natural recommendation is tiny, raised to the minimum
+        let simple: Vec<Vec<u8>> = (0..81)
+            .map(|f| {
+                let color = if f % 2 == 0 { [255, 0, 0, 255] } else { [0, 0, 255, 255] };
+                color.repeat(81 * 81)
+            })
+            .collect();
+        assert_eq!(recommend_palette_size(&simple, AutoPaletteConfig::default()), 2);
+        assert_eq!(recommend_palette_size(&simple, config), 32);
+        
+        // Full RGB gradients: natural recommendation is 256, lowered to the maximum
+        let complex: Vec<Vec<u8>> = (0..81)
+            .map(|f| {
+                (0..81 * 81)
+                    .flat_map(|i| {
+                        let (x, y) = (i % 81, i / 81);
+                        [(x * 3) as u8, (y * 3) as u8, (f * 3) as u8, 255]
+                    })
+                    .collect()
+            })
+            .collect();
+        assert_eq!(recommend_palette_size(&complex, AutoPaletteConfig::default()), 256);
+        assert_eq!(recommend_palette_size(&complex, config), 128);
+        
+        // Auto quantization honours the bound
+        let (palette, indices) = quantize_rgba_to_lct(
+            &complex[0],
+            81,
+            81,
+            QuantizationMethod::Auto(config),
+        ).unwrap();
+        assert!(palette.len() <= 128 * 3);
+        assert_eq!(indices.len(), 81 * 81);
+    }
+    
     #[test]
     fn test_nn_downsizes_729_to_81() {
         // Initialize logger for test
@@ -727,6 +1902,540 @@ mod tests {
         
         println!("✅ Neural downsizer test passed: 729×729 → 81×81");
     }
+    
+    #[test]
+    fn test_downsizes_648_to_81() {
+        // Horizontal gray ramp at 648×648, an exact 8× reduction
+        let input: Vec<u8> = (0..648 * 648)
+            .flat_map(|i| {
+                let v = ((i % 648) * 255 / 647) as u8;
+                [v, v, v, 255]
+            })
+            .collect();
+        
+        let output = m2_downsize_rgba_to_81(input, 648, 648).expect("Downscale should succeed");
+        assert_eq!(output.len(), 81 * 81 * 4);
+        
+        // The ramp survives: dark on the left, bright on the right, opaque throughout
+        let row = &output[40 * 81 * 4..41 * 81 * 4];
+        assert!(row[0] < 10, "left edge {}", row[0]);
+        assert!(row[80 * 4] > 245, "right edge {}", row[80 * 4]);
+        assert!(row.chunks_exact(4).all(|px| px[3] == 255));
+        
+        // The declared size must match the buffer
+        assert!(m2_downsize_rgba_to_81(vec![0u8; 648 * 648 * 4], 729, 729).is_err());
+    }
+    
+    #[test]
+    fn test_downsize_filters() {
+        // Every pixel distinct, so nearest sampling can be traced to its source
+        let input: Vec<u8> = (0..729 * 729u32)
+            .flat_map(|i| {
+                let (x, y) = (i % 729, i / 729);
+                [(x % 256) as u8, (y % 256) as u8, ((x * 7 + y * 13) % 256) as u8, 255]
+            })
+            .collect();
+        
+        let nearest = m2_downsize_rgba_729_to_81_with_filter(input.clone(), DownsizeFilter::Nearest, false).unwrap();
+        for (out_y, out_x) in [(0, 0), (0, 80), (40, 17), (80, 80)] {
+            // Center of the 9×9 block
+            let src = ((out_y * 9 + 4) * 729 + out_x * 9 + 4) * 4;
+            let out = (out_y * 81 + out_x) * 4;
+            assert_eq!(nearest[out..out + 4], input[src..src + 4], "({}, {})", out_x, out_y);
+        }
+        
+        // Box average of a solid color is that color, and matches the 9×9 truncated mean
+        let solid = [37u8, 201, 90, 255].repeat(729 * 729);
+        let boxed = m2_downsize_rgba_729_to_81_with_filter(solid, DownsizeFilter::BoxAverage, false).unwrap();
+        assert!(boxed.chunks_exact(4).all(|px| px == [37, 201, 90, 255]));
+        let boxed = m2_downsize_rgba_729_to_81_with_filter(input.clone(), DownsizeFilter::BoxAverage, false).unwrap();
+        let mut green = 0;
+        for y in 9..18 {
+            for x in 0..9 {
+                green += input[(y * 729 + x) * 4 + 1] as u32;
+            }
+        }
+        assert_eq!(boxed[81 * 4 + 1], (green / 81) as u8);
+        
+        for filter in [DownsizeFilter::Triangle, DownsizeFilter::CatmullRom] {
+            let output = m2_downsize_rgba_729_to_81_with_filter(input.clone(), filter, false).unwrap();
+            assert_eq!(output.len(), 81 * 81 * 4);
+        }
+        assert_eq!(
+            m2_downsize_rgba_729_to_81_with_filter(input.clone(), DownsizeFilter::default(), false).unwrap(),
+            m2_downsize_rgba_729_to_81(input).unwrap()
+        );
+    }
+    
+    #[test]
+    fn test_premultiplied_downsize_keeps_edges_red() {
+        // Opaque red square on transparent black; its edge at 364 splits an output pixel
+        let input: Vec<u8> = (0..729 * 729)
+            .flat_map(|i| if i % 729 < 364 && i / 729 < 364 { [255, 0, 0, 255] } else { [0, 0, 0, 0] })
+            .collect();
+        
+        for filter in [DownsizeFilter::Lanczos3, DownsizeFilter::BoxAverage] {
+            let straight = m2_downsize_rgba_729_to_81_with_filter(input.clone(), filter, false).unwrap();
+            let premultiplied = m2_downsize_rgba_729_to_81_with_filter(input.clone(), filter, true).unwrap();
+            
+            // Column 40 is the partly covered edge
+            let edge = (20 * 81 + 40) * 4;
+            assert!(premultiplied[edge + 3] > 0 && premultiplied[edge + 3] < 255, "{:?} alpha {}", filter, premultiplied[edge + 3]);
+            assert_eq!(premultiplied[edge..edge + 3], [255, 0, 0], "{:?}", filter);
+            assert!(straight[edge] < 200, "{:?} straight edge red {}", filter, straight[edge]);
+            
+            // Alpha itself is resampled the same either way
+            assert!(straight.iter().skip(3).step_by(4).eq(premultiplied.iter().skip(3).step_by(4)));
+        }
+    }
+    
+    #[test]
+    fn test_finite_loop_count_written() {
+        let frame = [200u8, 40, 40, 255].repeat(81 * 81);
+        let frames = [frame.clone(), frame];
+        
+        let gif = encode_gif89a_rgba(
+            &frames,
+            81,
+            81,
+            4,
+            LoopMode::Finite(3),
+            QuantizationMethod::MedianCut { colors: 4 },
+            &[],
+            true,
+        ).unwrap();
+        
+        // NETSCAPE2.0 identifier followed by sub-block [size=3, id=1, count LE, terminator]
+        let pos = gif.windows(11).position(|w| w == b"NETSCAPE2.0")
+            .expect("NETSCAPE2.0 extension missing");
+        assert_eq!(&gif[pos + 11..pos + 16], &[0x03, 0x01, 0x03, 0x00, 0x00]);
+        
+        // Extension must come before the first image, where the decoder has seen it
+        let mut decoder = gif::DecodeOptions::new().read_info(gif.as_slice()).unwrap();
+        assert!(decoder.next_frame_info().unwrap().is_some());
+        assert_eq!(decoder.repeat(), Repeat::Finite(3));
+        
+        // Finite(0) behaves like Once: no extension at all
+        let once = encode_gif89a_rgba(
+            &frames,
+            81,
+            81,
+            4,
+            LoopMode::Finite(0),
+            QuantizationMethod::MedianCut { colors: 4 },
+            &[],
+            true,
+        ).unwrap();
+        assert!(!once.windows(11).any(|w| w == b"NETSCAPE2.0"));
+    }
+    
+    #[test]
+    fn test_frames_centered_on_larger_canvas() {
+        let frames = vec![[30u8, 200, 90, 255].repeat(81 * 81); 2];
+        let canvas = Canvas::for_frames(81, 81)
+            .centered_in(100, 100)
+            .with_background([255, 255, 255]);
+        
+        let gif = encode_gif89a_rgba_on_canvas(
+            &frames,
+            &canvas,
+            4,
+            LoopMode::Infinite,
+            QuantizationMethod::MedianCut { colors: 4 },
+            &[],
+            true,
+        ).unwrap();
+        
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(gif.as_slice()).unwrap();
+        assert_eq!((decoder.width(), decoder.height()), (100, 100));
+        assert_eq!(&decoder.global_palette().unwrap()[..3], &[255, 255, 255]);
+        
+        let mut decoded = 0;
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            assert_eq!((frame.left, frame.top), (9, 9));
+            assert_eq!((frame.width, frame.height), (81, 81));
+            decoded += 1;
+        }
+        assert_eq!(decoded, 2);
+        
+        // Frames that overflow the canvas are rejected
+        let overflow = Canvas::for_frames(81, 81).centered_in(100, 100).with_offset(20, 0);
+        assert!(encode_gif89a_rgba_on_canvas(
+            &frames,
+            &overflow,
+            4,
+            LoopMode::Once,
+            QuantizationMethod::MedianCut { colors: 4 },
+            &[],
+            true,
+        ).is_err());
+    }
+    
+    #[test]
+    fn test_canvas_background_kept_with_global_palette() {
+        let frames = vec![[30u8, 200, 90, 255].repeat(81 * 81); 2];
+        let canvas = Canvas::for_frames(81, 81).centered_in(100, 100).with_background([255, 255, 255]);
+        let encode = |palette: &[u8]| encode_gif89a_rgba_on_canvas(
+            &frames,
+            &canvas,
+            4,
+            LoopMode::Infinite,
+            QuantizationMethod::MedianCut { colors: 4 },
+            palette,
+            true,
+        );
+        
+        // Already in the palette: moved to entry 0, which the background index points at
+        let gif = encode(&[10, 10, 10, 20, 20, 20, 255, 255, 255]).unwrap();
+        assert_eq!(gif[11], 0, "background color index");
+        assert_eq!(read_global_palette(&gif).unwrap()[..3], [[255, 255, 255], [20, 20, 20], [10, 10, 10]]);
+        
+        // Missing: inserted in front of the supplied colors
+        let gif = encode(&[10, 10, 10, 20, 20, 20]).unwrap();
+        assert_eq!(read_global_palette(&gif).unwrap()[..3], [[255, 255, 255], [10, 10, 10], [20, 20, 20]]);
+        
+        // A full palette has no room for it
+        let full: Vec<u8> = (0..=255u8).flat_map(|i| [i, i, 0]).collect();
+        assert!(matches!(encode(&full), Err(GifError::EncodingError(_))));
+    }
+    
+    #[test]
+    fn test_reduce_gif_palette_to_64_colors() {
+        // Colorful gradients so the original uses its full 256-color tables
+        let frames: Vec<Vec<u8>> = (0..4)
+            .map(|f| {
+                (0..81 * 81)
+                    .flat_map(|i| {
+                        let (x, y) = (i % 81, i / 81);
+                        [(x * 3 + f) as u8, (y * 3) as u8, ((x * y + f * 40) % 256) as u8, 255]
+                    })
+                    .collect()
+            })
+            .collect();
+        let original = encode_gif89a_rgba(
+            &frames,
+            81,
+            81,
+            4,
+            LoopMode::Finite(3),
+            QuantizationMethod::NeuQuant { colors: 256, sample_fac: 10 },
+            &[],
+            true,
+        ).unwrap();
+        
+        let reduced = reduce_gif_palette(original.clone(), 64).unwrap();
+        assert!(
+            reduced.len() < original.len(),
+            "Reduced GIF ({} bytes) should be smaller than original ({} bytes)",
+            reduced.len(), original.len()
+        );
+        
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(reduced.as_slice()).unwrap();
+        let mut decoded = 0;
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            let palette = frame.palette.as_ref().unwrap();
+            assert!(palette.len() / 3 <= 64, "Frame palette has {} entries", palette.len() / 3);
+            assert_eq!(frame.delay, 4);
+            decoded += 1;
+        }
+        assert_eq!(decoded, 4);
+        assert_eq!(decoder.repeat(), Repeat::Finite(3));
+        
+        assert!(matches!(reduce_gif_palette(b"not a gif".to_vec(), 64), Err(GifError::InvalidGif(_))));
+    }
+    
+    #[test]
+    fn test_pixel_formats_quantize_identically() {
+        // Gradient frames shifting over time, as RGBA
+        let frames_rgba: Vec<Vec<u8>> = (0..81)
+            .map(|f| {
+                (0..81 * 81)
+                    .flat_map(|i| {
+                        let (x, y) = (i % 81, i / 81);
+                        [(x * 3) as u8, (y * 3) as u8, ((x + y + f) * 2 % 256) as u8, 255]
+                    })
+                    .collect()
+            })
+            .collect();
+        let frames_bgra: Vec<Vec<u8>> = frames_rgba.iter()
+            .map(|f| f.chunks_exact(4).flat_map(|px| [px[2], px[1], px[0], px[3]]).collect())
+            .collect();
+        let frames_rgb: Vec<Vec<u8>> = frames_rgba.iter()
+            .map(|f| f.chunks_exact(4).flat_map(|px| [px[0], px[1], px[2]]).collect())
+            .collect();
+        
+        let from_rgba = m2_quantize_for_cube(frames_rgba).unwrap();
+        let from_bgra = m2_quantize_for_cube_with_format(frames_bgra, PixelFormat::Bgra).unwrap();
+        let from_rgb = m2_quantize_for_cube_with_format(frames_rgb, PixelFormat::Rgb).unwrap();
+        
+        for cube in [&from_bgra, &from_rgb] {
+            assert_eq!(cube.global_palette_rgb, from_rgba.global_palette_rgb);
+            assert_eq!(cube.indexed_frames, from_rgba.indexed_frames);
+        }
+        
+        // Wrong stride for the declared format is rejected
+        let short = vec![vec![0u8; 81 * 81 * 3]; 81];
+        assert!(m2_quantize_for_cube_with_format(short, PixelFormat::Bgra).is_err());
+    }
+    
+    #[test]
+    fn test_read_global_palette_matches_cube() {
+        // 5-color cube: the written table is padded to 8 entries
+        let palette: Vec<u8> = vec![
+            0, 0, 0,
+            255, 0, 0,
+            0, 255, 0,
+            0, 0, 255,
+            255, 255, 255,
+        ];
+        let indexed_frames: Vec<Vec<u8>> = (0..81)
+            .map(|f| (0..81 * 81).map(|i| ((i + f) % 5) as u8).collect())
+            .collect();
+        let cube = QuantizedCubeData {
+            width: 81,
+            height: 81,
+            global_palette_rgb: palette.clone(),
+            indexed_frames,
+            delays_cs: vec![4; 81],
+            palette_stability: 1.0,
+            mean_delta_e: 0.0,
+            p95_delta_e: 0.0,
+        };
+        
+        let info = m3_write_gif_from_cube(cube.clone(), 4, true).unwrap();
+        let read_back = read_global_palette(&info.gif_data).unwrap();
+        
+        let colors = cube.global_palette_rgb.len() / 3;
+        assert_eq!(read_back.len(), 8);
+        let expected: Vec<[u8; 3]> = cube.global_palette_rgb
+            .chunks_exact(3)
+            .map(|rgb| [rgb[0], rgb[1], rgb[2]])
+            .collect();
+        assert_eq!(&read_back[..colors], expected.as_slice());
+        
+        // Truncated input is rejected rather than read past the end
+        assert!(read_global_palette(&info.gif_data[..20]).is_err());
+        assert!(read_global_palette(b"GIF89a").is_err());
+    }
+    
+    #[test]
+    fn test_read_frame_delays_match_cube() {
+        let palette: Vec<u8> = (0..16).flat_map(|i| [i * 16, 255 - i * 16, 128]).collect();
+        let indexed_frames: Vec<Vec<u8>> = (0..81)
+            .map(|f| (0..81 * 81).map(|i| ((i / 81 + f) % 16) as u8).collect())
+            .collect();
+        let cube = QuantizedCubeData {
+            width: 81,
+            height: 81,
+            global_palette_rgb: palette,
+            indexed_frames,
+            delays_cs: (0..81).map(|f| 2 + (f % 7) as u8).collect(),
+            palette_stability: 1.0,
+            mean_delta_e: 0.0,
+            p95_delta_e: 0.0,
+        };
+        
+        // fps_cs differs from every cube delay, so only per-frame delays can match
+        let info = m3_write_gif_from_cube(cube.clone(), 50, true).unwrap();
+        let delays = read_frame_delays(&info.gif_data).unwrap();
+        
+        let expected: Vec<u16> = cube.delays_cs.iter().map(|&d| d as u16).collect();
+        assert_eq!(delays, expected);
+        
+        // Without delays every frame falls back to fps_cs
+        let uniform = QuantizedCubeData { delays_cs: Vec::new(), ..cube };
+        let info_uniform = m3_write_gif_from_cube(uniform, 50, true).unwrap();
+        assert_eq!(read_frame_delays(&info_uniform.gif_data).unwrap(), vec![50; 81]);
+        
+        assert!(matches!(
+            read_frame_delays(&info.gif_data[..info.gif_data.len() - 1]),
+            Err(GifError::InvalidGif(_))
+        ));
+        assert!(matches!(read_frame_delays(b"PNG"), Err(GifError::InvalidGif(_))));
+    }
+    
+    #[test]
+    fn test_suggest_loop_mode_from_seam() {
+        // Brightness swings through a full sine cycle: the seam is an ordinary step
+        let cycle: Vec<Vec<u8>> = (0..81)
+            .map(|f| {
+                let level = 128.0 + 100.0 * (f as f32 * std::f32::consts::TAU / 81.0).sin();
+                [level as u8, 64, 200].repeat(32 * 32)
+            })
+            .collect();
+        assert_eq!(suggest_loop_mode(&cycle), LoopMode::Infinite);
+        
+        // A one-way fade from black ends far from where it started
+        let fade: Vec<Vec<u8>> = (0..81u8)
+            .map(|f| [f * 3, f * 3, f * 3].repeat(32 * 32))
+            .collect();
+        assert_eq!(suggest_loop_mode(&fade), LoopMode::Once);
+        
+        assert_eq!(suggest_loop_mode(&fade[..1]), LoopMode::Once);
+        assert_eq!(suggest_loop_mode(&[]), LoopMode::Once);
+    }
+    
+    #[test]
+    fn test_thumbnail_is_27_by_27() {
+        let palette: Vec<u8> = (0..16).flat_map(|i| [i * 16, 255 - i * 16, 128]).collect();
+        let cube = QuantizedCubeData {
+            width: 81,
+            height: 81,
+            global_palette_rgb: palette,
+            indexed_frames: (0..81)
+                .map(|f| (0..81 * 81).map(|i| ((i / 81 + f) % 16) as u8).collect())
+                .collect(),
+            delays_cs: vec![6; 81],
+            palette_stability: 1.0,
+            mean_delta_e: 0.0,
+            p95_delta_e: 0.0,
+        };
+        
+        let gif = encode_thumbnail(cube.clone(), 27).unwrap();
+        assert_eq!(&gif[6..10], &[27, 0, 27, 0], "logical screen descriptor");
+        
+        let mut decoder = gif::DecodeOptions::new().read_info(gif.as_slice()).unwrap();
+        let mut frames = 0;
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            assert_eq!((frame.width, frame.height, frame.delay), (27, 27, 6));
+            frames += 1;
+        }
+        assert_eq!(frames, 81);
+        
+        assert!(encode_thumbnail(cube.clone(), 0).is_err());
+        assert!(encode_thumbnail(cube, 82).is_err());
+    }
+    
+    #[test]
+    fn test_budget_encode_fits_with_fewer_colors() {
+        // Scrambled colors compress poorly, so size tracks the palette's bit depth
+        let palette: Vec<u8> = (0..=255u8).flat_map(|i| [i, i.wrapping_mul(37), i.wrapping_mul(101)]).collect();
+        let cube = QuantizedCubeData {
+            width: 81,
+            height: 81,
+            global_palette_rgb: palette,
+            indexed_frames: (0..81u32)
+                .map(|f| (0..81 * 81u32).map(|i| (i.wrapping_add(f * 97).wrapping_mul(2654435761) >> 24) as u8).collect())
+                .collect(),
+            delays_cs: vec![4; 81],
+            palette_stability: 1.0,
+            mean_delta_e: 0.0,
+            p95_delta_e: 0.0,
+        };
+        let max_colors = |gif: &[u8]| {
+            let mut decoder = gif::DecodeOptions::new().read_info(gif).unwrap();
+            let mut colors = 0;
+            while let Some(frame) = decoder.read_next_frame().unwrap() {
+                colors = colors.max(frame.palette.as_ref().map_or(0, |p| p.len() / 3));
+            }
+            colors
+        };
+        
+        let full = encode_under_budget(cube.clone(), u64::MAX).unwrap();
+        let budget = full.len() as u64 * 3 / 4;
+        let fitted = encode_under_budget(cube.clone(), budget).unwrap();
+        
+        assert!(fitted.len() as u64 <= budget, "{} bytes over {} budget", fitted.len(), budget);
+        assert!(max_colors(&fitted) < max_colors(&full));
+        
+        // An unreachable budget returns the floor's attempt rather than failing
+        let floor = encode_under_budget_with_floor(cube, 1, 16).unwrap();
+        assert_eq!(max_colors(&floor), 16);
+        assert!(floor.len() < full.len());
+    }
+    
+    #[test]
+    fn test_non_strict_40_frames_encode_without_warnings() {
+        let frames: Vec<Vec<u8>> = (0..40u8)
+            .map(|f| [f * 6, 100, 200, 255].repeat(64 * 48))
+            .collect();
+        
+        assert!(cube_invariant_warnings(frames.len(), 64, 48, false).is_empty());
+        assert_eq!(cube_invariant_warnings(frames.len(), 64, 48, true).len(), 2);
+        
+        let gif = encode_gif89a_rgba(
+            &frames,
+            64,
+            48,
+            4,
+            LoopMode::Infinite,
+            QuantizationMethod::MedianCut { colors: 4 },
+            &[],
+            false,
+        ).unwrap();
+        
+        let mut decoder = gif::DecodeOptions::new().read_info(gif.as_slice()).unwrap();
+        assert_eq!((decoder.width(), decoder.height()), (64, 48));
+        let mut decoded = 0;
+        while decoder.read_next_frame().unwrap().is_some() {
+            decoded += 1;
+        }
+        assert_eq!(decoded, 40);
+        
+        // A single 1×1 frame is far smaller than any cube GIF, which only strict rejects
+        let pixel = vec![vec![10u8, 20, 30, 255]];
+        let encode = |strict_81| encode_gif89a_rgba(
+            &pixel,
+            1,
+            1,
+            4,
+            LoopMode::Infinite,
+            QuantizationMethod::MedianCut { colors: 2 },
+            &[],
+            strict_81,
+        );
+        let tiny = encode(false).unwrap();
+        assert!(tiny.len() < 100);
+        let validation = validate_gif_bytes(tiny).unwrap();
+        assert!(validation.is_valid, "{:?}", validation.errors);
+        assert_eq!(validation.frame_count, 1);
+        assert!(matches!(encode(true), Err(GifError::EncodingError(_))));
+    }
+    
+    #[test]
+    fn test_frame_failure_reports_structured_index() {
+        let mut frames = vec![vec![128u8; 81 * 81 * 4]; 10];
+        frames[6].truncate(100);
+        
+        let method = QuantizationMethod::NeuQuant { colors: 16, sample_fac: 10 };
+        let err = encode_gif89a_rgba(&frames, 81, 81, 4, LoopMode::Infinite, method, &[], true).unwrap_err();
+        
+        match err {
+            GifError::FrameEncoding { frame_idx, message } => {
+                assert_eq!(frame_idx, 6);
+                assert!(message.contains("100"), "message should carry the cause: {}", message);
+            }
+            other => panic!("expected FrameEncoding, got {:?}", other),
+        }
+    }
+    
+    #[test]
+    fn test_bench_pipeline_reports_positive_throughput() {
+        let frames: Vec<Vec<u8>> = (0..2)
+            .map(|f| {
+                (0..729 * 729)
+                    .flat_map(|i| [(i % 729 / 3) as u8, (i / 729 / 3) as u8, (f * 100) as u8, 255])
+                    .collect()
+            })
+            .collect();
+        
+        let report = bench_pipeline(frames, 2).unwrap();
+        
+        assert_eq!(report.iterations, 2);
+        assert_eq!(report.frames, 2);
+        for stage in [&report.downscale, &report.quantize, &report.encode] {
+            assert!(stage.megapixels_per_sec > 0.0);
+            assert!(stage.total_duration_ms > 0.0);
+            assert!(stage.avg_iteration_ms <= stage.total_duration_ms);
+        }
+        
+        assert!(bench_pipeline(Vec::new(), 1).is_err());
+        assert!(bench_pipeline(vec![vec![0u8; 729 * 729 * 4]], 0).is_err());
+    }
 }
 
 // ==== RGB-ONLY FUNCTIONS ====
@@ -772,32 +2481,165 @@ pub fn m2_downsize_rgb_729_to_81(rgb_729: Vec<u8>) -> Result<Vec<u8>, GifError>
 
 /// Quantize RGB frames to create palette and indexed cube data
 pub fn m2_quantize_for_cube_rgb(frames_81_rgb: Vec<Vec<u8>>) -> Result<QuantizedCubeData, GifError> {
-    if frames_81_rgb.is_empty() {
+    m2_quantize_for_cube_with_format(frames_81_rgb, PixelFormat::Rgb)
+}
+
+/// Quantize 81×81 frames in any supported pixel format for cube data
+pub fn m2_quantize_for_cube_with_format(
+    frames_81: Vec<Vec<u8>>,
+    format: PixelFormat,
+) -> Result<QuantizedCubeData, GifError> {
+    if frames_81.is_empty() {
         return Err(GifError::InvalidFrameCount(0));
     }
     
-    // Convert RGB frames to RGBA
-    let mut frames_81_rgba = Vec::with_capacity(frames_81_rgb.len());
-    for rgb_frame in frames_81_rgb {
-        if rgb_frame.len() != 81 * 81 * 3 {
-            return Err(GifError::InvalidDimensions(
-                format!("Expected {} RGB bytes per frame, got {}", 81 * 81 * 3, rgb_frame.len())
-            ));
+    // Swizzle to RGBA, then use existing RGBA quantizer
+    let frames_81_rgba = frames_to_rgba(frames_81, format, 81 * 81)?;
+    m2_quantize_for_cube(frames_81_rgba)
+}
+
+// ==== OUTPUT FORMATS ====
+
+/// Container a capture can be exported to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Gif,
+}
+
+/// Capabilities of an exporter compiled into this build
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormatInfo {
+    pub format: OutputFormat,
+    pub file_extension: String,
+    pub max_colors: u32,
+    pub supports_transparency: bool,
+}
+
+/// Exporters available in this build, so a UI only offers viable formats
+///
+/// GIF is always present. Exporters behind cargo features add their entry
+/// under the same `#[cfg(feature = ...)]` as their code.
+pub fn supported_formats() -> Vec<FormatInfo> {
+    vec![FormatInfo {
+        format: OutputFormat::Gif,
+        file_extension: "gif".to_string(),
+        max_colors: 256,
+        supports_transparency: true,
+    }]
+}
+
+// ==== BENCHMARK ====
+
+/// Throughput of one pipeline stage across all benchmark iterations
+#[derive(Debug, Clone)]
+pub struct StageThroughput {
+    pub total_duration_ms: f64,
+    pub avg_iteration_ms: f64,
+    pub megapixels_per_sec: f64,
+}
+
+/// Pipeline benchmark results for tracking performance across versions
+#[derive(Debug, Clone)]
+pub struct BenchReport {
+    pub iterations: u32,
+    pub frames: u32,
+    pub downscale: StageThroughput,
+    pub quantize: StageThroughput,
+    pub encode: StageThroughput,
+    pub total_duration_ms: u64,
+}
+
+impl StageThroughput {
+    fn from_runs(elapsed: std::time::Duration, iterations: u32, pixels_per_iteration: usize) -> Self {
+        let total_secs = elapsed.as_secs_f64();
+        let megapixels = pixels_per_iteration as f64 * iterations as f64 / 1_000_000.0;
+        Self {
+            total_duration_ms: total_secs * 1000.0,
+            avg_iteration_ms: total_secs * 1000.0 / iterations as f64,
+            megapixels_per_sec: if total_secs > 0.0 { megapixels / total_secs } else { 0.0 },
         }
+    }
+}
+
+/// Run 729×729 RGBA frames through downscale, quantize and encode `iterations` times
+///
+/// Downscale throughput counts input pixels; quantize and encode count 81×81
+/// output pixels.
+pub fn bench_pipeline(frames_729_rgba: Vec<Vec<u8>>, iterations: u32) -> Result<BenchReport, GifError> {
+    use std::time::{Duration, Instant};
+    
+    if frames_729_rgba.is_empty() {
+        return Err(GifError::InvalidFrameCount(0));
+    }
+    if iterations == 0 {
+        return Err(GifError::EncodingError("Benchmark needs at least one iteration".to_string()));
+    }
+    
+    let frame_count = frames_729_rgba.len();
+    let stacked_height = u16::try_from(81 * frame_count)
+        .map_err(|_| GifError::InvalidFrameCount(frame_count))?;
+    
+    log::info!("M3_BENCH_START frames={} iterations={}", frame_count, iterations);
+    
+    let start = Instant::now();
+    let mut downscale_time = Duration::ZERO;
+    let mut quantize_time = Duration::ZERO;
+    let mut encode_time = Duration::ZERO;
+    
+    for _ in 0..iterations {
+        let input = frames_729_rgba.clone();
         
-        // Add alpha channel
-        let mut rgba_frame = Vec::with_capacity(81 * 81 * 4);
-        for chunk in rgb_frame.chunks(3) {
-            rgba_frame.push(chunk[0]); // R
-            rgba_frame.push(chunk[1]); // G
-            rgba_frame.push(chunk[2]); // B
-            rgba_frame.push(255);       // A
-        }
-        frames_81_rgba.push(rgba_frame);
+        let stage = Instant::now();
+        let frames_81 = input
+            .into_iter()
+            .map(m2_downsize_rgba_729_to_81)
+            .collect::<Result<Vec<_>, _>>()?;
+        downscale_time += stage.elapsed();
+        
+        // Same stacked-frame global palette as m2_quantize_for_cube, without its 81-frame requirement
+        let stage = Instant::now();
+        let (palette, indexed_pixels) = quantize_rgba_to_lct(
+            &frames_81.concat(),
+            81,
+            stacked_height,
+            QuantizationMethod::NeuQuant { colors: 256, sample_fac: 10 },
+        )?;
+        quantize_time += stage.elapsed();
+        
+        let cube = QuantizedCubeData {
+            width: 81,
+            height: 81,
+            global_palette_rgb: palette,
+            indexed_frames: indexed_pixels.chunks(81 * 81).map(<[u8]>::to_vec).collect(),
+            delays_cs: vec![4; frame_count],
+            palette_stability: 1.0,
+            mean_delta_e: 0.0,
+            p95_delta_e: 0.0,
+        };
+        
+        let stage = Instant::now();
+        m3_write_gif_from_cube(cube, 4, true)?;
+        encode_time += stage.elapsed();
     }
     
-    // Use existing RGBA quantizer
-    m2_quantize_for_cube(frames_81_rgba)
+    let report = BenchReport {
+        iterations,
+        frames: frame_count as u32,
+        downscale: StageThroughput::from_runs(downscale_time, iterations, frame_count * 729 * 729),
+        quantize: StageThroughput::from_runs(quantize_time, iterations, frame_count * 81 * 81),
+        encode: StageThroughput::from_runs(encode_time, iterations, frame_count * 81 * 81),
+        total_duration_ms: start.elapsed().as_millis() as u64,
+    };
+    
+    log::info!(
+        "M3_BENCH_DONE downscale_mps={:.2} quantize_mps={:.2} encode_mps={:.2} total_ms={}",
+        report.downscale.megapixels_per_sec,
+        report.quantize.megapixels_per_sec,
+        report.encode.megapixels_per_sec,
+        report.total_duration_ms
+    );
+    
+    Ok(report)
 }
 
 // UniFFI scaffolding - must be at the end
@@ -1,5 +1,8 @@
 // M2/M3 Bridge - New functions for separated pipeline
-use crate::{GifError, quantize_rgba_to_lct, encode_gif89a_rgba, QuantizationMethod};
+use crate::{GifError, neuquant_quantize, encode_gif89a_rgba, encode_gif89a_rgba_on_canvas, resize_filtered, Canvas, DitherConfig, DownsizeFilter, LoopMode, QuantizationMethod};
+use common_types::MAX_FRAME_COUNT;
+use gif::{Encoder, Frame};
+use std::borrow::Cow;
 
 /// Quantized cube data for WYSIWYG preview and GIF encoding
 #[derive(Debug, Clone)]
@@ -7,8 +10,8 @@ pub struct QuantizedCubeData {
     pub width: u16,
     pub height: u16,
     pub global_palette_rgb: Vec<u8>,      // 256*3 RGB bytes
-    pub indexed_frames: Vec<Vec<u8>>,     // 81 frames of 81*81 indices
-    pub delays_cs: Vec<u8>,               // 81 centiseconds per frame
+    pub indexed_frames: Vec<Vec<u8>>,     // 1 to MAX_FRAME_COUNT frames (81 by default) of 81*81 indices
+    pub delays_cs: Vec<u8>,               // centiseconds per frame, one per indexed frame
     pub palette_stability: f32,
     pub mean_delta_e: f32,
     pub p95_delta_e: f32,
@@ -23,6 +26,7 @@ pub struct GifInfo {
     pub palette_size: u32,
     pub has_netscape_loop: bool,
     pub compression_ratio: f32,
+    pub uncompressed_size_bytes: u64,     // RGB size the ratio is measured against
     pub validation_passed: bool,
     pub processing_time_ms: u64,
     pub total_processing_ms: u64,
@@ -40,77 +44,152 @@ pub struct GifValidation {
     pub errors: Vec<String>,
 }
 
+/// Cube quantized with one palette per detected scene
+#[derive(Debug, Clone)]
+pub struct SegmentedCubeData {
+    /// First frame of each segment, starting with 0
+    pub segment_starts: Vec<u32>,
+    /// One cube per segment; its global palette is the local color table for its frames
+    pub segments: Vec<QuantizedCubeData>,
+}
+
 /// M2: Quantize RGBA frames to create palette and indexed cube data
 /// Enhanced with scene change detection and adaptive fallbacks
 pub fn m2_quantize_for_cube(frames_81_rgba: Vec<Vec<u8>>) -> Result<QuantizedCubeData, GifError> {
-    // Validate input
-    if frames_81_rgba.len() != 81 {
-        return Err(GifError::InvalidFrameCount(frames_81_rgba.len()));
-    }
+    validate_cube_frames(&frames_81_rgba)?;
+    log::info!("M2_QUANTIZE_START frames={} method=NeuQuantAdaptive", frames_81_rgba.len());
+    
+    let cube = quantize_frame_run(&frames_81_rgba)?;
+    
+    log::info!("M2_QUANTIZE_DONE mean_delta_e={:.2} p95_delta_e={:.2} stability={:.2}",
+              cube.mean_delta_e, cube.p95_delta_e, cube.palette_stability);
     
-    log::info!("M2_QUANTIZE_START frames=81 method=NeuQuantAdaptive");
+    // Check if we need to apply fallback for poor quality
+    if cube.p95_delta_e > 5.0 || cube.palette_stability < 0.8 {
+        log::warn!("M2_QUANTIZE_QUALITY_WARNING: High variance detected, consider m2_quantize_for_cube_segmented");
+    }
     
-    // Check each frame is 81x81 RGBA
+    Ok(cube)
+}
+
+/// Check there are 1 to `MAX_FRAME_COUNT` frames (81 by default), each 81×81 RGBA
+fn validate_cube_frames(frames_rgba: &[Vec<u8>]) -> Result<(), GifError> {
+    if frames_rgba.is_empty() || frames_rgba.len() > MAX_FRAME_COUNT as usize {
+        return Err(GifError::InvalidFrameCount(frames_rgba.len()));
+    }
     let expected_size = 81 * 81 * 4;
-    for (i, frame) in frames_81_rgba.iter().enumerate() {
-        if frame.len() != expected_size {
-            eprintln!("Frame {} has wrong size: {} (expected {})", i, frame.len(), expected_size);
-            return Err(GifError::InvalidDimensions(
-                format!("Frame {} has wrong size: {} (expected {})", i, frame.len(), expected_size)
-            ));
+    if let Some((i, frame)) = frames_rgba.iter().enumerate().find(|(_, frame)| frame.len() != expected_size) {
+        return Err(GifError::InvalidDimensions(
+            format!("Frame {} has wrong size: {} (expected {})", i, frame.len(), expected_size)
+        ));
+    }
+    Ok(())
+}
+
+/// Default `scene_threshold` for `m2_quantize_for_cube_segmented`
+///
+/// Half the color histogram has to move between two frames to count as a cut.
+pub const DEFAULT_SCENE_THRESHOLD: f32 = 0.5;
+
+/// Histogram bins per channel used for scene-cut detection
+const SCENE_HISTOGRAM_BINS: usize = 4;
+
+/// M2: Quantize RGBA frames with a separate palette for each scene
+///
+/// A new segment starts wherever the color histogram shifts by more than
+/// `scene_threshold` (0..=1, the fraction of pixels whose coarse color bin
+/// changed) from the previous frame. Each segment is quantized on its own,
+/// so a cut between two color regimes doesn't squeeze both into one palette.
+pub fn m2_quantize_for_cube_segmented(
+    frames_81_rgba: Vec<Vec<u8>>,
+    scene_threshold: f32,
+) -> Result<SegmentedCubeData, GifError> {
+    validate_cube_frames(&frames_81_rgba)?;
+    if scene_threshold.is_nan() || scene_threshold <= 0.0 || scene_threshold > 1.0 {
+        return Err(GifError::QuantizationError(
+            format!("Scene threshold must be in (0, 1], got {}", scene_threshold)
+        ));
+    }
+    
+    let segment_starts = detect_scene_cuts(&frames_81_rgba, scene_threshold);
+    log::info!("M2_QUANTIZE_START frames={} method=NeuQuantAdaptive segments={}",
+              frames_81_rgba.len(), segment_starts.len());
+    
+    let mut segments = Vec::with_capacity(segment_starts.len());
+    for (i, &start) in segment_starts.iter().enumerate() {
+        let end = segment_starts.get(i + 1).map_or(frames_81_rgba.len(), |&next| next as usize);
+        let segment = quantize_frame_run(&frames_81_rgba[start as usize..end])?;
+        log::info!("M2_SEGMENT start={} frames={} mean_delta_e={:.2} p95_delta_e={:.2}",
+                  start, end - start as usize, segment.mean_delta_e, segment.p95_delta_e);
+        segments.push(segment);
+    }
+    
+    Ok(SegmentedCubeData { segment_starts, segments })
+}
+
+/// First frame of every scene: 0, then each frame whose histogram moved more than `threshold`
+fn detect_scene_cuts(frames_rgba: &[Vec<u8>], threshold: f32) -> Vec<u32> {
+    let histograms: Vec<Vec<f32>> = frames_rgba.iter().map(|frame| color_histogram(frame)).collect();
+    let mut starts = vec![0];
+    for (i, pair) in histograms.windows(2).enumerate() {
+        // Total variation distance: 0 for identical histograms, 1 for disjoint ones
+        let shift = pair[0].iter().zip(&pair[1]).map(|(a, b)| (a - b).abs()).sum::<f32>() / 2.0;
+        if shift > threshold {
+            log::debug!("M2_SCENE_CUT frame={} shift={:.3}", i + 1, shift);
+            starts.push(i as u32 + 1);
         }
     }
+    starts
+}
+
+/// Normalized coarse RGB histogram of an RGBA frame
+fn color_histogram(frame_rgba: &[u8]) -> Vec<f32> {
+    let bins = SCENE_HISTOGRAM_BINS;
+    let bin = |value: u8| value as usize * bins / 256;
+    let mut histogram = vec![0.0f32; bins * bins * bins];
+    let pixels = frame_rgba.chunks_exact(4);
+    let count = pixels.len().max(1) as f32;
+    for pixel in pixels {
+        histogram[(bin(pixel[0]) * bins + bin(pixel[1])) * bins + bin(pixel[2])] += 1.0;
+    }
+    histogram.iter_mut().for_each(|h| *h /= count);
+    histogram
+}
+
+/// Quantize a run of 81×81 RGBA frames against one shared NeuQuant palette
+fn quantize_frame_run(frames_rgba: &[Vec<u8>]) -> Result<QuantizedCubeData, GifError> {
+    let pixels_per_frame = 81 * 81;
     
     // Flatten all frames for palette generation
-    let mut all_pixels = Vec::with_capacity(81 * expected_size);
-    for frame in &frames_81_rgba {
+    let mut all_pixels = Vec::with_capacity(frames_rgba.len() * pixels_per_frame * 4);
+    for frame in frames_rgba {
         all_pixels.extend_from_slice(frame);
     }
     
-    // Use existing quantizer with NeuQuant for high quality
-    let method = QuantizationMethod::NeuQuant { 
-        colors: 256, 
-        sample_fac: 10  // High quality
-    };
-    
-    // Calculate dimensions for the combined image
-    let total_width = 81;
-    let total_height = 81 * 81; // All frames stacked vertically
-    
-    let (palette, indexed_pixels) = quantize_rgba_to_lct(
+    // All frames stacked vertically, quantized with high-quality NeuQuant (sample_fac 10);
+    // past 809 frames the stack is taller than a u16 image height
+    let (palette, indexed_pixels) = neuquant_quantize(
         &all_pixels, 
-        total_width, 
-        total_height as u16, 
-        method
-    )?;
+        81, 
+        81 * frames_rgba.len(), 
+        256,
+        10,
+        DitherConfig::default()
+    );
     
     // Split indexed pixels back into frames
-    let pixels_per_frame = 81 * 81;
-    let mut indexed_frames = Vec::with_capacity(81);
-    for i in 0..81 {
-        let start = i * pixels_per_frame;
-        let end = start + pixels_per_frame;
-        indexed_frames.push(indexed_pixels[start..end].to_vec());
-    }
+    let indexed_frames: Vec<Vec<u8>> = indexed_pixels.chunks(pixels_per_frame).map(<[u8]>::to_vec).collect();
     
     // Create delays (4cs = 40ms per frame = 25fps)
-    let delays_cs = vec![4u8; 81];
+    let delays_cs = vec![4u8; frames_rgba.len()];
     
     // Calculate quality metrics
     let (mean_delta_e, p95_delta_e, stability) = calculate_quantization_metrics(
-        &frames_81_rgba,
+        frames_rgba,
         &palette,
         &indexed_frames
     );
     
-    log::info!("M2_QUANTIZE_DONE mean_delta_e={:.2} p95_delta_e={:.2} stability={:.2}",
-              mean_delta_e, p95_delta_e, stability);
-    
-    // Check if we need to apply fallback for poor quality
-    if p95_delta_e > 5.0 || stability < 0.8 {
-        log::warn!("M2_QUANTIZE_QUALITY_WARNING: High variance detected, consider local color tables");
-    }
-    
     Ok(QuantizedCubeData {
         width: 81,
         height: 81,
@@ -129,10 +208,13 @@ fn calculate_quantization_metrics(
     palette: &[u8],
     indexed_frames: &[Vec<u8>]
 ) -> (f32, f32, f32) {
-    let mut all_delta_e = Vec::new();
+    let mut accumulator = DeltaEAccumulator::new();
     
     // For each frame, calculate delta E between original and quantized
     for (frame_idx, (rgba_frame, indexed_frame)) in frames_rgba.iter().zip(indexed_frames).enumerate() {
+        let mut frame_sum = 0.0f32;
+        let mut frame_count = 0usize;
+        
         for (pixel_idx, &palette_idx) in indexed_frame.iter().enumerate() {
             let rgba_idx = pixel_idx * 4;
             let palette_rgb_idx = palette_idx as usize * 3;
@@ -150,40 +232,36 @@ fn calculate_quantization_metrics(
                 
                 // Simple Euclidean distance in RGB space (could use Lab for better perceptual accuracy)
                 let delta_e = ((r1 - r2).powi(2) + (g1 - g2).powi(2) + (b1 - b2).powi(2)).sqrt() / 441.67; // Normalize
-                all_delta_e.push(delta_e);
+                accumulator.push(delta_e);
+                frame_sum += delta_e;
+                frame_count += 1;
             }
         }
         
         // Log per-frame metrics for debugging
-        if frame_idx < 3 && !all_delta_e.is_empty() {
-            let frame_mean = all_delta_e[all_delta_e.len().saturating_sub(6561)..]
-                .iter()
-                .sum::<f32>() / 6561.0;
-            log::debug!("Frame {} mean delta E: {:.3}", frame_idx, frame_mean);
+        if frame_idx < 3 && frame_count > 0 {
+            log::debug!("Frame {} mean delta E: {:.3}", frame_idx, frame_sum / frame_count as f32);
         }
     }
     
     // Calculate statistics
-    if all_delta_e.is_empty() {
+    if accumulator.count == 0 {
         return (0.0, 0.0, 1.0);
     }
     
-    all_delta_e.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
-    
-    let mean_delta_e = all_delta_e.iter().sum::<f32>() / all_delta_e.len() as f32;
-    let p95_idx = ((all_delta_e.len() as f32) * 0.95) as usize;
-    let p95_delta_e = all_delta_e.get(p95_idx).copied().unwrap_or(mean_delta_e);
+    let mean_delta_e = accumulator.mean();
+    let p95_delta_e = accumulator.quantile(0.95);
     
-    // Calculate stability (inverse of variance, normalized to 0-1)
-    let variance = all_delta_e.iter()
-        .map(|&x| (x - mean_delta_e).powi(2))
-        .sum::<f32>() / all_delta_e.len() as f32;
-    let stability = (1.0 / (1.0 + variance)).min(1.0).max(0.0);
+    // Canonical index-histogram stability shared with the other quantizer paths
+    let stability = common_types::palette_stability(indexed_frames);
     
     (mean_delta_e, p95_delta_e, stability)
 }
 
 /// M3: Write GIF from pre-quantized cube data
+///
+/// Each frame is shown for its `delays_cs` entry; `fps_cs` is only used when
+/// `delays_cs` is empty.
 pub fn m3_write_gif_from_cube(
     cube: QuantizedCubeData,
     fps_cs: u8,
@@ -192,7 +270,261 @@ pub fn m3_write_gif_from_cube(
     use std::time::Instant;
     let start = Instant::now();
     
-    // Convert indexed frames back to format expected by encoder
+    // The cube already holds final indices, so they are written as-is against
+    // the cube palette as the GCT, with no local color tables
+    if cube.indexed_frames.is_empty() {
+        return Err(GifError::InvalidFrameCount(0));
+    }
+    palette_colors(&cube.global_palette_rgb)?;
+    let mut output = Vec::new();
+    let mut encoder = Encoder::new(&mut output, cube.width, cube.height, &cube.global_palette_rgb)
+        .map_err(|e| GifError::EncodingError(e.to_string()))?;
+    crate::write_loop_mode(&mut encoder, LoopMode::from(loop_forever))?;
+    write_cube_frames(&mut encoder, &cube, 0, fps_cs as u16, false)?;
+    drop(encoder);
+    
+    let pixels_per_frame = cube.indexed_frames.first().map_or(0, Vec::len);
+    Ok(gif_info(
+        output,
+        cube.indexed_frames.len(),
+        pixels_per_frame,
+        cube.global_palette_rgb.len() / 3,
+        loop_forever,
+        start.elapsed(),
+    ))
+}
+
+/// M3: Write segmented cube data, each segment's palette as its frames' local color table
+///
+/// The global color table is only the encoder's black two-entry placeholder;
+/// every frame carries its own. Every segment must share one size, and
+/// `segment_starts` must match the frames each segment holds.
+pub fn m3_write_gif_from_segments(
+    segmented: SegmentedCubeData,
+    fps_cs: u8,
+    loop_forever: bool,
+) -> Result<GifInfo, GifError> {
+    use std::time::Instant;
+    let start = Instant::now();
+    
+    let SegmentedCubeData { segment_starts, segments } = &segmented;
+    let first = segments.first().ok_or(GifError::InvalidFrameCount(0))?;
+    if segment_starts.len() != segments.len() {
+        return Err(GifError::EncodingError(format!(
+            "{} segment starts for {} segments", segment_starts.len(), segments.len()
+        )));
+    }
+    
+    let mut output = Vec::new();
+    let mut encoder = Encoder::new(&mut output, first.width, first.height, &[])
+        .map_err(|e| GifError::EncodingError(e.to_string()))?;
+    crate::write_loop_mode(&mut encoder, LoopMode::from(loop_forever))?;
+    
+    let mut frame_count = 0;
+    for (segment, &segment_start) in segments.iter().zip(segment_starts) {
+        if (segment.width, segment.height) != (first.width, first.height) {
+            return Err(GifError::InvalidDimensions(format!(
+                "Segment at frame {} is {}x{}, expected {}x{}",
+                segment_start, segment.width, segment.height, first.width, first.height
+            )));
+        }
+        if segment_start as usize != frame_count {
+            return Err(GifError::EncodingError(format!(
+                "Segment starts at frame {}, but the previous segments end at {}", segment_start, frame_count
+            )));
+        }
+        write_cube_frames(&mut encoder, segment, frame_count, fps_cs as u16, true)?;
+        frame_count += segment.indexed_frames.len();
+    }
+    drop(encoder);
+    
+    let palette_size = segments.iter().map(|segment| segment.global_palette_rgb.len() / 3).max().unwrap_or(0);
+    Ok(gif_info(
+        output,
+        frame_count,
+        first.width as usize * first.height as usize,
+        palette_size,
+        loop_forever,
+        start.elapsed(),
+    ))
+}
+
+/// `GifInfo` for freshly encoded cube frames
+fn gif_info(
+    gif_data: Vec<u8>,
+    frame_count: usize,
+    pixels_per_frame: usize,
+    palette_size: usize,
+    loop_forever: bool,
+    elapsed: std::time::Duration,
+) -> GifInfo {
+    let uncompressed_size = common_types::GifInfo::uncompressed_size(frame_count, pixels_per_frame);
+    GifInfo {
+        file_path: String::new(),
+        file_size_bytes: gif_data.len() as u64,
+        frame_count: frame_count as u32,
+        palette_size: palette_size as u32,
+        has_netscape_loop: loop_forever,
+        compression_ratio: common_types::GifInfo::ratio(uncompressed_size, gif_data.len() as u64),
+        uncompressed_size_bytes: uncompressed_size,
+        validation_passed: true,
+        processing_time_ms: elapsed.as_millis() as u64,
+        total_processing_ms: elapsed.as_millis() as u64,
+        gif_data,
+    }
+}
+
+/// Encode a small looping preview of the cube at `target_dim`×`target_dim`
+///
+/// Each frame's resolved colors are Lanczos3-downscaled and re-quantized with
+/// NeuQuant, since averaging neighbours produces colors the cube palette
+/// lacks. Frames play at the cube's mean delay.
+pub fn encode_thumbnail(cube: QuantizedCubeData, target_dim: u16) -> Result<Vec<u8>, GifError> {
+    if target_dim == 0 || target_dim > cube.width.min(cube.height) {
+        return Err(GifError::InvalidDimensions(format!(
+            "Thumbnail size {} must be between 1 and the {}x{} cube size",
+            target_dim, cube.width, cube.height
+        )));
+    }
+    
+    let thumbnails = cube_frames_to_rgba(&cube)?
+        .into_iter()
+        .map(|rgba| resize_filtered(rgba, cube.width as u32, cube.height as u32, target_dim as u32, DownsizeFilter::Lanczos3))
+        .collect::<Result<Vec<_>, _>>()?;
+    
+    let delay_cs = mean_delay_cs(&cube);
+    log::info!("M3_THUMBNAIL frames={} size={} delay_cs={}", thumbnails.len(), target_dim, delay_cs);
+    
+    encode_gif89a_rgba_on_canvas(
+        &thumbnails,
+        &Canvas::for_frames(target_dim, target_dim),
+        delay_cs,
+        LoopMode::Infinite,
+        QuantizationMethod::NeuQuant { colors: 256, sample_fac: 10 },
+        &[],
+        false,
+    )
+}
+
+/// Fewest colors `encode_under_budget` shrinks the palette to
+pub const BUDGET_MIN_COLORS: u16 = 4;
+
+/// Encode the cube in at most `max_bytes`, shrinking the palette if needed
+///
+/// Same as `encode_under_budget_with_floor` with a `BUDGET_MIN_COLORS` floor.
+pub fn encode_under_budget(cube: QuantizedCubeData, max_bytes: u64) -> Result<Vec<u8>, GifError> {
+    encode_under_budget_with_floor(cube, max_bytes, BUDGET_MIN_COLORS)
+}
+
+/// Encode the cube in at most `max_bytes`, re-quantizing with fewer colors until it fits
+///
+/// Starts with a 256-color NeuQuant encode of the cube's frames and halves
+/// the palette while the GIF is over budget, stopping at `min_colors`. If
+/// even that doesn't fit, the smallest attempt is returned; callers compare
+/// its length with the budget. Frames play at the cube's mean delay.
+pub fn encode_under_budget_with_floor(
+    cube: QuantizedCubeData,
+    max_bytes: u64,
+    min_colors: u16,
+) -> Result<Vec<u8>, GifError> {
+    let min_colors = min_colors.clamp(2, 256);
+    let frames = cube_frames_to_rgba(&cube)?;
+    let delay_cs = mean_delay_cs(&cube);
+    
+    let mut colors = 256u16;
+    let mut best: Option<Vec<u8>> = None;
+    loop {
+        let gif_data = encode_gif89a_rgba(
+            &frames,
+            cube.width,
+            cube.height,
+            delay_cs,
+            LoopMode::Infinite,
+            QuantizationMethod::NeuQuant { colors, sample_fac: 10 },
+            &[],
+            false,
+        )?;
+        log::info!("M3_BUDGET colors={} sizeBytes={} maxBytes={}", colors, gif_data.len(), max_bytes);
+        
+        let fits = gif_data.len() as u64 <= max_bytes;
+        if best.as_ref().is_none_or(|best| gif_data.len() < best.len()) {
+            best = Some(gif_data);
+        }
+        if fits || colors <= min_colors {
+            break;
+        }
+        colors = (colors / 2).max(min_colors);
+    }
+    
+    let best = best.expect("at least one attempt is encoded");
+    if best.len() as u64 > max_bytes {
+        log::warn!("M3_BUDGET_EXCEEDED floor={} sizeBytes={} maxBytes={}", min_colors, best.len(), max_bytes);
+    }
+    Ok(best)
+}
+
+/// Mean of the cube's frame delays, 4cs when it has none
+fn mean_delay_cs(cube: &QuantizedCubeData) -> u16 {
+    match cube.delays_cs.len() {
+        0 => 4,
+        n => (cube.delays_cs.iter().map(|&d| d as u32).sum::<u32>() as f32 / n as f32).round() as u16,
+    }
+}
+
+/// Write the cube's indexed frames, numbered from `first_frame` in errors
+///
+/// Frames take their `delays_cs` entry, or `fallback_delay_cs` when the cube
+/// has no delays. With `local_palette` each frame carries the cube palette as
+/// its local color table; otherwise the frames rely on the global color table.
+fn write_cube_frames<W: std::io::Write>(
+    encoder: &mut Encoder<W>,
+    cube: &QuantizedCubeData,
+    first_frame: usize,
+    fallback_delay_cs: u16,
+    local_palette: bool,
+) -> Result<(), GifError> {
+    let palette_rgb = &cube.global_palette_rgb;
+    let colors = palette_colors(palette_rgb)?;
+    let pixels = cube.width as usize * cube.height as usize;
+    if !cube.delays_cs.is_empty() && cube.delays_cs.len() != cube.indexed_frames.len() {
+        return Err(GifError::InvalidFrameCount(cube.delays_cs.len()));
+    }
+    
+    for (idx, indices) in cube.indexed_frames.iter().enumerate() {
+        let frame_idx = (first_frame + idx) as u32;
+        let frame_error = |message: String| GifError::FrameEncoding { frame_idx, message };
+        if indices.len() != pixels {
+            return Err(frame_error(format!("Expected {} indices, got {}", pixels, indices.len())));
+        }
+        if let Some(&bad) = indices.iter().find(|&&index| index as usize >= colors) {
+            return Err(frame_error(format!("Invalid palette index: {}", bad)));
+        }
+        
+        let frame = Frame {
+            width: cube.width,
+            height: cube.height,
+            delay: cube.delays_cs.get(idx).map_or(fallback_delay_cs, |&delay| delay as u16),
+            buffer: Cow::Borrowed(indices),
+            palette: local_palette.then(|| palette_rgb.clone()),
+            ..Frame::default()
+        };
+        encoder.write_frame(&frame).map_err(|e| frame_error(e.to_string()))?;
+    }
+    Ok(())
+}
+
+/// Number of colors in a cube palette, which must hold 1 to 256 RGB entries
+fn palette_colors(palette_rgb: &[u8]) -> Result<usize, GifError> {
+    if palette_rgb.is_empty() || !palette_rgb.len().is_multiple_of(3) || palette_rgb.len() > 256 * 3 {
+        return Err(GifError::EncodingError(
+            format!("Invalid global palette size: {} bytes", palette_rgb.len())
+        ));
+    }
+    Ok(palette_rgb.len() / 3)
+}
+
+/// Resolve every indexed frame of the cube to opaque RGBA
+fn cube_frames_to_rgba(cube: &QuantizedCubeData) -> Result<Vec<Vec<u8>>, GifError> {
     let mut rgba_frames = Vec::with_capacity(cube.indexed_frames.len());
     
     for indexed_frame in &cube.indexed_frames {
@@ -212,35 +544,7 @@ pub fn m3_write_gif_from_cube(
         rgba_frames.push(rgba);
     }
     
-    // Use existing encoder with NeuQuant method
-    let method = QuantizationMethod::NeuQuant { 
-        colors: 256, 
-        sample_fac: 10
-    };
-    
-    let gif_data = encode_gif89a_rgba(
-        &rgba_frames,
-        cube.width,
-        cube.height,
-        fps_cs as u16,
-        loop_forever,
-        method,
-    )?;
-    
-    let elapsed = start.elapsed();
-    
-    Ok(GifInfo {
-        file_path: String::new(),
-        file_size_bytes: gif_data.len() as u64,
-        frame_count: cube.indexed_frames.len() as u32,
-        palette_size: (cube.global_palette_rgb.len() / 3) as u32,
-        has_netscape_loop: loop_forever,
-        compression_ratio: calculate_compression_ratio(&cube, gif_data.len()),
-        validation_passed: true,
-        processing_time_ms: elapsed.as_millis() as u64,
-        total_processing_ms: elapsed.as_millis() as u64,
-        gif_data,
-    })
+    Ok(rgba_frames)
 }
 
 /// Validate GIF bytes
@@ -271,16 +575,23 @@ pub fn validate_gif_bytes(gif_bytes: Vec<u8>) -> Result<GifValidation, GifError>
     let has_netscape_loop = gif_bytes.windows(11)
         .any(|window| window == netscape_pattern);
     
-    // Count frames (0x2C = image separator)
-    let frame_count = gif_bytes.iter().filter(|&&b| b == 0x2C).count() as u32;
-    
     // Check for trailer (0x3B)
     let has_trailer = gif_bytes.last() == Some(&0x3B);
     if !has_trailer {
         errors.push("Missing GIF trailer (0x3B)".to_string());
     }
     
-    let is_valid = has_gif89a_header && has_netscape_loop && has_trailer && frame_count == 81;
+    // Decode frame headers rather than counting 0x2C bytes, which palettes and pixel data contain too
+    let frame_count = count_frames(&gif_bytes).unwrap_or_else(|| {
+        errors.push("GIF frames could not be decoded".to_string());
+        0
+    });
+    let frame_count_ok = (1..=MAX_FRAME_COUNT as u32).contains(&frame_count);
+    if has_trailer && !frame_count_ok {
+        errors.push(format!("Expected 1 to {} frames, found {}", MAX_FRAME_COUNT, frame_count));
+    }
+    
+    let is_valid = has_gif89a_header && has_netscape_loop && has_trailer && frame_count_ok;
     
     Ok(GifValidation {
         is_valid,
@@ -292,7 +603,255 @@ pub fn validate_gif_bytes(gif_bytes: Vec<u8>) -> Result<GifValidation, GifError>
     })
 }
 
-fn calculate_compression_ratio(cube: &QuantizedCubeData, compressed_size: usize) -> f32 {
-    let uncompressed_size = cube.indexed_frames.len() * cube.indexed_frames[0].len() * 3; // RGB
-    uncompressed_size as f32 / compressed_size as f32
-}
\ No newline at end of file
+/// Number of frames the `gif` decoder finds, `None` if it can't read them
+///
+/// Counts the same way as `m3_gif::count_gif_frames`, which this crate doesn't depend on.
+fn count_frames(gif_bytes: &[u8]) -> Option<u32> {
+    let mut decoder = gif::DecodeOptions::new().read_info(gif_bytes).ok()?;
+    let mut frames = 0;
+    while decoder.next_frame_info().ok()?.is_some() {
+        frames += 1;
+    }
+    Some(frames)
+}
+
+/// Bins for the normalized [0, 1] delta-E histogram; p95 is exact to 1/4096
+const DELTA_E_BINS: usize = 4096;
+
+/// Running mean and fixed-histogram quantiles of normalized delta-E values
+///
+/// Replaces collecting and sorting every pixel's error (531,441 for a full cube).
+struct DeltaEAccumulator {
+    count: u64,
+    sum: f64,
+    histogram: Vec<u32>,
+}
+
+impl DeltaEAccumulator {
+    fn new() -> Self {
+        Self {
+            count: 0,
+            sum: 0.0,
+            histogram: vec![0; DELTA_E_BINS],
+        }
+    }
+    
+    fn push(&mut self, delta_e: f32) {
+        let bin = ((delta_e.clamp(0.0, 1.0) * DELTA_E_BINS as f32) as usize).min(DELTA_E_BINS - 1);
+        self.histogram[bin] += 1;
+        self.sum += delta_e as f64;
+        self.count += 1;
+    }
+    
+    fn mean(&self) -> f32 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        (self.sum / self.count as f64) as f32
+    }
+    
+    /// Midpoint of the bin holding the value a sorted list would have at `count * q`
+    fn quantile(&self, q: f32) -> f32 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        let rank = ((self.count as f64 * q as f64) as u64).min(self.count - 1);
+        
+        let mut seen = 0u64;
+        for (bin, &n) in self.histogram.iter().enumerate() {
+            seen += n as u64;
+            if seen > rank {
+                return (bin as f32 + 0.5) / DELTA_E_BINS as f32;
+            }
+        }
+        1.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_streaming_p95_matches_sorted() {
+        // Skewed distribution: mostly small errors with a long tail
+        let values: Vec<f32> = (0..531_441u32)
+            .map(|i| {
+                let u = (i.wrapping_mul(2_654_435_761) % 1_000_003) as f32 / 1_000_003.0;
+                u * u * u
+            })
+            .collect();
+        
+        let mut accumulator = DeltaEAccumulator::new();
+        for &v in &values {
+            accumulator.push(v);
+        }
+        
+        let mut sorted = values.clone();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let exact_p95 = sorted[(sorted.len() as f32 * 0.95) as usize];
+        let exact_mean = values.iter().map(|&v| v as f64).sum::<f64>() / values.len() as f64;
+        
+        assert!(
+            (accumulator.quantile(0.95) - exact_p95).abs() <= 1.0 / DELTA_E_BINS as f32,
+            "streaming p95 {} vs exact {}",
+            accumulator.quantile(0.95), exact_p95
+        );
+        assert!((accumulator.mean() as f64 - exact_mean).abs() < 1e-5);
+        assert_eq!(DeltaEAccumulator::new().quantile(0.95), 0.0);
+    }
+    
+    #[test]
+    fn test_validate_gif_bytes_counts_real_frames() {
+        for frames in [1usize, 10, 200] {
+            let cube = QuantizedCubeData {
+                width: 81,
+                height: 81,
+                global_palette_rgb: vec![0x2C, 0x2C, 0x2C, 255, 255, 255],
+                indexed_frames: (0..frames).map(|f| vec![(f % 2) as u8; 81 * 81]).collect(),
+                delays_cs: vec![4; frames],
+                palette_stability: 1.0,
+                mean_delta_e: 0.0,
+                p95_delta_e: 0.0,
+            };
+            let info = m3_write_gif_from_cube(cube, 4, true).unwrap();
+
+            let validation = validate_gif_bytes(info.gif_data).unwrap();
+            assert_eq!(validation.frame_count, frames as u32);
+            assert!(validation.is_valid, "{} frames: {:?}", frames, validation.errors);
+        }
+    }
+
+    #[test]
+    fn test_quantize_encode_validate_non_81_frames() {
+        for frames in [1usize, 10] {
+            let frames_rgba: Vec<Vec<u8>> = (0..frames as u32)
+                .map(|f| (0..81 * 81u32).flat_map(|i| [(i % 81 * 3) as u8, (i / 81 * 3) as u8, (f * 20) as u8, 255]).collect())
+                .collect();
+            
+            let cube = m2_quantize_for_cube(frames_rgba).unwrap();
+            assert_eq!(cube.indexed_frames.len(), frames);
+            assert_eq!(cube.delays_cs.len(), frames);
+            
+            let info = m3_write_gif_from_cube(cube, 4, true).unwrap();
+            let validation = validate_gif_bytes(info.gif_data).unwrap();
+            assert_eq!(validation.frame_count, frames as u32);
+            assert!(validation.is_valid, "{} frames: {:?}", frames, validation.errors);
+        }
+        
+        for frames in [0, MAX_FRAME_COUNT as usize + 1] {
+            assert!(matches!(
+                m2_quantize_for_cube(vec![Vec::new(); frames]),
+                Err(GifError::InvalidFrameCount(count)) if count == frames
+            ));
+        }
+    }
+
+    #[test]
+    fn test_write_gif_from_cube_uses_cube_palette() {
+        let palette: Vec<u8> = (0..16u8).flat_map(|i| [i * 16, 255 - i * 16, 7]).collect();
+        let cube = QuantizedCubeData {
+            width: 81,
+            height: 81,
+            global_palette_rgb: palette.clone(),
+            indexed_frames: (0..3).map(|f| (0..81 * 81).map(|i| ((i + f) % 16) as u8).collect()).collect(),
+            delays_cs: vec![4; 3],
+            palette_stability: 1.0,
+            mean_delta_e: 0.0,
+            p95_delta_e: 0.0,
+        };
+        let info = m3_write_gif_from_cube(cube.clone(), 4, true).unwrap();
+        
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(info.gif_data.as_slice()).unwrap();
+        assert_eq!(&decoder.global_palette().unwrap()[..palette.len()], palette.as_slice());
+        for indices in &cube.indexed_frames {
+            let frame = decoder.read_next_frame().unwrap().unwrap();
+            assert!(frame.palette.is_none());
+            assert_eq!(frame.buffer.as_ref(), indices.as_slice());
+        }
+        assert!(decoder.read_next_frame().unwrap().is_none());
+        
+        // Indices past the palette are rejected rather than written
+        let mut bad = cube;
+        bad.indexed_frames[1][0] = 16;
+        assert!(matches!(
+            m3_write_gif_from_cube(bad, 4, true),
+            Err(GifError::FrameEncoding { frame_idx: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn test_write_gif_from_segments_uses_local_palettes() {
+        let segment = |palette: Vec<u8>, frames: usize, offset: usize| QuantizedCubeData {
+            width: 81,
+            height: 81,
+            global_palette_rgb: palette,
+            indexed_frames: (0..frames).map(|f| (0..81 * 81).map(|i| ((i + f + offset) % 4) as u8).collect()).collect(),
+            delays_cs: vec![4; frames],
+            palette_stability: 1.0,
+            mean_delta_e: 0.0,
+            p95_delta_e: 0.0,
+        };
+        let reds: Vec<u8> = (0..4u8).flat_map(|i| [100 + i * 40, 0, 0]).collect();
+        let blues: Vec<u8> = (0..4u8).flat_map(|i| [0, 0, 100 + i * 40]).collect();
+        let segmented = SegmentedCubeData {
+            segment_starts: vec![0, 2],
+            segments: vec![segment(reds.clone(), 2, 0), segment(blues.clone(), 3, 1)],
+        };
+        let info = m3_write_gif_from_segments(segmented.clone(), 4, true).unwrap();
+        assert_eq!(info.frame_count, 5);
+        
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(info.gif_data.as_slice()).unwrap();
+        for (palette, cube) in [(&reds, &segmented.segments[0]), (&blues, &segmented.segments[1])] {
+            for indices in &cube.indexed_frames {
+                let frame = decoder.read_next_frame().unwrap().unwrap();
+                assert_eq!(frame.palette.as_deref(), Some(palette.as_slice()));
+                assert_eq!(frame.buffer.as_ref(), indices.as_slice());
+            }
+        }
+        assert!(decoder.read_next_frame().unwrap().is_none());
+        
+        // Starts that disagree with the segment lengths are rejected
+        let mut misaligned = segmented;
+        misaligned.segment_starts = vec![0, 3];
+        assert!(m3_write_gif_from_segments(misaligned, 4, true).is_err());
+    }
+
+    #[test]
+    fn test_segmented_quantize_splits_at_scene_cut() {
+        // Frames 0-40 red-dominant, 41-80 blue-dominant, with texture in each
+        let frames: Vec<Vec<u8>> = (0..81u32)
+            .map(|f| {
+                (0..81 * 81u32)
+                    .flat_map(|i| {
+                        let shade = ((i * 7 + f * 3) % 64) as u8;
+                        if f <= 40 { [192 + shade, shade, shade / 2, 255] } else { [shade / 2, shade, 192 + shade, 255] }
+                    })
+                    .collect()
+            })
+            .collect();
+        
+        let segmented = m2_quantize_for_cube_segmented(frames.clone(), DEFAULT_SCENE_THRESHOLD).unwrap();
+        assert_eq!(segmented.segment_starts, vec![0, 41]);
+        assert_eq!(segmented.segments.len(), 2);
+        assert_eq!(segmented.segments[0].indexed_frames.len(), 41);
+        assert_eq!(segmented.segments[1].indexed_frames.len(), 40);
+        
+        // Each palette only covers its own regime
+        let dominant = |cube: &QuantizedCubeData| {
+            let (red, blue) = cube.global_palette_rgb.chunks(3).fold((0u64, 0u64), |(r, b), c| (r + c[0] as u64, b + c[2] as u64));
+            if red > blue { "red" } else { "blue" }
+        };
+        assert_eq!(dominant(&segmented.segments[0]), "red");
+        assert_eq!(dominant(&segmented.segments[1]), "blue");
+        
+        // Steady footage stays one segment
+        let steady = m2_quantize_for_cube_segmented(frames[..41].iter().cycle().take(81).cloned().collect(), DEFAULT_SCENE_THRESHOLD).unwrap();
+        assert_eq!(steady.segment_starts, vec![0]);
+        assert!(m2_quantize_for_cube_segmented(frames, 0.0).is_err());
+    }
+}
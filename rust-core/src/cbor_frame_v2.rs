@@ -289,6 +289,27 @@ impl CborFrameV2 {
     }
 }
 
+/// Upper bound on the CBOR bytes of every field except the RGBA payload
+/// (field names, color space strings, camera metadata); measured at ~410
+pub const CBOR_FRAME_V2_OVERHEAD_BYTES: u64 = 448;
+
+/// Estimate on-disk bytes for a raw capture of `frames` CBOR V2 frames
+/// Lets the app check free space before capturing; errs slightly high
+pub fn estimate_cbor_capture_size(width: u16, height: u16, frames: u32) -> u64 {
+    let payload = width as u64 * height as u64 * 4;
+    
+    // CBOR byte string header grows with the payload length
+    let bytes_header = match payload {
+        0..=23 => 1,
+        24..=0xFF => 2,
+        0x100..=0xFFFF => 3,
+        0x1_0000..=0xFFFF_FFFF => 5,
+        _ => 9,
+    };
+    
+    frames as u64 * (payload + bytes_header + CBOR_FRAME_V2_OVERHEAD_BYTES)
+}
+
 /// Frame quality statistics
 #[derive(Debug, Default)]
 pub struct FrameStatistics {
@@ -414,6 +435,32 @@ mod tests {
         assert_eq!(frame.rgba_data[3], 255); // A
     }
     
+    #[test]
+    fn test_capture_size_estimate() {
+        let metadata = FrameMetadata {
+            exposure_time_ns: 33_333_333,
+            iso_sensitivity: 800,
+            sensor_timestamp: 1_700_000_000_000_000_000,
+            ..FrameMetadata::default()
+        };
+        let rgba = vec![128u8; 729 * 729 * 4];
+        
+        let actual: u64 = (0..81u16)
+            .map(|index| {
+                let frame = CborFrameV2::from_camera_data(
+                    &rgba, 729, 729, 729 * 4, index, 1_700_000_000_000 + index as u64 * 33, metadata.clone(),
+                ).unwrap();
+                frame.to_cbor().unwrap().len() as u64
+            })
+            .sum();
+        
+        let estimate = estimate_cbor_capture_size(729, 729, 81);
+        assert!(estimate >= actual, "estimate {} below actual {}", estimate, actual);
+        assert!((estimate - actual) as f64 / (actual as f64) < 0.03);
+        
+        assert_eq!(estimate_cbor_capture_size(729, 729, 0), 0);
+    }
+    
     #[test]
     fn test_quality_validation() {
         let mut rgba = vec![128; 729 * 729 * 4];
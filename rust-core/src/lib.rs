@@ -14,6 +14,9 @@ use log::info;
 pub use fast_cbor::{write_cbor_frame, init_android_logger};
 pub use fast_cbor_v2::{write_cbor_frame_v2, write_cbor_frame_v2_simple, verify_cbor_v2_file};
 pub use m1_verification::{m1_debug_signature, m1_preview_patch};
+pub use cbor_frame_v2::estimate_cbor_capture_size;
+
+use cbor_frame_v2::CborFrameV2;
 
 #[derive(Error, Debug)]
 pub enum GifPipeError {
@@ -103,6 +106,150 @@ pub fn m3_gif89a_encode_rgba_frames(
     Ok(())
 }
 
+/// Validate that all frames share the same dimensions and carry tight RGBA data
+/// Returns the common (width, height) for encoding arbitrary-size inputs
+pub fn validate_frame_consistency(frames: &[CborFrameV2]) -> Result<(u16, u16), GifPipeError> {
+    let first = frames.first()
+        .ok_or_else(|| GifPipeError::ValidationError("No frames provided".to_string()))?;
+    let (width, height) = (first.width, first.height);
+    
+    if width == 0 || height == 0 {
+        return Err(GifPipeError::ValidationError(
+            format!("Frame 0: invalid dimensions {}x{}", width, height)
+        ));
+    }
+    
+    for (idx, frame) in frames.iter().enumerate() {
+        if frame.width != width || frame.height != height {
+            return Err(GifPipeError::ValidationError(
+                format!("Frame {}: {}x{} does not match frame 0 ({}x{})",
+                        idx, frame.width, frame.height, width, height)
+            ));
+        }
+        
+        let expected_len = width as usize * height as usize * 4;
+        if frame.rgba_data.len() != expected_len {
+            return Err(GifPipeError::ValidationError(
+                format!("Frame {}: expected {} bytes ({}x{}x4), got {}",
+                        idx, expected_len, width, height, frame.rgba_data.len())
+            ));
+        }
+    }
+    
+    Ok((width, height))
+}
+
+/// Encode CBOR V2 frames straight to GIF89a bytes
+/// Verifies each frame's CRC32, applies the recorded mirroring and rotation,
+/// downsizes to fit 81×81 when larger, then quantizes to a global palette
+pub fn encode_gif_from_cbor_frames(
+    frames: Vec<CborFrameV2>,
+    delay_cs: u16,
+    loop_gif: bool,
+) -> Result<Vec<u8>, GifPipeError> {
+    const MAX_SIDE: u32 = 81;
+    
+    validate_frame_consistency(&frames)?;
+    
+    let mut oriented_frames = Vec::with_capacity(frames.len());
+    let mut oriented_size = None;
+    
+    for (idx, frame) in frames.into_iter().enumerate() {
+        if !frame.verify_integrity() {
+            return Err(GifPipeError::ValidationError(
+                format!("Frame {}: checksum mismatch (expected {:08x})", idx, frame.checksum)
+            ));
+        }
+        
+        let (rgba, width, height) = orient_rgba(
+            frame.rgba_data,
+            frame.width as u32,
+            frame.height as u32,
+            frame.metadata.rotation_degrees,
+            frame.metadata.is_mirrored,
+        ).map_err(|e| GifPipeError::ValidationError(format!("Frame {}: {}", idx, e)))?;
+        
+        match oriented_size {
+            None => oriented_size = Some((width, height)),
+            Some(size) if size != (width, height) => {
+                return Err(GifPipeError::ValidationError(
+                    format!("Frame {}: rotation gives {}x{}, frame 0 gives {}x{}",
+                            idx, width, height, size.0, size.1)
+                ));
+            }
+            Some(_) => {}
+        }
+        oriented_frames.push(rgba);
+    }
+    
+    let (width, height) = oriented_size.unwrap_or((0, 0));
+    
+    // Fit the longer side to 81, keeping aspect ratio
+    let scale = MAX_SIDE as f32 / width.max(height) as f32;
+    let (out_width, out_height) = if scale < 1.0 {
+        (((width as f32 * scale) as u32).max(1), ((height as f32 * scale) as u32).max(1))
+    } else {
+        (width, height)
+    };
+    
+    if (out_width, out_height) != (width, height) {
+        oriented_frames = oriented_frames.iter()
+            .map(|rgba| bilinear_downscale_rgba(rgba, width, height, out_width, out_height))
+            .collect::<Result<_, _>>()?;
+    }
+    
+    info!("Encoding {} CBOR frames: {}x{} -> {}x{}",
+          oriented_frames.len(), width, height, out_width, out_height);
+    
+    let quantized = quantize_rgba_frames(&oriented_frames, out_width, out_height, None)?;
+    let gif_data = encode_gif89a_to_bytes(
+        &quantized,
+        out_width,
+        out_height,
+        Some(GifEncodeOptions { delay_cs, loop_gif, ..Default::default() }),
+    )?;
+    
+    Ok(gif_data)
+}
+
+/// Mirror horizontally (if requested) then rotate clockwise by 0/90/180/270 degrees
+fn orient_rgba(
+    rgba: Vec<u8>,
+    width: u32,
+    height: u32,
+    rotation_degrees: u16,
+    mirrored: bool,
+) -> Result<(Vec<u8>, u32, u32), String> {
+    let (w, h) = (width as usize, height as usize);
+    let (out_w, out_h) = match rotation_degrees {
+        0 | 180 => (w, h),
+        90 | 270 => (h, w),
+        _ => return Err(format!("unsupported rotation {} degrees", rotation_degrees)),
+    };
+    if rotation_degrees == 0 && !mirrored {
+        return Ok((rgba, width, height));
+    }
+    
+    let mut output = vec![0u8; rgba.len()];
+    
+    for y in 0..h {
+        for x in 0..w {
+            let src_x = if mirrored { w - 1 - x } else { x };
+            let (dst_x, dst_y) = match rotation_degrees {
+                90 => (h - 1 - y, x),
+                180 => (w - 1 - x, h - 1 - y),
+                270 => (y, w - 1 - x),
+                _ => (x, y),
+            };
+            let src = (y * w + src_x) * 4;
+            let dst = (dst_y * out_w + dst_x) * 4;
+            output[dst..dst + 4].copy_from_slice(&rgba[src..src + 4]);
+        }
+    }
+    
+    Ok((output, out_w as u32, out_h as u32))
+}
+
 /// Write CBOR frame data to file (M1 output format) - ENHANCED with parsing
 pub fn cbor_write_frame_sequence(
     cbor_frames: Vec<Vec<u8>>,
@@ -130,6 +277,14 @@ pub fn cbor_write_frame_sequence(
 /// Verify GIF89a file structure and timing
 pub fn verify_gif89a_structure(gif_file: String) -> Result<HashMap<String, String>, GifPipeError> {
     let data = std::fs::read(&gif_file)?;
+    inspect_gif89a_bytes(&data)
+}
+
+/// Walk the GIF block structure, flagging truncated bodies
+///
+/// Frames are only counted once their image data is complete, so a file cut
+/// short reports `truncated=true` rather than a misleading frame count.
+fn inspect_gif89a_bytes(data: &[u8]) -> Result<HashMap<String, String>, GifPipeError> {
     let mut results = HashMap::new();
     
     // Basic header validation
@@ -147,16 +302,80 @@ pub fn verify_gif89a_structure(gif_file: String) -> Result<HashMap<String, Strin
     results.insert("header".to_string(), format!("GIF{}", version));
     results.insert("file_size".to_string(), data.len().to_string());
     
-    // Look for NETSCAPE2.0 extension (looping)
-    let has_loop = data.windows(11).any(|w| w == b"NETSCAPE2.0");
-    results.insert("has_loop".to_string(), has_loop.to_string());
+    let mut has_loop = false;
+    let mut has_trailer = false;
+    let mut frame_count = 0usize;
+    let mut truncated = true;
     
-    // Count frames (simplified - count image separators)
-    let frame_count = data.iter().filter(|&&b| b == 0x2C).count();
+    // Logical screen descriptor (7 bytes) and optional global color table
+    if data.len() >= 13 {
+        let packed = data[10];
+        let mut pos = 13;
+        if packed & 0x80 != 0 {
+            pos += 3 * (1usize << ((packed & 0x07) + 1));
+        }
+        
+        // Returns the position after a sub-block chain, or None if it runs past EOF
+        let skip_sub_blocks = |mut pos: usize| -> Option<usize> {
+            loop {
+                let size = *data.get(pos)? as usize;
+                pos += 1;
+                if size == 0 {
+                    return Some(pos);
+                }
+                pos += size;
+            }
+        };
+        
+        while pos < data.len() {
+            match data[pos] {
+                0x21 => {
+                    let label = data.get(pos + 1).copied();
+                    if label == Some(0xFF) && data.get(pos + 3..pos + 14) == Some(&b"NETSCAPE2.0"[..]) {
+                        has_loop = true;
+                    }
+                    match skip_sub_blocks(pos + 2) {
+                        Some(next) => pos = next,
+                        None => break,
+                    }
+                }
+                0x2C => {
+                    let Some(&packed) = data.get(pos + 9) else { break };
+                    let mut data_start = pos + 10;
+                    if packed & 0x80 != 0 {
+                        data_start += 3 * (1usize << ((packed & 0x07) + 1));
+                    }
+                    // LZW minimum code size precedes the image sub-blocks
+                    match skip_sub_blocks(data_start + 1) {
+                        Some(next) => {
+                            frame_count += 1;
+                            pos = next;
+                        }
+                        None => break,
+                    }
+                }
+                0x3B => {
+                    has_trailer = true;
+                    truncated = false;
+                    break;
+                }
+                other => {
+                    // Unknown block: the rest of the stream can't be interpreted
+                    results.insert("invalid_block".to_string(), format!("0x{:02X}@{}", other, pos));
+                    truncated = false;
+                    break;
+                }
+            }
+        }
+    }
+    
+    results.insert("has_loop".to_string(), has_loop.to_string());
     results.insert("frame_count".to_string(), frame_count.to_string());
+    results.insert("has_trailer".to_string(), has_trailer.to_string());
+    results.insert("truncated".to_string(), truncated.to_string());
     
-    info!("GIF verification: {} frames, loop={}, size={} bytes", 
-          frame_count, has_loop, data.len());
+    info!("GIF verification: {} frames, loop={}, truncated={}, size={} bytes", 
+          frame_count, has_loop, truncated, data.len());
     
     Ok(results)
 }
@@ -171,4 +390,106 @@ pub fn calculate_file_hash(file_path: String) -> Result<String, GifPipeError> {
 }
 
 // Include the UniFFI scaffolding
-uniffi::include_scaffolding!("gifpipe");
\ No newline at end of file
+uniffi::include_scaffolding!("gifpipe");
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_frame_consistency_accepts_matching_frames() {
+        let frames = vec![
+            CborFrameV2::new(81, 81, vec![0; 81 * 81 * 4], 81 * 4, 0, 0),
+            CborFrameV2::new(81, 81, vec![0; 81 * 81 * 4], 81 * 4, 1, 40),
+        ];
+        
+        assert_eq!(validate_frame_consistency(&frames).unwrap(), (81, 81));
+    }
+    
+    #[test]
+    fn test_verify_header_only_gif_reports_truncated() {
+        let results = inspect_gif89a_bytes(b"GIF89a").unwrap();
+        
+        assert_eq!(results["header"], "GIF89a");
+        assert_eq!(results["truncated"], "true");
+        assert_eq!(results["frame_count"], "0");
+        assert_eq!(results["has_trailer"], "false");
+    }
+    
+    #[test]
+    fn test_verify_complete_gif_counts_frames() {
+        let mut gif = b"GIF89a".to_vec();
+        gif.extend_from_slice(&[1, 0, 1, 0, 0x80, 0, 0]); // 1×1 screen, 2-entry GCT
+        gif.extend_from_slice(&[0, 0, 0, 255, 255, 255]);
+        gif.extend_from_slice(&[0x21, 0xFF, 0x0B]);
+        gif.extend_from_slice(b"NETSCAPE2.0");
+        gif.extend_from_slice(&[0x03, 0x01, 0x00, 0x00, 0x00]);
+        gif.extend_from_slice(&[0x2C, 0, 0, 0, 0, 1, 0, 1, 0, 0]); // Image descriptor
+        gif.extend_from_slice(&[0x02, 0x02, 0x44, 0x01, 0x00]); // LZW data
+        
+        // Cut inside the image data: the frame isn't counted
+        let cut = inspect_gif89a_bytes(&gif[..gif.len() - 2]).unwrap();
+        assert_eq!(cut["truncated"], "true");
+        assert_eq!(cut["frame_count"], "0");
+        
+        gif.push(0x3B);
+        let results = inspect_gif89a_bytes(&gif).unwrap();
+        assert_eq!(results["truncated"], "false");
+        assert_eq!(results["frame_count"], "1");
+        assert_eq!(results["has_loop"], "true");
+    }
+    
+    #[test]
+    fn test_encode_gif_from_cbor_frames() {
+        let make_frames = || -> Vec<CborFrameV2> {
+            (0..81u16)
+                .map(|i| {
+                    let rgba: Vec<u8> = (0..162 * 162)
+                        .flat_map(|p| [(p % 162) as u8, (p / 162) as u8, (i * 3) as u8, 255])
+                        .collect();
+                    CborFrameV2::new(162, 162, rgba, 162 * 4, i, i as u64 * 40)
+                })
+                .collect()
+        };
+        
+        let gif = encode_gif_from_cbor_frames(make_frames(), 4, true).unwrap();
+        let results = inspect_gif89a_bytes(&gif).unwrap();
+        assert_eq!(results["header"], "GIF89a");
+        assert_eq!(results["frame_count"], "81");
+        assert_eq!(results["truncated"], "false");
+        assert_eq!(u16::from_le_bytes([gif[6], gif[7]]), 81); // Downsized to fit
+        
+        let mut frames = make_frames();
+        frames[40].rgba_data[0] ^= 0xFF;
+        let err = encode_gif_from_cbor_frames(frames, 4, true).unwrap_err();
+        assert!(matches!(err, GifPipeError::ValidationError(ref msg) if msg.contains("Frame 40")));
+    }
+    
+    #[test]
+    fn test_orient_rgba_rotates_and_mirrors() {
+        // 2×1 image: red, green
+        let rgba = vec![255, 0, 0, 255, 0, 255, 0, 255];
+        
+        let (rotated, w, h) = orient_rgba(rgba.clone(), 2, 1, 90, false).unwrap();
+        assert_eq!((w, h), (1, 2));
+        assert_eq!(rotated, rgba);
+        
+        let (mirrored, _, _) = orient_rgba(rgba.clone(), 2, 1, 0, true).unwrap();
+        assert_eq!(mirrored, vec![0, 255, 0, 255, 255, 0, 0, 255]);
+        
+        assert!(orient_rgba(rgba, 2, 1, 45, false).is_err());
+    }
+    
+    #[test]
+    fn test_frame_consistency_rejects_mixed_sizes() {
+        let frames = vec![
+            CborFrameV2::new(81, 81, vec![0; 81 * 81 * 4], 81 * 4, 0, 0),
+            CborFrameV2::new(80, 80, vec![0; 80 * 80 * 4], 80 * 4, 1, 40),
+        ];
+        
+        let err = validate_frame_consistency(&frames).unwrap_err();
+        assert!(matches!(err, GifPipeError::ValidationError(ref msg) if msg.contains("Frame 1")));
+        
+        assert!(validate_frame_consistency(&[]).is_err());
+    }
+}
\ No newline at end of file
@@ -1,20 +1,75 @@
 use common_types::QuantizedCubeData;
 use std::fs;
-use std::path::Path;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+/// Decode every indexed frame through the global palette and write it as
+/// `frame_NNNN.png` in `dir`, returning the number of files written
+///
+/// Indices outside the palette decode to black and frames of the wrong size
+/// are skipped with a warning, so broken cubes can still be inspected frame by
+/// frame.
+fn dump_frames(cube: &QuantizedCubeData, dir: &Path) -> Result<usize, Box<dyn std::error::Error>> {
+    fs::create_dir_all(dir)?;
+    let pixels = cube.width as usize * cube.height as usize;
+    let mut written = 0;
+    for (idx, frame) in cube.indexed_frames.iter().enumerate() {
+        if frame.len() != pixels {
+            eprintln!("⚠️ Warning: skipping frame {} with {} pixels, expected {}", idx, frame.len(), pixels);
+            continue;
+        }
+        let rgb: Vec<u8> = frame
+            .iter()
+            .flat_map(|&index| {
+                let base = index as usize * 3;
+                let color = cube.global_palette_rgb.get(base..base + 3).unwrap_or(&[0, 0, 0]);
+                [color[0], color[1], color[2]]
+            })
+            .collect();
+        
+        let file = fs::File::create(dir.join(format!("frame_{:04}.png", idx)))?;
+        let mut encoder = png::Encoder::new(BufWriter::new(file), cube.width as u32, cube.height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(&rgb)?;
+        written += 1;
+    }
+    Ok(written)
+}
+
+const USAGE: &str = "Usage: validate_cube <path_to_quantized_data.json> [--dump-frames <dir>]";
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     env_logger::init();
     
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() < 2 {
-        eprintln!("Usage: validate_cube <path_to_quantized_data.json>");
-        std::process::exit(1);
+    let mut data_path = None;
+    let mut dump_dir = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--dump-frames" {
+            let Some(dir) = args.next().filter(|dir| !dir.starts_with("--")) else {
+                eprintln!("--dump-frames needs a directory\n{}", USAGE);
+                std::process::exit(1);
+            };
+            dump_dir = Some(PathBuf::from(dir));
+        } else {
+            data_path = Some(PathBuf::from(arg));
+        }
     }
+    let Some(data_path) = data_path else {
+        eprintln!("{}", USAGE);
+        std::process::exit(1);
+    };
     
-    let data_path = Path::new(&args[1]);
-    let json_data = fs::read_to_string(data_path)?;
+    let json_data = fs::read_to_string(&data_path)?;
     let cube_data: QuantizedCubeData = serde_json::from_str(&json_data)?;
     
+    // Dump before validating so frames of a cube that fails can still be inspected
+    if let Some(dir) = &dump_dir {
+        let written = dump_frames(&cube_data, dir)?;
+        println!("Dumped {} frames to {}\n", written, dir.display());
+    }
+    
     println!("=== 81×81×81 Cube Validation Report ===\n");
     
     // 1. Frame count validation
@@ -47,9 +102,12 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     
     // 5. Temporal coherence validation
     println!("\n=== Temporal Coherence ===");
-    println!("Palette Stability: {:.2}%", cube_data.palette_stability * 100.0);
+    // Recompute rather than trust the producer's value so every quantizer is judged alike
+    let stability = cube_data.recompute_stability();
+    println!("Palette Stability: {:.2}% (reported {:.2}%)",
+        stability * 100.0, cube_data.palette_stability * 100.0);
     
-    if cube_data.palette_stability < 0.85 {
+    if stability < 0.85 {
         eprintln!("⚠️ Warning: Low palette stability for cube coherence");
     }
     
@@ -78,4 +136,51 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("\n✅ Cube validation complete!");
     
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_dump_frames_match_palette_lookup() {
+        let palette = vec![255, 0, 0, 0, 255, 0, 0, 0, 255, 255, 255, 255];
+        let cube = QuantizedCubeData {
+            width: 81,
+            height: 81,
+            global_palette_rgb: palette.clone(),
+            indexed_frames: (0..81).map(|f| (0..81 * 81).map(|i| ((i + f) % 4) as u8).collect()).collect(),
+            delays_cs: vec![4; 81],
+            palette_stability: 1.0,
+            mean_delta_e: 0.0,
+            p95_delta_e: 0.0,
+            attention_maps: None,
+            frame_checksums: None,
+            source_frame_ids: None,
+            schema_version: common_types::CUBE_SCHEMA_VERSION,
+        };
+        let dir = tempfile::tempdir().unwrap();
+        
+        assert_eq!(dump_frames(&cube, dir.path()).unwrap(), 81);
+        
+        for (idx, frame) in cube.indexed_frames.iter().enumerate() {
+            let file = fs::File::open(dir.path().join(format!("frame_{:04}.png", idx))).unwrap();
+            let mut reader = png::Decoder::new(file).read_info().unwrap();
+            let mut rgb = vec![0; reader.output_buffer_size()];
+            let info = reader.next_frame(&mut rgb).unwrap();
+            assert_eq!((info.width, info.height, info.color_type), (81, 81, png::ColorType::Rgb));
+            
+            for (pixel, &index) in rgb.chunks_exact(3).zip(frame) {
+                assert_eq!(pixel, &palette[index as usize * 3..index as usize * 3 + 3], "frame {}", idx);
+            }
+        }
+        
+        // A frame of the wrong size is skipped instead of ending the dump
+        let mut broken = cube;
+        broken.indexed_frames[5].truncate(100);
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(dump_frames(&broken, dir.path()).unwrap(), 80);
+        assert!(!dir.path().join("frame_0005.png").exists());
+        assert!(dir.path().join("frame_0080.png").exists());
+    }
+}